@@ -1,5 +1,6 @@
 use crate::tools::{
 	failed::failed,
+	filter::Filters,
 	index::{Index, MergeStatus},
 };
 use log::{info, warn};
@@ -12,10 +13,12 @@ where
 {
 	let mut index = Index::read()
 		.unwrap_or_else(|e| failed(&format!("failed to read index because of {:?}", e)));
+	let filters = Filters::read()
+		.unwrap_or_else(|e| failed(&format!("failed to read filters because of {:?}", e)));
 
 	for path in paths {
 		let path = path.as_ref();
-		if let Err(e) = index.add_change(MergeStatus::Regular, path) {
+		if let Err(e) = index.add_change(MergeStatus::Regular, path, &filters) {
 			warn!("Adding changes to {:?} failed: {:?}", path, e);
 		} else {
 			info!("Adding changes to {:?} succeeded", path);
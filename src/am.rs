@@ -0,0 +1,3 @@
+pub mod am_cmd;
+pub mod am_do;
+mod am_parse;
@@ -0,0 +1,62 @@
+use super::{
+	am_do::{am, am_abort, am_continue, am_skip, Session},
+	am_parse::Args,
+};
+use crate::tools::failed::failed;
+use std::{
+	error::Error,
+	fs,
+	io::{self, Read},
+	process::exit,
+};
+
+fn report_session(session: Result<Session, Box<dyn Error>>, command: &str) {
+	let session = session.unwrap_or_else(|e| failed(&format!("Failed {}: {:?}", command, e)));
+	for subject in &session.applied {
+		println!("Applying: {}", subject);
+	}
+	if let Some(stopped) = session.stopped {
+		println!("Applying: {}", stopped.subject);
+		if let Some(error) = &stopped.error {
+			println!("error: {}", error);
+		}
+		for path in &stopped.conflicts {
+			println!("CONFLICT in {}", path);
+		}
+		println!("Patch failed at {:04} {}", stopped.number, stopped.subject);
+		println!(
+			"Resolve the patch, add the files and run unrusty am --continue, or skip it with \
+			 unrusty am --skip"
+		);
+		exit(1);
+	}
+}
+
+pub fn cmd_am<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	if args.flag_abort {
+		am_abort().unwrap_or_else(|e| failed(&format!("Failed am --abort: {:?}", e)));
+	} else if args.flag_continue {
+		report_session(am_continue(), "am --continue");
+	} else if args.flag_skip {
+		report_session(am_skip(), "am --skip");
+	} else {
+		let mut mboxes = Vec::new();
+		if args.arg_mbox.is_empty() {
+			let mut data = Vec::new();
+			io::stdin()
+				.read_to_end(&mut data)
+				.unwrap_or_else(|e| failed(&format!("Failed am: {:?}", e)));
+			mboxes.push(data);
+		}
+		for path in &args.arg_mbox {
+			mboxes.push(fs::read(path).unwrap_or_else(|e| failed(&format!("Failed am: {:?}", e))));
+		}
+		report_session(am(&mboxes, args.flag_3way), "am");
+	}
+}
@@ -0,0 +1,233 @@
+use crate::{
+	apply::apply_do::{apply, ApplyOptions},
+	merge::merge_do::ensure_clean,
+	tools::{
+		commit::{commit_tree, subject, Commit},
+		db::ObjectRepr,
+		filter::Filters,
+		index::Index,
+		mail::{parse_mail, split_mbox, Mail},
+		path::{vcs_path_required, RelativePathToBase},
+		refs::{read_ref, update_ref, HEAD, MERGE_HEAD},
+		tree::write_flat_tree,
+		worktree::switch_tree,
+	},
+};
+use std::{convert::TryFrom, error::Error, fs, io::ErrorKind, path::PathBuf};
+
+// State of a series of mails being applied, one file per mail named by its
+// number, "0001" onwards
+const STATE_DIR: &str = "rebase-apply";
+// Number of the mail to apply, and of the last one
+const NEXT: &str = "next";
+const LAST: &str = "last";
+// HEAD before the first mail was applied, restored by --abort
+const ORIG_HEAD_FILE: &str = "orig-head";
+// Present when patches are merged with -3
+const THREE_WAY: &str = "threeway";
+
+// The mail a session stopped at, left to resolve
+#[derive(Debug)]
+pub struct Stopped {
+	pub number:    usize,
+	pub subject:   String,
+	// Why the patch did not apply, None if it was merged with conflicts
+	pub error:     Option<String>,
+	pub conflicts: Vec<RelativePathToBase>,
+}
+
+// Subjects of the mails committed, and where the session stopped if it did
+#[derive(Debug)]
+pub struct Session {
+	pub applied: Vec<String>,
+	pub stopped: Option<Stopped>,
+}
+
+fn state_path(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+	Ok(vcs_path_required(&format!("{}/{}", STATE_DIR, name))?)
+}
+
+fn in_progress() -> Result<bool, Box<dyn Error>> {
+	Ok(vcs_path_required(STATE_DIR)?.is_dir())
+}
+
+fn read_state(name: &str) -> Result<Option<String>, Box<dyn Error>> {
+	match fs::read_to_string(state_path(name)?) {
+		Ok(content) => Ok(Some(content)),
+		Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(e.into()),
+	}
+}
+
+fn read_number(name: &str) -> Result<usize, Box<dyn Error>> {
+	read_state(name)?
+		.and_then(|content| content.trim().parse().ok())
+		.ok_or_else(|| format!("am state {} is malformed", name).into())
+}
+
+fn write_number(name: &str, number: usize) -> Result<(), Box<dyn Error>> {
+	Ok(fs::write(state_path(name)?, format!("{}\n", number))?)
+}
+
+fn mail_name(number: usize) -> String {
+	format!("{:04}", number)
+}
+
+fn read_mail(number: usize) -> Result<Mail, Box<dyn Error>> {
+	Ok(parse_mail(&fs::read(state_path(&mail_name(number))?)?)?)
+}
+
+// Commits the index with the author and message of the mail
+fn commit_mail(index: &Index, mail: &Mail) -> Result<(), Box<dyn Error>> {
+	let parents = read_ref(HEAD)?.into_iter().collect::<Vec<_>>();
+	let tree = write_flat_tree(&index.flat_tree())?;
+	let commit = Commit::with_author(tree, parents, &mail.author, &mail.message)?.write()?;
+	update_ref(HEAD, &commit, &format!("am: {}", subject(&mail.message)))?;
+	Ok(())
+}
+
+// Applies the mails left until one does not apply cleanly
+fn run_session(mut applied: Vec<String>, filters: &Filters) -> Result<Session, Box<dyn Error>> {
+	let options = ApplyOptions {
+		index: true,
+		three_way: state_path(THREE_WAY)?.exists(),
+		..ApplyOptions::default()
+	};
+	let last = read_number(LAST)?;
+	loop {
+		let number = read_number(NEXT)?;
+		if number > last {
+			fs::remove_dir_all(vcs_path_required(STATE_DIR)?)?;
+			return Ok(Session {
+				applied,
+				stopped: None,
+			});
+		}
+		let mail = read_mail(number)?;
+		let stopped = match apply(&mail.patch, &options, filters) {
+			Ok(result) if result.conflicts.is_empty() => None,
+			Ok(result) => Some(Stopped {
+				number,
+				subject: subject(&mail.message),
+				error: None,
+				conflicts: result.conflicts,
+			}),
+			Err(e) => Some(Stopped {
+				number,
+				subject: subject(&mail.message),
+				error: Some(e.to_string()),
+				conflicts: Vec::new(),
+			}),
+		};
+		if stopped.is_some() {
+			return Ok(Session { applied, stopped });
+		}
+		commit_mail(&Index::read()?, &mail)?;
+		applied.push(subject(&mail.message));
+		write_number(NEXT, number + 1)?;
+	}
+}
+
+pub fn am(mboxes: &[Vec<u8>], three_way: bool) -> Result<Session, Box<dyn Error>> {
+	if in_progress()? {
+		return Err("an am session is in progress, use --continue, --skip or --abort".into());
+	}
+	if read_ref(MERGE_HEAD)?.is_some() {
+		return Err("a merge is in progress, finish or abort it first".into());
+	}
+	let mails = mboxes
+		.iter()
+		.flat_map(|mbox| split_mbox(mbox))
+		.collect::<Vec<_>>();
+	if mails.is_empty() {
+		return Err("no mail found".into());
+	}
+	// Mails are checked before anything is applied
+	for mail in &mails {
+		parse_mail(mail)?;
+	}
+	let head = read_ref(HEAD)?;
+	let filters = Filters::read()?;
+	ensure_clean(&Index::read()?, &commit_tree(head.as_ref())?, &filters)?;
+
+	fs::create_dir_all(vcs_path_required(STATE_DIR)?)?;
+	for (i, mail) in mails.iter().enumerate() {
+		fs::write(state_path(&mail_name(i + 1))?, mail)?;
+	}
+	write_number(NEXT, 1)?;
+	write_number(LAST, mails.len())?;
+	if let Some(head) = &head {
+		fs::write(state_path(ORIG_HEAD_FILE)?, format!("{}\n", head))?;
+	}
+	if three_way {
+		fs::write(state_path(THREE_WAY)?, "")?;
+	}
+	run_session(Vec::new(), &filters)
+}
+
+pub fn am_continue() -> Result<Session, Box<dyn Error>> {
+	if !in_progress()? {
+		return Err("there is no am session in progress".into());
+	}
+	let index = Index::read()?;
+	let conflicts = index.conflicts();
+	if !conflicts.is_empty() {
+		let paths = conflicts.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+		return Err(format!(
+			"unresolved conflicts, add the files first: {}",
+			paths.join(", ")
+		)
+		.into());
+	}
+	if index.flat_tree() == commit_tree(read_ref(HEAD)?.as_ref())? {
+		return Err("the index has no changes, add the resolved files or use --skip".into());
+	}
+	let number = read_number(NEXT)?;
+	let mail = read_mail(number)?;
+	commit_mail(&index, &mail)?;
+	write_number(NEXT, number + 1)?;
+	run_session(vec![subject(&mail.message)], &Filters::read()?)
+}
+
+pub fn am_skip() -> Result<Session, Box<dyn Error>> {
+	if !in_progress()? {
+		return Err("there is no am session in progress".into());
+	}
+	let mut index = Index::read()?;
+	let filters = Filters::read()?;
+	let from = index.flat_tree();
+	switch_tree(
+		&mut index,
+		&from,
+		&commit_tree(read_ref(HEAD)?.as_ref())?,
+		true,
+		&filters,
+	)?;
+	index.write()?;
+	write_number(NEXT, read_number(NEXT)? + 1)?;
+	run_session(Vec::new(), &filters)
+}
+
+pub fn am_abort() -> Result<(), Box<dyn Error>> {
+	if !in_progress()? {
+		return Err("there is no am session in progress".into());
+	}
+	let orig_head = match read_state(ORIG_HEAD_FILE)? {
+		Some(content) => Some(
+			ObjectRepr::try_from(content.trim()).map_err(|_| "am state orig-head is malformed")?,
+		),
+		None => None,
+	};
+	let mut index = Index::read()?;
+	let from = index.flat_tree();
+	let to = commit_tree(orig_head.as_ref())?;
+	switch_tree(&mut index, &from, &to, true, &Filters::read()?)?;
+	index.write()?;
+	if let Some(orig_head) = orig_head {
+		if read_ref(HEAD)?.as_ref() != Some(&orig_head) {
+			update_ref(HEAD, &orig_head, "am: abort")?;
+		}
+	}
+	fs::remove_dir_all(vcs_path_required(STATE_DIR)?)?;
+	Ok(())
+}
@@ -0,0 +1,41 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty am [-3] [<mbox>...]
+         unrusty am --continue
+         unrusty am --skip
+         unrusty am --abort
+         unrusty am --help
+
+Options:
+         <mbox>                       Mailboxes of patches to commit in order, the standard input \
+                         if none
+         -3, --3way                   Merge the patches that do not apply with the blobs they were \
+                         made for
+         --continue                   Commit the resolved patch and go on with the next ones
+         --skip                       Leave out the patch that failed and go on
+         --abort                      Abort and restore HEAD as it was before
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub arg_mbox:      Vec<String>,
+	pub flag_3way:     bool,
+	pub flag_continue: bool,
+	pub flag_skip:     bool,
+	pub flag_abort:    bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
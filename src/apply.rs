@@ -0,0 +1,3 @@
+pub mod apply_cmd;
+pub mod apply_do;
+mod apply_parse;
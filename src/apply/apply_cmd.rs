@@ -0,0 +1,49 @@
+use super::{
+	apply_do::{apply, ApplyOptions},
+	apply_parse::Args,
+};
+use crate::tools::{failed::failed, filter::Filters};
+use std::{
+	fs,
+	io::{self, Read},
+	process::exit,
+};
+
+pub fn cmd_apply<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+	let mut patch = Vec::new();
+	if args.arg_patch.is_empty() {
+		io::stdin()
+			.read_to_end(&mut patch)
+			.unwrap_or_else(|e| failed(&format!("Failed apply: {:?}", e)));
+	}
+	for file in &args.arg_patch {
+		patch.extend(fs::read(file).unwrap_or_else(|e| failed(&format!("Failed apply: {:?}", e))));
+	}
+
+	let options = ApplyOptions {
+		check:     args.flag_check,
+		index:     args.flag_index,
+		three_way: args.flag_3way,
+		reverse:   args.flag_reverse,
+	};
+	let filters = Filters::read().unwrap_or_else(|e| failed(&format!("Failed apply: {:?}", e)));
+	let applied = apply(&patch, &options, &filters)
+		.unwrap_or_else(|e| failed(&format!("Failed apply: {:?}", e)));
+	for path in &applied.merged {
+		println!("Applied patch to '{}' cleanly.", path);
+	}
+	for path in &applied.conflicts {
+		println!("Applied patch to '{}' with conflicts.", path);
+	}
+	for path in &applied.conflicts {
+		println!("U {}", path);
+	}
+	if !applied.conflicts.is_empty() {
+		exit(1);
+	}
+}
@@ -0,0 +1,270 @@
+use crate::{
+	merge::merge_do::conflict_style,
+	tools::{
+		apply::{apply_file, check_preimage, parse_patch, Body, FilePatch},
+		db::{insert, read, simulate_insert, ObjectRepr, ObjectType},
+		filter::Filters,
+		index::Index,
+		merge::{merge_content, MergeLabels},
+		path::RelativePathToBase,
+		rev::resolve_short_hash,
+		tree::{FileMode, FlatEntry},
+		worktree::{checkout_content, read_worktree_entry, remove_entry},
+	},
+};
+use std::{collections::BTreeMap, convert::TryFrom, error::Error};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApplyOptions {
+	// Only checks that the patches apply
+	pub check:     bool,
+	pub index:     bool,
+	// Merges the changes of patches that do not apply, implies index
+	pub three_way: bool,
+	pub reverse:   bool,
+}
+
+// Paths merged with three-way merges, cleanly or not
+#[derive(Debug, Default)]
+pub struct Applied {
+	pub merged:    Vec<RelativePathToBase>,
+	pub conflicts: Vec<RelativePathToBase>,
+}
+
+// A file as the patches found or left it, None if it does not exist
+type Version = Option<(FileMode, Vec<u8>)>;
+
+// Stages of a file whose changes conflicted
+type Conflict = (FlatEntry, Option<FlatEntry>, FlatEntry);
+
+// Files as earlier patches left them, so that patches can follow each other
+struct Files<'a> {
+	index:     Option<&'a Index>,
+	filters:   &'a Filters,
+	versions:  BTreeMap<RelativePathToBase, Version>,
+	conflicts: BTreeMap<RelativePathToBase, Conflict>,
+	merged:    Vec<RelativePathToBase>,
+}
+
+impl Files<'_> {
+	// The file before the patch. With the index, the working tree must match it
+	fn current(&self, path: &RelativePathToBase) -> Result<Version, Box<dyn Error>> {
+		if let Some(version) = self.versions.get(path) {
+			return Ok(version.clone());
+		}
+		if let Some(index) = self.index {
+			match index.get(path) {
+				Some(_) if index.worktree_modified(path, self.filters)? => {
+					return Err(format!("{}: does not match index", path).into());
+				},
+				None if read_worktree_entry(path, self.filters)?.is_some() => {
+					return Err(format!("{}: does not exist in index", path).into());
+				},
+				_ => {},
+			}
+		}
+		Ok(read_worktree_entry(path, self.filters)?)
+	}
+}
+
+fn blob_hash(content: &[u8]) -> Result<ObjectRepr, Box<dyn Error>> {
+	Ok(simulate_insert(content, ObjectType::Blob)?)
+}
+
+// The blob a patch was made for, from the hash of its index line
+fn base_blob(patch: &FilePatch) -> Result<Option<ObjectRepr>, Box<dyn Error>> {
+	let hash = match &patch.old_hash {
+		Some(hash) => hash,
+		None => return Ok(None),
+	};
+	match ObjectRepr::try_from(hash.as_str()) {
+		Ok(hash) => Ok(read(&hash).ok().map(|_| hash)),
+		Err(_) => Ok(resolve_short_hash(hash)?),
+	}
+}
+
+// The changes of the patch merged into `ours` from the blob the patch was
+// made for. Without that blob the patch does not apply
+fn three_way(
+	patch: &FilePatch,
+	path: &RelativePathToBase,
+	ours: &[u8],
+	mode: FileMode,
+	files: &mut Files,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+	let base_hash = match base_blob(patch)? {
+		Some(hash) => hash,
+		None => return Ok(None),
+	};
+	let base = read(&base_hash)?.data;
+	let theirs = match apply_file(patch, &base) {
+		Ok(theirs) => theirs,
+		Err(_) => return Ok(None),
+	};
+	let labels = MergeLabels {
+		base:   "base",
+		ours:   "ours",
+		theirs: "theirs",
+	};
+	let merge = merge_content(&base, ours, &theirs, &labels, conflict_style(None)?);
+	if merge.conflicts > 0 {
+		let ours = files
+			.index
+			.and_then(|index| index.get(path))
+			.map(|entry| entry.flat_entry());
+		let base = FlatEntry {
+			mode: patch.old_mode.unwrap_or(mode),
+			hash: base_hash,
+		};
+		let theirs = FlatEntry {
+			mode: patch.new_mode.unwrap_or(mode),
+			hash: insert(&theirs, ObjectType::Blob)?,
+		};
+		files.conflicts.insert(path.clone(), (base, ours, theirs));
+	} else {
+		files.merged.push(path.clone());
+	}
+	Ok(Some(merge.content))
+}
+
+fn apply_one(
+	patch: &FilePatch,
+	files: &mut Files,
+	options: &ApplyOptions,
+) -> Result<(), Box<dyn Error>> {
+	let old = match &patch.old_path {
+		Some(path) => {
+			let (mode, content) = files
+				.current(path)?
+				.ok_or_else(|| format!("{}: does not exist", path))?;
+			check_preimage(patch, blob_hash(&content)?.hash())?;
+			Some((path, mode, content))
+		},
+		None => None,
+	};
+	if let Some(path) = &patch.new_path {
+		let renamed = old.as_ref().is_none_or(|(old_path, ..)| *old_path != path);
+		if renamed && files.current(path)?.is_some() {
+			return Err(format!("{}: already exists", path).into());
+		}
+	}
+
+	let (old_mode, old_content) = match &old {
+		Some((_, mode, content)) => (*mode, content.as_slice()),
+		None => (FileMode::Regular, &[][..]),
+	};
+	let new_path = match &patch.new_path {
+		Some(path) => path,
+		None => {
+			// Deletions must remove every line, unless the patch shows none
+			let shows_lines = !matches!(&patch.body, Body::Text(hunks) if hunks.is_empty());
+			if shows_lines && !apply_file(patch, old_content)?.is_empty() {
+				return Err(format!(
+					"{}: the patch does not delete all of the file",
+					patch.path()
+				)
+				.into());
+			}
+			let (path, ..) = old.as_ref().expect("deletions have an old path");
+			files.versions.insert((*path).clone(), None);
+			return Ok(());
+		},
+	};
+	let content = match apply_file(patch, old_content) {
+		Ok(content) => content,
+		Err(e) => {
+			let merged = match &old {
+				Some((path, ..)) if options.three_way && *path == new_path => {
+					three_way(patch, path, old_content, old_mode, files)?
+				},
+				_ => None,
+			};
+			merged.ok_or(e)?
+		},
+	};
+	if let Some((path, ..)) = &old {
+		if *path != new_path && !patch.copy {
+			files.versions.insert((*path).clone(), None);
+		}
+	}
+	let mode = patch.new_mode.unwrap_or(old_mode);
+	files
+		.versions
+		.insert(new_path.clone(), Some((mode, content)));
+	Ok(())
+}
+
+// Applies the patches to the working tree, and to the index if asked. Nothing
+// is changed unless all of them apply
+pub fn apply(
+	patch: &[u8],
+	options: &ApplyOptions,
+	filters: &Filters,
+) -> Result<Applied, Box<dyn Error>> {
+	let mut patches = parse_patch(patch)?;
+	if patches.is_empty() {
+		return Err("no patch found".into());
+	}
+	if options.reverse {
+		patches = patches.into_iter().map(FilePatch::reverse).collect();
+	}
+	let mut index = if options.index || options.three_way {
+		Some(Index::read()?)
+	} else {
+		None
+	};
+
+	let mut files = Files {
+		index: index.as_ref(),
+		filters,
+		versions: BTreeMap::new(),
+		conflicts: BTreeMap::new(),
+		merged: Vec::new(),
+	};
+	for patch in &patches {
+		apply_one(patch, &mut files, options)?;
+	}
+	let Files {
+		versions,
+		conflicts,
+		merged,
+		..
+	} = files;
+	let applied = Applied {
+		merged,
+		conflicts: conflicts.keys().cloned().collect(),
+	};
+	if options.check {
+		return Ok(applied);
+	}
+
+	// Files are removed first, a directory may take the place of one
+	for path in versions.iter().filter(|(_, v)| v.is_none()).map(|(p, _)| p) {
+		remove_entry(path)?;
+		if let Some(index) = &mut index {
+			index.remove(path);
+		}
+	}
+	for (path, version) in versions {
+		let (mode, content) = match version {
+			Some(version) => version,
+			None => continue,
+		};
+		checkout_content(&path, mode, &content, filters)?;
+		if let Some(index) = &mut index {
+			match conflicts.get(&path) {
+				Some((base, ours, theirs)) => {
+					index.set_conflict(path, Some(base), ours.as_ref(), Some(theirs))
+				},
+				None => {
+					let hash = insert(&content, ObjectType::Blob)?;
+					index.set_entry(path, &FlatEntry { mode, hash }, true)?;
+				},
+			}
+		}
+	}
+	if let Some(index) = index {
+		index.write()?;
+	}
+	Ok(applied)
+}
@@ -0,0 +1,37 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty apply [--check] [--index] [-3] [-R] [<patch>...]
+         unrusty apply --help
+
+Options:
+         <patch>                      Files with patches as written by diff, standard input without
+         --check                      Only check that the patches apply, change nothing
+         --index                      Apply to the index too, patched files must match it
+         -3, --3way                   Merge the changes into files the patches do not apply to, \
+                         from the blobs they were made for. Implies --index
+         -R, --reverse                Undo the changes of the patches
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub arg_patch:    Vec<String>,
+	pub flag_check:   bool,
+	pub flag_index:   bool,
+	pub flag_3way:    bool,
+	pub flag_reverse: bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
@@ -0,0 +1,3 @@
+pub mod archive_cmd;
+pub mod archive_do;
+mod archive_parse;
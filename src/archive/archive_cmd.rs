@@ -0,0 +1,43 @@
+use super::{
+	archive_do::{archive, ArchiveFormat},
+	archive_parse::Args,
+};
+use crate::tools::failed::failed;
+use std::{
+	fs::File,
+	io::{self, BufWriter, Write},
+};
+
+pub fn cmd_archive<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+	let format = match &args.flag_format {
+		Some(name) => ArchiveFormat::from_name(name)
+			.unwrap_or_else(|| failed(&format!("Unknown archive format {:?}", name))),
+		None => args
+			.flag_output
+			.as_deref()
+			.and_then(ArchiveFormat::from_file_name)
+			.unwrap_or(ArchiveFormat::Tar),
+	};
+	let prefix = args.flag_prefix.as_deref().unwrap_or("");
+
+	let writer: Box<dyn Write> = match &args.flag_output {
+		Some(path) => Box::new(
+			File::create(path).unwrap_or_else(|e| failed(&format!("Failed archive: {:?}", e))),
+		),
+		None => Box::new(io::stdout()),
+	};
+	archive(
+		BufWriter::new(writer),
+		format,
+		prefix,
+		&args.arg_tree_ish,
+		&args.arg_path,
+	)
+	.and_then(|mut writer| Ok(writer.flush()?))
+	.unwrap_or_else(|e| failed(&format!("Failed archive: {:?}", e)));
+}
@@ -0,0 +1,187 @@
+use crate::tools::{
+	archive::{ArchiveEntry, EntryKind, TarWriter, ZipWriter},
+	commit::Commit,
+	date::signature_time,
+	db::{read, ObjectRepr, ObjectType},
+	path::RelativePathToBase,
+	rev::{peel_tags, peel_to_tree, resolve_revision},
+	tree::{FileMode, Tree},
+};
+use flate2::{write::GzEncoder, Compression};
+use std::{
+	error::Error,
+	io::Write,
+	path::{Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+	Tar,
+	TarGz,
+	Zip,
+}
+
+impl ArchiveFormat {
+	pub fn from_name(name: &str) -> Option<ArchiveFormat> {
+		match name {
+			"tar" => Some(ArchiveFormat::Tar),
+			"tar.gz" | "tgz" => Some(ArchiveFormat::TarGz),
+			"zip" => Some(ArchiveFormat::Zip),
+			_ => None,
+		}
+	}
+
+	// The format an output file name suggests
+	pub fn from_file_name(path: &Path) -> Option<ArchiveFormat> {
+		let name = path.file_name()?.to_str()?;
+		[".tar.gz", ".tgz", ".tar", ".zip"]
+			.iter()
+			.find(|extension| name.ends_with(*extension))
+			.and_then(|extension| ArchiveFormat::from_name(&extension[1..]))
+	}
+}
+
+// An entry before the data of its blob is read
+struct PendingEntry {
+	path: String,
+	kind: EntryKind,
+	blob: Option<ObjectRepr>,
+}
+
+// Collects the entries of the tree in the order of the tree. Directories on
+// the way to a path given on the command line are entries too. Submodules
+// become empty directories
+fn list(
+	tree: &ObjectRepr,
+	prefix: &Path,
+	pathspecs: &[RelativePathToBase],
+	matched: &mut [bool],
+	entries: &mut Vec<PendingEntry>,
+) -> Result<(), Box<dyn Error>> {
+	for entry in Tree::read(tree)?.entries {
+		let path = RelativePathToBase::new(prefix.join(&entry.name));
+		let is_tree = entry.mode == FileMode::Directory;
+		let selected = pathspecs.is_empty() || pathspecs.iter().any(|p| p.contains(&path));
+		let on_the_way = is_tree && pathspecs.iter().any(|p| path.contains(p) && path != *p);
+		if !selected && !on_the_way {
+			continue;
+		}
+		for (pathspec, matched) in pathspecs.iter().zip(matched.iter_mut()) {
+			*matched |= pathspec.contains(&path);
+		}
+
+		let name = path.as_path().to_string_lossy().into_owned();
+		let (kind, blob) = match entry.mode {
+			FileMode::Regular => (EntryKind::File, Some(entry.hash.clone())),
+			FileMode::Executable => (EntryKind::Executable, Some(entry.hash.clone())),
+			FileMode::Symlink => (EntryKind::Symlink, Some(entry.hash.clone())),
+			FileMode::Directory | FileMode::Gitlink => (EntryKind::Directory, None),
+		};
+		let path_in_archive = if kind == EntryKind::Directory {
+			format!("{}/", name)
+		} else {
+			name
+		};
+		entries.push(PendingEntry {
+			path: path_in_archive,
+			kind,
+			blob,
+		});
+		if is_tree {
+			list(&entry.hash, path.as_path(), pathspecs, matched, entries)?;
+		}
+	}
+	Ok(())
+}
+
+fn write_entries<F>(
+	entries: &[PendingEntry],
+	prefix: &str,
+	mut add: F,
+) -> Result<(), Box<dyn Error>>
+where
+	F: FnMut(&ArchiveEntry) -> Result<(), Box<dyn Error>>,
+{
+	// The prefix is a directory of its own when it ends with a slash
+	if prefix.ends_with('/') {
+		add(&ArchiveEntry {
+			path: prefix.to_owned(),
+			kind: EntryKind::Directory,
+			data: Vec::new(),
+		})?;
+	}
+	for entry in entries {
+		let data = match &entry.blob {
+			Some(blob) => read(blob)?.data,
+			None => Vec::new(),
+		};
+		add(&ArchiveEntry {
+			path: format!("{}{}", prefix, entry.path),
+			kind: entry.kind,
+			data,
+		})?;
+	}
+	Ok(())
+}
+
+// Writes the tree of `tree_ish`, restricted to `paths` if any, as an archive
+// whose paths start with `prefix`. Files get the time of the commit, if the
+// tree comes from one, whose id is then recorded in the archive
+pub fn archive<W: Write>(
+	writer: W,
+	format: ArchiveFormat,
+	prefix: &str,
+	tree_ish: &str,
+	paths: &[PathBuf],
+) -> Result<W, Box<dyn Error>> {
+	let (object_repr, object) = peel_tags(&resolve_revision(tree_ish)?)?;
+	let (commit, time) = if object.object_type == ObjectType::Commit {
+		let committer = Commit::parse(&object.data)?.committer;
+		(Some(object_repr.to_string()), signature_time(&committer))
+	} else {
+		(None, None)
+	};
+	let mtime = time
+		.unwrap_or_else(SystemTime::now)
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0);
+
+	let tree = peel_to_tree(&object_repr)?;
+	let pathspecs = paths
+		.iter()
+		.map(|p| RelativePathToBase::from_user_path(p))
+		.collect::<Result<Vec<_>, _>>()?;
+	let mut matched = vec![false; pathspecs.len()];
+	let mut entries = Vec::new();
+	list(
+		&tree,
+		&PathBuf::new(),
+		&pathspecs,
+		&mut matched,
+		&mut entries,
+	)?;
+	if let Some((pathspec, _)) = pathspecs.iter().zip(&matched).find(|(_, m)| !**m) {
+		return Err(format!("Path {} is not in {}", pathspec, tree_ish).into());
+	}
+
+	match format {
+		ArchiveFormat::Tar => {
+			let mut tar = TarWriter::new(writer, mtime, commit.as_deref())?;
+			write_entries(&entries, prefix, |entry| Ok(tar.add(entry)?))?;
+			Ok(tar.finish()?)
+		},
+		ArchiveFormat::TarGz => {
+			let encoder = GzEncoder::new(writer, Compression::default());
+			let mut tar = TarWriter::new(encoder, mtime, commit.as_deref())?;
+			write_entries(&entries, prefix, |entry| Ok(tar.add(entry)?))?;
+			Ok(tar.finish()?.finish()?)
+		},
+		ArchiveFormat::Zip => {
+			let mut zip = ZipWriter::new(writer, mtime);
+			write_entries(&entries, prefix, |entry| Ok(zip.add(entry)?))?;
+			Ok(zip.finish(commit.as_deref())?)
+		},
+	}
+}
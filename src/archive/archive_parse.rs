@@ -0,0 +1,40 @@
+use docopt::Docopt;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "
+Usage:   unrusty archive [--format=<format>] [--prefix=<prefix>] [-o <file>] <tree-ish> [<path>...]
+         unrusty archive --help
+
+Options:
+         <tree-ish>                   Tree, or commit whose tree is archived
+         <path>                       Only archive these paths
+         --format=<format>            tar, tar.gz or zip. Defaults to what the name of the output \
+                         file suggests, else tar
+         --prefix=<prefix>            Prepended to every path, e.g. project/
+         -o, --output=<file>          Write the archive to this file instead of stdout
+         -h, --help                   Shows this help message
+
+Paths are relative to the repository root.
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub arg_tree_ish: String,
+	pub arg_path:     Vec<PathBuf>,
+	pub flag_format:  Option<String>,
+	pub flag_prefix:  Option<String>,
+	pub flag_output:  Option<PathBuf>,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
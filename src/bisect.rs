@@ -0,0 +1,3 @@
+pub mod bisect_cmd;
+pub mod bisect_do;
+mod bisect_parse;
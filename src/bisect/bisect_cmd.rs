@@ -0,0 +1,78 @@
+use super::{
+	bisect_do::{
+		bisect_log, bisect_mark, bisect_replay, bisect_reset, bisect_run, bisect_start, BisectStep,
+		Term,
+	},
+	bisect_parse::Args,
+};
+use crate::tools::failed::failed;
+use std::{error::Error, process::exit};
+
+fn report(step: Result<BisectStep, Box<dyn Error>>, command: &str) {
+	match step {
+		Ok(BisectStep::Waiting { bad, good }) => match (bad, good) {
+			(false, false) => println!("status: waiting for both good and bad commits"),
+			(true, _) => println!("status: waiting for good commit(s), bad commit known"),
+			(false, true) => println!("status: waiting for bad commit, good commit(s) known"),
+		},
+		Ok(BisectStep::Testing {
+			commit,
+			subject,
+			left,
+			steps,
+		}) => {
+			println!(
+				"Bisecting: {} revisions left to test after this (roughly {} steps)",
+				left, steps
+			);
+			println!("[{}] {}", commit, subject);
+		},
+		Ok(BisectStep::Found(commit, subject)) => {
+			println!("{} is the first bad commit", commit);
+			println!("    {}", subject);
+		},
+		Ok(BisectStep::OnlySkipped(commits)) => {
+			println!("There are only 'skip'ped commits left to test.");
+			println!("The first bad commit could be any of:");
+			for commit in commits {
+				println!("{}", commit);
+			}
+			exit(2);
+		},
+		Err(e) => failed(&format!("Failed bisect {}: {:?}", command, e)),
+	}
+}
+
+pub fn cmd_bisect<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	if args.cmd_start {
+		report(
+			bisect_start(args.arg_bad.as_deref(), &args.arg_good),
+			"start",
+		);
+	} else if args.cmd_bad {
+		report(bisect_mark(Term::Bad, &args.arg_rev), "bad");
+	} else if args.cmd_good {
+		report(bisect_mark(Term::Good, &args.arg_rev), "good");
+	} else if args.cmd_skip {
+		report(bisect_mark(Term::Skip, &args.arg_rev), "skip");
+	} else if args.cmd_reset {
+		bisect_reset(args.arg_commit.as_deref())
+			.unwrap_or_else(|e| failed(&format!("Failed bisect reset: {:?}", e)));
+	} else if args.cmd_log {
+		let log = bisect_log().unwrap_or_else(|e| failed(&format!("Failed bisect log: {:?}", e)));
+		print!("{}", log);
+	} else if args.cmd_replay {
+		report(
+			bisect_replay(args.arg_logfile.as_deref().unwrap_or_default()),
+			"replay",
+		);
+	} else if args.cmd_run {
+		report(bisect_run(&args.arg_cmd), "run");
+	}
+}
@@ -0,0 +1,407 @@
+use crate::{
+	merge::merge_do::{ensure_clean, ensure_nothing_in_the_way},
+	tools::{
+		commit::{commit_tree, subject, Commit},
+		db::ObjectRepr,
+		filter::Filters,
+		index::Index,
+		merge::commits_between,
+		path::{root_path_required, vcs_path_required},
+		reflog::append_reflog,
+		refs::{
+			current_branch, delete_ref, list_refs, read_ref, write_ref, write_symbolic_ref, HEAD,
+		},
+		rev::{peel_to_commit, resolve_revision},
+		worktree::switch_tree,
+	},
+};
+use log::info;
+use std::{
+	collections::{HashMap, HashSet},
+	convert::TryFrom,
+	error::Error,
+	fs::{self, OpenOptions},
+	io::{ErrorKind, Write},
+	process::Command,
+	slice,
+};
+
+// What HEAD pointed to before bisecting: a branch or a detached commit
+const BISECT_START: &str = "BISECT_START";
+// The commands given so far, replayable with bisect replay
+const BISECT_LOG: &str = "BISECT_LOG";
+// The commit checked out for testing
+const BISECT_EXPECTED_REV: &str = "BISECT_EXPECTED_REV";
+const BISECT_REFS: &str = "refs/bisect/";
+const BAD_REF: &str = "refs/bisect/bad";
+// Exit code of bisect run commands for commits that cannot be tested
+const SKIP_EXIT_CODE: i32 = 125;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Term {
+	Bad,
+	Good,
+	Skip,
+}
+
+impl Term {
+	fn from_name(name: &str) -> Option<Term> {
+		match name {
+			"bad" => Some(Term::Bad),
+			"good" => Some(Term::Good),
+			"skip" => Some(Term::Skip),
+			_ => None,
+		}
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			Term::Bad => "bad",
+			Term::Good => "good",
+			Term::Skip => "skip",
+		}
+	}
+
+	fn ref_name(self, commit: &ObjectRepr) -> String {
+		match self {
+			Term::Bad => BAD_REF.to_owned(),
+			_ => format!("{}{}-{}", BISECT_REFS, self.name(), commit),
+		}
+	}
+}
+
+pub enum BisectStep {
+	// Bisecting needs a bad and at least one good commit
+	Waiting {
+		bad:  bool,
+		good: bool,
+	},
+	// The commit checked out to test, with the revisions left after it in the
+	// worst case and the steps that takes
+	Testing {
+		commit:  ObjectRepr,
+		subject: String,
+		left:    usize,
+		steps:   usize,
+	},
+	Found(ObjectRepr, String),
+	// The first bad commit is one of these, which were skipped, or the bad one
+	OnlySkipped(Vec<ObjectRepr>),
+}
+
+fn in_progress() -> Result<bool, Box<dyn Error>> {
+	Ok(vcs_path_required(BISECT_START)?.is_file())
+}
+
+fn ensure_in_progress() -> Result<(), Box<dyn Error>> {
+	if in_progress()? {
+		Ok(())
+	} else {
+		Err("there is no bisect in progress, use bisect start".into())
+	}
+}
+
+fn append_log(text: &str) -> Result<(), Box<dyn Error>> {
+	let mut f = OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(vcs_path_required(BISECT_LOG)?)?;
+	Ok(f.write_all(text.as_bytes())?)
+}
+
+fn remove_file(name: &str) -> Result<(), Box<dyn Error>> {
+	match fs::remove_file(vcs_path_required(name)?) {
+		Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+		_ => Ok(()),
+	}
+}
+
+// The bad commit, the good ones and the skipped ones
+type Marks = (Option<ObjectRepr>, Vec<ObjectRepr>, HashSet<ObjectRepr>);
+
+fn read_marks() -> Result<Marks, Box<dyn Error>> {
+	let mut good = Vec::new();
+	let mut skipped = HashSet::new();
+	for (name, commit) in list_refs()? {
+		let name = match name.strip_prefix(BISECT_REFS) {
+			Some(name) => name,
+			None => continue,
+		};
+		if name.starts_with("good-") {
+			good.push(commit);
+		} else if name.starts_with("skip-") {
+			skipped.insert(commit);
+		}
+	}
+	Ok((read_ref(BAD_REF)?, good, skipped))
+}
+
+// Checks out the commit with HEAD detached
+fn checkout(commit: &ObjectRepr) -> Result<(), Box<dyn Error>> {
+	let head = read_ref(HEAD)?;
+	let mut index = Index::read()?;
+	let filters = Filters::read()?;
+	let head_tree = commit_tree(head.as_ref())?;
+	ensure_clean(&index, &head_tree, &filters)?;
+	let tree = commit_tree(Some(commit))?;
+	ensure_nothing_in_the_way(&index, &tree)?;
+	switch_tree(&mut index, &head_tree, &tree, false, &filters)?;
+	index.write()?;
+	write_ref(HEAD, commit)?;
+	append_reflog(
+		HEAD,
+		head.as_ref(),
+		Some(commit),
+		&format!("checkout: moving to {}", commit),
+	);
+	Ok(())
+}
+
+// Records the commits as bad, good or skipped
+fn mark(term: Term, revs: &[String]) -> Result<(), Box<dyn Error>> {
+	let revs = if revs.is_empty() {
+		vec![HEAD.to_owned()]
+	} else {
+		revs.to_vec()
+	};
+	if term == Term::Bad && revs.len() > 1 {
+		return Err("only one commit can be bad".into());
+	}
+	for rev in revs {
+		let commit = peel_to_commit(&resolve_revision(&rev)?)?;
+		write_ref(&term.ref_name(&commit), &commit)?;
+		append_log(&format!(
+			"# {}: [{}] {}\nunrusty bisect {} {}\n",
+			term.name(),
+			commit,
+			subject(&Commit::read(&commit)?.message),
+			term.name(),
+			commit
+		))?;
+	}
+	Ok(())
+}
+
+// For every candidate, how many candidates it reaches, itself included
+fn reach_counts(candidates: &[ObjectRepr]) -> Result<HashMap<ObjectRepr, usize>, Box<dyn Error>> {
+	let in_set = candidates.iter().collect::<HashSet<_>>();
+	let mut reach: HashMap<&ObjectRepr, HashSet<&ObjectRepr>> = HashMap::new();
+	// Candidates come parents first
+	for commit in candidates {
+		let mut reached = HashSet::new();
+		reached.insert(commit);
+		for parent in Commit::read(commit)?.parents {
+			if let Some(parent) = in_set.get(&parent) {
+				reached.extend(reach[parent].iter().copied());
+			}
+		}
+		reach.insert(commit, reached);
+	}
+	Ok(reach
+		.into_iter()
+		.map(|(c, reached)| (c.clone(), reached.len()))
+		.collect())
+}
+
+// Checks out the commit splitting the candidates most evenly, or tells the
+// first bad commit when none is left to test
+fn next_step() -> Result<BisectStep, Box<dyn Error>> {
+	let (bad, good, skipped) = read_marks()?;
+	let bad = match bad {
+		Some(bad) if !good.is_empty() => bad,
+		bad => {
+			return Ok(BisectStep::Waiting {
+				bad:  bad.is_some(),
+				good: !good.is_empty(),
+			})
+		},
+	};
+	// Commits that may have introduced the regression
+	let candidates = commits_between(&good, &bad)?;
+	if candidates.is_empty() {
+		return Err(format!("the bad commit {} is an ancestor of a good commit", bad).into());
+	}
+	let total = candidates.len();
+	let counts = reach_counts(&candidates)?;
+	let testable = candidates
+		.iter()
+		.filter(|c| **c != bad && !skipped.contains(*c))
+		.collect::<Vec<_>>();
+
+	if testable.is_empty() {
+		if total == 1 {
+			let found = subject(&Commit::read(&bad)?.message);
+			append_log(&format!("# first bad commit: [{}] {}\n", bad, found))?;
+			return Ok(BisectStep::Found(bad, found));
+		}
+		let mut left = candidates
+			.into_iter()
+			.filter(|c| skipped.contains(c))
+			.collect::<Vec<_>>();
+		left.push(bad);
+		return Ok(BisectStep::OnlySkipped(left));
+	}
+
+	// A commit reaching n candidates leaves n - 1 if it is bad, the other
+	// total - n if it is good
+	let best = testable
+		.into_iter()
+		.max_by_key(|c| {
+			let n = counts[*c];
+			(n - 1).min(total - n)
+		})
+		.unwrap()
+		.clone();
+	let n = counts[&best];
+	let left = (n - 1).max(total - n);
+	checkout(&best)?;
+	fs::write(
+		vcs_path_required(BISECT_EXPECTED_REV)?,
+		format!("{}\n", best),
+	)?;
+	Ok(BisectStep::Testing {
+		subject: subject(&Commit::read(&best)?.message),
+		commit: best,
+		left,
+		steps: (usize::BITS - left.leading_zeros()).saturating_sub(1) as usize,
+	})
+}
+
+// Removes the bisect refs and files, leaving the working tree as it is
+fn clear_state() -> Result<(), Box<dyn Error>> {
+	for (name, _) in list_refs()? {
+		if name.starts_with(BISECT_REFS) {
+			delete_ref(&name)?;
+		}
+	}
+	for name in &[BISECT_START, BISECT_LOG, BISECT_EXPECTED_REV] {
+		remove_file(name)?;
+	}
+	Ok(())
+}
+
+// Records what HEAD points to, a bisect started again keeps the first one
+fn begin() -> Result<(), Box<dyn Error>> {
+	let start = if in_progress()? {
+		fs::read_to_string(vcs_path_required(BISECT_START)?)?
+	} else {
+		match current_branch()? {
+			Some(branch) => format!("{}\n", branch),
+			None => format!(
+				"{}\n",
+				read_ref(HEAD)?.ok_or("HEAD does not point to a commit")?
+			),
+		}
+	};
+	clear_state()?;
+	Ok(fs::write(vcs_path_required(BISECT_START)?, start)?)
+}
+
+pub fn bisect_start(bad: Option<&str>, good: &[String]) -> Result<BisectStep, Box<dyn Error>> {
+	begin()?;
+	let mut revs = Vec::new();
+	if let Some(bad) = bad {
+		mark(Term::Bad, &[bad.to_owned()])?;
+		revs.push(bad.to_owned());
+		mark(Term::Good, good)?;
+		revs.extend(good.iter().cloned());
+	}
+	let quoted = revs.iter().map(|r| format!(" '{}'", r)).collect::<String>();
+	append_log(&format!("unrusty bisect start{}\n", quoted))?;
+	next_step()
+}
+
+pub fn bisect_mark(term: Term, revs: &[String]) -> Result<BisectStep, Box<dyn Error>> {
+	ensure_in_progress()?;
+	mark(term, revs)?;
+	next_step()
+}
+
+// Checks out the branch or commit bisect started on, or the given commit
+pub fn bisect_reset(commit: Option<&str>) -> Result<(), Box<dyn Error>> {
+	ensure_in_progress()?;
+	let start = fs::read_to_string(vcs_path_required(BISECT_START)?)?
+		.trim()
+		.to_owned();
+	let target = match commit {
+		Some(rev) => peel_to_commit(&resolve_revision(rev)?)?,
+		None => match ObjectRepr::try_from(start.as_str()) {
+			Ok(commit) => commit,
+			Err(_) => read_ref(&start)?.ok_or_else(|| format!("{} does not exist", start))?,
+		},
+	};
+	checkout(&target)?;
+	if commit.is_none() && ObjectRepr::try_from(start.as_str()).is_err() {
+		write_symbolic_ref(HEAD, &start)?;
+	}
+	clear_state()
+}
+
+pub fn bisect_log() -> Result<String, Box<dyn Error>> {
+	ensure_in_progress()?;
+	Ok(fs::read_to_string(vcs_path_required(BISECT_LOG)?)?)
+}
+
+// Redoes the commands of a log, checking out only the commit to test next
+pub fn bisect_replay(log: &str) -> Result<BisectStep, Box<dyn Error>> {
+	let content = fs::read_to_string(log)?;
+	let mut started = false;
+	for line in content.lines() {
+		let command = match line
+			.strip_prefix("unrusty bisect ")
+			.or_else(|| line.strip_prefix("git bisect "))
+		{
+			Some(command) => command,
+			None => continue,
+		};
+		let mut words = command
+			.split_whitespace()
+			.map(|w| w.trim_matches('\'').to_owned());
+		let name = words.next().unwrap_or_default();
+		let revs = words.collect::<Vec<_>>();
+		if name == "start" {
+			begin()?;
+			if let Some((bad, good)) = revs.split_first() {
+				mark(Term::Bad, slice::from_ref(bad))?;
+				mark(Term::Good, good)?;
+			}
+			append_log(&format!("{}\n", line))?;
+			started = true;
+		} else if let Some(term) = Term::from_name(&name) {
+			if !started {
+				return Err("the log does not start with bisect start".into());
+			}
+			mark(term, &revs)?;
+		} else {
+			return Err(format!("unknown bisect command {:?} in the log", name).into());
+		}
+	}
+	if !started {
+		return Err("the log does not start with bisect start".into());
+	}
+	next_step()
+}
+
+// Marks commits by the exit code of the command until the first bad commit
+// is found
+pub fn bisect_run(command: &[String]) -> Result<BisectStep, Box<dyn Error>> {
+	ensure_in_progress()?;
+	let mut step = next_step()?;
+	while let BisectStep::Testing { commit, .. } = &step {
+		info!("running {} on {}", command.join(" "), commit);
+		let status = Command::new(&command[0])
+			.args(&command[1..])
+			.current_dir(root_path_required()?)
+			.status()?;
+		let term = match status.code() {
+			Some(0) => Term::Good,
+			Some(SKIP_EXIT_CODE) => Term::Skip,
+			Some(code) if (1..128).contains(&code) => Term::Bad,
+			Some(code) => return Err(format!("bisect run failed: exit code {}", code).into()),
+			None => return Err("bisect run failed: the command was killed".into()),
+		};
+		mark(term, &[commit.to_string()])?;
+		step = next_step()?;
+	}
+	Ok(step)
+}
@@ -0,0 +1,54 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty bisect start [<bad> [<good>...]]
+         unrusty bisect (bad | good | skip) [<rev>...]
+         unrusty bisect reset [<commit>]
+         unrusty bisect log
+         unrusty bisect replay <logfile>
+         unrusty bisect run <cmd>...
+         unrusty bisect --help
+
+Options:
+         <bad>                        A commit with the regression
+         <good>                       Commits without the regression
+         <rev>                        Commits to mark, defaults to HEAD. Only one can be bad
+         <commit>                     Commit to check out instead of the branch bisect started on
+         <logfile>                    Log of a bisect, as written by bisect log, to redo
+         <cmd>                        Command telling the state of the checked out commit by its \
+                         exit code: 0 good, 125 skip, 1 to 127 bad
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub cmd_start:   bool,
+	pub cmd_bad:     bool,
+	pub cmd_good:    bool,
+	pub cmd_skip:    bool,
+	pub cmd_reset:   bool,
+	pub cmd_log:     bool,
+	pub cmd_replay:  bool,
+	pub cmd_run:     bool,
+	pub arg_bad:     Option<String>,
+	pub arg_good:    Vec<String>,
+	pub arg_rev:     Vec<String>,
+	pub arg_commit:  Option<String>,
+	pub arg_logfile: Option<String>,
+	pub arg_cmd:     Vec<String>,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		// The command to run may have options of its own
+		Docopt::new(USAGE)
+			.map(|d| d.options_first(true))
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
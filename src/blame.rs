@@ -0,0 +1,3 @@
+pub mod blame_cmd;
+pub mod blame_do;
+mod blame_parse;
@@ -0,0 +1,30 @@
+use super::{
+	blame_do::{blame, format_blame, BlameOptions},
+	blame_parse::Args,
+};
+use crate::tools::failed::failed;
+use std::io::{self, Write};
+
+pub fn cmd_blame<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+	let options = BlameOptions {
+		ignore_whitespace: args.flag_ignore_whitespace,
+		moves:             args.flag_moves || args.flag_copies,
+		copies:            args.flag_copies,
+	};
+
+	let result = blame(
+		&args.arg_file,
+		args.arg_rev.as_deref(),
+		&args.flag_lines,
+		&options,
+	)
+	.unwrap_or_else(|e| failed(&format!("Failed blame: {:?}", e)));
+	io::stdout()
+		.write_all(&format_blame(&result, args.flag_porcelain))
+		.unwrap_or_else(|e| failed(&format!("Failed blame: {:?}", e)));
+}
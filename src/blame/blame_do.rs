@@ -0,0 +1,494 @@
+use crate::tools::{
+	commit::{subject, Commit},
+	date::{format_signature_date, signature_time},
+	db::{read, ObjectRepr},
+	diff::{matching_lines, split_lines},
+	path::RelativePathToBase,
+	refs::HEAD,
+	rev::{peel_to_commit, resolve_revision},
+	tree::{flatten_tree, FlatTree},
+};
+use std::{
+	collections::{BTreeMap, BinaryHeap, HashMap, HashSet},
+	error::Error,
+	path::Path,
+	time::UNIX_EPOCH,
+};
+
+// Moved or copied blocks need this many alphanumeric characters to count,
+// like git's default, so that braces and blank lines do not wander
+const MOVE_SCORE: usize = 20;
+
+#[derive(Debug, Clone, Default)]
+pub struct BlameOptions {
+	pub ignore_whitespace: bool,
+	// Follow lines moved within the file
+	pub moves:             bool,
+	// Follow lines moved or copied from other files changed in the same commit
+	pub copies:            bool,
+}
+
+// A line of the file and where it comes from. Line numbers are 1-based
+pub struct BlameLine {
+	pub commit:     ObjectRepr,
+	pub path:       RelativePathToBase,
+	pub orig_line:  usize,
+	pub final_line: usize,
+	pub content:    Vec<u8>,
+}
+
+pub struct BlameCommit {
+	pub author:    String,
+	pub committer: String,
+	pub summary:   String,
+	// Root commits, whose lines cannot come from anywhere else
+	pub boundary:  bool,
+	pub previous:  Option<(ObjectRepr, RelativePathToBase)>,
+}
+
+pub struct Blame {
+	pub path:    RelativePathToBase,
+	pub lines:   Vec<BlameLine>,
+	pub commits: HashMap<ObjectRepr, BlameCommit>,
+}
+
+// Lines still looking for their origin: index in the final file and index in
+// the version of the suspect
+type Suspects = Vec<(usize, usize)>;
+
+// Commits, trees and file contents read during the walk
+struct Walk {
+	commits:           HashMap<ObjectRepr, Commit>,
+	trees:             HashMap<ObjectRepr, FlatTree>,
+	ignore_whitespace: bool,
+}
+
+impl Walk {
+	fn commit(&mut self, commit: &ObjectRepr) -> Result<&Commit, Box<dyn Error>> {
+		if !self.commits.contains_key(commit) {
+			self.commits.insert(commit.clone(), Commit::read(commit)?);
+		}
+		Ok(&self.commits[commit])
+	}
+
+	fn tree(&mut self, commit: &ObjectRepr) -> Result<&FlatTree, Box<dyn Error>> {
+		if !self.trees.contains_key(commit) {
+			let tree = self.commit(commit)?.tree.clone();
+			self.trees.insert(commit.clone(), flatten_tree(&tree)?);
+		}
+		Ok(&self.trees[commit])
+	}
+
+	fn content(
+		&mut self,
+		commit: &ObjectRepr,
+		path: &RelativePathToBase,
+	) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+		match self.tree(commit)?.get(path) {
+			Some(entry) => Ok(Some(read(&entry.hash)?.data)),
+			None => Ok(None),
+		}
+	}
+
+	// Lines as compared, without whitespace for -w
+	fn keys(&self, content: &[u8]) -> Vec<Vec<u8>> {
+		split_lines(content)
+			.into_iter()
+			.map(|line| {
+				if self.ignore_whitespace {
+					line.iter()
+						.filter(|b| !b.is_ascii_whitespace())
+						.copied()
+						.collect()
+				} else {
+					line.to_vec()
+				}
+			})
+			.collect()
+	}
+
+	fn time(&mut self, commit: &ObjectRepr) -> Result<u64, Box<dyn Error>> {
+		let committer = &self.commit(commit)?.committer;
+		Ok(signature_time(committer)
+			.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+			.map_or(0, |d| d.as_secs()))
+	}
+}
+
+fn alphanumeric_count(lines: &[Vec<u8>]) -> usize {
+	lines
+		.iter()
+		.map(|l| l.iter().filter(|b| b.is_ascii_alphanumeric()).count())
+		.sum()
+}
+
+// Blocks of the suspected lines found anywhere in the source, as pairs of
+// the suspect index and the source index. Longest blocks are taken first at
+// every position
+fn find_blocks(suspects: &Suspects, target: &[Vec<u8>], source: &[Vec<u8>]) -> Vec<(usize, usize)> {
+	let wanted = suspects.iter().map(|&(_, i)| i).collect::<HashSet<_>>();
+	let mut found = Vec::new();
+	let mut indexes = wanted.iter().copied().collect::<Vec<_>>();
+	indexes.sort_unstable();
+	let mut skip_until = 0;
+	for &start in &indexes {
+		if start < skip_until {
+			continue;
+		}
+		let mut best = (0, 0);
+		for from in 0..source.len() {
+			let mut length = 0;
+			while start + length < target.len()
+				&& from + length < source.len()
+				&& wanted.contains(&(start + length))
+				&& target[start + length] == source[from + length]
+			{
+				length += 1;
+			}
+			if length > best.1 {
+				best = (from, length);
+			}
+		}
+		let (from, length) = best;
+		if length > 0 && alphanumeric_count(&target[start..start + length]) >= MOVE_SCORE {
+			found.extend((0..length).map(|k| (start + k, from + k)));
+			skip_until = start + length;
+		}
+	}
+	found
+}
+
+// Moves the suspects found in the source to it, returns the others
+fn pass_blame(suspects: Suspects, mapping: &HashMap<usize, usize>, to: &mut Suspects) -> Suspects {
+	let mut kept = Vec::new();
+	for (final_index, index) in suspects {
+		match mapping.get(&index) {
+			Some(&source_index) => to.push((final_index, source_index)),
+			None => kept.push((final_index, index)),
+		}
+	}
+	kept
+}
+
+// Parses -L ranges into 0-based half-open ranges of the file
+fn parse_range(range: &str, count: usize) -> Result<(usize, usize), Box<dyn Error>> {
+	let invalid = || format!("invalid line range {:?}", range);
+	let (start, end) = range.split_once(',').unwrap_or((range, ""));
+	let start = if start.is_empty() {
+		1
+	} else {
+		start.parse::<usize>().map_err(|_| invalid())?
+	};
+	if start == 0 || start > count {
+		return Err(format!("file has only {} lines", count).into());
+	}
+	let (first, last) = if end.is_empty() {
+		(start, count)
+	} else if let Some(n) = end.strip_prefix('+') {
+		let n = n.parse::<usize>().map_err(|_| invalid())?;
+		(start, start + n.max(1) - 1)
+	} else if let Some(n) = end.strip_prefix('-') {
+		let n = n.parse::<usize>().map_err(|_| invalid())?;
+		((start + 1).saturating_sub(n.max(1)).max(1), start)
+	} else {
+		let end = end.parse::<usize>().map_err(|_| invalid())?;
+		(start.min(end), start.max(end))
+	};
+	Ok((first - 1, last.min(count)))
+}
+
+pub fn blame(
+	path: &str,
+	rev: Option<&str>,
+	ranges: &[String],
+	options: &BlameOptions,
+) -> Result<Blame, Box<dyn Error>> {
+	let path = RelativePathToBase::from_user_path(Path::new(path))?;
+	let rev = rev.unwrap_or(HEAD);
+	let start = peel_to_commit(&resolve_revision(rev)?)?;
+	let mut walk = Walk {
+		commits:           HashMap::new(),
+		trees:             HashMap::new(),
+		ignore_whitespace: options.ignore_whitespace,
+	};
+	let content = walk
+		.content(&start, &path)?
+		.ok_or_else(|| format!("no such path {} in {}", path, rev))?;
+	let final_lines = split_lines(&content);
+
+	let mut selected = BTreeMap::new();
+	if ranges.is_empty() {
+		selected.extend((0..final_lines.len()).map(|i| (i, i)));
+	}
+	for range in ranges {
+		let (first, end) = parse_range(range, final_lines.len())?;
+		selected.extend((first..end).map(|i| (i, i)));
+	}
+
+	// Suspects of every commit and path, commits are visited newest first so
+	// that lines reach a commit from all its children before it is blamed
+	let mut pending: HashMap<ObjectRepr, BTreeMap<RelativePathToBase, Suspects>> = HashMap::new();
+	let mut queue = BinaryHeap::new();
+	let mut queued = HashSet::new();
+	pending
+		.entry(start.clone())
+		.or_default()
+		.insert(path.clone(), selected.into_iter().collect());
+	queue.push((walk.time(&start)?, start.clone()));
+	queued.insert(start);
+
+	let mut origins = BTreeMap::new();
+	let mut commits = HashMap::new();
+	while let Some((_, commit_repr)) = queue.pop() {
+		queued.remove(&commit_repr);
+		let files = pending.remove(&commit_repr).unwrap_or_default();
+		let commit = walk.commit(&commit_repr)?.clone();
+		let mut previous = None;
+		for (file, mut suspects) in files {
+			let content = walk.content(&commit_repr, &file)?.unwrap_or_default();
+			let keys = walk.keys(&content);
+			let mut passed: Vec<(ObjectRepr, RelativePathToBase, Suspects)> = Vec::new();
+
+			for parent in &commit.parents {
+				if suspects.is_empty() {
+					break;
+				}
+				let parent_content = match walk.content(parent, &file)? {
+					Some(content) => content,
+					None => continue,
+				};
+				let parent_keys = walk.keys(&parent_content);
+				let mapping = matching_lines(&parent_keys, &keys)
+					.into_iter()
+					.map(|(i, j)| (j, i))
+					.collect::<HashMap<_, _>>();
+				let mut to = Vec::new();
+				suspects = pass_blame(suspects, &mapping, &mut to);
+				if options.moves && !suspects.is_empty() {
+					let moved = find_blocks(&suspects, &keys, &parent_keys)
+						.into_iter()
+						.collect::<HashMap<_, _>>();
+					suspects = pass_blame(suspects, &moved, &mut to);
+				}
+				previous.get_or_insert((parent.clone(), file.clone()));
+				passed.push((parent.clone(), file.clone(), to));
+			}
+
+			if options.copies && !suspects.is_empty() {
+				let tree = walk.tree(&commit_repr)?.clone();
+				for parent in &commit.parents {
+					let parent_tree = walk.tree(parent)?.clone();
+					// Files of the parent the commit changed, or removed
+					let changed = parent_tree
+						.iter()
+						.filter(|(p, e)| **p != file && tree.get(*p) != Some(*e))
+						.map(|(p, _)| p.clone())
+						.collect::<Vec<_>>();
+					for source in changed {
+						if suspects.is_empty() {
+							break;
+						}
+						let source_content = walk.content(parent, &source)?.unwrap_or_default();
+						let source_keys = walk.keys(&source_content);
+						let copied = find_blocks(&suspects, &keys, &source_keys)
+							.into_iter()
+							.collect::<HashMap<_, _>>();
+						let mut to = Vec::new();
+						suspects = pass_blame(suspects, &copied, &mut to);
+						passed.push((parent.clone(), source, to));
+					}
+				}
+			}
+
+			for (parent, source, to) in passed {
+				if to.is_empty() {
+					continue;
+				}
+				pending
+					.entry(parent.clone())
+					.or_default()
+					.entry(source)
+					.or_default()
+					.extend(to);
+				if queued.insert(parent.clone()) {
+					queue.push((walk.time(&parent)?, parent));
+				}
+			}
+			for (final_index, index) in suspects {
+				origins.insert(final_index, (commit_repr.clone(), file.clone(), index));
+			}
+		}
+
+		if !commits.contains_key(&commit_repr) {
+			commits.insert(
+				commit_repr.clone(),
+				BlameCommit {
+					author: commit.author.clone(),
+					committer: commit.committer.clone(),
+					summary: subject(&commit.message),
+					boundary: commit.parents.is_empty(),
+					previous,
+				},
+			);
+		}
+	}
+
+	let lines = origins
+		.into_iter()
+		.map(|(final_index, (commit, path, index))| BlameLine {
+			commit,
+			path,
+			orig_line: index + 1,
+			final_line: final_index + 1,
+			content: final_lines[final_index].to_vec(),
+		})
+		.collect::<Vec<_>>();
+	// Only commits lines are attributed to are shown
+	let shown = lines.iter().map(|l| &l.commit).collect::<HashSet<_>>();
+	let commits = commits
+		.into_iter()
+		.filter(|(c, _)| shown.contains(c))
+		.collect();
+	Ok(Blame {
+		path,
+		lines,
+		commits,
+	})
+}
+
+// "Name", "<email>" and the date of a signature
+fn split_signature(signature: &str) -> (&str, &str, &str, &str) {
+	let (name, rest) = signature.split_once(" <").unwrap_or((signature, ""));
+	let (email, rest) = rest.split_once("> ").unwrap_or((rest, ""));
+	let (time, zone) = rest.split_once(' ').unwrap_or((rest, ""));
+	(name, email, time, zone)
+}
+
+fn push_line(output: &mut Vec<u8>, content: &[u8]) {
+	output.extend_from_slice(content);
+	if !content.ends_with(b"\n") {
+		output.push(b'\n');
+	}
+}
+
+fn format_porcelain(blame: &Blame) -> Vec<u8> {
+	let mut output = Vec::new();
+	let mut described = HashSet::new();
+	let mut previous: Option<&BlameLine> = None;
+	for (n, line) in blame.lines.iter().enumerate() {
+		// Consecutive lines from consecutive lines of a commit form a group
+		let continues = previous.is_some_and(|p| {
+			p.commit == line.commit
+				&& p.path == line.path
+				&& p.orig_line + 1 == line.orig_line
+				&& p.final_line + 1 == line.final_line
+		});
+		if continues {
+			output.extend(
+				format!("{} {} {}\n", line.commit, line.orig_line, line.final_line).bytes(),
+			);
+		} else {
+			let size = blame.lines[n..]
+				.iter()
+				.enumerate()
+				.take_while(|(k, l)| {
+					l.commit == line.commit
+						&& l.path == line.path
+						&& l.orig_line == line.orig_line + k
+						&& l.final_line == line.final_line + k
+				})
+				.count();
+			output.extend(
+				format!(
+					"{} {} {} {}\n",
+					line.commit, line.orig_line, line.final_line, size
+				)
+				.bytes(),
+			);
+			if described.insert(&line.commit) {
+				let info = &blame.commits[&line.commit];
+				for (role, signature) in &[("author", &info.author), ("committer", &info.committer)]
+				{
+					let (name, email, time, zone) = split_signature(signature);
+					output.extend(
+						format!(
+							"{0} {1}\n{0}-mail <{2}>\n{0}-time {3}\n{0}-tz {4}\n",
+							role, name, email, time, zone
+						)
+						.bytes(),
+					);
+				}
+				output.extend(format!("summary {}\n", info.summary).bytes());
+				if info.boundary {
+					output.extend(b"boundary\n");
+				}
+				if let Some((parent, path)) = &info.previous {
+					output.extend(format!("previous {} {}\n", parent, path).bytes());
+				}
+			}
+			output.extend(format!("filename {}\n", line.path).bytes());
+		}
+		output.push(b'\t');
+		push_line(&mut output, &line.content);
+		previous = Some(line);
+	}
+	output
+}
+
+// Lines as "<hash> [<path>] (<author> <date> <line>) <content>", the path
+// only when some lines come from another file
+pub fn format_blame(blame: &Blame, porcelain: bool) -> Vec<u8> {
+	if porcelain {
+		return format_porcelain(blame);
+	}
+	let show_path = blame.lines.iter().any(|l| l.path != blame.path);
+	let path_width = blame
+		.lines
+		.iter()
+		.map(|l| l.path.to_string().len())
+		.max()
+		.unwrap_or(0);
+	let author_width = blame
+		.commits
+		.values()
+		.map(|c| split_signature(&c.author).0.chars().count())
+		.max()
+		.unwrap_or(0);
+	let line_width = blame
+		.lines
+		.last()
+		.map_or(1, |l| l.final_line.to_string().len());
+
+	let mut output = Vec::new();
+	for line in &blame.lines {
+		let info = &blame.commits[&line.commit];
+		// Boundary commits are marked without changing the width
+		let hash = if info.boundary {
+			format!("^{}", line.commit.short())
+		} else {
+			line.commit.hash()[..line.commit.short().len() + 1].to_owned()
+		};
+		let path = if show_path {
+			format!(" {:<width$}", line.path.to_string(), width = path_width)
+		} else {
+			String::new()
+		};
+		let name = split_signature(&info.author).0;
+		let date = format_signature_date(&info.author).unwrap_or_default();
+		output.extend(
+			format!(
+				"{}{} ({:<author_width$} {} {:>line_width$}) ",
+				hash,
+				path,
+				name,
+				date,
+				line.final_line,
+				author_width = author_width,
+				line_width = line_width
+			)
+			.bytes(),
+		);
+		push_line(&mut output, &line.content);
+	}
+	output
+}
@@ -0,0 +1,42 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty blame [-w] [-M] [-C] [--porcelain] [-L <range>]... <file> [<rev>]
+         unrusty blame --help
+
+Options:
+         <file>                       File to annotate
+         <rev>                        Commit to annotate the file at, defaults to HEAD
+         -L <range>, --lines=<range>  Only annotate the lines in range: <start>,<end>, \
+                         <start>,+<count>, <start>,-<count> or <start> for the rest of the file
+         -w, --ignore-whitespace      Ignore whitespace when comparing lines
+         -M, --moves                  Follow lines moved within the file
+         -C, --copies                 Also follow lines moved or copied from other files changed \
+                         in the same commit
+         --porcelain                  Show the result in a format for scripts
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub arg_file:               String,
+	pub arg_rev:                Option<String>,
+	pub flag_lines:             Vec<String>,
+	pub flag_ignore_whitespace: bool,
+	pub flag_moves:             bool,
+	pub flag_copies:            bool,
+	pub flag_porcelain:         bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
@@ -0,0 +1,3 @@
+pub mod bundle_cmd;
+pub mod bundle_do;
+mod bundle_parse;
@@ -0,0 +1,58 @@
+use super::{
+	bundle_do::{bundle_create, bundle_list_heads, bundle_unbundle, bundle_verify},
+	bundle_parse::Args,
+};
+use crate::tools::{db::ObjectRepr, failed::failed};
+use std::path::Path;
+
+fn print_refs(refs: &[(ObjectRepr, String)]) {
+	for (object_repr, name) in refs {
+		println!("{} {}", object_repr, name);
+	}
+}
+
+// "this ref" or "these 2 refs"
+fn count_refs(n: usize) -> String {
+	if n == 1 {
+		"this ref".to_owned()
+	} else {
+		format!("these {} refs", n)
+	}
+}
+
+pub fn cmd_bundle<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+	let file = Path::new(&args.arg_file);
+
+	if args.cmd_create {
+		bundle_create(file, &args.arg_rev, args.flag_all)
+			.unwrap_or_else(|e| failed(&format!("Failed bundle create: {:?}", e)));
+	} else if args.cmd_verify {
+		let bundle = bundle_verify(file)
+			.unwrap_or_else(|e| failed(&format!("Failed bundle verify: {:?}", e)));
+		println!("The bundle contains {}:", count_refs(bundle.refs.len()));
+		print_refs(&bundle.refs);
+		if bundle.prerequisites.is_empty() {
+			println!("The bundle records a complete history.");
+		} else {
+			println!(
+				"The bundle requires {}:",
+				count_refs(bundle.prerequisites.len())
+			);
+			print_refs(&bundle.prerequisites);
+		}
+		println!("{} is okay", file.display());
+	} else if args.cmd_list_heads {
+		let refs = bundle_list_heads(file, &args.arg_refname)
+			.unwrap_or_else(|e| failed(&format!("Failed bundle list-heads: {:?}", e)));
+		print_refs(&refs);
+	} else if args.cmd_unbundle {
+		let refs = bundle_unbundle(file)
+			.unwrap_or_else(|e| failed(&format!("Failed bundle unbundle: {:?}", e)));
+		print_refs(&refs);
+	}
+}
@@ -0,0 +1,165 @@
+use crate::tools::{
+	bundle::Bundle,
+	commit::{subject, Commit},
+	db::{insert, read, ObjectRepr, ObjectType},
+	object_format::ObjectFormat,
+	pack::{read_pack, write_pack},
+	reachable::{objects_between, reachable_from},
+	refs::{list_refs, HEAD},
+	rev::{expand_ref_name, resolve_revision},
+};
+use std::{collections::BTreeSet, error::Error, path::Path};
+
+// Objects to bundle, with the full name of those given as refs
+type Included = Vec<(ObjectRepr, Option<String>)>;
+
+// Splits revisions into included and excluded objects: ^rev excludes, and
+// a..b stands for ^a b with HEAD for a missing side. Included refs are named
+fn parse_revisions(revs: &[String]) -> Result<(Included, Vec<ObjectRepr>), Box<dyn Error>> {
+	let mut included = Vec::new();
+	let mut excluded = Vec::new();
+	let or_head = |rev: &str| {
+		if rev.is_empty() {
+			HEAD.to_owned()
+		} else {
+			rev.to_owned()
+		}
+	};
+	for rev in revs {
+		let (negative, positive) = match (rev.strip_prefix('^'), rev.split_once("..")) {
+			(Some(negative), _) => (Some(negative.to_owned()), None),
+			(None, Some((from, to))) => (Some(or_head(from)), Some(or_head(to))),
+			(None, None) => (None, Some(rev.clone())),
+		};
+		if let Some(negative) = negative {
+			excluded.push(resolve_revision(&negative)?);
+		}
+		if let Some(positive) = positive {
+			included.push((resolve_revision(&positive)?, expand_ref_name(&positive)?));
+		}
+	}
+	Ok((included, excluded))
+}
+
+// The first line of the message of a commit, empty for other objects
+// The subject of commits, nothing for other objects
+fn comment(object_repr: &ObjectRepr) -> Result<String, Box<dyn Error>> {
+	let object = read(object_repr)?;
+	if object.object_type != ObjectType::Commit {
+		return Ok(String::new());
+	}
+	Ok(subject(&Commit::parse(&object.data)?.message))
+}
+
+// Writes the objects of the revisions and their refs to `file`. The commits
+// the bundle builds upon without containing them become prerequisites
+pub fn bundle_create(file: &Path, revs: &[String], all: bool) -> Result<Bundle, Box<dyn Error>> {
+	let (included, excluded) = if all {
+		let refs = list_refs()?
+			.into_iter()
+			.map(|(name, object_repr)| (object_repr, Some(name)))
+			.collect();
+		(refs, Vec::new())
+	} else {
+		parse_revisions(revs)?
+	};
+
+	let mut bundle = Bundle {
+		object_format: ObjectFormat::current(),
+		..Bundle::default()
+	};
+	for (object_repr, name) in &included {
+		if let Some(name) = name {
+			if !bundle.refs.iter().any(|(_, n)| n == name) {
+				bundle.refs.push((object_repr.clone(), name.clone()));
+			}
+		}
+	}
+	if bundle.refs.is_empty() {
+		return Err("Refusing to create a bundle without refs".into());
+	}
+	let wants = included.into_iter().map(|(o, _)| o).collect::<Vec<_>>();
+	let objects = objects_between(&wants, &excluded)?;
+
+	// Parents of bundled commits that are not bundled
+	let bundled = objects.iter().collect::<BTreeSet<_>>();
+	let mut prerequisites = BTreeSet::new();
+	for object_repr in &objects {
+		let object = read(object_repr)?;
+		if object.object_type == ObjectType::Commit {
+			let commit = Commit::parse(&object.data)?;
+			prerequisites.extend(commit.parents.into_iter().filter(|p| !bundled.contains(p)));
+		}
+	}
+	for object_repr in prerequisites {
+		let comment = comment(&object_repr)?;
+		bundle.prerequisites.push((object_repr, comment));
+	}
+
+	write_pack(&mut bundle.pack, &objects)?;
+	bundle.write(file)?;
+	Ok(bundle)
+}
+
+// Fails unless the current repository has the prerequisites, entirely, and
+// the object format of the bundle
+fn check_prerequisites(bundle: &Bundle) -> Result<(), Box<dyn Error>> {
+	let format = ObjectFormat::current();
+	if bundle.object_format != format {
+		return Err(format!(
+			"Bundle uses {} objects, the repository {}",
+			bundle.object_format, format
+		)
+		.into());
+	}
+	let mut missing = Vec::new();
+	for (object_repr, _) in &bundle.prerequisites {
+		if !reachable_from(vec![(None, object_repr.clone())])?
+			.missing
+			.is_empty()
+		{
+			missing.push(object_repr.to_string());
+		}
+	}
+	if !missing.is_empty() {
+		return Err(format!(
+			"Repository lacks these prerequisite commits: {}",
+			missing.join(", ")
+		)
+		.into());
+	}
+	Ok(())
+}
+
+// Reads the bundle and checks that its pack is sound and that the current
+// repository has its prerequisites
+pub fn bundle_verify(file: &Path) -> Result<Bundle, Box<dyn Error>> {
+	let bundle = Bundle::read(file)?;
+	check_prerequisites(&bundle)?;
+	read_pack(&bundle.pack)?;
+	Ok(bundle)
+}
+
+// The refs of the bundle, restricted to `names` if any are given
+pub fn bundle_list_heads(
+	file: &Path,
+	names: &[String],
+) -> Result<Vec<(ObjectRepr, String)>, Box<dyn Error>> {
+	let bundle = Bundle::read(file)?;
+	Ok(bundle
+		.refs
+		.into_iter()
+		.filter(|(_, name)| names.is_empty() || names.contains(name))
+		.collect())
+}
+
+// Stores the objects of the bundle. Refs are not updated, the refs of the
+// bundle are returned instead
+pub fn bundle_unbundle(file: &Path) -> Result<Vec<(ObjectRepr, String)>, Box<dyn Error>> {
+	let bundle = Bundle::read(file)?;
+	check_prerequisites(&bundle)?;
+	for (object_type, data) in read_pack(&bundle.pack)? {
+		insert(&data, object_type)?;
+	}
+	Ok(bundle.refs)
+}
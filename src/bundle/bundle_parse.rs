@@ -0,0 +1,42 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty bundle create <file> (--all | <rev>...)
+         unrusty bundle verify <file>
+         unrusty bundle list-heads <file> [<refname>...]
+         unrusty bundle unbundle <file>
+         unrusty bundle --help
+
+Options:
+         <file>                       Bundle to create or read
+         <rev>                        What to bundle, e.g. master, ^v1 master or v1..master. \
+                         Excluded commits become prerequisites
+         --all                        Bundle all refs
+         <refname>                    Only list these refs
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub cmd_create:     bool,
+	pub cmd_verify:     bool,
+	pub cmd_list_heads: bool,
+	pub cmd_unbundle:   bool,
+	pub arg_file:       String,
+	pub arg_rev:        Vec<String>,
+	pub arg_refname:    Vec<String>,
+	pub flag_all:       bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
@@ -1,4 +1,7 @@
-use crate::tools::db::{read, read_with_invalid, Object, ObjectRepr, ObjectType};
+use crate::tools::{
+	db::{read, read_with_invalid, Object, ObjectRepr, ObjectType},
+	lfs::resolve,
+};
 use log::error;
 use std::{error::Error, process::exit};
 
@@ -6,7 +9,7 @@ pub fn cat_file_type(
 	object_repr: &ObjectRepr,
 	allow_invalid_type: bool,
 ) -> Result<ObjectType, Box<dyn Error>> {
-	let obj = read_with_invalid(&object_repr, allow_invalid_type)?;
+	let obj = read_with_invalid(object_repr, allow_invalid_type)?;
 	cat_file_type_prepared(&obj)
 }
 
@@ -18,7 +21,7 @@ pub fn cat_file_size(
 	object_repr: &ObjectRepr,
 	allow_invalid_type: bool,
 ) -> Result<usize, Box<dyn Error>> {
-	let obj = read_with_invalid(&object_repr, allow_invalid_type)?;
+	let obj = read_with_invalid(object_repr, allow_invalid_type)?;
 	cat_file_size_prepared(&obj)
 }
 
@@ -27,16 +30,22 @@ pub fn cat_file_size_prepared(obj: &Object) -> Result<usize, Box<dyn Error>> {
 }
 
 pub fn cat_file_print(object_repr: &ObjectRepr) -> Result<String, Box<dyn Error>> {
-	let obj = read_with_invalid(&object_repr, false)?;
+	let obj = read_with_invalid(object_repr, false)?;
 	cat_file_print_prepared(&obj)
 }
 
+// Pointers to large files show the file, when the store has it
 pub fn cat_file_print_prepared(obj: &Object) -> Result<String, Box<dyn Error>> {
-	Ok(std::string::String::from_utf8_lossy(&obj.data).to_string())
+	let resolved = match obj.object_type {
+		ObjectType::Blob => resolve(&obj.data)?,
+		_ => None,
+	};
+	let data = resolved.as_deref().unwrap_or(&obj.data);
+	Ok(std::string::String::from_utf8_lossy(data).to_string())
 }
 
 pub fn cat_file_check(object_repr: &ObjectRepr) -> ! {
-	exit(match read(&object_repr) {
+	exit(match read(object_repr) {
 		Ok(_) => 0,
 		Err(e) => {
 			error!("entry has invalid format: {:?}", e);
@@ -0,0 +1,3 @@
+pub mod cherry_pick_cmd;
+pub mod cherry_pick_do;
+mod cherry_pick_parse;
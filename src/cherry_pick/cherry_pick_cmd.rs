@@ -0,0 +1,55 @@
+use super::{
+	cherry_pick_do::{
+		cherry_pick, replay_abort, replay_continue, replay_skip, ReplayOptions, Sequence,
+	},
+	cherry_pick_parse::Args,
+};
+use crate::tools::failed::failed;
+use std::{error::Error, process::exit};
+
+// Shared with revert, whose sequence works the same
+pub fn report_sequence(sequence: Result<Sequence, Box<dyn Error>>, command: &str) {
+	let sequence = sequence.unwrap_or_else(|e| failed(&format!("Failed {}: {:?}", command, e)));
+	for (commit, subject) in &sequence.made {
+		println!("[{}] {}", commit.short(), subject);
+	}
+	if let Some(stopped) = sequence.stopped {
+		for path in &stopped.paths {
+			println!("CONFLICT in {}", path);
+		}
+		println!(
+			"Could not apply {}... {}",
+			stopped.commit.short(),
+			stopped.subject
+		);
+		println!(
+			"Resolve the conflicts, add the files and run unrusty {0} --continue, or skip the \
+			 commit with unrusty {0} --skip",
+			command.split(' ').next().unwrap_or(command)
+		);
+		exit(1);
+	}
+}
+
+pub fn cmd_cherry_pick<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	if args.flag_abort {
+		replay_abort().unwrap_or_else(|e| failed(&format!("Failed cherry-pick --abort: {:?}", e)));
+	} else if args.flag_continue {
+		report_sequence(replay_continue(), "cherry-pick --continue");
+	} else if args.flag_skip {
+		report_sequence(replay_skip(), "cherry-pick --skip");
+	} else {
+		let options = ReplayOptions {
+			no_commit:     args.flag_no_commit,
+			record_origin: args.flag_x,
+			mainline:      args.flag_mainline,
+		};
+		report_sequence(cherry_pick(&args.arg_commit, &options), "cherry-pick");
+	}
+}
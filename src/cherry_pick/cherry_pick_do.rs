@@ -0,0 +1,528 @@
+use crate::{
+	merge::merge_do::{conflict_style, ensure_clean, ensure_nothing_in_the_way},
+	tools::{
+		commit::{commit_tree, signature_now, subject, Commit},
+		db::ObjectRepr,
+		filter::Filters,
+		index::Index,
+		merge::{commits_between, merge_trees, MergeLabels},
+		path::{vcs_path_required, RelativePathToBase},
+		refs::{
+			delete_ref, read_ref, update_ref, write_ref, CHERRY_PICK_HEAD, HEAD, MERGE_HEAD,
+			REVERT_HEAD,
+		},
+		rev::{peel_to_commit, resolve_revision},
+		tree::{write_flat_tree, FlatTree},
+		worktree::{checkout_merge, switch_tree},
+	},
+};
+use log::warn;
+use std::{convert::TryFrom, error::Error, fs, io::ErrorKind, path::PathBuf};
+
+// State of a cherry-pick or revert of several commits, shared by both
+const STATE_DIR: &str = "sequencer";
+const TODO: &str = "todo";
+// HEAD before the first commit was applied, restored by --abort
+const ORIG_HEAD_FILE: &str = "head";
+const OPTIONS: &str = "opts";
+// The commit left to make by --continue after a conflict
+const MESSAGE: &str = "message";
+const AUTHOR: &str = "author";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replay {
+	// Applies the changes of a commit
+	Pick,
+	// Applies the inverse of the changes of a commit
+	Revert,
+}
+
+impl Replay {
+	fn from_name(name: &str) -> Option<Replay> {
+		match name {
+			"pick" => Some(Replay::Pick),
+			"revert" => Some(Replay::Revert),
+			_ => None,
+		}
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			Replay::Pick => "pick",
+			Replay::Revert => "revert",
+		}
+	}
+
+	fn command(self) -> &'static str {
+		match self {
+			Replay::Pick => "cherry-pick",
+			Replay::Revert => "revert",
+		}
+	}
+
+	// Records the commit being applied while its conflicts are resolved
+	fn head_ref(self) -> &'static str {
+		match self {
+			Replay::Pick => CHERRY_PICK_HEAD,
+			Replay::Revert => REVERT_HEAD,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayOptions {
+	pub no_commit:     bool,
+	// Appends "(cherry picked from commit ...)" to the message
+	pub record_origin: bool,
+	// Parent of merge commits the changes are taken against, from 1
+	pub mainline:      Option<usize>,
+}
+
+impl ReplayOptions {
+	fn parse(content: &str) -> Result<ReplayOptions, Box<dyn Error>> {
+		let mut options = ReplayOptions::default();
+		for line in content.lines() {
+			match line.split_once(' ') {
+				Some(("mainline", n)) => options.mainline = Some(n.parse()?),
+				_ if line == "no-commit" => options.no_commit = true,
+				_ if line == "record-origin" => options.record_origin = true,
+				_ => return Err(format!("unknown sequencer option {:?}", line).into()),
+			}
+		}
+		Ok(options)
+	}
+
+	fn serialize(&self) -> String {
+		let mut content = String::new();
+		if self.no_commit {
+			content.push_str("no-commit\n");
+		}
+		if self.record_origin {
+			content.push_str("record-origin\n");
+		}
+		if let Some(mainline) = self.mainline {
+			content.push_str(&format!("mainline {}\n", mainline));
+		}
+		content
+	}
+}
+
+// A commit made, with its subject
+pub type Made = (ObjectRepr, String);
+
+// A commit whose changes conflicted, with its subject
+pub struct Stopped {
+	pub commit:  ObjectRepr,
+	pub subject: String,
+	pub paths:   Vec<RelativePathToBase>,
+}
+
+pub struct Sequence {
+	pub made:    Vec<Made>,
+	pub stopped: Option<Stopped>,
+}
+
+enum Step {
+	Committed(Made),
+	// Left in the index, or nothing to commit
+	Applied,
+	Stopped(Stopped),
+}
+
+fn state_path(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+	Ok(vcs_path_required(&format!("{}/{}", STATE_DIR, name))?)
+}
+
+fn in_progress() -> Result<bool, Box<dyn Error>> {
+	Ok(vcs_path_required(STATE_DIR)?.is_dir())
+}
+
+fn read_state(name: &str) -> Result<Option<String>, Box<dyn Error>> {
+	match fs::read_to_string(state_path(name)?) {
+		Ok(content) => Ok(Some(content)),
+		Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(e.into()),
+	}
+}
+
+fn remove_state(name: &str) -> Result<(), Box<dyn Error>> {
+	match fs::remove_file(state_path(name)?) {
+		Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+		_ => Ok(()),
+	}
+}
+
+// Lines of "<pick|revert> <hash> <subject>"
+fn read_todo() -> Result<Vec<(Replay, ObjectRepr)>, Box<dyn Error>> {
+	let content = read_state(TODO)?.unwrap_or_default();
+	let mut todo = Vec::new();
+	for line in content.lines() {
+		let mut words = line.splitn(3, ' ');
+		let kind = words.next().and_then(Replay::from_name);
+		let commit = words.next().and_then(|h| ObjectRepr::try_from(h).ok());
+		match (kind, commit) {
+			(Some(kind), Some(commit)) => todo.push((kind, commit)),
+			_ => return Err(format!("malformed sequencer todo line {:?}", line).into()),
+		}
+	}
+	Ok(todo)
+}
+
+fn write_todo(todo: &[(Replay, ObjectRepr)]) -> Result<(), Box<dyn Error>> {
+	let mut content = String::new();
+	for (kind, commit) in todo {
+		let message = Commit::read(commit)?.message;
+		content.push_str(&format!(
+			"{} {} {}\n",
+			kind.name(),
+			commit,
+			subject(&message)
+		));
+	}
+	Ok(fs::write(state_path(TODO)?, content)?)
+}
+
+// The commits named by the arguments. Ranges "a..b" stand for the commits
+// of b not in a, oldest first, or newest first when reverting
+fn resolve_commits(kind: Replay, revs: &[String]) -> Result<Vec<ObjectRepr>, Box<dyn Error>> {
+	let mut commits = Vec::new();
+	for rev in revs {
+		match rev.split_once("..") {
+			Some((from, to)) => {
+				let from = peel_to_commit(&resolve_revision(if from.is_empty() {
+					HEAD
+				} else {
+					from
+				})?)?;
+				let to = peel_to_commit(&resolve_revision(if to.is_empty() { HEAD } else { to })?)?;
+				let mut range = commits_between(&[from], &to)?;
+				if kind == Replay::Revert {
+					range.reverse();
+				}
+				commits.extend(range);
+			},
+			None => commits.push(peel_to_commit(&resolve_revision(rev)?)?),
+		}
+	}
+	if commits.is_empty() {
+		return Err("no commits to apply".into());
+	}
+	Ok(commits)
+}
+
+// The parent the changes of the commit are taken against
+fn replay_parent(
+	commit_repr: &ObjectRepr,
+	commit: &Commit,
+	mainline: Option<usize>,
+) -> Result<Option<ObjectRepr>, Box<dyn Error>> {
+	match (commit.parents.len(), mainline) {
+		(n, None) if n > 1 => Err(format!(
+			"commit {} is a merge but no -m option was given",
+			commit_repr
+		)
+		.into()),
+		(_, None) => Ok(commit.parents.first().cloned()),
+		(n, Some(_)) if n <= 1 => Err(format!(
+			"mainline was specified but commit {} is not a merge",
+			commit_repr
+		)
+		.into()),
+		(n, Some(m)) if m == 0 || m > n => {
+			Err(format!("commit {} does not have parent {}", commit_repr, m).into())
+		},
+		(_, Some(m)) => Ok(Some(commit.parents[m - 1].clone())),
+	}
+}
+
+// Three-way merge of the changes of one commit into the index and working
+// tree, committed unless --no-commit
+fn apply(
+	kind: Replay,
+	commit_repr: &ObjectRepr,
+	options: &ReplayOptions,
+	index: &mut Index,
+	filters: &Filters,
+) -> Result<Step, Box<dyn Error>> {
+	let commit = Commit::read(commit_repr)?;
+	let commit_subject = subject(&commit.message);
+	let parent = replay_parent(commit_repr, &commit, options.mainline)?;
+	let head = read_ref(HEAD)?;
+	// Without committing, changes pile up in the index
+	let ours = if options.no_commit {
+		index.flat_tree()
+	} else {
+		commit_tree(head.as_ref())?
+	};
+
+	let commit_label = format!("{} ({})", commit_repr.short(), commit_subject);
+	let parent_label = format!("parent of {}", commit_label);
+	let (base, theirs, labels) = match kind {
+		Replay::Pick => (
+			commit_tree(parent.as_ref())?,
+			commit_tree(Some(commit_repr))?,
+			MergeLabels {
+				base:   &parent_label,
+				ours:   HEAD,
+				theirs: &commit_label,
+			},
+		),
+		Replay::Revert => (
+			commit_tree(Some(commit_repr))?,
+			commit_tree(parent.as_ref())?,
+			MergeLabels {
+				base:   &commit_label,
+				ours:   HEAD,
+				theirs: &parent_label,
+			},
+		),
+	};
+	let result = merge_trees(&base, &ours, &theirs, &labels, conflict_style(None)?)?;
+	// A commit that can not be made must not leave the merge behind
+	if !options.no_commit {
+		signature_now()?;
+	}
+	ensure_nothing_in_the_way(index, &result.tree)?;
+	checkout_merge(index, &ours, &result, filters)?;
+	index.write()?;
+
+	let (message, author) = match kind {
+		Replay::Pick if options.record_origin => (
+			format!(
+				"{}\n\n(cherry picked from commit {})",
+				commit.message.trim_end(),
+				commit_repr
+			),
+			Some(commit.author),
+		),
+		Replay::Pick => (commit.message, Some(commit.author)),
+		Replay::Revert => {
+			let mut message = format!(
+				"Revert \"{}\"\n\nThis reverts commit {}",
+				commit_subject, commit_repr
+			);
+			match (&parent, options.mainline) {
+				(Some(parent), Some(_)) => {
+					message.push_str(&format!(", reversing\nchanges made to {}.", parent))
+				},
+				_ => message.push('.'),
+			}
+			(message, None)
+		},
+	};
+
+	if !result.conflicts.is_empty() {
+		if !options.no_commit {
+			fs::write(state_path(MESSAGE)?, &message)?;
+			if let Some(author) = author {
+				fs::write(state_path(AUTHOR)?, author)?;
+			}
+			write_ref(kind.head_ref(), commit_repr)?;
+		}
+		return Ok(Step::Stopped(Stopped {
+			commit:  commit_repr.clone(),
+			subject: commit_subject,
+			paths:   result.conflicts.into_iter().map(|c| c.path).collect(),
+		}));
+	}
+	if options.no_commit {
+		return Ok(Step::Applied);
+	}
+	if result.tree == ours {
+		warn!(
+			"{} {} changes nothing, skipped",
+			commit_repr.short(),
+			commit_subject
+		);
+		return Ok(Step::Applied);
+	}
+	let made = commit_index(kind, &result.tree, &message, author.as_deref())?;
+	Ok(Step::Committed(made))
+}
+
+fn commit_index(
+	kind: Replay,
+	tree: &FlatTree,
+	message: &str,
+	author: Option<&str>,
+) -> Result<Made, Box<dyn Error>> {
+	let parents = read_ref(HEAD)?.into_iter().collect::<Vec<_>>();
+	let tree = write_flat_tree(tree)?;
+	let commit = match author {
+		Some(author) => Commit::with_author(tree, parents, author, message)?,
+		None => Commit::new(tree, parents, message)?,
+	}
+	.write()?;
+	let message_subject = subject(message);
+	update_ref(
+		HEAD,
+		&commit,
+		&format!("{}: {}", kind.command(), message_subject),
+	)?;
+	Ok((commit, message_subject))
+}
+
+fn clear_pending() -> Result<(), Box<dyn Error>> {
+	remove_state(MESSAGE)?;
+	remove_state(AUTHOR)?;
+	delete_ref(CHERRY_PICK_HEAD)?;
+	delete_ref(REVERT_HEAD)?;
+	Ok(())
+}
+
+// Applies the commits left in the todo list until one conflicts
+fn run_sequence(
+	index: &mut Index,
+	mut made: Vec<Made>,
+	filters: &Filters,
+) -> Result<Sequence, Box<dyn Error>> {
+	let options = ReplayOptions::parse(&read_state(OPTIONS)?.unwrap_or_default())?;
+	loop {
+		let mut todo = read_todo()?;
+		if todo.is_empty() {
+			fs::remove_dir_all(vcs_path_required(STATE_DIR)?)?;
+			return Ok(Sequence {
+				made,
+				stopped: None,
+			});
+		}
+		// The commit stays in the todo list until it is applied, a failure
+		// leaves it to --continue
+		let (kind, commit) = todo[0].clone();
+		let step = apply(kind, &commit, &options, index, filters)?;
+		todo.remove(0);
+		write_todo(&todo)?;
+		match step {
+			Step::Committed(commit) => made.push(commit),
+			Step::Applied => {},
+			Step::Stopped(stopped) => {
+				return Ok(Sequence {
+					made,
+					stopped: Some(stopped),
+				})
+			},
+		}
+	}
+}
+
+pub fn replay(
+	kind: Replay,
+	revs: &[String],
+	options: &ReplayOptions,
+) -> Result<Sequence, Box<dyn Error>> {
+	if in_progress()? {
+		return Err(
+			"a cherry-pick or revert is in progress, use --continue, --skip or --abort".into(),
+		);
+	}
+	if read_ref(MERGE_HEAD)?.is_some() {
+		return Err("a merge is in progress, finish or abort it first".into());
+	}
+	let commits = resolve_commits(kind, revs)?;
+	// Merges without -m are refused before anything is applied
+	for commit in &commits {
+		replay_parent(commit, &Commit::read(commit)?, options.mainline)?;
+	}
+	let head = read_ref(HEAD)?;
+	let mut index = Index::read()?;
+	let filters = Filters::read()?;
+	if options.no_commit {
+		if !index.conflicts().is_empty() {
+			return Err("the index has unresolved conflicts".into());
+		}
+	} else {
+		ensure_clean(&index, &commit_tree(head.as_ref())?, &filters)?;
+		signature_now()?;
+	}
+
+	fs::create_dir_all(vcs_path_required(STATE_DIR)?)?;
+	if let Some(head) = &head {
+		fs::write(state_path(ORIG_HEAD_FILE)?, format!("{}\n", head))?;
+	}
+	fs::write(state_path(OPTIONS)?, options.serialize())?;
+	let todo = commits.into_iter().map(|c| (kind, c)).collect::<Vec<_>>();
+	write_todo(&todo)?;
+	run_sequence(&mut index, Vec::new(), &filters)
+}
+
+pub fn cherry_pick(revs: &[String], options: &ReplayOptions) -> Result<Sequence, Box<dyn Error>> {
+	replay(Replay::Pick, revs, options)
+}
+
+pub fn replay_continue() -> Result<Sequence, Box<dyn Error>> {
+	if !in_progress()? {
+		return Err("there is no cherry-pick or revert in progress".into());
+	}
+	let mut index = Index::read()?;
+	let conflicts = index.conflicts();
+	if !conflicts.is_empty() {
+		let paths = conflicts.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+		return Err(format!(
+			"unresolved conflicts, add the files first: {}",
+			paths.join(", ")
+		)
+		.into());
+	}
+	let mut made = Vec::new();
+	if let Some(message) = read_state(MESSAGE)? {
+		let kind = if read_ref(REVERT_HEAD)?.is_some() {
+			Replay::Revert
+		} else {
+			Replay::Pick
+		};
+		let tree = index.flat_tree();
+		if tree == commit_tree(read_ref(HEAD)?.as_ref())? {
+			warn!("the resolved commit changes nothing, skipped");
+		} else {
+			let author = read_state(AUTHOR)?;
+			made.push(commit_index(kind, &tree, &message, author.as_deref())?);
+		}
+	}
+	clear_pending()?;
+	run_sequence(&mut index, made, &Filters::read()?)
+}
+
+pub fn replay_skip() -> Result<Sequence, Box<dyn Error>> {
+	if !in_progress()? {
+		return Err("there is no cherry-pick or revert in progress".into());
+	}
+	let mut index = Index::read()?;
+	let filters = Filters::read()?;
+	let from = index.flat_tree();
+	switch_tree(
+		&mut index,
+		&from,
+		&commit_tree(read_ref(HEAD)?.as_ref())?,
+		true,
+		&filters,
+	)?;
+	index.write()?;
+	clear_pending()?;
+	run_sequence(&mut index, Vec::new(), &filters)
+}
+
+pub fn replay_abort() -> Result<(), Box<dyn Error>> {
+	if !in_progress()? {
+		return Err("there is no cherry-pick or revert in progress".into());
+	}
+	let orig_head = match read_state(ORIG_HEAD_FILE)? {
+		Some(content) => Some(
+			ObjectRepr::try_from(content.trim())
+				.map_err(|_| "sequencer state head is malformed")?,
+		),
+		None => None,
+	};
+	let mut index = Index::read()?;
+	let from = index.flat_tree();
+	let to = commit_tree(orig_head.as_ref())?;
+	switch_tree(&mut index, &from, &to, true, &Filters::read()?)?;
+	index.write()?;
+	if let Some(orig_head) = orig_head {
+		if read_ref(HEAD)?.as_ref() != Some(&orig_head) {
+			update_ref(HEAD, &orig_head, "cherry-pick: abort")?;
+		}
+	}
+	clear_pending()?;
+	fs::remove_dir_all(vcs_path_required(STATE_DIR)?)?;
+	Ok(())
+}
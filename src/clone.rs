@@ -0,0 +1,3 @@
+pub mod clone_cmd;
+pub mod clone_do;
+mod clone_parse;
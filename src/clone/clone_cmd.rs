@@ -0,0 +1,21 @@
+use super::{clone_do::clone, clone_parse::Args};
+use crate::tools::failed::failed;
+use std::path::Path;
+
+pub fn cmd_clone<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	let res = clone(
+		&args.arg_repository,
+		args.arg_directory.as_deref().map(Path::new),
+		!args.flag_no_hardlinks,
+	);
+	match res {
+		Ok(dir) => println!("Cloned into {}", dir.display()),
+		Err(e) => failed(&format!("Failed clone: {:?}", e)),
+	}
+}
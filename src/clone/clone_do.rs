@@ -0,0 +1,165 @@
+use crate::{
+	init::init_do::init_at,
+	tools::{
+		config::{BranchConfig, Config, RemoteConfig},
+		db::ObjectRepr,
+		filter::Filters,
+		index::Index,
+		reflog::append_reflog,
+		refs::{update_ref, write_ref, write_symbolic_ref, RefValue, HEAD},
+		remote::{
+			default_fetch_refspec, remote_tracking_prefix, DEFAULT_REMOTE, HEADS_PREFIX,
+			TAGS_PREFIX,
+		},
+		rev::peel_to_tree,
+		transport::{in_repository, Source},
+		tree::{flatten_tree, FlatTree},
+		worktree::switch_tree,
+	},
+};
+use log::warn;
+use std::{
+	env,
+	error::Error,
+	fs,
+	path::{Path, PathBuf},
+};
+
+// The last component of the path or URL, without a .git suffix
+fn default_directory(url: &str) -> Result<PathBuf, Box<dyn Error>> {
+	url.trim_end_matches('/')
+		.rsplit('/')
+		.next()
+		.map(|name| name.strip_suffix(".git").unwrap_or(name))
+		.filter(|name| !name.is_empty())
+		.map(PathBuf::from)
+		.ok_or_else(|| format!("can not derive a directory name from {}", url).into())
+}
+
+// Clones the repository at the path or URL `source` into `dir`: all objects
+// reachable from its refs, its branches as remote-tracking refs, its tags, and
+// a checkout of the branch its HEAD points to. Returns the directory of the
+// clone. A clone that fails leaves nothing behind
+pub fn clone(source: &str, dir: Option<&Path>, hardlinks: bool) -> Result<PathBuf, Box<dyn Error>> {
+	let mut source = Source::open(source)?;
+	let url = source.url();
+	let dir = match dir {
+		Some(dir) => env::current_dir()?.join(dir),
+		None => env::current_dir()?.join(default_directory(&url)?),
+	};
+	if dir.exists() && fs::read_dir(&dir)?.next().is_some() {
+		return Err(format!("{} exists and is not empty", dir.display()).into());
+	}
+	let remote = source.refs()?;
+	let message = format!("clone: from {}", url);
+
+	let created = !dir.exists();
+	fs::create_dir_all(&dir)?;
+	let result = in_repository(&dir, || {
+		init_at(&dir, source.object_format()?)?;
+		// Only branches and tags are cloned
+		let wants = remote
+			.refs
+			.iter()
+			.filter(|(name, _)| name.starts_with(HEADS_PREFIX) || name.starts_with(TAGS_PREFIX))
+			.map(|(_, object_repr)| object_repr.clone())
+			.collect::<Vec<_>>();
+		source.fetch_objects(&wants, hardlinks)?;
+
+		let tracking = remote_tracking_prefix(DEFAULT_REMOTE);
+		for (name, object_repr) in &remote.refs {
+			if let Some(branch) = name.strip_prefix(HEADS_PREFIX) {
+				update_ref(&format!("{}{}", tracking, branch), object_repr, &message)?;
+			} else if name.starts_with(TAGS_PREFIX) {
+				update_ref(name, object_repr, &message)?;
+			}
+		}
+
+		let mut config = Config::read()?;
+		config.remote.insert(
+			DEFAULT_REMOTE.to_owned(),
+			RemoteConfig {
+				url:   url.clone(),
+				fetch: vec![default_fetch_refspec(DEFAULT_REMOTE)],
+			},
+		);
+
+		// Check out what HEAD of the source points to
+		let head = match &remote.head {
+			Some(RefValue::Symbolic(target)) => remote
+				.refs
+				.iter()
+				.find(|(name, _)| name == target)
+				.map(|(name, object_repr)| (Some(name.clone()), object_repr.clone())),
+			Some(RefValue::Direct(object_repr)) => Some((None, object_repr.clone())),
+			None => None,
+		};
+		match head {
+			Some((Some(branch), commit)) => {
+				let short = branch.trim_start_matches(HEADS_PREFIX);
+				write_symbolic_ref(HEAD, &branch)?;
+				update_ref(HEAD, &commit, &message)?;
+				write_symbolic_ref(
+					&format!("{}{}", tracking, HEAD),
+					&format!("{}{}", tracking, short),
+				)?;
+				config.branch.insert(
+					short.to_owned(),
+					BranchConfig {
+						remote: Some(DEFAULT_REMOTE.to_owned()),
+						merge:  Some(branch.clone()),
+					},
+				);
+				checkout(&commit)?;
+			},
+			Some((None, commit)) => {
+				write_ref(HEAD, &commit)?;
+				append_reflog(HEAD, None, Some(&commit), &message);
+				checkout(&commit)?;
+			},
+			None => warn!("the cloned repository is empty"),
+		}
+		config.write()?;
+		Ok(())
+	});
+	if let Err(e) = result {
+		remove_partial_clone(&dir, created);
+		return Err(e);
+	}
+	Ok(dir)
+}
+
+// Removes the directory if the clone created it, else what the clone put
+// into it, as it was empty before
+fn remove_partial_clone(dir: &Path, created: bool) {
+	let removed = if created {
+		fs::remove_dir_all(dir)
+	} else {
+		fs::read_dir(dir).and_then(|entries| {
+			for entry in entries {
+				let path = entry?.path();
+				if fs::symlink_metadata(&path)?.is_dir() {
+					fs::remove_dir_all(&path)?;
+				} else {
+					fs::remove_file(&path)?;
+				}
+			}
+			Ok(())
+		})
+	};
+	if let Err(e) = removed {
+		warn!(
+			"Failed removing the partial clone in {}: {}",
+			dir.display(),
+			e
+		);
+	}
+}
+
+fn checkout(commit: &ObjectRepr) -> Result<(), Box<dyn Error>> {
+	let mut index = Index::read()?;
+	let tree = flatten_tree(&peel_to_tree(commit)?)?;
+	let filters = Filters::read()?;
+	switch_tree(&mut index, &FlatTree::new(), &tree, false, &filters)?;
+	index.write()
+}
@@ -0,0 +1,33 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty clone [--no-hardlinks] <repository> [<directory>]
+         unrusty clone --help
+
+Options:
+         <repository>                 Path or http(s) URL of the repository to clone
+         <directory>                  Where to create the clone, defaults to the name of the \
+                         repository
+         --no-hardlinks               Copy the object files instead of linking them
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub arg_repository:    String,
+	pub arg_directory:     Option<String>,
+	pub flag_no_hardlinks: bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
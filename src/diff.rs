@@ -0,0 +1,3 @@
+pub mod diff_cmd;
+pub mod diff_do;
+mod diff_parse;
@@ -0,0 +1,101 @@
+use super::{
+	diff_do::{diff, DiffFormat},
+	diff_parse::Args,
+};
+use crate::tools::{
+	failed::failed,
+	moved::ColorMoved,
+	patch::PatchOptions,
+	rename::{parse_score, RenameOptions},
+	word_diff::WordDiff,
+};
+use std::io::{self, IsTerminal, Write};
+
+fn score(repr: &str) -> usize {
+	parse_score(repr).unwrap_or_else(|| failed(&format!("Invalid similarity score {:?}", repr)))
+}
+
+fn rename_options(args: &Args) -> RenameOptions {
+	let mut options = if args.flag_no_renames {
+		RenameOptions::none()
+	} else {
+		RenameOptions::default()
+	};
+	if let Some(repr) = &args.flag_find_renames {
+		options.renames = true;
+		options.threshold = score(repr);
+	}
+	if let Some(repr) = &args.flag_find_copies {
+		options.renames = true;
+		options.copies = true;
+		options.threshold = score(repr);
+	}
+	if args.flag_find_copies_harder {
+		options.renames = true;
+		options.copies_harder = true;
+	}
+	options
+}
+
+fn patch_options(args: &Args) -> PatchOptions {
+	let color = match args.flag_color.as_str() {
+		_ if args.flag_no_color => false,
+		"always" => true,
+		"never" => false,
+		"auto" => io::stdout().is_terminal(),
+		when => failed(&format!("Invalid color setting {:?}", when)),
+	};
+	let word_diff = args.flag_word_diff.as_ref().map(|mode| {
+		WordDiff::from_name(mode)
+			.unwrap_or_else(|| failed(&format!("Invalid word diff mode {:?}", mode)))
+	});
+	let color_moved = args.flag_color_moved.as_ref().map(|mode| {
+		ColorMoved::from_name(mode)
+			.unwrap_or_else(|| failed(&format!("Invalid moved color mode {:?}", mode)))
+	});
+	PatchOptions {
+		color,
+		color_moved,
+		word_diff,
+		binary: args.flag_binary,
+	}
+}
+
+fn diff_format(args: &Args) -> DiffFormat {
+	let options = patch_options(args);
+	let listed = args.flag_raw
+		|| args.flag_name_status
+		|| args.flag_name_only
+		|| args.flag_numstat
+		|| args.flag_stat
+		|| args.flag_shortstat;
+	DiffFormat {
+		raw: args.flag_raw,
+		name_status: args.flag_name_status,
+		name_only: args.flag_name_only,
+		numstat: args.flag_numstat,
+		stat: args.flag_stat,
+		shortstat: args.flag_shortstat,
+		patch: args.flag_patch || options.word_diff.is_some() || !listed,
+		options,
+	}
+}
+
+pub fn cmd_diff<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+	let output = diff(
+		&args.arg_commit,
+		&args.arg_path,
+		args.flag_cached,
+		&rename_options(&args),
+		diff_format(&args),
+	)
+	.unwrap_or_else(|e| failed(&format!("Failed diff: {:?}", e)));
+	io::stdout()
+		.write_all(&output)
+		.unwrap_or_else(|e| failed(&format!("Failed diff: {:?}", e)));
+}
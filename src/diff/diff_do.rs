@@ -0,0 +1,153 @@
+use crate::tools::{
+	db::{simulate_insert, ObjectType},
+	filter::Filters,
+	index::Index,
+	patch::{
+		diffstat, file_stats, name_only, name_status, numstat, raw, shortstat, tree_changes,
+		write_patches, Blobs, PatchOptions, STAT_WIDTH,
+	},
+	path::RelativePathToBase,
+	refs::{read_ref, HEAD},
+	rename::{detect_renames, RenameOptions},
+	rev::{peel_to_tree, resolve_revision},
+	tree::{flatten_tree, FlatEntry, FlatTree},
+	worktree::read_worktree_entry,
+};
+use std::{error::Error, path::PathBuf};
+
+// The parts of the output, shown in the order of the fields. Of the lists of
+// files only the first asked for is shown
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiffFormat {
+	pub raw:         bool,
+	pub name_status: bool,
+	pub name_only:   bool,
+	pub numstat:     bool,
+	pub stat:        bool,
+	pub shortstat:   bool,
+	pub patch:       bool,
+	pub options:     PatchOptions,
+}
+
+fn revision_tree(rev: &str) -> Result<FlatTree, Box<dyn Error>> {
+	Ok(flatten_tree(&peel_to_tree(&resolve_revision(rev)?)?)?)
+}
+
+// Empty before the first commit
+fn head_tree() -> Result<FlatTree, Box<dyn Error>> {
+	match read_ref(HEAD)? {
+		Some(head) => Ok(flatten_tree(&peel_to_tree(&head)?)?),
+		None => Ok(FlatTree::new()),
+	}
+}
+
+// The tracked files as they are in the working tree. Deleted files are left
+// out, unmodified ones keep the entry of the index. Modified contents are
+// hashed without being stored, and kept in `blobs`
+fn worktree_tree(index: &Index, blobs: &mut Blobs) -> Result<FlatTree, Box<dyn Error>> {
+	let filters = Filters::read()?;
+	let mut tree = FlatTree::new();
+	for (path, entry) in index.flat_tree() {
+		if !index.worktree_modified(&path, &filters)? {
+			tree.insert(path, entry);
+			continue;
+		}
+		if let Some((mode, content)) = read_worktree_entry(&path, &filters)? {
+			let hash = simulate_insert(&content, ObjectType::Blob)?;
+			blobs.add_unstored(hash.clone(), content);
+			tree.insert(path, FlatEntry { mode, hash });
+		}
+	}
+	Ok(tree)
+}
+
+// The trees compared: commits and the index, or the index and the working
+// tree
+fn diff_trees(
+	commits: &[String],
+	cached: bool,
+	blobs: &mut Blobs,
+) -> Result<(FlatTree, FlatTree), Box<dyn Error>> {
+	let commits = match commits {
+		[range] => match range.split_once("..") {
+			Some((old, new)) => vec![old, new],
+			None => vec![range.as_str()],
+		},
+		commits => commits.iter().map(String::as_str).collect(),
+	};
+	let old = |rev: &str| match rev {
+		"" => head_tree(),
+		rev => revision_tree(rev),
+	};
+	match (commits.as_slice(), cached) {
+		([], false) => {
+			let index = Index::read()?;
+			Ok((index.flat_tree(), worktree_tree(&index, blobs)?))
+		},
+		([], true) => Ok((head_tree()?, Index::read()?.flat_tree())),
+		([commit], true) => Ok((old(commit)?, Index::read()?.flat_tree())),
+		([commit], false) => Ok((old(commit)?, worktree_tree(&Index::read()?, blobs)?)),
+		([a, b], false) => Ok((old(a)?, old(b)?)),
+		(_, true) => Err("--cached takes at most one commit".into()),
+		_ => Err("too many commits to compare".into()),
+	}
+}
+
+fn filter_tree(tree: FlatTree, pathspecs: &[RelativePathToBase]) -> FlatTree {
+	if pathspecs.is_empty() {
+		return tree;
+	}
+	tree.into_iter()
+		.filter(|(path, _)| pathspecs.iter().any(|p| p.contains(path)))
+		.collect()
+}
+
+// The changes between two trees in the given format, files moved or copied
+// between them detected as the options ask for
+pub fn diff(
+	commits: &[String],
+	paths: &[PathBuf],
+	cached: bool,
+	renames: &RenameOptions,
+	format: DiffFormat,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+	let pathspecs = paths
+		.iter()
+		.map(|p| RelativePathToBase::from_user_path(p))
+		.collect::<Result<Vec<_>, _>>()?;
+	let mut blobs = Blobs::new();
+	let (old, new) = diff_trees(commits, cached, &mut blobs)?;
+	let (old, new) = (filter_tree(old, &pathspecs), filter_tree(new, &pathspecs));
+	let changes = detect_renames(tree_changes(&old, &new), &old, &blobs, renames)?;
+
+	let mut output = Vec::new();
+	if changes.is_empty() {
+		return Ok(output);
+	}
+	if format.raw {
+		output.extend(raw(&changes, &blobs).into_bytes());
+	} else if format.name_status {
+		output.extend(name_status(&changes).into_bytes());
+	} else if format.name_only {
+		output.extend(name_only(&changes).into_bytes());
+	}
+	if format.numstat || format.stat || format.shortstat {
+		let stats = file_stats(&changes, &blobs)?;
+		if format.numstat {
+			output.extend(numstat(&stats).into_bytes());
+		}
+		if format.stat {
+			output.extend(diffstat(&stats, STAT_WIDTH, format.options.color).into_bytes());
+		} else if format.shortstat {
+			output.extend(shortstat(&stats).into_bytes());
+		}
+	}
+	if format.patch {
+		// Patches are set apart from what comes before them
+		if !output.is_empty() {
+			output.push(b'\n');
+		}
+		output.extend(write_patches(&changes, &blobs, &format.options)?);
+	}
+	Ok(output)
+}
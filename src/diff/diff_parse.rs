@@ -0,0 +1,98 @@
+use docopt::Docopt;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "
+Usage:   unrusty diff [options] [<commit>...]
+         unrusty diff [options] [<commit>...] -- <path>...
+         unrusty diff --help
+
+Options:
+         <commit>                     Without commits the working tree is compared with the index, \
+                         with one commit with that commit, with two commits or a..b the trees of \
+                         both are compared
+         --cached                     Compare the index with the commit, HEAD by default
+         -p, --patch                  Show a patch, the default without other formats
+         --raw                        Show modes, hashes and the status of changed files
+         --name-status                Show the status and the paths of changed files
+         --name-only                  Show the paths of changed files
+         --numstat                    Show the lines added and removed in each file
+         --stat                       Show a diffstat
+         --shortstat                  Show only the summary line of the diffstat
+         --word-diff=<mode>           Show changed words instead of lines, as plain, color or \
+                         porcelain. --word-diff alone uses plain
+         --binary                     Show binary files as patches that can be applied
+         --color=<when>               Color the output always, never or auto when writing to a \
+                         terminal. --color alone means always [default: auto]
+         --no-color                   Do not color the output
+         --color-moved=<mode>         Color moved lines apart from removed and added ones, as \
+                         plain, blocks or zebra. --color-moved alone uses zebra
+         -M <n>, --find-renames=<n>   Detect renames of files at least <n> similar, as 50% or 5, \
+                         -M alone uses 50%. Renames are detected by default
+         -C <n>, --find-copies=<n>    Also detect copies of changed files, implies -M
+         --find-copies-harder         Detect copies of unchanged files too, implies -C
+         --no-renames                 Do not detect renames
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub arg_commit:              Vec<String>,
+	pub arg_path:                Vec<PathBuf>,
+	pub flag_cached:             bool,
+	pub flag_patch:              bool,
+	pub flag_raw:                bool,
+	pub flag_name_status:        bool,
+	pub flag_name_only:          bool,
+	pub flag_numstat:            bool,
+	pub flag_stat:               bool,
+	pub flag_shortstat:          bool,
+	pub flag_word_diff:          Option<String>,
+	pub flag_binary:             bool,
+	pub flag_color:              String,
+	pub flag_no_color:           bool,
+	pub flag_color_moved:        Option<String>,
+	pub flag_find_renames:       Option<String>,
+	pub flag_find_copies:        Option<String>,
+	pub flag_find_copies_harder: bool,
+	pub flag_no_renames:         bool,
+}
+
+// Some option values are optional, which docopt cannot express, so bare
+// options are given their default value. Scores of -M and -C are left empty
+fn with_values(argv: &[String]) -> Vec<String> {
+	argv.iter()
+		.map(|arg| match arg.as_str() {
+			"-M" | "--find-renames" => "--find-renames=".to_owned(),
+			"-C" | "--find-copies" => "--find-copies=".to_owned(),
+			"--word-diff" => "--word-diff=plain".to_owned(),
+			"--color" => "--color=always".to_owned(),
+			"--color-moved" => "--color-moved=default".to_owned(),
+			arg => arg.to_owned(),
+		})
+		.collect()
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		let argv = argv_it
+			.into_iter()
+			.map(|arg| arg.as_ref().to_owned())
+			.collect::<Vec<_>>();
+		// Docopt would take paths after "--" for commits, so they are split
+		// off before
+		let (argv, paths) = match argv.iter().position(|arg| arg == "--") {
+			Some(i) => (&argv[..i], &argv[i + 1..]),
+			None => (&argv[..], &[][..]),
+		};
+		let mut args: Args = Docopt::new(USAGE)
+			.and_then(|d| d.argv(with_values(argv)).deserialize())
+			.unwrap_or_else(|e| e.exit());
+		args.arg_path = paths.iter().map(PathBuf::from).collect();
+		args
+	}
+}
@@ -0,0 +1,3 @@
+pub mod fetch_cmd;
+pub mod fetch_do;
+mod fetch_parse;
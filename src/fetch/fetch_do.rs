@@ -0,0 +1,216 @@
+use crate::tools::{
+	config::Config,
+	db::ObjectRepr,
+	path::{objects_dir_required, vcs_path_required},
+	refs::{current_branch, read_ref, update_ref, RefValue, FETCH_HEAD},
+	remote::{
+		default_remote_name, parse_refspecs, resolve_remote, Remote, HEADS_PREFIX, TAGS_PREFIX,
+	},
+	rev::is_safe_ref_name,
+	transport::{classify_update, RefUpdate, RemoteRefs, Source, UpdateStatus},
+};
+use std::{error::Error, fs};
+
+// A ref of the remote and the local ref it goes to, if any
+type Matched = (String, Option<String>, ObjectRepr, bool);
+
+// Fetches from a remote, or a repository path or URL, the objects the refs
+// matching the refspecs need, and updates the local refs they map to. Tags of
+// the remote that point into the fetched history are fetched as well. All
+// fetched refs are listed in FETCH_HEAD, those of refspecs without
+// destination only there. Refs requested explicitly are the ones to merge,
+// else the upstream of the current branch or the branch HEAD of the remote
+// points to
+pub fn fetch(
+	remote: Option<&str>,
+	refspecs: &[String],
+	force: bool,
+) -> Result<Vec<RefUpdate>, Box<dyn Error>> {
+	let remote = resolve_remote(&match remote {
+		Some(remote) => remote.to_owned(),
+		None => default_remote_name()?,
+	})?;
+	let explicit = !refspecs.is_empty();
+	let refspecs = if refspecs.is_empty() {
+		remote.fetch.clone()
+	} else {
+		parse_refspecs(refspecs)?
+	};
+	if refspecs.is_empty() {
+		return Err(format!("no refspecs to fetch from {}", remote.url).into());
+	}
+	let mut source = Source::open(&remote.url)?;
+	let advertised = source.refs()?;
+	let names = advertised
+		.refs
+		.iter()
+		.map(|(name, _)| name.as_str())
+		.collect::<Vec<_>>();
+	let refspecs = refspecs
+		.iter()
+		.map(|refspec| refspec.qualify(&names))
+		.collect::<Vec<_>>();
+	if let Some(missing) = refspecs
+		.iter()
+		.find(|r| !r.is_wildcard() && !names.contains(&r.src.as_str()))
+	{
+		return Err(format!("couldn't find remote ref {}", missing.src).into());
+	}
+
+	// (remote ref, local ref, object, forced)
+	let mut matched: Vec<Matched> = Vec::new();
+	for (name, object_repr) in &advertised.refs {
+		for refspec in &refspecs {
+			if refspec.matches(name) {
+				let dst = refspec.map(name);
+				// Both become paths, refs of other repositories are not trusted
+				for checked in Some(name).into_iter().chain(&dst) {
+					if !is_safe_ref_name(checked) {
+						return Err(format!("refusing the unsafe ref name {}", checked).into());
+					}
+				}
+				matched.push((
+					name.clone(),
+					dst,
+					object_repr.clone(),
+					refspec.force || force,
+				));
+				break;
+			}
+		}
+	}
+	let wants = matched
+		.iter()
+		.map(|(_, _, object_repr, _)| object_repr.clone())
+		.collect::<Vec<_>>();
+	source.fetch_objects(&wants, true)?;
+
+	// Follow tags whose target is now present
+	let local_objects = objects_dir_required()?;
+	let mut tags = Vec::new();
+	for (name, object_repr) in &advertised.refs {
+		if !name.starts_with(TAGS_PREFIX)
+			|| read_ref(name)?.is_some()
+			|| matched
+				.iter()
+				.any(|(_, dst, _, _)| dst.as_deref() == Some(name.as_str()))
+		{
+			continue;
+		}
+		let target = advertised.peeled.get(name).unwrap_or(object_repr);
+		if local_objects.join(target.hash()).is_file() {
+			tags.push(object_repr.clone());
+			matched.push((name.clone(), Some(name.clone()), object_repr.clone(), false));
+		}
+	}
+	source.fetch_objects(&tags, true)?;
+
+	let merge = if explicit {
+		refspecs
+			.iter()
+			.filter(|refspec| !refspec.is_wildcard())
+			.map(|refspec| refspec.src.clone())
+			.collect()
+	} else {
+		default_merge(&remote, &advertised)?
+			.into_iter()
+			.collect::<Vec<_>>()
+	};
+	let label = remote.name.as_deref().unwrap_or(&remote.url);
+	write_fetch_head(&matched, &merge, &remote.url)?;
+	let current = current_branch()?;
+	let mut updates = Vec::new();
+	for (src, dst, new, force) in matched {
+		let dst = match dst {
+			Some(dst) => dst,
+			None => {
+				updates.push(RefUpdate {
+					src,
+					dst: FETCH_HEAD.to_owned(),
+					old: None,
+					new: Some(new),
+					status: UpdateStatus::Fetched,
+				});
+				continue;
+			},
+		};
+		let old = read_ref(&dst)?;
+		let status = if current.as_deref() == Some(dst.as_str()) && old.as_ref() != Some(&new) {
+			UpdateStatus::Rejected("refusing to fetch into the current branch".to_owned())
+		} else {
+			classify_update(&dst, old.as_ref(), Some(&new), force)?
+		};
+		apply_update(&dst, &new, &status, label)?;
+		updates.push(RefUpdate {
+			src,
+			dst,
+			old,
+			new: Some(new),
+			status,
+		});
+	}
+	Ok(updates)
+}
+
+// The remote ref to merge when fetching with the configured refspecs
+fn default_merge(
+	remote: &Remote,
+	advertised: &RemoteRefs,
+) -> Result<Option<String>, Box<dyn Error>> {
+	let config = Config::read()?;
+	let upstream = current_branch()?
+		.as_deref()
+		.and_then(|branch| branch.strip_prefix(HEADS_PREFIX))
+		.and_then(|branch| config.branch.get(branch))
+		.filter(|upstream| upstream.remote.is_some() && upstream.remote == remote.name)
+		.and_then(|upstream| upstream.merge.clone());
+	Ok(upstream.or_else(|| match &advertised.head {
+		Some(RefValue::Symbolic(target)) => Some(target.clone()),
+		_ => None,
+	}))
+}
+
+// Like git: the refs to merge first, then the others marked not-for-merge.
+// Lines say what was fetched from where
+fn write_fetch_head(
+	matched: &[Matched],
+	merge: &[String],
+	url: &str,
+) -> Result<(), Box<dyn Error>> {
+	let mut lines = matched
+		.iter()
+		.map(|(src, _, object_repr, _)| {
+			let what = if let Some(branch) = src.strip_prefix(HEADS_PREFIX) {
+				format!("branch '{}'", branch)
+			} else if let Some(tag) = src.strip_prefix(TAGS_PREFIX) {
+				format!("tag '{}'", tag)
+			} else {
+				format!("'{}'", src)
+			};
+			let not_for_merge = !merge.contains(src);
+			let flag = if not_for_merge { "not-for-merge" } else { "" };
+			(
+				not_for_merge,
+				format!("{}\t{}\t{} of {}\n", object_repr, flag, what, url),
+			)
+		})
+		.collect::<Vec<_>>();
+	lines.sort_by_key(|(not_for_merge, _)| *not_for_merge);
+	let content = lines.into_iter().map(|(_, line)| line).collect::<String>();
+	Ok(fs::write(vcs_path_required(FETCH_HEAD)?, content)?)
+}
+
+fn apply_update(
+	dst: &str,
+	new: &ObjectRepr,
+	status: &UpdateStatus,
+	label: &str,
+) -> Result<(), Box<dyn Error>> {
+	let what = match status {
+		UpdateStatus::New => "storing head",
+		UpdateStatus::FastForward => "fast-forward",
+		UpdateStatus::Forced => "forced-update",
+		_ => return Ok(()),
+	};
+	Ok(update_ref(dst, new, &format!("fetch {}: {}", label, what))?)
+}
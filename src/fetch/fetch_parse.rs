@@ -0,0 +1,34 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty fetch [-f] [<remote> [<refspec>...]]
+         unrusty fetch --help
+
+Options:
+         <remote>                     Name of a remote or path of a repository, defaults to the \
+                         remote of the current branch or origin
+         <refspec>                    Refs to fetch, e.g. +refs/heads/*:refs/remotes/origin/*, \
+                         defaults to the refspecs of the remote
+         -f, --force                  Update refs even if the update is not a fast-forward
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub arg_remote:  Option<String>,
+	pub arg_refspec: Vec<String>,
+	pub flag_force:  bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
@@ -0,0 +1,3 @@
+pub mod format_patch_cmd;
+pub mod format_patch_do;
+mod format_patch_parse;
@@ -0,0 +1,41 @@
+use super::{format_patch_do::format_patch, format_patch_parse::Args};
+use crate::tools::failed::failed;
+use std::{
+	fs,
+	io::{self, Write},
+	path::PathBuf,
+};
+
+pub fn cmd_format_patch<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	let patches = format_patch(&args.arg_revision_range)
+		.unwrap_or_else(|e| failed(&format!("Failed format-patch: {:?}", e)));
+	if args.flag_stdout {
+		let mut stdout = io::stdout();
+		for patch in &patches {
+			stdout
+				.write_all(&patch.mail)
+				.unwrap_or_else(|e| failed(&format!("Failed format-patch: {:?}", e)));
+		}
+		return;
+	}
+	let dir = args.flag_output_directory.map(PathBuf::from);
+	if let Some(dir) = &dir {
+		fs::create_dir_all(dir)
+			.unwrap_or_else(|e| failed(&format!("Failed format-patch: {:?}", e)));
+	}
+	for patch in &patches {
+		let path = match &dir {
+			Some(dir) => dir.join(&patch.file_name),
+			None => PathBuf::from(&patch.file_name),
+		};
+		fs::write(&path, &patch.mail)
+			.unwrap_or_else(|e| failed(&format!("Failed format-patch: {:?}", e)));
+		println!("{}", path.display());
+	}
+}
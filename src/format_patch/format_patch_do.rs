@@ -0,0 +1,120 @@
+use crate::tools::{
+	commit::Commit,
+	db::ObjectRepr,
+	mail::{format_mail, split_message},
+	merge::commits_between,
+	patch::{diffstat, file_stats, summary, tree_changes, write_patches, Blobs, PatchOptions},
+	refs::HEAD,
+	rename::{detect_renames, RenameOptions},
+	rev::{peel_to_commit, resolve_revision},
+	tree::{flatten_tree, FlatTree},
+};
+use std::error::Error;
+
+// Diffstats of mails are narrower than those of terminals
+const MAIL_STAT_WIDTH: usize = 72;
+// File names of patches, with their number and suffix
+const FILE_NAME_LEN: usize = 64;
+const SUFFIX: &str = ".patch";
+
+pub struct PatchMail {
+	pub file_name: String,
+	pub mail:      Vec<u8>,
+}
+
+// "0001-Subject-of-the-commit.patch": letters, digits, dots and underscores
+// of the subject, with dashes for what is between them
+fn file_name(number: usize, subject: &str) -> String {
+	let prefix = format!("{:04}-", number);
+	let mut name = String::new();
+	let mut dash = false;
+	for c in subject.chars() {
+		if c.is_ascii_alphanumeric() || c == '_' || (c == '.' && !name.ends_with('.')) {
+			if dash && !name.is_empty() {
+				name.push('-');
+			}
+			name.push(c);
+			dash = false;
+		} else if c != '.' {
+			dash = true;
+		}
+	}
+	let name = name.trim_start_matches('.');
+	let max = FILE_NAME_LEN - prefix.len() - SUFFIX.len();
+	let name = &name[..name.len().min(max)];
+	format!("{}{}{}", prefix, name.trim_end_matches(['.', '-']), SUFFIX)
+}
+
+// The commits of the range, merges left out as they cannot be mailed
+fn range_commits(range: &str) -> Result<Vec<ObjectRepr>, Box<dyn Error>> {
+	let (from, to) = range.split_once("..").unwrap_or((range, HEAD));
+	let resolve = |rev: &str| -> Result<ObjectRepr, Box<dyn Error>> {
+		let rev = if rev.is_empty() { HEAD } else { rev };
+		Ok(peel_to_commit(&resolve_revision(rev)?)?)
+	};
+	let mut commits = Vec::new();
+	for commit in commits_between(&[resolve(from)?], &resolve(to)?)? {
+		if Commit::read(&commit)?.parents.len() <= 1 {
+			commits.push(commit);
+		}
+	}
+	Ok(commits)
+}
+
+// The diffstat, summary and patch of a commit against its parent, as put
+// after the message of its mail
+fn commit_patch(commit: &Commit) -> Result<Vec<u8>, Box<dyn Error>> {
+	let old = match commit.parents.first() {
+		Some(parent) => flatten_tree(&Commit::read(parent)?.tree)?,
+		None => FlatTree::new(),
+	};
+	let new = flatten_tree(&commit.tree)?;
+	let blobs = Blobs::new();
+	let changes = detect_renames(
+		tree_changes(&old, &new),
+		&old,
+		&blobs,
+		&RenameOptions::default(),
+	)?;
+	let mut patch = Vec::new();
+	if !changes.is_empty() {
+		let stats = file_stats(&changes, &blobs)?;
+		patch.extend(diffstat(&stats, MAIL_STAT_WIDTH, false).into_bytes());
+		patch.extend(summary(&changes).into_bytes());
+		patch.push(b'\n');
+		let options = PatchOptions {
+			binary: true,
+			..PatchOptions::default()
+		};
+		patch.extend(write_patches(&changes, &blobs, &options)?);
+	}
+	patch.extend(format!("-- \nunrusty {}\n\n", env!("CARGO_PKG_VERSION")).into_bytes());
+	Ok(patch)
+}
+
+// Every commit of the range as a mail, numbered when there are several
+pub fn format_patch(range: &str) -> Result<Vec<PatchMail>, Box<dyn Error>> {
+	let commits = range_commits(range)?;
+	let total = commits.len();
+	let mut patches = Vec::new();
+	for (i, hash) in commits.iter().enumerate() {
+		let commit = Commit::read(hash)?;
+		let prefix = if total == 1 {
+			"PATCH".to_owned()
+		} else {
+			format!("PATCH {}/{}", i + 1, total)
+		};
+		let (subject, _) = split_message(&commit.message);
+		patches.push(PatchMail {
+			file_name: file_name(i + 1, &subject),
+			mail:      format_mail(
+				hash.hash(),
+				&commit.author,
+				&commit.message,
+				&prefix,
+				&commit_patch(&commit)?,
+			),
+		});
+	}
+	Ok(patches)
+}
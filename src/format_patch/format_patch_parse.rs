@@ -0,0 +1,34 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty format-patch [-o <dir>] [--stdout] <revision-range>
+         unrusty format-patch --help
+
+Options:
+         <revision-range>             Commits to format, a..b for those of b not in a, or a alone \
+                         for those of HEAD not in a
+         -o <dir>, --output-directory=<dir>  Write the patches in this directory, the current one \
+                         by default
+         --stdout                     Write all patches to the standard output as one mbox
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub arg_revision_range:    String,
+	pub flag_output_directory: Option<String>,
+	pub flag_stdout:           bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
@@ -0,0 +1,3 @@
+pub mod fsck_cmd;
+pub mod fsck_do;
+mod fsck_parse;
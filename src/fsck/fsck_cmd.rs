@@ -0,0 +1,39 @@
+use super::{fsck_do::fsck, fsck_parse::Args};
+use crate::tools::failed::failed;
+use log::error;
+use std::process::exit;
+
+pub fn cmd_fsck<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+	let report = fsck().unwrap_or_else(|e| failed(&format!("Failed fsck: {:?}", e)));
+
+	for corruption in &report.corrupt {
+		error!("{}: {}", corruption.object, corruption.msg);
+	}
+	for (object_type, object_repr) in &report.missing {
+		match object_type {
+			Some(object_type) => println!("missing {} {}", object_type, object_repr),
+			None => println!("missing object {}", object_repr),
+		}
+	}
+	for unreachable in &report.unreachable {
+		if args.flag_unreachable {
+			println!(
+				"unreachable {} {}",
+				unreachable.object_type, unreachable.object
+			);
+		} else if unreachable.dangling && !args.flag_no_dangling {
+			println!(
+				"dangling {} {}",
+				unreachable.object_type, unreachable.object
+			);
+		}
+	}
+	if !report.is_ok() {
+		exit(1);
+	}
+}
@@ -0,0 +1,120 @@
+use crate::tools::{
+	commit::{is_valid_signature, Commit},
+	db::{get_object_repr, list_objects, parse_store, read_store, Object, ObjectRepr, ObjectType},
+	reachable::{reachable_from, references, roots, Reference},
+	tag::Tag,
+	tree::Tree,
+};
+use std::{collections::BTreeSet, error::Error};
+
+pub struct Corruption {
+	pub object: ObjectRepr,
+	pub msg:    String,
+}
+
+pub struct Unreachable {
+	pub object_type: ObjectType,
+	pub object:      ObjectRepr,
+	// Not referenced by any other object either, i.e. the tip of what is lost
+	pub dangling:    bool,
+}
+
+pub struct FsckReport {
+	pub corrupt:     Vec<Corruption>,
+	pub missing:     Vec<Reference>,
+	pub unreachable: Vec<Unreachable>,
+}
+
+impl FsckReport {
+	// Unreachable objects are harmless, gc removes them
+	pub fn is_ok(&self) -> bool {
+		self.corrupt.is_empty() && self.missing.is_empty()
+	}
+}
+
+// Reads the object file and verifies that it hashes to its name and parses
+fn check_object(object_repr: &ObjectRepr) -> Result<Object, String> {
+	let store = read_store(object_repr).map_err(|e| format!("unable to read: {:?}", e))?;
+	let actual = get_object_repr(&store);
+	if actual != *object_repr {
+		return Err(format!("hash mismatch, content hashes to {}", actual));
+	}
+	let object = parse_store(&store, false).map_err(|e| format!("invalid header: {:?}", e))?;
+	check_syntax(&object)?;
+	Ok(object)
+}
+
+fn check_syntax(object: &Object) -> Result<(), String> {
+	match object.object_type {
+		ObjectType::Tree => {
+			let tree = Tree::parse(&object.data).map_err(|e| e.to_string())?;
+			if !tree.is_sorted() {
+				return Err("tree entries not properly sorted or duplicated".to_owned());
+			}
+		},
+		ObjectType::Commit => {
+			if !object.data.starts_with(b"tree ") {
+				return Err("commit does not start with a tree".to_owned());
+			}
+			let commit = Commit::parse(&object.data).map_err(|e| e.to_string())?;
+			check_signature("author", &commit.author)?;
+			check_signature("committer", &commit.committer)?;
+		},
+		ObjectType::Tag => {
+			let tag = Tag::parse(&object.data).map_err(|e| e.to_string())?;
+			if let Some(tagger) = tag.tagger {
+				check_signature("tagger", &tagger)?;
+			}
+		},
+		_ => {},
+	}
+	Ok(())
+}
+
+fn check_signature(field: &str, signature: &str) -> Result<(), String> {
+	if is_valid_signature(signature) {
+		Ok(())
+	} else {
+		Err(format!("invalid {} {:?}", field, signature))
+	}
+}
+
+// Only loose objects are checked, there are no packs yet
+pub fn fsck() -> Result<FsckReport, Box<dyn Error>> {
+	let mut corrupt = Vec::new();
+	let mut valid = Vec::new();
+	let mut referenced = BTreeSet::new();
+	for object_repr in list_objects()? {
+		match check_object(&object_repr) {
+			Ok(object) => {
+				referenced.extend(references(&object).into_iter().map(|(_, r)| r));
+				valid.push((object_repr, object.object_type));
+			},
+			Err(msg) => corrupt.push(Corruption {
+				object: object_repr,
+				msg,
+			}),
+		}
+	}
+
+	let reachability = reachable_from(roots()?)?;
+	let unreachable = valid
+		.into_iter()
+		.filter(|(object_repr, _)| !reachability.reachable.contains(object_repr))
+		.map(|(object_repr, object_type)| Unreachable {
+			object_type,
+			dangling: !referenced.contains(&object_repr),
+			object: object_repr,
+		})
+		.collect();
+
+	Ok(FsckReport {
+		corrupt,
+		missing: reachability
+			.missing
+			.into_iter()
+			.map(|(r, t)| (t, r))
+			.collect(),
+		unreachable,
+	})
+}
@@ -0,0 +1,31 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty fsck [--unreachable] [--no-dangling]
+         unrusty fsck --help
+
+Options:
+         --unreachable                Show all objects not reachable from refs, the index or HEAD, \
+                         not only the dangling ones
+         --no-dangling                Do not show dangling objects
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub flag_unreachable: bool,
+	pub flag_no_dangling: bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
@@ -0,0 +1,3 @@
+pub mod gc_cmd;
+pub mod gc_do;
+mod gc_parse;
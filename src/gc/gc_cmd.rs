@@ -0,0 +1,25 @@
+use super::{gc_do::gc, gc_parse::Args};
+use crate::tools::{date::parse_date, failed::failed};
+
+// Like git's gc.reflogExpire
+const REFLOG_EXPIRE: &str = "90.days.ago";
+
+pub fn cmd_gc<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+	let prune_expire = if args.flag_no_prune {
+		None
+	} else {
+		Some(
+			parse_date(&args.flag_prune)
+				.unwrap_or_else(|| failed(&format!("Invalid date {:?}", args.flag_prune))),
+		)
+	};
+
+	let reflog_expire = parse_date(REFLOG_EXPIRE).unwrap();
+
+	gc(reflog_expire, prune_expire).unwrap_or_else(|e| failed(&format!("Failed gc: {:?}", e)));
+}
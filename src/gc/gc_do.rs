@@ -0,0 +1,24 @@
+use crate::{
+	pack_refs::pack_refs_do::pack_refs,
+	prune::prune_do::prune,
+	tools::reflog::{expire_reflog, list_reflogs},
+};
+use std::{error::Error, time::SystemTime};
+
+// Housekeeping of the repository. All refs are packed, and reflog entries
+// older than `reflog_expire` are removed, so that the objects only they kept
+// alive can be pruned. Objects are only stored loose, so there is nothing to
+// repack, and unreachable objects older than `prune_expire` are removed
+pub fn gc(
+	reflog_expire: SystemTime,
+	prune_expire: Option<SystemTime>,
+) -> Result<(), Box<dyn Error>> {
+	pack_refs(true, true)?;
+	for name in list_reflogs()? {
+		expire_reflog(&name, reflog_expire, false)?;
+	}
+	if let Some(expire) = prune_expire {
+		prune(expire, false)?;
+	}
+	Ok(())
+}
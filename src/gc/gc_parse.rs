@@ -0,0 +1,31 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty gc [--prune=<date> | --no-prune]
+         unrusty gc --help
+
+Options:
+         --prune=<date>               Remove unreachable objects older than the date [default: \
+                         2.weeks.ago]
+         --no-prune                   Do not remove any unreachable objects
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub flag_prune:    String,
+	pub flag_no_prune: bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
@@ -1,11 +1,14 @@
 use super::hash_object_parse::Args;
 use crate::tools::{
-	db::{insert_file_with_simulate, insert_with_simulate, DbError, ObjectRepr},
+	db::{insert_with_simulate, DbError, ObjectRepr, ObjectType},
 	failed::failed,
+	filter::Filters,
+	path::RelativePathToBase,
 };
 use std::{
+	fs,
 	io::{self, BufRead, Read},
-	path::PathBuf,
+	path::{Path, PathBuf},
 };
 
 fn extract_file_paths(args: &Args) -> Vec<PathBuf> {
@@ -34,6 +37,23 @@ fn handle_hash_object_result(result: Result<ObjectRepr, DbError>) {
 	}
 }
 
+// Blobs are hashed as they would be stored, through the clean filters of
+// --path or of the file itself
+fn filtered(content: Vec<u8>, path: Option<&Path>, args: &Args) -> Vec<u8> {
+	let path = match args.flag_path.as_deref().or(path) {
+		Some(path) if !args.flag_no_filters && args.flag_type == ObjectType::Blob => path,
+		_ => return content,
+	};
+	// Outside of a repository there are no attributes to filter with
+	let path = match RelativePathToBase::from_user_path(path) {
+		Ok(path) => path,
+		Err(_) => return content,
+	};
+	Filters::read()
+		.and_then(|filters| filters.clean(&path, content))
+		.unwrap_or_else(|e| failed(&format!("failed hash-object {:?}", e)))
+}
+
 pub fn cmd_hash_object<'a, I, J>(argv_it: I)
 where
 	I: IntoIterator<Item = &'a J>,
@@ -48,13 +68,17 @@ where
 		io::stdin()
 			.read_to_end(&mut content)
 			.unwrap_or_else(|e| failed(&format!("Failed to read content from stdin {:?}", e)));
+		let content = filtered(content, None, &args);
 		let result = insert_with_simulate(&content, object_type, !write);
 		handle_hash_object_result(result);
 	}
 
 	let file_paths = extract_file_paths(&args);
 	for path in &file_paths {
-		let result = insert_file_with_simulate(&path, object_type, !write);
+		let content =
+			fs::read(path).unwrap_or_else(|e| failed(&format!("failed hash-object {:?}", e)));
+		let content = filtered(content, Some(path), &args);
+		let result = insert_with_simulate(&content, object_type, !write);
 		handle_hash_object_result(result);
 	}
 }
@@ -4,8 +4,9 @@ use serde::Deserialize;
 use std::path::PathBuf;
 
 pub const USAGE: &str = "
-Usage:   unrusty hash-object [-t <type>] [-w] [--stdin] [--] <file>...
-         unrusty hash-object [-t <type>] [-w] --stdin-paths [--no-filters]
+Usage:   unrusty hash-object [-t <type>] [-w] [--no-filters | --path=<path>] [--stdin] [--] \
+                         [<file>...]
+         unrusty hash-object [-t <type>] [-w] [--no-filters | --path=<path>] --stdin-paths
          unrusty hash-object --help
 
 Options:
@@ -13,6 +14,9 @@ Options:
          -w, --write                  write object
          --stdin-paths                Reads file paths from stdin, one per line 
          --stdin                      Read content to hash from stdin
+         --no-filters                 Hash the content as it is, without the clean filters the \
+                         attributes of the file ask for
+         --path=<path>                Filter the content as if it was the file at this path
          -h, --help                   Shows this help message
 ";
 
@@ -24,6 +28,8 @@ pub struct Args {
 	pub flag_type:        ObjectType,
 	pub flag_stdin:       bool,
 	pub flag_stdin_paths: bool,
+	pub flag_no_filters:  bool,
+	pub flag_path:        Option<PathBuf>,
 }
 
 impl Args {
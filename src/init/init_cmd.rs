@@ -1,4 +1,5 @@
 use super::{init_do::init, init_parse::Args};
+use crate::tools::object_format::ObjectFormat;
 use log::error;
 
 pub fn cmd_init<'a, I, J>(argv_it: I)
@@ -9,7 +10,14 @@ where
 	let args = Args::from_cmd(argv_it);
 	let force_rewrite = args.flag_force;
 
-	if let Err(e) = init(force_rewrite) {
+	let object_format = match ObjectFormat::from_name(&args.flag_object_format) {
+		Ok(object_format) => object_format,
+		Err(e) => {
+			error!("Failed init: {:?}", e);
+			return;
+		},
+	};
+	if let Err(e) = init(force_rewrite, object_format) {
 		error!("Failed init: {:?}", e);
 	}
 }
@@ -1,11 +1,13 @@
 use crate::tools::{
 	index::Index,
+	object_format::ObjectFormat,
 	path::{root_path, vcs_dir_from_base},
+	refs::{write_symbolic_ref, DEFAULT_BRANCH, HEAD},
 };
 use log::{info, warn};
-use std::{env, error::Error, fs};
+use std::{env, error::Error, fs, path::Path};
 
-pub fn init(force_rewrite: bool) -> Result<(), Box<dyn Error>> {
+pub fn init(force_rewrite: bool, object_format: ObjectFormat) -> Result<(), Box<dyn Error>> {
 	let root = root_path()?;
 
 	let base = match root.as_ref() {
@@ -22,9 +24,19 @@ pub fn init(force_rewrite: bool) -> Result<(), Box<dyn Error>> {
 	if root.is_some() && !force_rewrite {
 		warn!("repository already exists, use --force to reset");
 	} else {
-		fs::create_dir_all(&vcs_dir)?;
-		Index::create()?;
+		init_at(&base, object_format)?;
 		info!("created new repository at {:?}", base);
 	}
 	Ok(())
 }
+
+// Creates an empty repository in `base`, even if `base` is inside another
+// repository. The current directory must be `base`. The object format is
+// recorded first, as it decides how anything in the repository is hashed
+pub fn init_at(base: &Path, object_format: ObjectFormat) -> Result<(), Box<dyn Error>> {
+	fs::create_dir_all(vcs_dir_from_base(base))?;
+	object_format.write()?;
+	Index::create()?;
+	write_symbolic_ref(HEAD, DEFAULT_BRANCH)?;
+	Ok(())
+}
@@ -2,18 +2,21 @@ use docopt::Docopt;
 use serde::Deserialize;
 
 pub const USAGE: &str = "
-Usage:   unrusty init [--force]
+Usage:   unrusty init [--force] [--object-format=<format>]
          unrusty init --help
 
 Options:
          --force                      If the current location is in a repo, reset the VCS instead \
                          of doing nothing
+         --object-format=<format>     Hash function naming the objects, sha1 or sha256 [default: \
+                         sha1]
          -h, --help                   Shows this help message
 ";
 
 #[derive(Deserialize, Debug)]
 pub struct Args {
-	pub flag_force: bool,
+	pub flag_force:         bool,
+	pub flag_object_format: String,
 }
 
 impl Args {
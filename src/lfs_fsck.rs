@@ -0,0 +1,3 @@
+pub mod lfs_fsck_cmd;
+pub mod lfs_fsck_do;
+mod lfs_fsck_parse;
@@ -0,0 +1,26 @@
+use super::{lfs_fsck_do::lfs_fsck, lfs_fsck_parse::Args};
+use crate::tools::failed::failed;
+use log::error;
+use std::process::exit;
+
+pub fn cmd_lfs_fsck<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	Args::from_cmd(argv_it);
+	let report = lfs_fsck().unwrap_or_else(|e| failed(&format!("Failed lfs-fsck: {:?}", e)));
+
+	for corruption in &report.corrupt {
+		error!("{}: {}", corruption.oid, corruption.msg);
+	}
+	for pointer in &report.missing {
+		println!(
+			"missing large file {} ({} bytes)",
+			pointer.oid, pointer.size
+		);
+	}
+	if !report.is_ok() {
+		exit(1);
+	}
+}
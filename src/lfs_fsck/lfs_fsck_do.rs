@@ -0,0 +1,56 @@
+use crate::tools::lfs::{lfs_hash, lfs_object_path, list_lfs_objects, reachable_pointers, Pointer};
+use std::{error::Error, fs};
+
+pub struct LfsCorruption {
+	pub oid: String,
+	pub msg: String,
+}
+
+pub struct LfsFsckReport {
+	pub corrupt: Vec<LfsCorruption>,
+	// Reachable pointers whose content is not in the store
+	pub missing: Vec<Pointer>,
+}
+
+impl LfsFsckReport {
+	pub fn is_ok(&self) -> bool {
+		self.corrupt.is_empty() && self.missing.is_empty()
+	}
+}
+
+// Verifies that every large file in the store hashes to its name and has the
+// size its pointers say, and that every reachable pointer has its content
+pub fn lfs_fsck() -> Result<LfsFsckReport, Box<dyn Error>> {
+	let mut pointers = reachable_pointers()?;
+	let mut corrupt = Vec::new();
+	for oid in list_lfs_objects()? {
+		let pointer = pointers.remove(&oid);
+		let content = match fs::read(lfs_object_path(&oid)?) {
+			Ok(content) => content,
+			Err(e) => {
+				corrupt.push(LfsCorruption {
+					oid,
+					msg: format!("unable to read: {}", e),
+				});
+				continue;
+			},
+		};
+		let actual = lfs_hash(&content);
+		let msg = if actual != oid {
+			format!("hash mismatch, content hashes to {}", actual)
+		} else if let Some(pointer) = pointer.filter(|p| p.size != content.len() as u64) {
+			format!(
+				"size mismatch, {} bytes but pointers say {}",
+				content.len(),
+				pointer.size
+			)
+		} else {
+			continue;
+		};
+		corrupt.push(LfsCorruption { oid, msg });
+	}
+	Ok(LfsFsckReport {
+		corrupt,
+		missing: pointers.into_values().collect(),
+	})
+}
@@ -0,0 +1,3 @@
+pub mod lfs_prune_cmd;
+pub mod lfs_prune_do;
+mod lfs_prune_parse;
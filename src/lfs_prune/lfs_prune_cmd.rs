@@ -0,0 +1,21 @@
+use super::{lfs_prune_do::lfs_prune, lfs_prune_parse::Args};
+use crate::tools::failed::failed;
+
+pub fn cmd_lfs_prune<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	match lfs_prune(args.flag_dry_run) {
+		Ok(pruned) => {
+			if args.flag_verbose || args.flag_dry_run {
+				for oid in pruned {
+					println!("{}", oid);
+				}
+			}
+		},
+		Err(e) => failed(&format!("Failed lfs-prune: {:?}", e)),
+	}
+}
@@ -0,0 +1,20 @@
+use crate::tools::lfs::{lfs_object_path, list_lfs_objects, reachable_pointers};
+use std::{error::Error, fs};
+
+// Removes the large files that no reachable pointer refers to, as left by
+// files that were added again or commits that were dropped. Returns their
+// oids
+pub fn lfs_prune(dry_run: bool) -> Result<Vec<String>, Box<dyn Error>> {
+	let pointers = reachable_pointers()?;
+	let mut pruned = Vec::new();
+	for oid in list_lfs_objects()? {
+		if pointers.contains_key(&oid) {
+			continue;
+		}
+		if !dry_run {
+			fs::remove_file(lfs_object_path(&oid)?)?;
+		}
+		pruned.push(oid);
+	}
+	Ok(pruned)
+}
@@ -0,0 +1,3 @@
+pub mod ls_files_cmd;
+pub mod ls_files_do;
+mod ls_files_parse;
@@ -0,0 +1,37 @@
+use super::{
+	ls_files_do::{ls_files, ListOptions},
+	ls_files_parse::Args,
+};
+use crate::tools::failed::failed;
+
+pub fn cmd_ls_files<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+	let explicit = args.flag_modified || args.flag_deleted || args.flag_others;
+	if args.flag_ignored && !args.flag_cached && !args.flag_others {
+		failed("ls-files -i must be used with either -o or -c");
+	}
+
+	let options = ListOptions {
+		cached:   args.flag_cached || args.flag_unmerged || !explicit,
+		modified: args.flag_modified,
+		deleted:  args.flag_deleted,
+		others:   args.flag_others,
+		ignored:  args.flag_ignored,
+		stage:    args.flag_stage || args.flag_unmerged,
+		unmerged: args.flag_unmerged,
+	};
+	let terminator = if args.flag_z { '\0' } else { '\n' };
+
+	match ls_files(&options) {
+		Ok(lines) => {
+			for line in lines {
+				print!("{}{}", line, terminator);
+			}
+		},
+		Err(e) => failed(&format!("Failed ls-files: {:?}", e)),
+	}
+}
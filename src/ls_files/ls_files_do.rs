@@ -0,0 +1,72 @@
+use crate::tools::{
+	filter::Filters,
+	ignore::Ignore,
+	index::{Index, IndexEntry, IndexKey, MergeStatus},
+	worktree::list_worktree_files,
+};
+use std::{error::Error, fs};
+
+pub struct ListOptions {
+	pub cached:   bool,
+	pub modified: bool,
+	pub deleted:  bool,
+	pub others:   bool,
+	pub ignored:  bool,
+	pub stage:    bool,
+	pub unmerged: bool,
+}
+
+fn format_entry(key: &IndexKey, entry: &IndexEntry, stage: bool) -> String {
+	if stage {
+		format!(
+			"{} {} {}\t{}",
+			entry.mode().octal(),
+			entry.hash(),
+			key.stage.number(),
+			key.path
+		)
+	} else {
+		key.path.to_string()
+	}
+}
+
+// One line per file, in the format of git ls-files: "<path>" or, with stage,
+// "<mode> <object> <stage>\t<path>". Tracked files come first, in the order of
+// the index, a file is listed once for every option it matches
+pub fn ls_files(options: &ListOptions) -> Result<Vec<String>, Box<dyn Error>> {
+	let index = Index::read()?;
+	let ignore = Ignore::read()?;
+	let filters = Filters::read()?;
+	let mut lines = Vec::new();
+
+	for (key, entry) in index.entries() {
+		if options.unmerged && key.stage == MergeStatus::Regular {
+			continue;
+		}
+		if options.ignored && !ignore.is_ignored(&key.path, false) {
+			continue;
+		}
+		if options.cached {
+			lines.push(format_entry(key, entry, options.stage));
+		}
+		if options.deleted || options.modified {
+			let deleted = fs::symlink_metadata(key.path.absolute()?).is_err();
+			if options.deleted && deleted {
+				lines.push(format_entry(key, entry, options.stage));
+			}
+			if options.modified && (deleted || index.worktree_modified(&key.path, &filters)?) {
+				lines.push(format_entry(key, entry, options.stage));
+			}
+		}
+	}
+
+	if options.others {
+		let tracked = index.flat_tree();
+		for path in list_worktree_files()? {
+			if !tracked.contains_key(&path) && ignore.is_ignored(&path, false) == options.ignored {
+				lines.push(path.to_string());
+			}
+		}
+	}
+	Ok(lines)
+}
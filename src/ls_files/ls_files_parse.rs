@@ -0,0 +1,46 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty ls-files [-c] [-m] [-d] [-o] [-i] [-s | -u] [-z]
+         unrusty ls-files --help
+
+Options:
+         -c, --cached                 Show files in the index, the default without -m, -d and -o
+         -m, --modified               Show tracked files that differ from the index, including \
+                         deleted ones
+         -d, --deleted                Show tracked files missing in the working tree
+         -o, --others                 Show untracked files that are not ignored
+         -i, --ignored                Show only ignored files instead, needs -c or -o
+         -s, --stage                  Show mode, object and stage of each entry
+         -u, --unmerged               Show only conflicted entries, implies --stage
+         -z                           Terminate entries with NUL instead of newline
+         -h, --help                   Shows this help message
+
+Paths are relative to the repository root. Ignore rules are read from .gitignore and \
+                         .unrustyignore at the root.
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub flag_cached:   bool,
+	pub flag_modified: bool,
+	pub flag_deleted:  bool,
+	pub flag_others:   bool,
+	pub flag_ignored:  bool,
+	pub flag_stage:    bool,
+	pub flag_unmerged: bool,
+	pub flag_z:        bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
@@ -0,0 +1,3 @@
+pub mod ls_tree_cmd;
+pub mod ls_tree_do;
+mod ls_tree_parse;
@@ -0,0 +1,29 @@
+use super::{
+	ls_tree_do::{ls_tree, TreeListOptions},
+	ls_tree_parse::Args,
+};
+use crate::tools::failed::failed;
+
+pub fn cmd_ls_tree<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+	let options = TreeListOptions {
+		recursive:  args.flag_r,
+		show_trees: args.flag_t,
+		long:       args.flag_long,
+		name_only:  args.flag_name_only,
+	};
+	let terminator = if args.flag_z { '\0' } else { '\n' };
+
+	match ls_tree(&args.arg_tree_ish, &args.arg_path, &options) {
+		Ok(lines) => {
+			for line in lines {
+				print!("{}{}", line, terminator);
+			}
+		},
+		Err(e) => failed(&format!("Failed ls-tree: {:?}", e)),
+	}
+}
@@ -0,0 +1,88 @@
+use crate::tools::{
+	db::{read, ObjectRepr, ObjectType},
+	path::RelativePathToBase,
+	rev::{peel_to_tree, resolve_revision},
+	tree::{FileMode, Tree, TreeEntry},
+};
+use std::{
+	error::Error,
+	path::{Path, PathBuf},
+};
+
+pub struct TreeListOptions {
+	pub recursive:  bool,
+	pub show_trees: bool,
+	pub long:       bool,
+	pub name_only:  bool,
+}
+
+// "<mode> <type> <object>\t<path>" like git ls-tree, with the blob size
+// before the tab for --long
+fn format_entry(
+	entry: &TreeEntry,
+	path: &RelativePathToBase,
+	options: &TreeListOptions,
+) -> Result<String, Box<dyn Error>> {
+	if options.name_only {
+		return Ok(path.to_string());
+	}
+	let object_type = entry.mode.object_type();
+	let mode = format!("{:0>6}", entry.mode.octal());
+	if options.long {
+		let size = match object_type {
+			ObjectType::Blob => read(&entry.hash)?.data.len().to_string(),
+			_ => String::from("-"),
+		};
+		Ok(format!(
+			"{} {} {} {:>7}\t{}",
+			mode, object_type, entry.hash, size, path
+		))
+	} else {
+		Ok(format!("{} {} {}\t{}", mode, object_type, entry.hash, path))
+	}
+}
+
+fn list(
+	tree: &ObjectRepr,
+	prefix: &Path,
+	pathspecs: &[RelativePathToBase],
+	options: &TreeListOptions,
+	lines: &mut Vec<String>,
+) -> Result<(), Box<dyn Error>> {
+	for entry in Tree::read(tree)?.entries {
+		let path = RelativePathToBase::new(prefix.join(&entry.name));
+		let is_tree = entry.mode == FileMode::Directory;
+		let selected = pathspecs.is_empty() || pathspecs.iter().any(|p| p.contains(&path));
+		// Trees on the way to a path given on the command line are entered
+		let on_the_way = is_tree && pathspecs.iter().any(|p| path.contains(p) && path != *p);
+
+		if selected && !(is_tree && options.recursive) {
+			lines.push(format_entry(&entry, &path, options)?);
+		} else if (selected && is_tree) || on_the_way {
+			if options.show_trees {
+				lines.push(format_entry(&entry, &path, options)?);
+			}
+			list(&entry.hash, path.as_path(), pathspecs, options, lines)?;
+		}
+	}
+	Ok(())
+}
+
+pub fn ls_tree<'a, I, J>(
+	tree_ish: &str,
+	paths: I,
+	options: &TreeListOptions,
+) -> Result<Vec<String>, Box<dyn Error>>
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<Path> + 'a,
+{
+	let tree = peel_to_tree(&resolve_revision(tree_ish)?)?;
+	let pathspecs = paths
+		.into_iter()
+		.map(|p| RelativePathToBase::from_user_path(p.as_ref()))
+		.collect::<Result<Vec<_>, _>>()?;
+	let mut lines = Vec::new();
+	list(&tree, &PathBuf::new(), &pathspecs, options, &mut lines)?;
+	Ok(lines)
+}
@@ -0,0 +1,43 @@
+use docopt::Docopt;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "
+Usage:   unrusty ls-tree [-r] [-t] [-l] [--name-only] [-z] <tree-ish> [<path>...]
+         unrusty ls-tree --help
+
+Options:
+         <tree-ish>                   Tree, or commit whose tree is listed
+         <path>                       Only list entries at or below these paths
+         -r                           Recurse into subtrees
+         -t                           Show trees even when recursing into them
+         -l, --long                   Show the size of blobs
+         --name-only                  Only show paths
+         -z                           Terminate entries with NUL instead of newline
+         -h, --help                   Shows this help message
+
+Paths are relative to the repository root.
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub arg_tree_ish:   String,
+	pub arg_path:       Vec<PathBuf>,
+	pub flag_r:         bool,
+	pub flag_t:         bool,
+	pub flag_long:      bool,
+	pub flag_name_only: bool,
+	pub flag_z:         bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
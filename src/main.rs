@@ -1,13 +1,58 @@
+// Error variants are suffixed with Error throughout the crate
+#![allow(clippy::enum_variant_names)]
+
 mod add;
+mod am;
+mod apply;
+mod archive;
+mod bisect;
+mod blame;
+mod bundle;
 mod cat_file;
+mod cherry_pick;
+mod clone;
+mod diff;
+mod fetch;
+mod format_patch;
+mod fsck;
+mod gc;
 mod hash_object;
 mod init;
+mod lfs_fsck;
+mod lfs_prune;
+mod ls_files;
+mod ls_tree;
+mod merge;
+mod pack_refs;
+mod prune;
+mod push;
+mod rebase;
+mod receive_pack;
+mod reflog;
+mod remote;
+mod restore;
+mod revert;
+mod stash;
 mod tools;
+mod upload_pack;
 
 // Command imports
 use crate::{
-	add::add_cmd::cmd_add, cat_file::cat_file_cmd::cmd_cat_file,
+	add::add_cmd::cmd_add, am::am_cmd::cmd_am, apply::apply_cmd::cmd_apply,
+	archive::archive_cmd::cmd_archive, bisect::bisect_cmd::cmd_bisect, blame::blame_cmd::cmd_blame,
+	bundle::bundle_cmd::cmd_bundle, cat_file::cat_file_cmd::cmd_cat_file,
+	cherry_pick::cherry_pick_cmd::cmd_cherry_pick, clone::clone_cmd::cmd_clone,
+	diff::diff_cmd::cmd_diff, fetch::fetch_cmd::cmd_fetch,
+	format_patch::format_patch_cmd::cmd_format_patch, fsck::fsck_cmd::cmd_fsck, gc::gc_cmd::cmd_gc,
 	hash_object::hash_object_cmd::cmd_hash_object, init::init_cmd::cmd_init,
+	lfs_fsck::lfs_fsck_cmd::cmd_lfs_fsck, lfs_prune::lfs_prune_cmd::cmd_lfs_prune,
+	ls_files::ls_files_cmd::cmd_ls_files, ls_tree::ls_tree_cmd::cmd_ls_tree,
+	merge::merge_cmd::cmd_merge, pack_refs::pack_refs_cmd::cmd_pack_refs,
+	prune::prune_cmd::cmd_prune, push::push_cmd::cmd_push, rebase::rebase_cmd::cmd_rebase,
+	receive_pack::receive_pack_cmd::cmd_receive_pack, reflog::reflog_cmd::cmd_reflog,
+	remote::remote_cmd::cmd_remote, restore::restore_cmd::cmd_restore,
+	revert::revert_cmd::cmd_revert, stash::stash_cmd::cmd_stash,
+	upload_pack::upload_pack_cmd::cmd_upload_pack,
 };
 
 // Library function public imports
@@ -16,6 +61,32 @@ pub use crate::cat_file::cat_file_do::{
 	cat_file_type, cat_file_type_prepared,
 };
 
+pub use crate::am::am_do::{am, am_abort, am_continue, am_skip};
+
+pub use crate::apply::apply_do::apply;
+
+pub use crate::archive::archive_do::archive;
+
+pub use crate::bisect::bisect_do::{
+	bisect_log, bisect_mark, bisect_replay, bisect_reset, bisect_run, bisect_start,
+};
+
+pub use crate::blame::blame_do::{blame, format_blame};
+
+pub use crate::cherry_pick::cherry_pick_do::{
+	cherry_pick, replay_abort, replay_continue, replay_skip,
+};
+
+pub use crate::bundle::bundle_do::{
+	bundle_create, bundle_list_heads, bundle_unbundle, bundle_verify,
+};
+
+pub use crate::clone::clone_do::clone;
+
+pub use crate::diff::diff_do::diff;
+
+pub use crate::format_patch::format_patch_do::format_patch;
+
 pub use crate::tools::db::{
 	delete, insert, insert_file, insert_file_with_simulate, insert_with_simulate, read,
 	read_with_invalid, simulate_insert, simulate_insert_file,
@@ -23,17 +94,89 @@ pub use crate::tools::db::{
 
 pub use crate::tools::path::{db_path, index_path_required, root_path, root_path_required};
 
+pub use crate::fetch::fetch_do::fetch;
+
+pub use crate::fsck::fsck_do::fsck;
+
+pub use crate::gc::gc_do::gc;
+
 pub use crate::init::init_do::init;
 
+pub use crate::lfs_fsck::lfs_fsck_do::lfs_fsck;
+
+pub use crate::lfs_prune::lfs_prune_do::lfs_prune;
+
+pub use crate::ls_files::ls_files_do::ls_files;
+
+pub use crate::ls_tree::ls_tree_do::ls_tree;
+
+pub use crate::merge::merge_do::{merge, merge_abort, merge_continue};
+
+pub use crate::pack_refs::pack_refs_do::pack_refs;
+
+pub use crate::prune::prune_do::prune;
+
+pub use crate::push::push_do::push;
+
+pub use crate::rebase::rebase_do::{rebase, rebase_abort, rebase_continue, rebase_skip};
+
+pub use crate::receive_pack::receive_pack_do::receive_pack;
+
+pub use crate::reflog::reflog_do::{reflog_delete, reflog_expire, reflog_show};
+
+pub use crate::remote::remote_do::{remote_add, remote_list, remote_remove, remote_rename};
+
+pub use crate::restore::restore_do::restore;
+
+pub use crate::revert::revert_do::revert;
+
+pub use crate::stash::stash_do::{
+	stash_apply, stash_clear, stash_drop, stash_list, stash_pop, stash_push, stash_show,
+};
+
+pub use crate::upload_pack::upload_pack_do::upload_pack;
+
+pub use crate::tools::merge::{is_ancestor, merge_bases, merge_content, merge_trees};
+
 // Regular imports
+use crate::tools::{failed::failed, object_format::ObjectFormat};
 use log::{error, LevelFilter};
 use std::{env, iter::Iterator, path::Path};
 
 pub const USAGE: &str = "
 Commands:   unrusty hash-object
             unrusty cat-file
+            unrusty cherry-pick
             unrusty add
+            unrusty am
+            unrusty apply
+            unrusty archive
+            unrusty bisect
+            unrusty blame
+            unrusty bundle
+            unrusty clone
+            unrusty diff
+            unrusty fetch
+            unrusty format-patch
+            unrusty fsck
+            unrusty gc
             unrusty init
+            unrusty lfs-fsck
+            unrusty lfs-prune
+            unrusty ls-files
+            unrusty ls-tree
+            unrusty merge
+            unrusty pack-refs
+            unrusty prune
+            unrusty push
+            unrusty rebase
+            unrusty receive-pack
+            unrusty reflog
+            unrusty remote
+            unrusty restore
+            unrusty revert
+            unrusty stash
+            unrusty upload-pack
             unrusty help
 ";
 
@@ -66,6 +209,13 @@ fn main() {
 	let argv = retrieve_argv();
 
 	if let Some(command) = argv.get(1) {
+		// Objects of the repository can not be hashed without knowing how. Init
+		// may replace the config, so it does not need a readable one
+		if command != "init" {
+			if let Err(e) = ObjectFormat::load() {
+				failed(&format!("Failed reading the object format: {:?}", e));
+			}
+		}
 		match command.as_str() {
 			"hash-object" => {
 				cmd_hash_object(&argv);
@@ -73,12 +223,99 @@ fn main() {
 			"cat-file" => {
 				cmd_cat_file(&argv);
 			},
+			"cherry-pick" => {
+				cmd_cherry_pick(&argv);
+			},
 			"add" => {
 				cmd_add(&argv);
 			},
+			"am" => {
+				cmd_am(&argv);
+			},
+			"apply" => {
+				cmd_apply(&argv);
+			},
+			"archive" => {
+				cmd_archive(&argv);
+			},
+			"bisect" => {
+				cmd_bisect(&argv);
+			},
+			"blame" => {
+				cmd_blame(&argv);
+			},
+			"bundle" => {
+				cmd_bundle(&argv);
+			},
+			"diff" => {
+				cmd_diff(&argv);
+			},
+			"fetch" => {
+				cmd_fetch(&argv);
+			},
+			"format-patch" => {
+				cmd_format_patch(&argv);
+			},
+			"fsck" => {
+				cmd_fsck(&argv);
+			},
+			"gc" => {
+				cmd_gc(&argv);
+			},
+			"clone" => {
+				cmd_clone(&argv);
+			},
 			"init" => {
 				cmd_init(&argv);
 			},
+			"lfs-fsck" => {
+				cmd_lfs_fsck(&argv);
+			},
+			"lfs-prune" => {
+				cmd_lfs_prune(&argv);
+			},
+			"ls-files" => {
+				cmd_ls_files(&argv);
+			},
+			"ls-tree" => {
+				cmd_ls_tree(&argv);
+			},
+			"merge" => {
+				cmd_merge(&argv);
+			},
+			"pack-refs" => {
+				cmd_pack_refs(&argv);
+			},
+			"prune" => {
+				cmd_prune(&argv);
+			},
+			"push" => {
+				cmd_push(&argv);
+			},
+			"rebase" => {
+				cmd_rebase(&argv);
+			},
+			"receive-pack" => {
+				cmd_receive_pack(&argv);
+			},
+			"reflog" => {
+				cmd_reflog(&argv);
+			},
+			"remote" => {
+				cmd_remote(&argv);
+			},
+			"restore" => {
+				cmd_restore(&argv);
+			},
+			"revert" => {
+				cmd_revert(&argv);
+			},
+			"stash" => {
+				cmd_stash(&argv);
+			},
+			"upload-pack" => {
+				cmd_upload_pack(&argv);
+			},
 			"help" => {
 				print!("{}", USAGE);
 			},
@@ -0,0 +1,3 @@
+pub mod merge_cmd;
+pub mod merge_do;
+mod merge_parse;
@@ -0,0 +1,46 @@
+use super::{
+	merge_do::{merge, merge_abort, merge_continue, MergeOutcome},
+	merge_parse::Args,
+};
+use crate::tools::failed::failed;
+use std::process::exit;
+
+pub fn cmd_merge<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	if args.flag_abort {
+		merge_abort().unwrap_or_else(|e| failed(&format!("Failed merge --abort: {:?}", e)));
+	} else if args.flag_continue {
+		match merge_continue() {
+			Ok(commit) => println!("Merge made, commit {}", commit),
+			Err(e) => failed(&format!("Failed merge --continue: {:?}", e)),
+		}
+	} else {
+		let res = merge(
+			&args.arg_branch,
+			args.flag_no_ff,
+			args.flag_conflict.as_deref(),
+			args.flag_message.as_deref(),
+		);
+		match res {
+			Ok(MergeOutcome::UpToDate) => println!("Already up to date."),
+			Ok(MergeOutcome::FastForward(commit)) => println!("Fast-forward to {}", commit),
+			Ok(MergeOutcome::Merged(commit)) => println!("Merge made, commit {}", commit),
+			Ok(MergeOutcome::Conflicted(paths)) => {
+				for path in paths {
+					println!("CONFLICT in {}", path);
+				}
+				println!(
+					"Automatic merge failed; fix conflicts, add the files and run unrusty merge \
+					 --continue"
+				);
+				exit(1);
+			},
+			Err(e) => failed(&format!("Failed merge: {:?}", e)),
+		}
+	}
+}
@@ -0,0 +1,228 @@
+use crate::tools::{
+	commit::{commit_tree, Commit},
+	config::Config,
+	db::ObjectRepr,
+	filter::Filters,
+	index::Index,
+	merge::{is_ancestor, merge_bases, merge_trees, ConflictStyle, MergeLabels},
+	path::{vcs_path_required, RelativePathToBase},
+	refs::{delete_ref, read_ref, update_ref, write_ref, HEAD, MERGE_HEAD, ORIG_HEAD},
+	rev::{peel_to_commit, resolve_revision},
+	tree::{write_flat_tree, FlatTree},
+	worktree::{checkout_merge, switch_tree, untracked_in_the_way},
+};
+use log::{info, warn};
+use std::{convert::TryFrom, error::Error, fs, io::ErrorKind};
+
+const MERGE_MSG: &str = "MERGE_MSG";
+
+pub enum MergeOutcome {
+	UpToDate,
+	FastForward(ObjectRepr),
+	Merged(ObjectRepr),
+	Conflicted(Vec<RelativePathToBase>),
+}
+
+// The style given on the command line, else the configured one
+pub fn conflict_style(style: Option<&str>) -> Result<ConflictStyle, Box<dyn Error>> {
+	let style = match style {
+		Some(style) => Some(style.to_owned()),
+		None => Config::read()?.merge.conflict_style,
+	};
+	match style {
+		Some(style) => ConflictStyle::from_name(&style)
+			.ok_or_else(|| format!("unknown conflict style {:?}", style).into()),
+		None => Ok(ConflictStyle::Merge),
+	}
+}
+
+// A merge starts from a committed state, so that --abort can go back to it
+pub fn ensure_clean(
+	index: &Index,
+	head_tree: &FlatTree,
+	filters: &Filters,
+) -> Result<(), Box<dyn Error>> {
+	if !index.conflicts().is_empty() {
+		return Err("the index has unresolved conflicts".into());
+	}
+	if index.flat_tree() != *head_tree {
+		return Err("the index has changes that are not committed".into());
+	}
+	let mut modified = Vec::new();
+	for path in head_tree.keys() {
+		if index.worktree_modified(path, filters)? {
+			modified.push(path.to_string());
+		}
+	}
+	if !modified.is_empty() {
+		return Err(format!("local changes would be lost: {}", modified.join(", ")).into());
+	}
+	Ok(())
+}
+
+pub fn ensure_nothing_in_the_way(index: &Index, to: &FlatTree) -> Result<(), Box<dyn Error>> {
+	let in_the_way = untracked_in_the_way(index, to)?;
+	if in_the_way.is_empty() {
+		Ok(())
+	} else {
+		let paths = in_the_way.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+		Err(format!("untracked files would be overwritten: {}", paths.join(", ")).into())
+	}
+}
+
+fn default_message(rev: &str) -> String {
+	if ObjectRepr::try_from(rev).is_ok() {
+		format!("Merge commit '{}'", rev)
+	} else {
+		format!("Merge branch '{}'", rev)
+	}
+}
+
+fn clear_state() -> Result<(), Box<dyn Error>> {
+	delete_ref(MERGE_HEAD)?;
+	match fs::remove_file(vcs_path_required(MERGE_MSG)?) {
+		Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+		_ => Ok(()),
+	}
+}
+
+pub fn merge(
+	rev: &str,
+	no_ff: bool,
+	style: Option<&str>,
+	message: Option<&str>,
+) -> Result<MergeOutcome, Box<dyn Error>> {
+	if read_ref(MERGE_HEAD)?.is_some() {
+		return Err("a merge is in progress, use --continue or --abort".into());
+	}
+	let style = conflict_style(style)?;
+	let theirs = peel_to_commit(&resolve_revision(rev)?)?;
+	let ours = read_ref(HEAD)?;
+	let mut index = Index::read()?;
+	let filters = Filters::read()?;
+	let head_tree = commit_tree(ours.as_ref())?;
+	ensure_clean(&index, &head_tree, &filters)?;
+	let theirs_tree = commit_tree(Some(&theirs))?;
+
+	let ours = match ours {
+		Some(ours) => ours,
+		// Nothing committed yet, the branch simply starts at theirs
+		None => {
+			ensure_nothing_in_the_way(&index, &theirs_tree)?;
+			switch_tree(&mut index, &head_tree, &theirs_tree, false, &filters)?;
+			index.write()?;
+			update_ref(HEAD, &theirs, &format!("merge {}: Fast-forward", rev))?;
+			return Ok(MergeOutcome::FastForward(theirs));
+		},
+	};
+	if is_ancestor(&theirs, &ours)? {
+		return Ok(MergeOutcome::UpToDate);
+	}
+	write_ref(ORIG_HEAD, &ours)?;
+
+	if !no_ff && is_ancestor(&ours, &theirs)? {
+		info!("fast-forwarding from {} to {}", ours, theirs);
+		ensure_nothing_in_the_way(&index, &theirs_tree)?;
+		switch_tree(&mut index, &head_tree, &theirs_tree, false, &filters)?;
+		index.write()?;
+		update_ref(HEAD, &theirs, &format!("merge {}: Fast-forward", rev))?;
+		return Ok(MergeOutcome::FastForward(theirs));
+	}
+
+	let bases = merge_bases(&ours, &theirs)?;
+	if bases.len() > 1 {
+		warn!("{} merge bases, merging with {}", bases.len(), bases[0]);
+	}
+	let base = bases.first();
+	let base_label = base.map_or_else(|| String::from("empty tree"), |b| b.to_string());
+	let labels = MergeLabels {
+		base:   &base_label,
+		ours:   HEAD,
+		theirs: rev,
+	};
+	let result = merge_trees(
+		&commit_tree(base)?,
+		&head_tree,
+		&theirs_tree,
+		&labels,
+		style,
+	)?;
+
+	let message = message.map_or_else(|| default_message(rev), |m| m.to_owned());
+	// The commit is prepared before the merge is checked out, so that a
+	// missing identity leaves the worktree as it was
+	let commit = if result.conflicts.is_empty() {
+		let tree = write_flat_tree(&result.tree)?;
+		Some(Commit::new(tree, vec![ours, theirs.clone()], &message)?)
+	} else {
+		None
+	};
+	ensure_nothing_in_the_way(&index, &result.tree)?;
+	checkout_merge(&mut index, &head_tree, &result, &filters)?;
+	index.write()?;
+
+	if let Some(commit) = commit {
+		let commit = commit.write()?;
+		update_ref(HEAD, &commit, &format!("merge {}: Merge made", rev))?;
+		Ok(MergeOutcome::Merged(commit))
+	} else {
+		let paths = result
+			.conflicts
+			.into_iter()
+			.map(|c| c.path)
+			.collect::<Vec<_>>();
+		let mut merge_msg = format!("{}\n\n# Conflicts:\n", message);
+		for path in &paths {
+			merge_msg.push_str(&format!("#\t{}\n", path));
+		}
+		write_ref(MERGE_HEAD, &theirs)?;
+		fs::write(vcs_path_required(MERGE_MSG)?, merge_msg)?;
+		Ok(MergeOutcome::Conflicted(paths))
+	}
+}
+
+pub fn merge_continue() -> Result<ObjectRepr, Box<dyn Error>> {
+	let merge_head = read_ref(MERGE_HEAD)?.ok_or("there is no merge in progress")?;
+	let head = read_ref(HEAD)?.ok_or("HEAD does not point to a commit")?;
+	let index = Index::read()?;
+	let conflicts = index.conflicts();
+	if !conflicts.is_empty() {
+		let paths = conflicts.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+		return Err(format!(
+			"unresolved conflicts, add the files first: {}",
+			paths.join(", ")
+		)
+		.into());
+	}
+
+	// Lines starting with # are comments, like the list of conflicts
+	let message = fs::read_to_string(vcs_path_required(MERGE_MSG)?)
+		.unwrap_or_default()
+		.lines()
+		.filter(|l| !l.starts_with('#'))
+		.collect::<Vec<_>>()
+		.join("\n");
+	let tree = write_flat_tree(&index.flat_tree())?;
+	let commit = Commit::new(tree, vec![head, merge_head], message.trim_end())?.write()?;
+	update_ref(
+		HEAD,
+		&commit,
+		&format!("commit (merge): {}", message.trim()),
+	)?;
+	clear_state()?;
+	Ok(commit)
+}
+
+pub fn merge_abort() -> Result<(), Box<dyn Error>> {
+	read_ref(MERGE_HEAD)?.ok_or("there is no merge in progress")?;
+	let orig_head = match read_ref(ORIG_HEAD)? {
+		Some(orig_head) => Some(orig_head),
+		None => read_ref(HEAD)?,
+	};
+	let mut index = Index::read()?;
+	let from = index.flat_tree();
+	let to = commit_tree(orig_head.as_ref())?;
+	switch_tree(&mut index, &from, &to, true, &Filters::read()?)?;
+	index.write()?;
+	clear_state()
+}
@@ -0,0 +1,41 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty merge [--no-ff] [--conflict=<style>] [-m <msg>] <branch>
+         unrusty merge --abort
+         unrusty merge --continue
+         unrusty merge --help
+
+Options:
+         <branch>                     Branch, or any commit, to merge into HEAD
+         --no-ff                      Create a merge commit even if a fast-forward is possible
+         --conflict=<style>           Style of conflict markers: merge, diff3 or zdiff3. Defaults \
+                         to merge.conflict_style in the config, else merge
+         -m <msg>, --message=<msg>    Message of the merge commit
+         --abort                      Abort the merge in progress and restore the previous state
+         --continue                   Commit the merge in progress once conflicts are resolved
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub arg_branch:    String,
+	pub flag_no_ff:    bool,
+	pub flag_conflict: Option<String>,
+	pub flag_message:  Option<String>,
+	pub flag_abort:    bool,
+	pub flag_continue: bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
@@ -0,0 +1,3 @@
+pub mod pack_refs_cmd;
+pub mod pack_refs_do;
+mod pack_refs_parse;
@@ -0,0 +1,13 @@
+use super::{pack_refs_do::pack_refs, pack_refs_parse::Args};
+use crate::tools::failed::failed;
+
+pub fn cmd_pack_refs<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	pack_refs(args.flag_all, !args.flag_no_prune)
+		.unwrap_or_else(|e| failed(&format!("Failed pack-refs: {:?}", e)));
+}
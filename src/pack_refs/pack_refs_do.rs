@@ -0,0 +1,39 @@
+use crate::tools::{
+	refs::{
+		delete_loose_ref, list_loose_refs, read_loose_ref, read_packed_refs, write_packed_refs,
+		PackedRef, RefValue,
+	},
+	rev::peel_tags,
+};
+use std::error::Error;
+
+const TAGS_PREFIX: &str = "refs/tags/";
+
+// Moves loose refs into the packed-refs file: tags and refs that are packed
+// already, or all refs. Symbolic refs stay loose. Returns the number of
+// packed refs
+pub fn pack_refs(all: bool, prune: bool) -> Result<usize, Box<dyn Error>> {
+	let mut packed = read_packed_refs()?;
+	let mut newly_packed = Vec::new();
+	for name in list_loose_refs()? {
+		if !all && !name.starts_with(TAGS_PREFIX) && !packed.contains_key(&name) {
+			continue;
+		}
+		let object = match read_loose_ref(&name)? {
+			Some(RefValue::Direct(object)) => object,
+			_ => continue,
+		};
+		let (target, _) = peel_tags(&object)?;
+		let peeled = if target != object { Some(target) } else { None };
+		packed.insert(name.clone(), PackedRef { object, peeled });
+		newly_packed.push(name);
+	}
+	write_packed_refs(&packed)?;
+
+	if prune {
+		for name in &newly_packed {
+			delete_loose_ref(name)?;
+		}
+	}
+	Ok(newly_packed.len())
+}
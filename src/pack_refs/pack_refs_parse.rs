@@ -0,0 +1,30 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty pack-refs [--all] [--no-prune]
+         unrusty pack-refs --help
+
+Options:
+         --all                        Pack all refs, not only tags and refs that are already packed
+         --no-prune                   Keep the loose refs after packing them
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub flag_all:      bool,
+	pub flag_no_prune: bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
@@ -0,0 +1,3 @@
+pub mod prune_cmd;
+pub mod prune_do;
+mod prune_parse;
@@ -0,0 +1,23 @@
+use super::{prune_do::prune, prune_parse::Args};
+use crate::tools::{date::parse_date, failed::failed};
+
+pub fn cmd_prune<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+	let expire = parse_date(&args.flag_expire)
+		.unwrap_or_else(|| failed(&format!("Invalid date {:?}", args.flag_expire)));
+
+	match prune(expire, args.flag_dry_run) {
+		Ok(pruned) => {
+			if args.flag_verbose || args.flag_dry_run {
+				for (object_repr, object_type) in pruned {
+					println!("{} {}", object_repr, object_type);
+				}
+			}
+		},
+		Err(e) => failed(&format!("Failed prune: {:?}", e)),
+	}
+}
@@ -0,0 +1,35 @@
+use crate::tools::{
+	db::{delete, list_objects, read_with_invalid, ObjectRepr, ObjectType},
+	reachable::{reachable_from, roots},
+};
+use std::{error::Error, fs, time::SystemTime};
+
+// Removes the loose objects that are unreachable and were written before
+// `expire`. Recent objects are kept together with everything they reference,
+// so that e.g. a commit being created does not lose its older trees. Returns
+// the removed objects with their types
+pub fn prune(
+	expire: SystemTime,
+	dry_run: bool,
+) -> Result<Vec<(ObjectRepr, ObjectType)>, Box<dyn Error>> {
+	let objects = list_objects()?;
+	let mut roots = roots()?;
+	for object_repr in &objects {
+		if fs::metadata(object_repr.path()?)?.modified()? > expire {
+			roots.push((None, object_repr.clone()));
+		}
+	}
+	let reachable = reachable_from(roots)?.reachable;
+
+	let mut pruned = Vec::new();
+	for object_repr in objects.into_iter().filter(|o| !reachable.contains(o)) {
+		let object_type = read_with_invalid(&object_repr, true)
+			.map(|o| o.object_type)
+			.unwrap_or(ObjectType::Invalid);
+		if !dry_run {
+			delete(&object_repr)?;
+		}
+		pruned.push((object_repr, object_type));
+	}
+	Ok(pruned)
+}
@@ -0,0 +1,33 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty prune [-n] [-v] [--expire=<date>]
+         unrusty prune --help
+
+Options:
+         -n, --dry-run                Only report what would be removed
+         -v, --verbose                Show the removed objects
+         --expire=<date>              Only remove objects older than the date, e.g. 2.weeks.ago, \
+                         2024-01-31 or now [default: now]
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub flag_dry_run: bool,
+	pub flag_verbose: bool,
+	pub flag_expire:  String,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
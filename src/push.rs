@@ -0,0 +1,3 @@
+pub mod push_cmd;
+pub mod push_do;
+mod push_parse;
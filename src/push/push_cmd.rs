@@ -0,0 +1,36 @@
+use super::{push_do::push, push_parse::Args};
+use crate::tools::{failed::failed, transport::UpdateStatus};
+use std::process::exit;
+
+pub fn cmd_push<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	let res = push(
+		args.arg_remote.as_deref(),
+		&args.arg_refspec,
+		args.flag_force,
+	);
+	match res {
+		Ok(updates) => {
+			let changed = updates
+				.iter()
+				.filter(|u| u.status != UpdateStatus::UpToDate)
+				.collect::<Vec<_>>();
+			for update in &changed {
+				println!("{}", update);
+			}
+			if changed.is_empty() {
+				println!("Everything up-to-date");
+			}
+			if updates.iter().any(|u| u.is_rejected()) {
+				println!("error: failed to push some refs");
+				exit(1);
+			}
+		},
+		Err(e) => failed(&format!("Failed push: {:?}", e)),
+	}
+}
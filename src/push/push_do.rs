@@ -0,0 +1,164 @@
+use crate::tools::{
+	db::ObjectRepr,
+	path::{objects_dir_required, root_path_required},
+	refs::{current_branch, delete_ref, list_refs, read_ref, update_ref},
+	remote::{
+		default_remote_name, parse_refspecs, resolve_remote, Refspec, HEADS_PREFIX, TAGS_PREFIX,
+	},
+	rev::{expand_ref_name, resolve_revision},
+	transport::{
+		classify_update, copy_lfs_objects, copy_objects, find_repository, in_repository,
+		objects_to_transfer, RefUpdate, UpdateStatus,
+	},
+};
+use std::error::Error;
+
+// A ref of the remote to set to a local object, or to delete
+struct PushTarget {
+	src:   String,
+	dst:   String,
+	new:   Option<ObjectRepr>,
+	force: bool,
+}
+
+// Expands the refspecs against the local refs. Sources may be short names or
+// any revision, destinations default to the full name of the source and are
+// qualified by the remote
+fn push_targets(refspecs: &[Refspec], force: bool) -> Result<Vec<PushTarget>, Box<dyn Error>> {
+	let mut targets = Vec::new();
+	for refspec in refspecs {
+		let force = force || refspec.force;
+		if refspec.is_wildcard() {
+			for (name, object_repr) in list_refs()? {
+				if let Some(dst) = refspec.map(&name) {
+					targets.push(PushTarget {
+						src: name,
+						dst,
+						new: Some(object_repr),
+						force,
+					});
+				}
+			}
+			continue;
+		}
+		if refspec.src.is_empty() {
+			targets.push(PushTarget {
+				src: String::new(),
+				dst: refspec.dst.clone().unwrap_or_default(),
+				new: None,
+				force,
+			});
+			continue;
+		}
+		let full_name = expand_ref_name(&refspec.src)?;
+		let dst = match (&refspec.dst, &full_name) {
+			(Some(dst), _) => dst.clone(),
+			(None, Some(full_name)) => full_name.clone(),
+			(None, None) => {
+				return Err(format!("{} is not a ref, give a destination", refspec.src).into())
+			},
+		};
+		targets.push(PushTarget {
+			src: full_name.unwrap_or_else(|| refspec.src.clone()),
+			dst,
+			new: Some(resolve_revision(&refspec.src)?),
+			force,
+		});
+	}
+	Ok(targets)
+}
+
+// The full name of a destination in the remote, which must be the current
+// repository: an existing ref it names, else a branch or a tag like the source
+fn qualify_destination(dst: &str, src: &str) -> Result<String, Box<dyn Error>> {
+	if dst.starts_with("refs/") {
+		return Ok(dst.to_owned());
+	}
+	if let Some(full_name) = expand_ref_name(dst)? {
+		return Ok(full_name);
+	}
+	let prefix = if src.starts_with(TAGS_PREFIX) {
+		TAGS_PREFIX
+	} else {
+		HEADS_PREFIX
+	};
+	Ok(format!("{}{}", prefix, dst))
+}
+
+// Pushes local refs to a remote, or a repository path: copies the objects the
+// remote lacks and updates its refs. Updates that are not fast-forwards are
+// rejected unless forced, as is updating the branch checked out in the remote.
+// Accepted updates also move the matching remote-tracking refs
+pub fn push(
+	remote: Option<&str>,
+	refspecs: &[String],
+	force: bool,
+) -> Result<Vec<RefUpdate>, Box<dyn Error>> {
+	let remote = resolve_remote(&match remote {
+		Some(remote) => remote.to_owned(),
+		None => default_remote_name()?,
+	})?;
+	let refspecs = if refspecs.is_empty() {
+		let branch = current_branch()?.ok_or("HEAD is detached, give a refspec")?;
+		vec![Refspec {
+			force: false,
+			src:   branch.clone(),
+			dst:   Some(branch),
+		}]
+	} else {
+		parse_refspecs(refspecs)?
+	};
+	let targets = push_targets(&refspecs, force)?;
+	let root = find_repository(remote.url.as_ref())?;
+	let local_root = root_path_required()?;
+	let local_objects = objects_dir_required()?;
+	let wants = targets
+		.iter()
+		.filter_map(|t| t.new.clone())
+		.collect::<Vec<_>>();
+
+	let updates = in_repository(&root, || {
+		let objects = objects_to_transfer(&local_root, &wants)?;
+		copy_objects(&local_objects, &objects_dir_required()?, &objects, true)?;
+		copy_lfs_objects(&local_root, &objects, true)?;
+		let checked_out = current_branch()?;
+		let mut updates = Vec::new();
+		for mut target in targets {
+			if !target.dst.is_empty() {
+				target.dst = qualify_destination(&target.dst, &target.src)?;
+			}
+			let old = read_ref(&target.dst)?;
+			let status = if checked_out.as_deref() == Some(target.dst.as_str()) && old != target.new
+			{
+				UpdateStatus::Rejected("branch is currently checked out".to_owned())
+			} else {
+				classify_update(&target.dst, old.as_ref(), target.new.as_ref(), target.force)?
+			};
+			match (&status, &target.new) {
+				(UpdateStatus::Deleted, _) => delete_ref(&target.dst)?,
+				(UpdateStatus::New, Some(new))
+				| (UpdateStatus::FastForward, Some(new))
+				| (UpdateStatus::Forced, Some(new)) => update_ref(&target.dst, new, "push")?,
+				_ => {},
+			}
+			updates.push(RefUpdate {
+				src: target.src,
+				dst: target.dst,
+				old,
+				new: target.new,
+				status,
+			});
+		}
+		Ok(updates)
+	})?;
+
+	for update in updates.iter().filter(|u| !u.is_rejected()) {
+		let tracking = remote.fetch.iter().find_map(|r| r.map(&update.dst));
+		match (tracking, &update.new) {
+			(Some(tracking), Some(new)) => update_ref(&tracking, new, "update by push")?,
+			(Some(tracking), None) => delete_ref(&tracking)?,
+			_ => {},
+		}
+	}
+	Ok(updates)
+}
@@ -0,0 +1,3 @@
+pub mod rebase_cmd;
+pub mod rebase_do;
+mod rebase_parse;
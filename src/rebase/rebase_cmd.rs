@@ -0,0 +1,66 @@
+use super::{
+	rebase_do::{rebase, rebase_abort, rebase_continue, rebase_skip, RebaseOutcome, Stop},
+	rebase_parse::Args,
+};
+use crate::tools::{failed::failed, remote::HEADS_PREFIX};
+use std::{error::Error, process::exit};
+
+fn report(outcome: Result<RebaseOutcome, Box<dyn Error>>, action: &str) {
+	match outcome {
+		Ok(RebaseOutcome::UpToDate(branch)) => {
+			println!("Current branch {} is up to date.", branch)
+		},
+		Ok(RebaseOutcome::Finished(branch)) => println!(
+			"Successfully rebased and updated {}.",
+			branch.trim_start_matches(HEADS_PREFIX)
+		),
+		Ok(RebaseOutcome::Stopped(Stop::Conflicted(commit, subject, paths))) => {
+			for path in paths {
+				println!("CONFLICT in {}", path);
+			}
+			println!("Could not apply {}... {}", commit.short(), subject);
+			println!(
+				"Resolve the conflicts, add the files and run unrusty rebase --continue, or skip \
+				 the commit with unrusty rebase --skip"
+			);
+			exit(1);
+		},
+		Ok(RebaseOutcome::Stopped(Stop::Edit(commit, subject))) => {
+			println!("Stopped at {}... {}", commit.short(), subject);
+			println!(
+				"You can amend the commit now by changing and adding files, then run unrusty \
+				 rebase --continue"
+			);
+		},
+		Ok(RebaseOutcome::Stopped(Stop::ExecFailed(command))) => {
+			println!("Execution failed: {}", command);
+			println!("Fix the problem and run unrusty rebase --continue");
+			exit(1);
+		},
+		Err(e) => failed(&format!("Failed rebase{}: {:?}", action, e)),
+	}
+}
+
+pub fn cmd_rebase<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	if args.flag_abort {
+		rebase_abort().unwrap_or_else(|e| failed(&format!("Failed rebase --abort: {:?}", e)));
+	} else if args.flag_continue {
+		report(rebase_continue(), " --continue");
+	} else if args.flag_skip {
+		report(rebase_skip(), " --skip");
+	} else {
+		let outcome = rebase(
+			&args.arg_upstream,
+			args.flag_onto.as_deref(),
+			args.flag_interactive,
+			args.flag_autosquash,
+		);
+		report(outcome, "");
+	}
+}
@@ -0,0 +1,781 @@
+use crate::{
+	merge::merge_do::{conflict_style, ensure_clean, ensure_nothing_in_the_way},
+	tools::{
+		commit::{commit_tree, signature_now, subject, Commit},
+		db::ObjectRepr,
+		editor::{edit_file, strip_comments},
+		filter::Filters,
+		index::Index,
+		merge::{commits_between, is_ancestor, merge_trees, MergeLabels},
+		path::{root_path_required, vcs_path_required, RelativePathToBase},
+		reflog::append_reflog,
+		refs::{
+			current_branch, read_ref, update_ref, write_ref, write_symbolic_ref, HEAD, MERGE_HEAD,
+			ORIG_HEAD,
+		},
+		rev::{peel_to_commit, resolve_revision},
+		tree::{flatten_tree, write_flat_tree, FlatTree},
+		worktree::{checkout_merge, switch_tree},
+	},
+};
+use log::info;
+use std::{
+	collections::HashMap,
+	convert::TryFrom,
+	error::Error,
+	fmt::{self, Display, Formatter},
+	fs,
+	io::ErrorKind,
+	path::PathBuf,
+	process::Command,
+	slice,
+};
+
+// State of the rebase in progress, in the layout of git's rebase-merge
+const STATE_DIR: &str = "rebase-merge";
+const HEAD_NAME: &str = "head-name";
+const ONTO: &str = "onto";
+const ORIG_HEAD_FILE: &str = "orig-head";
+const TODO: &str = "git-rebase-todo";
+const DONE: &str = "done";
+const INTERACTIVE: &str = "interactive";
+// The commit that could not be applied or is being edited
+const STOPPED_SHA: &str = "stopped-sha";
+// A commit left to make by --continue: its message and author, and whether it
+// replaces HEAD or its message is edited first
+const MESSAGE: &str = "message";
+const AUTHOR: &str = "author";
+const SQUASH: &str = "squash";
+const EDIT_MESSAGE: &str = "edit-message";
+// HEAD when stopping for an edit, --continue amends it with the index
+const AMEND: &str = "amend";
+const DETACHED: &str = "detached HEAD";
+
+const TODO_HELP: &str = "
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\", but discard this commit's log message
+# x, exec <command> = run command (the rest of the line) using shell
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+# If you remove a line here THAT COMMIT WILL BE LOST.
+# However, if you remove everything, the rebase will be aborted.
+";
+
+const MESSAGE_HELP: &str = "
+# Please enter the commit message for your changes. Lines starting
+# with '#' will be ignored, and an empty message aborts the commit.
+";
+
+pub enum RebaseOutcome {
+	UpToDate(String),
+	Finished(String),
+	Stopped(Stop),
+}
+
+// Why the rebase stopped before the end of the todo list. Commits come with
+// their subject
+pub enum Stop {
+	Conflicted(ObjectRepr, String, Vec<RelativePathToBase>),
+	Edit(ObjectRepr, String),
+	ExecFailed(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+	Pick,
+	Reword,
+	Edit,
+	Squash,
+	Fixup,
+	Exec,
+	Drop,
+}
+
+impl Action {
+	fn from_name(name: &str) -> Option<Action> {
+		match name {
+			"p" | "pick" => Some(Action::Pick),
+			"r" | "reword" => Some(Action::Reword),
+			"e" | "edit" => Some(Action::Edit),
+			"s" | "squash" => Some(Action::Squash),
+			"f" | "fixup" => Some(Action::Fixup),
+			"x" | "exec" => Some(Action::Exec),
+			"d" | "drop" => Some(Action::Drop),
+			_ => None,
+		}
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			Action::Pick => "pick",
+			Action::Reword => "reword",
+			Action::Edit => "edit",
+			Action::Squash => "squash",
+			Action::Fixup => "fixup",
+			Action::Exec => "exec",
+			Action::Drop => "drop",
+		}
+	}
+}
+
+// A line of the todo list: "<action> <commit> <subject>", or "exec <command>"
+#[derive(Debug, Clone)]
+struct TodoLine {
+	action:   Action,
+	argument: String,
+	subject:  String,
+}
+
+impl TodoLine {
+	fn new(action: Action, commit: &ObjectRepr) -> Result<TodoLine, Box<dyn Error>> {
+		Ok(TodoLine {
+			action,
+			argument: commit.short().to_owned(),
+			subject: subject(&Commit::read(commit)?.message),
+		})
+	}
+
+	// None for blank lines and comments
+	fn parse(line: &str) -> Result<Option<TodoLine>, Box<dyn Error>> {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			return Ok(None);
+		}
+		let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+		let action =
+			Action::from_name(name).ok_or_else(|| format!("unknown todo command {:?}", name))?;
+		let rest = rest.trim();
+		if rest.is_empty() {
+			return Err(format!("missing argument in todo line {:?}", line).into());
+		}
+		let todo = if action == Action::Exec {
+			TodoLine {
+				action,
+				argument: rest.to_owned(),
+				subject: String::new(),
+			}
+		} else {
+			let (argument, subject) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+			TodoLine {
+				action,
+				argument: argument.to_owned(),
+				subject: subject.trim().to_owned(),
+			}
+		};
+		// Unknown commits are reported while the list is read, not halfway through
+		todo.commit()?;
+		Ok(Some(todo))
+	}
+
+	fn commit(&self) -> Result<Option<ObjectRepr>, Box<dyn Error>> {
+		if self.action == Action::Exec {
+			return Ok(None);
+		}
+		let commit = peel_to_commit(&resolve_revision(&self.argument)?)?;
+		Ok(Some(commit))
+	}
+}
+
+impl Display for TodoLine {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		if self.subject.is_empty() {
+			write!(f, "{} {}", self.action.name(), self.argument)
+		} else {
+			write!(
+				f,
+				"{} {} {}",
+				self.action.name(),
+				self.argument,
+				self.subject
+			)
+		}
+	}
+}
+
+fn state_path(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+	Ok(vcs_path_required(&format!("{}/{}", STATE_DIR, name))?)
+}
+
+fn in_progress() -> Result<bool, Box<dyn Error>> {
+	Ok(vcs_path_required(STATE_DIR)?.is_dir())
+}
+
+fn read_state(name: &str) -> Result<Option<String>, Box<dyn Error>> {
+	match fs::read_to_string(state_path(name)?) {
+		Ok(content) => Ok(Some(content)),
+		Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(e.into()),
+	}
+}
+
+fn read_state_required(name: &str) -> Result<String, Box<dyn Error>> {
+	let content = read_state(name)?.ok_or_else(|| format!("rebase state {} is missing", name))?;
+	Ok(content.trim_end().to_owned())
+}
+
+fn read_state_commit(name: &str) -> Result<Option<ObjectRepr>, Box<dyn Error>> {
+	match read_state(name)? {
+		Some(content) => ObjectRepr::try_from(content.trim())
+			.map(Some)
+			.map_err(|_| format!("rebase state {} is malformed", name).into()),
+		None => Ok(None),
+	}
+}
+
+fn write_state(name: &str, content: &str) -> Result<(), Box<dyn Error>> {
+	Ok(fs::write(state_path(name)?, content)?)
+}
+
+fn remove_state(name: &str) -> Result<(), Box<dyn Error>> {
+	match fs::remove_file(state_path(name)?) {
+		Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+		_ => Ok(()),
+	}
+}
+
+fn read_todo(name: &str) -> Result<Vec<TodoLine>, Box<dyn Error>> {
+	let content = read_state(name)?.unwrap_or_default();
+	let mut todo = Vec::new();
+	for line in content.lines() {
+		if let Some(line) = TodoLine::parse(line)? {
+			todo.push(line);
+		}
+	}
+	Ok(todo)
+}
+
+fn write_todo(name: &str, todo: &[TodoLine]) -> Result<(), Box<dyn Error>> {
+	let content = todo.iter().map(|l| format!("{}\n", l)).collect::<String>();
+	write_state(name, &content)
+}
+
+fn append_done(line: &TodoLine) -> Result<(), Box<dyn Error>> {
+	let mut done = read_state(DONE)?.unwrap_or_default();
+	done.push_str(&format!("{}\n", line));
+	write_state(DONE, &done)
+}
+
+// The title a "fixup! " or "squash! " commit refers to, with the action it
+// asks for. Repeated prefixes refer to the same commit
+fn autosquash_target(subject: &str) -> Option<(Action, &str)> {
+	let (action, mut target) = if let Some(rest) = subject.strip_prefix("fixup! ") {
+		(Action::Fixup, rest)
+	} else if let Some(rest) = subject.strip_prefix("squash! ") {
+		(Action::Squash, rest)
+	} else {
+		return None;
+	};
+	while let Some(rest) = target
+		.strip_prefix("fixup! ")
+		.or_else(|| target.strip_prefix("squash! "))
+	{
+		target = rest;
+	}
+	Some((action, target))
+}
+
+// Moves fixup and squash commits right after the earlier commit whose subject
+// or hash they name, in the order they were made
+fn autosquash(todo: Vec<TodoLine>) -> Vec<TodoLine> {
+	let mut attached: HashMap<usize, Vec<usize>> = HashMap::new();
+	let mut moved = vec![false; todo.len()];
+	for (i, line) in todo.iter().enumerate() {
+		let target = match autosquash_target(&line.subject) {
+			Some((_, target)) => target,
+			None => continue,
+		};
+		let is_target = |candidate: &TodoLine| {
+			autosquash_target(&candidate.subject).is_none()
+				&& (candidate.subject == target || candidate.argument.starts_with(target))
+		};
+		let found = todo[..i]
+			.iter()
+			.position(is_target)
+			.or_else(|| todo[..i].iter().position(|c| c.subject.starts_with(target)));
+		if let Some(j) = found {
+			attached.entry(j).or_default().push(i);
+			moved[i] = true;
+		}
+	}
+	let mut result = Vec::new();
+	for (i, line) in todo.iter().enumerate() {
+		if moved[i] {
+			continue;
+		}
+		result.push(line.clone());
+		for &k in attached.get(&i).into_iter().flatten() {
+			let mut fold = todo[k].clone();
+			fold.action = autosquash_target(&fold.subject).map(|(a, _)| a).unwrap();
+			result.push(fold);
+		}
+	}
+	result
+}
+
+fn head_required() -> Result<ObjectRepr, Box<dyn Error>> {
+	Ok(read_ref(HEAD)?.ok_or("HEAD does not point to a commit")?)
+}
+
+// Lets the user edit a commit message, comments are dropped
+fn edit_message(message: &str) -> Result<String, Box<dyn Error>> {
+	let path = state_path("COMMIT_EDITMSG")?;
+	fs::write(&path, format!("{}\n{}", message.trim_end(), MESSAGE_HELP))?;
+	edit_file(&path)?;
+	let message = strip_comments(&fs::read_to_string(&path)?);
+	if message.is_empty() {
+		return Err("empty commit message".into());
+	}
+	Ok(message)
+}
+
+fn squash_message(previous: &str, message: &str) -> String {
+	format!(
+		"# This is a combination of commits.\n# The previous commit message:\n\n{}\n\n# The \
+		 commit message to squash:\n\n{}",
+		previous.trim_end(),
+		message.trim_end()
+	)
+}
+
+// Commits the tree on top of HEAD, or in place of HEAD when squashing
+fn commit_pending(
+	tree: &FlatTree,
+	message: &str,
+	author: &str,
+	squash: bool,
+	edit: bool,
+	action: Action,
+) -> Result<ObjectRepr, Box<dyn Error>> {
+	let head = head_required()?;
+	let parents = if squash {
+		Commit::read(&head)?.parents
+	} else {
+		vec![head]
+	};
+	let message = if edit {
+		edit_message(message)?
+	} else {
+		strip_comments(message)
+	};
+	let tree = write_flat_tree(tree)?;
+	let commit = Commit::with_author(tree, parents, author, &message)?.write()?;
+	update_ref(
+		HEAD,
+		&commit,
+		&format!("rebase ({}): {}", action.name(), subject(&message)),
+	)?;
+	Ok(commit)
+}
+
+fn clear_pending() -> Result<(), Box<dyn Error>> {
+	for name in &[MESSAGE, AUTHOR, SQUASH, EDIT_MESSAGE, AMEND, STOPPED_SHA] {
+		remove_state(name)?;
+	}
+	Ok(())
+}
+
+// Applies the changes of the commit on top of HEAD with a three-way merge
+// between its parent, HEAD and the commit
+fn pick(
+	line: &TodoLine,
+	index: &mut Index,
+	filters: &Filters,
+) -> Result<Option<Stop>, Box<dyn Error>> {
+	let commit_repr = line.commit()?.ok_or("exec has no commit")?;
+	let commit = Commit::read(&commit_repr)?;
+	if commit.parents.len() > 1 {
+		return Err(format!("{} is a merge commit and cannot be replayed", commit_repr).into());
+	}
+	let head = head_required()?;
+	let head_commit = Commit::read(&head)?;
+	let commit_subject = subject(&commit.message);
+	let squash = matches!(line.action, Action::Squash | Action::Fixup);
+	let edit = matches!(line.action, Action::Reword | Action::Squash);
+
+	let head_tree = flatten_tree(&head_commit.tree)?;
+	let base_tree = commit_tree(commit.parents.first())?;
+	let base_label = format!("parent of {} ({})", commit_repr.short(), commit_subject);
+	let theirs_label = format!("{} ({})", commit_repr.short(), commit_subject);
+	let labels = MergeLabels {
+		base:   &base_label,
+		ours:   HEAD,
+		theirs: &theirs_label,
+	};
+	let result = merge_trees(
+		&base_tree,
+		&head_tree,
+		&flatten_tree(&commit.tree)?,
+		&labels,
+		conflict_style(None)?,
+	)?;
+	// A commit that can not be made must not leave the merge behind
+	signature_now()?;
+	ensure_nothing_in_the_way(index, &result.tree)?;
+	checkout_merge(index, &head_tree, &result, filters)?;
+	index.write()?;
+
+	let (message, author) = match line.action {
+		Action::Fixup => (head_commit.message.clone(), head_commit.author.clone()),
+		Action::Squash => (
+			squash_message(&head_commit.message, &commit.message),
+			head_commit.author.clone(),
+		),
+		_ => (commit.message.clone(), commit.author.clone()),
+	};
+
+	if !result.conflicts.is_empty() {
+		write_state(MESSAGE, &message)?;
+		write_state(AUTHOR, &author)?;
+		if squash {
+			write_state(SQUASH, "")?;
+		}
+		if edit {
+			write_state(EDIT_MESSAGE, "")?;
+		}
+		write_state(STOPPED_SHA, &commit_repr.to_string())?;
+		let paths = result.conflicts.into_iter().map(|c| c.path).collect();
+		return Ok(Some(Stop::Conflicted(commit_repr, commit_subject, paths)));
+	}
+
+	if !squash && !edit && commit.parents.first() == Some(&head) {
+		// Nothing to replay, the commit already sits on HEAD
+		update_ref(
+			HEAD,
+			&commit_repr,
+			&format!("rebase ({}): fast-forward", line.action.name()),
+		)?;
+	} else if !squash && result.tree == head_tree {
+		// The changes are already upstream
+		info!(
+			"dropping {} {}, its changes are already applied",
+			commit_repr.short(),
+			commit_subject
+		);
+	} else {
+		commit_pending(&result.tree, &message, &author, squash, edit, line.action)?;
+	}
+
+	if line.action == Action::Edit {
+		write_state(AMEND, &head_required()?.to_string())?;
+		write_state(STOPPED_SHA, &commit_repr.to_string())?;
+		return Ok(Some(Stop::Edit(commit_repr, commit_subject)));
+	}
+	Ok(None)
+}
+
+// Works through the todo list until it is empty or a command stops
+fn run_todo(index: &mut Index, filters: &Filters) -> Result<RebaseOutcome, Box<dyn Error>> {
+	loop {
+		let mut todo = read_todo(TODO)?;
+		if todo.is_empty() {
+			return finish();
+		}
+		// The line is done once it ran, even if the rebase stops there. An
+		// error leaves it in the todo list for --continue
+		let line = todo.remove(0);
+		let stop = match line.action {
+			Action::Drop => None,
+			Action::Exec => {
+				let status = Command::new("sh")
+					.arg("-c")
+					.arg(&line.argument)
+					.current_dir(root_path_required()?)
+					.status()?;
+				// The command may have changed the index, e.g. by amending
+				*index = Index::read()?;
+				if status.success() {
+					None
+				} else {
+					Some(Stop::ExecFailed(line.argument.clone()))
+				}
+			},
+			_ => pick(&line, index, filters)?,
+		};
+		write_todo(TODO, &todo)?;
+		append_done(&line)?;
+		if let Some(stop) = stop {
+			return Ok(RebaseOutcome::Stopped(stop));
+		}
+	}
+}
+
+// Moves the branch to the rebased commits and checks it out again
+fn finish() -> Result<RebaseOutcome, Box<dyn Error>> {
+	let head_name = read_state_required(HEAD_NAME)?;
+	let onto = read_state_required(ONTO)?;
+	let head = head_required()?;
+	if head_name != DETACHED {
+		update_ref(
+			&head_name,
+			&head,
+			&format!("rebase (finish): {} onto {}", head_name, onto),
+		)?;
+		write_symbolic_ref(HEAD, &head_name)?;
+		append_reflog(
+			HEAD,
+			Some(&head),
+			Some(&head),
+			&format!("rebase (finish): returning to {}", head_name),
+		);
+	}
+	fs::remove_dir_all(vcs_path_required(STATE_DIR)?)?;
+	Ok(RebaseOutcome::Finished(head_name))
+}
+
+pub fn rebase(
+	upstream: &str,
+	onto: Option<&str>,
+	interactive: bool,
+	autosquash_commits: bool,
+) -> Result<RebaseOutcome, Box<dyn Error>> {
+	if in_progress()? {
+		return Err("a rebase is in progress, use --continue, --skip or --abort".into());
+	}
+	if read_ref(MERGE_HEAD)?.is_some() {
+		return Err("a merge is in progress, finish or abort it first".into());
+	}
+	let head = head_required()?;
+	let head_name = current_branch()?.unwrap_or_else(|| DETACHED.to_owned());
+	let mut index = Index::read()?;
+	let filters = Filters::read()?;
+	let head_tree = commit_tree(Some(&head))?;
+	ensure_clean(&index, &head_tree, &filters)?;
+
+	let upstream_commit = peel_to_commit(&resolve_revision(upstream)?)?;
+	let onto_commit = match onto {
+		Some(onto) => peel_to_commit(&resolve_revision(onto)?)?,
+		None => upstream_commit.clone(),
+	};
+	if !interactive
+		&& !autosquash_commits
+		&& onto_commit == upstream_commit
+		&& is_ancestor(&upstream_commit, &head)?
+	{
+		return Ok(RebaseOutcome::UpToDate(head_name));
+	}
+
+	// Merge commits are left out, their changes are in the commits replayed
+	let mut todo = Vec::new();
+	for commit in commits_between(slice::from_ref(&upstream_commit), &head)? {
+		if Commit::read(&commit)?.parents.len() <= 1 {
+			todo.push(TodoLine::new(Action::Pick, &commit)?);
+		}
+	}
+	if autosquash_commits {
+		todo = autosquash(todo);
+	}
+
+	fs::create_dir_all(vcs_path_required(STATE_DIR)?)?;
+	write_state(HEAD_NAME, &format!("{}\n", head_name))?;
+	write_state(ONTO, &format!("{}\n", onto_commit))?;
+	write_state(ORIG_HEAD_FILE, &format!("{}\n", head))?;
+	write_todo(TODO, &todo)?;
+	if interactive {
+		write_state(INTERACTIVE, "")?;
+		let mut content = read_state(TODO)?.unwrap_or_default();
+		content.push_str(&format!(
+			"\n# Rebase {}..{} onto {} ({} commands)\n#{}",
+			upstream_commit.short(),
+			head.short(),
+			onto_commit.short(),
+			todo.len(),
+			TODO_HELP
+		));
+		write_state(TODO, &content)?;
+		let edited = edit_file(&state_path(TODO)?)
+			.map_err(Box::<dyn Error>::from)
+			.and_then(|_| {
+				let edited = read_todo(TODO)?;
+				if edited.is_empty() {
+					Err("nothing to do".into())
+				} else {
+					Ok(edited)
+				}
+			});
+		match edited {
+			Ok(edited) => write_todo(TODO, &edited)?,
+			Err(e) => {
+				fs::remove_dir_all(vcs_path_required(STATE_DIR)?)?;
+				return Err(e);
+			},
+		}
+	}
+
+	// HEAD is detached at onto while the commits are replayed
+	let onto_tree = commit_tree(Some(&onto_commit))?;
+	ensure_nothing_in_the_way(&index, &onto_tree)?;
+	write_ref(ORIG_HEAD, &head)?;
+	switch_tree(&mut index, &head_tree, &onto_tree, false, &filters)?;
+	index.write()?;
+	write_ref(HEAD, &onto_commit)?;
+	append_reflog(
+		HEAD,
+		Some(&head),
+		Some(&onto_commit),
+		&format!("rebase (start): checkout {}", onto.unwrap_or(upstream)),
+	);
+	run_todo(&mut index, &filters)
+}
+
+pub fn rebase_continue() -> Result<RebaseOutcome, Box<dyn Error>> {
+	if !in_progress()? {
+		return Err("there is no rebase in progress".into());
+	}
+	let mut index = Index::read()?;
+	let conflicts = index.conflicts();
+	if !conflicts.is_empty() {
+		let paths = conflicts.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+		return Err(format!(
+			"unresolved conflicts, add the files first: {}",
+			paths.join(", ")
+		)
+		.into());
+	}
+
+	let head = head_required()?;
+	let head_commit = Commit::read(&head)?;
+	let tree = index.flat_tree();
+	if let Some(message) = read_state(MESSAGE)? {
+		let author = read_state_required(AUTHOR)?;
+		let squash = read_state(SQUASH)?.is_some();
+		let edit = read_state(EDIT_MESSAGE)?.is_some();
+		if !squash && tree == flatten_tree(&head_commit.tree)? {
+			info!("dropping the commit, it has no changes left");
+		} else {
+			commit_pending(&tree, &message, &author, squash, edit, Action::Pick)?;
+		}
+	} else if read_state_commit(AMEND)?.as_ref() == Some(&head)
+		&& tree != flatten_tree(&head_commit.tree)?
+	{
+		// Changes added while stopped at an edit go into the commit
+		let amended = Commit::with_author(
+			write_flat_tree(&tree)?,
+			head_commit.parents,
+			&head_commit.author,
+			&head_commit.message,
+		)?
+		.write()?;
+		update_ref(
+			HEAD,
+			&amended,
+			&format!("rebase (amend): {}", subject(&head_commit.message)),
+		)?;
+	}
+	clear_pending()?;
+	run_todo(&mut index, &Filters::read()?)
+}
+
+pub fn rebase_skip() -> Result<RebaseOutcome, Box<dyn Error>> {
+	if !in_progress()? {
+		return Err("there is no rebase in progress".into());
+	}
+	let mut index = Index::read()?;
+	let filters = Filters::read()?;
+	let from = index.flat_tree();
+	let to = commit_tree(Some(&head_required()?))?;
+	switch_tree(&mut index, &from, &to, true, &filters)?;
+	index.write()?;
+	clear_pending()?;
+	run_todo(&mut index, &filters)
+}
+
+pub fn rebase_abort() -> Result<(), Box<dyn Error>> {
+	if !in_progress()? {
+		return Err("there is no rebase in progress".into());
+	}
+	let head_name = read_state_required(HEAD_NAME)?;
+	let orig_head =
+		read_state_commit(ORIG_HEAD_FILE)?.ok_or("rebase state orig-head is missing")?;
+	let head = read_ref(HEAD)?;
+	let mut index = Index::read()?;
+	let from = index.flat_tree();
+	let to = commit_tree(Some(&orig_head))?;
+	switch_tree(&mut index, &from, &to, true, &Filters::read()?)?;
+	index.write()?;
+	if head_name == DETACHED {
+		write_ref(HEAD, &orig_head)?;
+	} else {
+		write_symbolic_ref(HEAD, &head_name)?;
+	}
+	append_reflog(
+		HEAD,
+		head.as_ref(),
+		Some(&orig_head),
+		&format!("rebase (abort): returning to {}", head_name),
+	);
+	fs::remove_dir_all(vcs_path_required(STATE_DIR)?)?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tools::{
+		object_format::ObjectFormat,
+		testing::{commit, lock, TempRepo},
+	};
+	use std::env;
+
+	#[test]
+	fn keeps_commits_that_fail_to_apply_in_the_todo_list() {
+		let _lock = lock();
+		let repo = TempRepo::new(ObjectFormat::Sha1);
+		let identity = ["UNRUSTY_AUTHOR_NAME", "UNRUSTY_AUTHOR_EMAIL"]
+			.iter()
+			.map(|name| (*name, env::var(name).ok()))
+			.collect::<Vec<_>>();
+		repo.run(|| {
+			let base = commit(&[("a.txt", "a\n")], &[])?;
+			let upstream = commit(
+				&[("a.txt", "a\n"), ("u.txt", "u\n")],
+				slice::from_ref(&base),
+			)?;
+			let topic = commit(
+				&[("a.txt", "a\n"), ("t.txt", "t\n")],
+				slice::from_ref(&base),
+			)?;
+			update_ref("refs/heads/upstream", &upstream, "test")?;
+			update_ref(HEAD, &topic, "test")?;
+			let mut index = Index::read()?;
+			let tree = commit_tree(Some(&topic))?;
+			switch_tree(
+				&mut index,
+				&FlatTree::new(),
+				&tree,
+				false,
+				&Filters::read()?,
+			)?;
+			index.write()?;
+
+			// Without an identity the commit can not be made
+			for (name, _) in &identity {
+				env::remove_var(name);
+			}
+			assert!(rebase("upstream", None, false, false).is_err());
+			let todo = read_todo(TODO)?;
+			assert_eq!(todo.len(), 1);
+			assert_eq!(todo[0].commit()?, Some(topic.clone()));
+			assert_eq!(read_state(DONE)?, None);
+			assert_eq!(Index::read()?.flat_tree(), commit_tree(Some(&upstream))?);
+
+			env::set_var("UNRUSTY_AUTHOR_NAME", "A U Thor");
+			env::set_var("UNRUSTY_AUTHOR_EMAIL", "author@example.com");
+			assert!(matches!(rebase_continue()?, RebaseOutcome::Finished(_)));
+			let rebased = Commit::read(&head_required()?)?;
+			assert_eq!(rebased.parents, vec![upstream]);
+			assert_eq!(flatten_tree(&rebased.tree)?.len(), 3);
+			Ok(())
+		});
+		for (name, value) in identity {
+			match value {
+				Some(value) => env::set_var(name, value),
+				None => env::remove_var(name),
+			}
+		}
+	}
+}
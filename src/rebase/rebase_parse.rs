@@ -0,0 +1,44 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty rebase [-i] [--autosquash] [--onto <newbase>] <upstream>
+         unrusty rebase --continue
+         unrusty rebase --skip
+         unrusty rebase --abort
+         unrusty rebase --help
+
+Options:
+         <upstream>                   Commits of HEAD not in upstream are replayed onto it
+         --onto <newbase>             Replay the commits onto newbase instead of upstream
+         -i, --interactive            Edit the list of commits to replay first, in $EDITOR
+         --autosquash                 Move commits titled \"fixup! ...\" or \"squash! ...\" after \
+                         the commit they refer to and fold them into it
+         --continue                   Commit the resolved conflicts or amended commit and go on
+         --skip                       Leave out the commit the rebase stopped at and go on
+         --abort                      Abort the rebase and restore the branch as it was
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub arg_upstream:     String,
+	pub flag_onto:        Option<String>,
+	pub flag_interactive: bool,
+	pub flag_autosquash:  bool,
+	pub flag_continue:    bool,
+	pub flag_skip:        bool,
+	pub flag_abort:       bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
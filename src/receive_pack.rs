@@ -0,0 +1,3 @@
+pub mod receive_pack_cmd;
+pub mod receive_pack_do;
+mod receive_pack_parse;
@@ -0,0 +1,20 @@
+use super::{receive_pack_do::receive_pack, receive_pack_parse::Args};
+use crate::tools::failed::failed;
+use std::{io, path::Path};
+
+pub fn cmd_receive_pack<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	let res = receive_pack(
+		Path::new(&args.arg_directory),
+		&mut io::stdin().lock(),
+		&mut io::stdout().lock(),
+	);
+	if let Err(e) = res {
+		failed(&format!("Failed receive-pack: {:?}", e));
+	}
+}
@@ -0,0 +1,394 @@
+use crate::tools::{
+	db::{insert, ObjectRepr},
+	object_format::ObjectFormat,
+	pack::read_pack,
+	pktline::{read_until_flush, write_line, write_packet, Packet, AGENT},
+	reachable::reachable_from,
+	refs::{current_branch, delete_ref, list_refs, read_ref, update_ref},
+	rev::is_safe_ref_name,
+	transport::{find_repository, in_repository},
+};
+use std::{
+	convert::TryFrom,
+	error::Error,
+	io::{Read, Write},
+	path::Path,
+};
+
+const CAPABILITIES: &str = "report-status delete-refs ofs-delta";
+
+// An update requested by the client. Missing objects are given as zero hashes
+struct Command {
+	old:  Option<ObjectRepr>,
+	new:  Option<ObjectRepr>,
+	name: String,
+}
+
+fn parse_hash(hash: &str) -> Result<Option<ObjectRepr>, Box<dyn Error>> {
+	if hash == ObjectFormat::current().null_hash() {
+		return Ok(None);
+	}
+	ObjectRepr::try_from(hash)
+		.map(Some)
+		.map_err(|_| format!("Invalid object {}", hash).into())
+}
+
+// Lines of "<old> <new> <ref>", the first one followed by a NUL and the
+// capabilities of the client
+fn parse_commands(packets: &[Packet]) -> Result<(Vec<Command>, Vec<String>), Box<dyn Error>> {
+	let mut commands = Vec::new();
+	let mut capabilities = Vec::new();
+	for packet in packets {
+		let line = packet.text().ok_or("Invalid command")?;
+		let line = match line.split_once('\0') {
+			Some((line, client_capabilities)) => {
+				capabilities.extend(client_capabilities.split(' ').map(str::to_owned));
+				line
+			},
+			None => line,
+		};
+		let mut fields = line.splitn(3, ' ');
+		match (fields.next(), fields.next(), fields.next()) {
+			(Some(old), Some(new), Some(name)) => commands.push(Command {
+				old:  parse_hash(old)?,
+				new:  parse_hash(new)?,
+				name: name.to_owned(),
+			}),
+			_ => return Err(format!("Invalid command {:?}", line).into()),
+		}
+	}
+	Ok((commands, capabilities))
+}
+
+// Protocol v0 lists the refs with the capabilities after the first one. An
+// empty repository advertises the capabilities on a placeholder
+fn advertise_refs(output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+	let capabilities = format!(
+		"{} object-format={} agent={}",
+		CAPABILITIES,
+		ObjectFormat::current(),
+		AGENT
+	);
+	let mut refs = list_refs()?
+		.into_iter()
+		.map(|(name, object_repr)| (object_repr.to_string(), name))
+		.collect::<Vec<_>>();
+	if refs.is_empty() {
+		refs.push((
+			ObjectFormat::current().null_hash(),
+			"capabilities^{}".to_owned(),
+		));
+	}
+	for (i, (object, name)) in refs.iter().enumerate() {
+		if i == 0 {
+			write_line(output, &format!("{} {}\0{}", object, name, capabilities))?;
+		} else {
+			write_line(output, &format!("{} {}", object, name))?;
+		}
+	}
+	write_packet(output, &Packet::Flush)?;
+	Ok(())
+}
+
+// Why an update is refused before any object is stored, None if it may go
+// ahead. The checked out branch may only be pushed to while it is unborn
+fn check_command(command: &Command) -> Result<Option<String>, Box<dyn Error>> {
+	if !command.name.starts_with("refs/") || !is_safe_ref_name(&command.name) {
+		return Ok(Some("funny refname".to_owned()));
+	}
+	let current = read_ref(&command.name)?;
+	if current != command.old {
+		return Ok(Some("stale info".to_owned()));
+	}
+	if current_branch()?.as_deref() == Some(command.name.as_str()) {
+		match (&command.new, &current) {
+			(Some(_), None) => {},
+			(Some(_), Some(_)) => return Ok(Some("branch is currently checked out".to_owned())),
+			(None, _) => return Ok(Some("deletion of the current branch prohibited".to_owned())),
+		}
+	}
+	Ok(None)
+}
+
+// Updates the ref once the objects are stored, or tells why it is refused
+fn apply_command(command: &Command) -> Result<Option<String>, Box<dyn Error>> {
+	match &command.new {
+		Some(new) => {
+			if !reachable_from(vec![(None, new.clone())])?
+				.missing
+				.is_empty()
+			{
+				return Ok(Some("missing necessary objects".to_owned()));
+			}
+			update_ref(&command.name, new, "push")?;
+		},
+		None => delete_ref(&command.name)?,
+	}
+	Ok(None)
+}
+
+// Stores the objects of the pack sent by the client
+fn unpack(pack: &[u8]) -> Result<(), String> {
+	let objects = read_pack(pack).map_err(|e| e.to_string())?;
+	for (object_type, data) in objects {
+		insert(&data, object_type).map_err(|e| e.to_string())?;
+	}
+	Ok(())
+}
+
+// Accepts a push into the repository at `directory` with protocol v0, as git
+// does not push with v2: the refs are advertised, the client sends the
+// updates and a pack of the objects, and each update is reported back.
+// Updates need not be fast-forwards, but the checked out branch is refused.
+// Commands are checked before the objects are stored, and each one that
+// fails is reported as refused without stopping the others
+pub fn receive_pack(
+	directory: &Path,
+	input: &mut dyn Read,
+	output: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+	let root = find_repository(directory)?;
+	in_repository(&root, || {
+		advertise_refs(output)?;
+		output.flush()?;
+		let (commands, capabilities) = parse_commands(&read_until_flush(input)?)?;
+		if commands.is_empty() {
+			return Ok(());
+		}
+
+		let mut pack = Vec::new();
+		if commands.iter().any(|c| c.new.is_some()) {
+			input.read_to_end(&mut pack)?;
+		}
+		let refused = commands
+			.iter()
+			.map(|c| check_command(c).unwrap_or_else(|e| Some(e.to_string())))
+			.collect::<Vec<_>>();
+		// Objects are only stored if some update may use them
+		let unpacked = if !pack.is_empty() && refused.iter().any(Option::is_none) {
+			unpack(&pack)
+		} else {
+			Ok(())
+		};
+
+		let mut report = vec![match &unpacked {
+			Ok(()) => "unpack ok".to_owned(),
+			Err(e) => format!("unpack {}", e),
+		}];
+		for (command, refused) in commands.iter().zip(refused) {
+			let refused = match (refused, &unpacked) {
+				(Some(reason), _) => Some(reason),
+				(None, Err(_)) => Some("unpacker error".to_owned()),
+				(None, Ok(())) => apply_command(command).unwrap_or_else(|e| Some(e.to_string())),
+			};
+			match refused {
+				// Reasons of failed updates must fit on the line
+				Some(reason) => {
+					report.push(format!("ng {} {}", command.name, reason.replace('\n', " ")))
+				},
+				None => report.push(format!("ok {}", command.name)),
+			}
+		}
+
+		if capabilities.iter().any(|c| c == "report-status") {
+			for line in report {
+				write_line(output, &line)?;
+			}
+			write_packet(output, &Packet::Flush)?;
+		}
+		Ok(())
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tools::{
+		pack::write_pack,
+		reachable::objects_between,
+		testing::{commit, lock, TempRepo},
+	};
+	use std::slice;
+
+	// Two commits and a pack of their objects, made in another repository
+	struct Pushed {
+		first:  ObjectRepr,
+		second: ObjectRepr,
+		pack:   Vec<u8>,
+	}
+
+	fn pushed() -> Pushed {
+		let source = TempRepo::new(ObjectFormat::Sha1);
+		source.run(|| {
+			let first = commit(&[("a.txt", "a\n")], &[])?;
+			let second = commit(&[("a.txt", "b\n")], slice::from_ref(&first))?;
+			let mut pack = Vec::new();
+			write_pack(&mut pack, &objects_between(slice::from_ref(&second), &[])?)?;
+			Ok(Pushed {
+				first,
+				second,
+				pack,
+			})
+		})
+	}
+
+	fn hash(object_repr: Option<&ObjectRepr>) -> String {
+		object_repr.map_or_else(|| ObjectFormat::Sha1.null_hash(), |o| o.to_string())
+	}
+
+	// Runs receive-pack with the commands, given as old, new and ref name, and
+	// the pack. Returns the advertisement and the report
+	fn push(
+		repo: &TempRepo,
+		commands: &[(Option<&ObjectRepr>, Option<&ObjectRepr>, &str)],
+		pack: &[u8],
+	) -> (Vec<String>, Vec<String>) {
+		let mut input = Vec::new();
+		for (i, (old, new, name)) in commands.iter().enumerate() {
+			let mut line = format!("{} {} {}", hash(*old), hash(*new), name);
+			if i == 0 {
+				line.push_str("\0report-status agent=test");
+			}
+			write_line(&mut input, &line).unwrap();
+		}
+		write_packet(&mut input, &Packet::Flush).unwrap();
+		input.extend_from_slice(pack);
+
+		let mut output = Vec::new();
+		receive_pack(&repo.path, &mut &input[..], &mut output).unwrap();
+		let mut reader = &output[..];
+		let mut sections = vec![];
+		for _ in 0..2 {
+			let lines = read_until_flush(&mut reader)
+				.unwrap()
+				.iter()
+				.map(|p| p.text().unwrap().to_owned())
+				.collect::<Vec<_>>();
+			sections.push(lines);
+		}
+		assert!(reader.is_empty());
+		let report = sections.pop().unwrap();
+		(sections.pop().unwrap(), report)
+	}
+
+	fn read(repo: &TempRepo, name: &str) -> Option<ObjectRepr> {
+		repo.run(|| Ok(read_ref(name)?))
+	}
+
+	#[test]
+	fn creates_refs_and_the_unborn_current_branch() {
+		let _lock = lock();
+		let pushed = pushed();
+		let repo = TempRepo::new(ObjectFormat::Sha1);
+		let (advertisement, report) = push(
+			&repo,
+			&[
+				(None, Some(&pushed.second), "refs/heads/master"),
+				(None, Some(&pushed.first), "refs/heads/topic"),
+			],
+			&pushed.pack,
+		);
+		assert_eq!(
+			advertisement,
+			vec![format!(
+				"{} capabilities^{{}}\0{} object-format=sha1 agent={}",
+				ObjectFormat::Sha1.null_hash(),
+				CAPABILITIES,
+				AGENT
+			)]
+		);
+		assert_eq!(
+			report,
+			vec!["unpack ok", "ok refs/heads/master", "ok refs/heads/topic"]
+		);
+		assert_eq!(read(&repo, "refs/heads/master"), Some(pushed.second));
+		assert_eq!(read(&repo, "refs/heads/topic"), Some(pushed.first));
+	}
+
+	#[test]
+	fn reports_refused_updates_per_command() {
+		let _lock = lock();
+		let pushed = pushed();
+		let repo = TempRepo::new(ObjectFormat::Sha1);
+		push(
+			&repo,
+			&[
+				(None, Some(&pushed.first), "refs/heads/master"),
+				(None, Some(&pushed.first), "refs/heads/topic"),
+				(None, Some(&pushed.first), "refs/heads/old"),
+			],
+			&pushed.pack,
+		);
+
+		let (advertisement, report) = push(
+			&repo,
+			&[
+				(
+					Some(&pushed.first),
+					Some(&pushed.second),
+					"refs/heads/master",
+				),
+				(
+					Some(&pushed.second),
+					Some(&pushed.first),
+					"refs/heads/topic",
+				),
+				(None, Some(&pushed.second), "refs/heads/../../config"),
+				(Some(&pushed.first), None, "refs/heads/old"),
+				(None, Some(&pushed.second), "refs/heads/new"),
+			],
+			&pushed.pack,
+		);
+		assert_eq!(advertisement.len(), 3);
+		assert!(advertisement[0].starts_with(&format!("{} refs/heads/master\0", pushed.first)));
+		assert_eq!(
+			report,
+			vec![
+				"unpack ok",
+				"ng refs/heads/master branch is currently checked out",
+				"ng refs/heads/topic stale info",
+				"ng refs/heads/../../config funny refname",
+				"ok refs/heads/old",
+				"ok refs/heads/new",
+			]
+		);
+		assert_eq!(read(&repo, "refs/heads/master"), Some(pushed.first.clone()));
+		assert_eq!(read(&repo, "refs/heads/topic"), Some(pushed.first));
+		assert_eq!(read(&repo, "refs/heads/old"), None);
+		assert_eq!(read(&repo, "refs/heads/new"), Some(pushed.second));
+	}
+
+	#[test]
+	fn refuses_updates_when_the_pack_is_corrupt() {
+		let _lock = lock();
+		let pushed = pushed();
+		let repo = TempRepo::new(ObjectFormat::Sha1);
+		let mut pack = pushed.pack.clone();
+		let last = pack.len() - 1;
+		pack[last] ^= 1;
+		let (_, report) = push(
+			&repo,
+			&[(None, Some(&pushed.second), "refs/heads/topic")],
+			&pack,
+		);
+		assert_eq!(report[0], "unpack Corrupt pack: checksum mismatch");
+		assert_eq!(report[1], "ng refs/heads/topic unpacker error");
+		assert_eq!(read(&repo, "refs/heads/topic"), None);
+	}
+
+	#[test]
+	fn refuses_updates_to_missing_objects() {
+		let _lock = lock();
+		let pushed = pushed();
+		let repo = TempRepo::new(ObjectFormat::Sha1);
+		let (_, report) = push(
+			&repo,
+			&[(None, Some(&pushed.second), "refs/heads/topic")],
+			&[],
+		);
+		assert_eq!(
+			report,
+			vec!["unpack ok", "ng refs/heads/topic missing necessary objects"]
+		);
+	}
+}
@@ -0,0 +1,3 @@
+pub mod reflog_cmd;
+pub mod reflog_do;
+mod reflog_parse;
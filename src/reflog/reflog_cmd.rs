@@ -0,0 +1,31 @@
+use super::{
+	reflog_do::{reflog_delete, reflog_expire, reflog_show},
+	reflog_parse::Args,
+};
+use crate::tools::{date::parse_date, failed::failed, refs::HEAD};
+
+pub fn cmd_reflog<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	if args.cmd_expire {
+		let expire = parse_date(&args.flag_expire)
+			.unwrap_or_else(|| failed(&format!("Invalid date {:?}", args.flag_expire)));
+		reflog_expire(&args.arg_ref, args.flag_all, expire, args.flag_dry_run)
+			.unwrap_or_else(|e| failed(&format!("Failed reflog expire: {:?}", e)));
+	} else if args.cmd_delete {
+		reflog_delete(&args.arg_entry, args.flag_dry_run)
+			.unwrap_or_else(|e| failed(&format!("Failed reflog delete: {:?}", e)));
+	} else {
+		let name = args.arg_ref.first().map_or(HEAD, |r| r.as_str());
+		let entries =
+			reflog_show(name).unwrap_or_else(|e| failed(&format!("Failed reflog show: {:?}", e)));
+		for (n, entry) in entries.iter().enumerate() {
+			let hash = entry.new.as_ref().map_or("0000000", |r| r.short());
+			println!("{} {}@{{{}}}: {}", hash, name, n, entry.message);
+		}
+	}
+}
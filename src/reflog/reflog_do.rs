@@ -0,0 +1,60 @@
+use crate::tools::{
+	reflog::{expire_reflog, list_reflogs, read_reflog, write_reflog, ReflogEntry},
+	rev::{reflog_ref_name, split_reflog_selector},
+};
+use std::{error::Error, time::SystemTime};
+
+// Entries of the log of the ref, newest first
+pub fn reflog_show(name: &str) -> Result<Vec<ReflogEntry>, Box<dyn Error>> {
+	let mut entries = read_reflog(&reflog_ref_name(name)?)?;
+	entries.reverse();
+	Ok(entries)
+}
+
+// Removes entries older than `expire` from the logs of the refs, or of all
+// refs. Returns the number of removed entries
+pub fn reflog_expire(
+	names: &[String],
+	all: bool,
+	expire: SystemTime,
+	dry_run: bool,
+) -> Result<usize, Box<dyn Error>> {
+	let names = if all {
+		list_reflogs()?
+	} else {
+		names
+			.iter()
+			.map(|n| reflog_ref_name(n))
+			.collect::<Result<Vec<_>, _>>()?
+	};
+	let mut expired = 0;
+	for name in names {
+		expired += expire_reflog(&name, expire, dry_run)?;
+	}
+	Ok(expired)
+}
+
+// Deletes single entries, given as "<ref>@{<n>}"
+pub fn reflog_delete(selectors: &[String], dry_run: bool) -> Result<(), Box<dyn Error>> {
+	let mut deletions = Vec::new();
+	for selector in selectors {
+		let (name, n) = split_reflog_selector(selector)
+			.and_then(|(name, n)| n.parse::<usize>().ok().map(|n| (name, n)))
+			.ok_or_else(|| format!("{:?} is not a reflog entry like HEAD@{{1}}", selector))?;
+		deletions.push((reflog_ref_name(name)?, n));
+	}
+	// Higher numbers first, so that deleting keeps the other numbers valid
+	deletions.sort_by(|a, b| b.cmp(a));
+	deletions.dedup();
+	for (name, n) in deletions {
+		let mut entries = read_reflog(&name)?;
+		if n >= entries.len() {
+			return Err(format!("{}@{{{}}} does not exist", name, n).into());
+		}
+		entries.remove(entries.len() - 1 - n);
+		if !dry_run {
+			write_reflog(&name, &entries)?;
+		}
+	}
+	Ok(())
+}
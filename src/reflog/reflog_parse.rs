@@ -0,0 +1,41 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty reflog [show] [<ref>]
+         unrusty reflog expire [-n] [--expire=<date>] (--all | <ref>...)
+         unrusty reflog delete [-n] <entry>...
+         unrusty reflog --help
+
+Options:
+         <ref>                        Ref whose log is considered, defaults to HEAD for show
+         <entry>                      Log entry to delete, e.g. master@{2}
+         --all                        Expire the logs of all refs
+         --expire=<date>              Remove entries older than the date, e.g. 90.days.ago or \
+                         2024-01-31 [default: 90.days.ago]
+         -n, --dry-run                Do not change the logs
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub cmd_expire:   bool,
+	pub cmd_delete:   bool,
+	pub arg_ref:      Vec<String>,
+	pub arg_entry:    Vec<String>,
+	pub flag_all:     bool,
+	pub flag_expire:  String,
+	pub flag_dry_run: bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
@@ -0,0 +1,3 @@
+pub mod remote_cmd;
+pub mod remote_do;
+mod remote_parse;
@@ -0,0 +1,37 @@
+use super::{
+	remote_do::{remote_add, remote_list, remote_remove, remote_rename},
+	remote_parse::Args,
+};
+use crate::tools::failed::failed;
+
+pub fn cmd_remote<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	if args.cmd_add {
+		remote_add(&args.arg_name, &args.arg_url, &args.flag_fetch)
+			.unwrap_or_else(|e| failed(&format!("Failed remote add: {:?}", e)));
+	} else if args.cmd_remove {
+		remote_remove(&args.arg_name)
+			.unwrap_or_else(|e| failed(&format!("Failed remote remove: {:?}", e)));
+	} else if args.cmd_rename {
+		remote_rename(&args.arg_old, &args.arg_new)
+			.unwrap_or_else(|e| failed(&format!("Failed remote rename: {:?}", e)));
+	} else {
+		let remotes = remote_list().unwrap_or_else(|e| failed(&format!("Failed remote: {:?}", e)));
+		for (name, remote) in remotes {
+			if args.flag_verbose {
+				println!("{}\t{} (fetch)", name, remote.url);
+				println!("{}\t{} (push)", name, remote.url);
+				for refspec in &remote.fetch {
+					println!("{}\t{} (refspec)", name, refspec);
+				}
+			} else {
+				println!("{}", name);
+			}
+		}
+	}
+}
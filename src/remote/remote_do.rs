@@ -0,0 +1,111 @@
+use crate::tools::{
+	config::{Config, RemoteConfig},
+	reflog::{read_reflog, write_reflog},
+	refs::{
+		delete_ref, list_loose_refs, list_refs, read_ref_raw, write_ref, write_symbolic_ref,
+		RefValue,
+	},
+	remote::{default_fetch_refspec, is_valid_remote_name, parse_refspecs, remote_tracking_prefix},
+};
+use std::{collections::BTreeSet, error::Error};
+
+pub fn remote_list() -> Result<Vec<(String, RemoteConfig)>, Box<dyn Error>> {
+	Ok(Config::read()?.remote.into_iter().collect())
+}
+
+pub fn remote_add(name: &str, url: &str, fetch: &[String]) -> Result<(), Box<dyn Error>> {
+	if !is_valid_remote_name(name) {
+		return Err(format!("{:?} is not a valid remote name", name).into());
+	}
+	let mut config = Config::read()?;
+	if config.remote.contains_key(name) {
+		return Err(format!("remote {} already exists", name).into());
+	}
+	let fetch = if fetch.is_empty() {
+		vec![default_fetch_refspec(name)]
+	} else {
+		fetch.to_vec()
+	};
+	parse_refspecs(&fetch)?;
+	config.remote.insert(
+		name.to_owned(),
+		RemoteConfig {
+			url: url.to_owned(),
+			fetch,
+		},
+	);
+	Ok(config.write()?)
+}
+
+// Refs that fetching from the remote creates, loose and packed, including
+// symbolic ones like refs/remotes/origin/HEAD
+fn tracking_refs(remote: &RemoteConfig) -> Result<Vec<String>, Box<dyn Error>> {
+	let refspecs = parse_refspecs(&remote.fetch)?;
+	let mut names = list_refs()?
+		.into_iter()
+		.map(|(name, _)| name)
+		.collect::<BTreeSet<_>>();
+	names.extend(list_loose_refs()?);
+	Ok(names
+		.into_iter()
+		.filter(|name| refspecs.iter().any(|r| r.reverse_map(name).is_some()))
+		.collect())
+}
+
+// Removes the remote, its remote-tracking refs and the upstream settings of
+// branches that track it
+pub fn remote_remove(name: &str) -> Result<(), Box<dyn Error>> {
+	let mut config = Config::read()?;
+	let remote = config
+		.remote
+		.remove(name)
+		.ok_or_else(|| format!("no such remote {}", name))?;
+	for tracking in tracking_refs(&remote)? {
+		delete_ref(&tracking)?;
+	}
+	config
+		.branch
+		.retain(|_, branch| branch.remote.as_deref() != Some(name));
+	Ok(config.write()?)
+}
+
+// Renames the remote and moves its remote-tracking refs, with their logs
+pub fn remote_rename(old: &str, new: &str) -> Result<(), Box<dyn Error>> {
+	if !is_valid_remote_name(new) {
+		return Err(format!("{:?} is not a valid remote name", new).into());
+	}
+	let mut config = Config::read()?;
+	if config.remote.contains_key(new) {
+		return Err(format!("remote {} already exists", new).into());
+	}
+	let mut remote = config
+		.remote
+		.remove(old)
+		.ok_or_else(|| format!("no such remote {}", old))?;
+	let old_prefix = remote_tracking_prefix(old);
+	let new_prefix = remote_tracking_prefix(new);
+	let renamed = |name: &str| name.replacen(&old_prefix, &new_prefix, 1);
+
+	for tracking in tracking_refs(&remote)? {
+		match read_ref_raw(&tracking)? {
+			Some(RefValue::Direct(object_repr)) => write_ref(&renamed(&tracking), &object_repr)?,
+			Some(RefValue::Symbolic(target)) => {
+				write_symbolic_ref(&renamed(&tracking), &renamed(&target))?
+			},
+			None => continue,
+		}
+		let log = read_reflog(&tracking)?;
+		if !log.is_empty() {
+			write_reflog(&renamed(&tracking), &log)?;
+		}
+		delete_ref(&tracking)?;
+	}
+	remote.fetch = remote.fetch.iter().map(|spec| renamed(spec)).collect();
+	config.remote.insert(new.to_owned(), remote);
+	for branch in config.branch.values_mut() {
+		if branch.remote.as_deref() == Some(old) {
+			branch.remote = Some(new.to_owned());
+		}
+	}
+	Ok(config.write()?)
+}
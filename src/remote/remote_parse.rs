@@ -0,0 +1,41 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty remote [-v]
+         unrusty remote add [--fetch=<refspec>...] <name> <url>
+         unrusty remote remove <name>
+         unrusty remote rename <old> <new>
+         unrusty remote --help
+
+Options:
+         -v, --verbose                Show the URL and refspecs of each remote
+         --fetch=<refspec>            Refspec to fetch with, defaults to all branches as \
+                         refs/remotes/<name>/*
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub cmd_add:      bool,
+	pub cmd_remove:   bool,
+	pub cmd_rename:   bool,
+	pub arg_name:     String,
+	pub arg_url:      String,
+	pub arg_old:      String,
+	pub arg_new:      String,
+	pub flag_fetch:   Vec<String>,
+	pub flag_verbose: bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
@@ -0,0 +1,3 @@
+pub mod restore_cmd;
+pub mod restore_do;
+mod restore_parse;
@@ -0,0 +1,21 @@
+use super::{restore_do::restore, restore_parse::Args};
+use crate::tools::failed::failed;
+
+pub fn cmd_restore<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+	// Without any location, only the working tree is restored
+	let worktree = args.flag_worktree || !args.flag_staged;
+
+	if let Err(e) = restore(
+		&args.arg_pathspec,
+		args.flag_source.as_deref(),
+		args.flag_staged,
+		worktree,
+	) {
+		failed(&format!("Failed restore: {:?}", e));
+	}
+}
@@ -0,0 +1,96 @@
+use crate::tools::{
+	filter::Filters,
+	index::Index,
+	path::RelativePathToBase,
+	refs::{read_ref, HEAD},
+	rev::{peel_to_tree, resolve_revision},
+	tree::{flatten_tree, FlatTree},
+	worktree::{checkout_entry, remove_entry},
+};
+use log::info;
+use std::{collections::BTreeSet, error::Error, path::Path};
+
+// The tree to restore from. Without an explicit source, the working tree is
+// restored from the index and the index from HEAD
+fn source_tree(
+	source: Option<&str>,
+	staged: bool,
+	index: &Index,
+) -> Result<FlatTree, Box<dyn Error>> {
+	let rev = match source {
+		Some(rev) => Some(resolve_revision(rev)?),
+		None if staged => read_ref(HEAD)?,
+		None => return Ok(index.flat_tree()),
+	};
+	match rev {
+		Some(rev) => Ok(flatten_tree(&peel_to_tree(&rev)?)?),
+		// HEAD of a repository without commits, there is nothing staged yet
+		None => Ok(FlatTree::new()),
+	}
+}
+
+pub fn restore<'a, I, J>(
+	pathspecs: I,
+	source: Option<&str>,
+	staged: bool,
+	worktree: bool,
+) -> Result<(), Box<dyn Error>>
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<Path> + 'a,
+{
+	let mut index = Index::read()?;
+	let filters = Filters::read()?;
+	let source = source_tree(source, staged, &index)?;
+	let tracked = index.flat_tree();
+
+	let mut paths = BTreeSet::new();
+	for pathspec in pathspecs {
+		let pathspec = RelativePathToBase::from_user_path(pathspec.as_ref())?;
+		let matching = source
+			.keys()
+			.chain(tracked.keys())
+			.filter(|p| pathspec.contains(p))
+			.cloned()
+			.collect::<Vec<_>>();
+		if matching.is_empty() {
+			return Err(format!(
+				"pathspec {:?} did not match any file known to unrusty",
+				pathspec.as_path()
+			)
+			.into());
+		}
+		paths.extend(matching);
+	}
+
+	for path in &paths {
+		match source.get(path) {
+			Some(entry) => {
+				if worktree {
+					info!("restoring {:?} in working tree", path.as_path());
+					checkout_entry(path, entry, &filters)?;
+				}
+				if staged {
+					info!("restoring {:?} in index", path.as_path());
+					index.set_entry(path.clone(), entry, worktree)?;
+				}
+			},
+			// Tracked files missing in the source are removed
+			None => {
+				if worktree {
+					info!("removing {:?} from working tree", path.as_path());
+					remove_entry(path)?;
+				}
+				if staged {
+					info!("removing {:?} from index", path.as_path());
+					index.remove(path);
+				}
+			},
+		}
+	}
+
+	if staged {
+		index.write()?;
+	}
+	Ok(())
+}
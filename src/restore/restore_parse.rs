@@ -0,0 +1,35 @@
+use docopt::Docopt;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "
+Usage:   unrusty restore [--staged] [--worktree] [--source=<rev>] [--] <pathspec>...
+         unrusty restore --help
+
+Options:
+         -W, --worktree               Restore the working tree (default)
+         -S, --staged                 Restore the index
+         -s <rev>, --source=<rev>     Restore from the given commit or tree, defaults to the index \
+                         for the working tree and to HEAD for the index
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub arg_pathspec:  Vec<PathBuf>,
+	pub flag_staged:   bool,
+	pub flag_worktree: bool,
+	pub flag_source:   Option<String>,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
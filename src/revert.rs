@@ -0,0 +1,3 @@
+pub mod revert_cmd;
+pub mod revert_do;
+mod revert_parse;
@@ -0,0 +1,31 @@
+use super::{revert_do::revert, revert_parse::Args};
+use crate::{
+	cherry_pick::{
+		cherry_pick_cmd::report_sequence,
+		cherry_pick_do::{replay_abort, replay_continue, replay_skip, ReplayOptions},
+	},
+	tools::failed::failed,
+};
+
+pub fn cmd_revert<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	if args.flag_abort {
+		replay_abort().unwrap_or_else(|e| failed(&format!("Failed revert --abort: {:?}", e)));
+	} else if args.flag_continue {
+		report_sequence(replay_continue(), "revert --continue");
+	} else if args.flag_skip {
+		report_sequence(replay_skip(), "revert --skip");
+	} else {
+		let options = ReplayOptions {
+			no_commit: args.flag_no_commit,
+			mainline: args.flag_mainline,
+			..ReplayOptions::default()
+		};
+		report_sequence(revert(&args.arg_commit, &options), "revert");
+	}
+}
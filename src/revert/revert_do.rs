@@ -0,0 +1,8 @@
+use crate::cherry_pick::cherry_pick_do::{replay, Replay, ReplayOptions, Sequence};
+use std::error::Error;
+
+// Reverting shares the sequencer of cherry-pick, --continue, --skip and --abort
+// work on either
+pub fn revert(revs: &[String], options: &ReplayOptions) -> Result<Sequence, Box<dyn Error>> {
+	replay(Replay::Revert, revs, options)
+}
@@ -0,0 +1,43 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty revert [-n] [-m <parent>] <commit>...
+         unrusty revert --continue
+         unrusty revert --skip
+         unrusty revert --abort
+         unrusty revert --help
+
+Options:
+         <commit>                     Commits to revert in order, or ranges like main..topic which \
+                         are reverted newest first
+         -n, --no-commit              Apply the changes to the index and working tree only
+         -m <parent>, --mainline=<parent>  Parent number of merge commits to revert the changes \
+                         against, from 1
+         --continue                   Commit the resolved conflicts and go on with the next commits
+         --skip                       Leave out the commit that conflicted and go on
+         --abort                      Abort and restore HEAD as it was before
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub arg_commit:     Vec<String>,
+	pub flag_no_commit: bool,
+	pub flag_mainline:  Option<usize>,
+	pub flag_continue:  bool,
+	pub flag_skip:      bool,
+	pub flag_abort:     bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
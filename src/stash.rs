@@ -0,0 +1,3 @@
+pub mod stash_cmd;
+pub mod stash_do;
+mod stash_parse;
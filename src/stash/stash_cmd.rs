@@ -0,0 +1,81 @@
+use super::{
+	stash_do::{
+		stash_apply, stash_clear, stash_drop, stash_list, stash_pop, stash_push, stash_show,
+		ApplyOutcome,
+	},
+	stash_parse::Args,
+};
+use crate::tools::{db::ObjectRepr, failed::failed, path::RelativePathToBase};
+use std::{
+	io::{self, Write},
+	process::exit,
+};
+
+fn print_conflicts(paths: &[RelativePathToBase]) {
+	for path in paths {
+		println!("CONFLICT in {}", path);
+	}
+}
+
+fn print_dropped(name: &str, commit: &ObjectRepr) {
+	println!("Dropped {} ({})", name, commit);
+}
+
+pub fn cmd_stash<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+	let stash = args.arg_stash.as_deref();
+
+	if args.cmd_list {
+		let entries =
+			stash_list().unwrap_or_else(|e| failed(&format!("Failed stash list: {:?}", e)));
+		for (n, entry) in entries.iter().enumerate() {
+			println!("stash@{{{}}}: {}", n, entry.message);
+		}
+	} else if args.cmd_show {
+		let output = stash_show(stash, args.flag_patch)
+			.unwrap_or_else(|e| failed(&format!("Failed stash show: {:?}", e)));
+		io::stdout()
+			.write_all(&output)
+			.unwrap_or_else(|e| failed(&format!("Failed stash show: {:?}", e)));
+	} else if args.cmd_apply {
+		match stash_apply(stash, args.flag_index) {
+			Ok(ApplyOutcome::Applied) => {},
+			Ok(ApplyOutcome::Conflicted(paths)) => {
+				print_conflicts(&paths);
+				exit(1);
+			},
+			Err(e) => failed(&format!("Failed stash apply: {:?}", e)),
+		}
+	} else if args.cmd_pop {
+		match stash_pop(stash, args.flag_index) {
+			Ok((_, Some((name, commit)))) => print_dropped(&name, &commit),
+			Ok((ApplyOutcome::Conflicted(paths), _)) => {
+				print_conflicts(&paths);
+				println!("The stash entry is kept in case you need it again.");
+				exit(1);
+			},
+			Ok((ApplyOutcome::Applied, None)) => {},
+			Err(e) => failed(&format!("Failed stash pop: {:?}", e)),
+		}
+	} else if args.cmd_drop {
+		let (name, commit) =
+			stash_drop(stash).unwrap_or_else(|e| failed(&format!("Failed stash drop: {:?}", e)));
+		print_dropped(&name, &commit);
+	} else if args.cmd_clear {
+		stash_clear().unwrap_or_else(|e| failed(&format!("Failed stash clear: {:?}", e)));
+	} else {
+		match stash_push(
+			args.flag_message.as_deref(),
+			args.flag_keep_index,
+			args.flag_include_untracked,
+		) {
+			Ok(Some(message)) => println!("Saved working directory and index state {}", message),
+			Ok(None) => println!("No local changes to save"),
+			Err(e) => failed(&format!("Failed stash push: {:?}", e)),
+		}
+	}
+}
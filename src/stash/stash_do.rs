@@ -0,0 +1,400 @@
+use crate::{
+	merge::merge_do::conflict_style,
+	tools::{
+		commit::{commit_tree, subject, Commit},
+		db::{insert, ObjectRepr, ObjectType},
+		filter::Filters,
+		ignore::Ignore,
+		index::Index,
+		merge::{merge_trees, MergeLabels},
+		patch::{
+			diffstat, file_stats, tree_changes, write_patches, Blobs, PatchOptions, STAT_WIDTH,
+		},
+		path::RelativePathToBase,
+		reflog::{read_reflog, write_reflog, ReflogEntry},
+		refs::{current_branch, delete_ref, read_ref, update_ref, write_ref, HEAD, STASH},
+		rename::{detect_renames, RenameOptions},
+		rev::split_reflog_selector,
+		tree::{flatten_tree, write_flat_tree, FlatEntry, FlatTree},
+		worktree::{
+			checkout_content, checkout_entry, list_worktree_files, read_worktree_entry,
+			remove_entry, switch_tree, untracked_in_the_way,
+		},
+	},
+};
+use std::{collections::BTreeSet, error::Error, fs};
+
+const HEADS_PREFIX: &str = "refs/heads/";
+
+// Name and commit of a dropped stash
+pub type Dropped = (String, ObjectRepr);
+
+pub enum ApplyOutcome {
+	Applied,
+	Conflicted(Vec<RelativePathToBase>),
+}
+
+// "stash@{<n>}", as stashes are shown
+fn stash_name(n: usize) -> String {
+	format!("stash@{{{}}}", n)
+}
+
+// Position of a stash given as "stash@{<n>}" or "<n>", the newest by default
+fn stash_position(stash: Option<&str>) -> Result<usize, Box<dyn Error>> {
+	let stash = match stash {
+		Some(stash) => stash,
+		None => return Ok(0),
+	};
+	let n = match split_reflog_selector(stash) {
+		Some(("stash", n)) | Some((STASH, n)) => n,
+		Some(_) => "",
+		None => stash,
+	};
+	n.parse::<usize>()
+		.map_err(|_| format!("{:?} is not a stash reference", stash).into())
+}
+
+// Stashes, newest first
+fn stash_entries() -> Result<Vec<ReflogEntry>, Box<dyn Error>> {
+	let mut entries = read_reflog(STASH)?;
+	entries.reverse();
+	Ok(entries)
+}
+
+fn stash_commit(n: usize) -> Result<ObjectRepr, Box<dyn Error>> {
+	let entries = stash_entries()?;
+	if entries.is_empty() {
+		return Err("no stash entries found".into());
+	}
+	entries
+		.get(n)
+		.and_then(|entry| entry.new.clone())
+		.ok_or_else(|| format!("{} does not exist", stash_name(n)).into())
+}
+
+// A stash is a commit of the working tree whose parents are HEAD at the time,
+// a commit of the index and, optionally, a commit of the untracked files
+struct Stash {
+	base:      ObjectRepr,
+	index:     ObjectRepr,
+	untracked: Option<ObjectRepr>,
+	worktree:  ObjectRepr,
+}
+
+fn read_stash(stash: &ObjectRepr) -> Result<Stash, Box<dyn Error>> {
+	let commit = Commit::read(stash)?;
+	match commit.parents.as_slice() {
+		[base, index, rest @ ..] if rest.len() <= 1 => Ok(Stash {
+			base:      base.clone(),
+			index:     index.clone(),
+			untracked: rest.first().cloned(),
+			worktree:  commit.tree,
+		}),
+		_ => Err(format!("{} is not a stash commit", stash).into()),
+	}
+}
+
+// HEAD, the name of the branch and "<branch>: <short hash> <subject>" as
+// used in the messages of the stash commits
+fn describe_head() -> Result<(ObjectRepr, String, String), Box<dyn Error>> {
+	let head = read_ref(HEAD)?.ok_or("you do not have the initial commit yet")?;
+	let branch = match current_branch()? {
+		Some(branch) => branch.trim_start_matches(HEADS_PREFIX).to_owned(),
+		None => "(no branch)".to_owned(),
+	};
+	let message = Commit::read(&head)?.message;
+	let description = format!("{}: {} {}", branch, head.short(), subject(&message));
+	Ok((head, branch, description))
+}
+
+// Stores the file as it is in the working tree
+fn file_entry(path: &RelativePathToBase, filters: &Filters) -> Result<FlatEntry, Box<dyn Error>> {
+	let (mode, content) =
+		read_worktree_entry(path, filters)?.ok_or_else(|| format!("{} does not exist", path))?;
+	Ok(FlatEntry {
+		mode,
+		hash: insert(&content, ObjectType::Blob)?,
+	})
+}
+
+// The tracked files as they are in the working tree. Deleted files are left
+// out, unmodified ones keep the entry of the index
+fn worktree_tree(index: &Index, filters: &Filters) -> Result<FlatTree, Box<dyn Error>> {
+	let mut tree = FlatTree::new();
+	for (path, entry) in index.flat_tree() {
+		if fs::symlink_metadata(path.absolute()?).is_err() {
+			continue;
+		}
+		let entry = if index.worktree_modified(&path, filters)? {
+			file_entry(&path, filters)?
+		} else {
+			entry
+		};
+		tree.insert(path, entry);
+	}
+	Ok(tree)
+}
+
+fn untracked_files(index: &Index) -> Result<Vec<RelativePathToBase>, Box<dyn Error>> {
+	let ignore = Ignore::read()?;
+	let tracked = index.flat_tree();
+	Ok(list_worktree_files()?
+		.into_iter()
+		.filter(|path| !tracked.contains_key(path) && !ignore.is_ignored(path, false))
+		.collect())
+}
+
+// Saves the local changes as a new stash and resets the working tree and the
+// index to HEAD, or the working tree to the index with `keep_index`. Returns
+// the message of the stash, None if there was nothing to save
+pub fn stash_push(
+	message: Option<&str>,
+	keep_index: bool,
+	include_untracked: bool,
+) -> Result<Option<String>, Box<dyn Error>> {
+	let mut index = Index::read()?;
+	if !index.conflicts().is_empty() {
+		return Err("the index has unresolved conflicts".into());
+	}
+	let filters = Filters::read()?;
+	let (head, branch, description) = describe_head()?;
+	let head_tree = commit_tree(Some(&head))?;
+	let index_tree = index.flat_tree();
+	let worktree = worktree_tree(&index, &filters)?;
+	let untracked = if include_untracked {
+		untracked_files(&index)?
+	} else {
+		Vec::new()
+	};
+	if index_tree == head_tree && worktree == index_tree && untracked.is_empty() {
+		return Ok(None);
+	}
+
+	let index_commit = Commit::new(
+		write_flat_tree(&index_tree)?,
+		vec![head.clone()],
+		&format!("index on {}", description),
+	)?
+	.write()?;
+	let mut parents = vec![head, index_commit];
+	if !untracked.is_empty() {
+		let mut tree = FlatTree::new();
+		for path in &untracked {
+			tree.insert(path.clone(), file_entry(path, &filters)?);
+		}
+		let untracked_commit = Commit::new(
+			write_flat_tree(&tree)?,
+			Vec::new(),
+			&format!("untracked files on {}", description),
+		)?
+		.write()?;
+		parents.push(untracked_commit);
+	}
+	let message = match message {
+		Some(message) => format!("On {}: {}", branch, message),
+		None => format!("WIP on {}", description),
+	};
+	let stash = Commit::new(write_flat_tree(&worktree)?, parents, &message)?.write()?;
+	update_ref(STASH, &stash, &message)?;
+
+	let target = if keep_index { index_tree } else { head_tree };
+	switch_tree(&mut index, &worktree, &target, false, &filters)?;
+	// Staged files that are deleted in the working tree
+	let leftover = index
+		.flat_tree()
+		.into_keys()
+		.filter(|path| !target.contains_key(path))
+		.collect::<Vec<_>>();
+	for path in leftover {
+		index.remove(&path);
+	}
+	for path in &untracked {
+		remove_entry(path)?;
+	}
+	index.write()?;
+	Ok(Some(message))
+}
+
+// Stashes, newest first
+pub fn stash_list() -> Result<Vec<ReflogEntry>, Box<dyn Error>> {
+	stash_entries()
+}
+
+// The changes of the stash to the commit it was made on, as a diffstat or a
+// patch
+pub fn stash_show(stash: Option<&str>, patch: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+	let stash = read_stash(&stash_commit(stash_position(stash)?)?)?;
+	let base = commit_tree(Some(&stash.base))?;
+	let changes = tree_changes(&base, &flatten_tree(&stash.worktree)?);
+	let blobs = Blobs::new();
+	let changes = detect_renames(changes, &base, &blobs, &RenameOptions::default())?;
+	if patch {
+		Ok(write_patches(&changes, &blobs, &PatchOptions::default())?)
+	} else if changes.is_empty() {
+		Ok(Vec::new())
+	} else {
+		Ok(diffstat(&file_stats(&changes, &blobs)?, STAT_WIDTH, false).into_bytes())
+	}
+}
+
+// Applies the changes of the stash to the working tree with a three-way merge
+// against the commit it was made on. Added files are staged, other changes
+// only are with `restore_index`, which then must apply without conflicts
+pub fn stash_apply(
+	stash: Option<&str>,
+	restore_index: bool,
+) -> Result<ApplyOutcome, Box<dyn Error>> {
+	let stash = read_stash(&stash_commit(stash_position(stash)?)?)?;
+	let mut index = Index::read()?;
+	if !index.conflicts().is_empty() {
+		return Err("the index has unresolved conflicts".into());
+	}
+	let filters = Filters::read()?;
+	let current = index.flat_tree();
+	let base = commit_tree(Some(&stash.base))?;
+	let untracked = commit_tree(stash.untracked.as_ref())?;
+	for path in untracked.keys() {
+		if current.contains_key(path) || fs::symlink_metadata(path.absolute()?).is_ok() {
+			return Err(format!("{} already exists, no checkout", path).into());
+		}
+	}
+
+	let style = conflict_style(None)?;
+	let labels = MergeLabels {
+		base:   "Stash base",
+		ours:   "Updated upstream",
+		theirs: "Stashed changes",
+	};
+	let staged = if restore_index {
+		let merged = merge_trees(
+			&base,
+			&current,
+			&commit_tree(Some(&stash.index))?,
+			&labels,
+			style,
+		)?;
+		if !merged.conflicts.is_empty() {
+			return Err("conflicts in the index, try without --index".into());
+		}
+		Some(merged.tree)
+	} else {
+		None
+	};
+	let result = merge_trees(
+		&base,
+		&current,
+		&flatten_tree(&stash.worktree)?,
+		&labels,
+		style,
+	)?;
+	let conflicted = result
+		.conflicts
+		.iter()
+		.map(|c| c.path.clone())
+		.collect::<BTreeSet<_>>();
+	let changes = tree_changes(&current, &result.tree)
+		.into_iter()
+		.filter(|change| !conflicted.contains(&change.path))
+		.collect::<Vec<_>>();
+
+	// Local changes to the files about to be written would be lost
+	let mut modified = Vec::new();
+	for path in changes.iter().map(|c| &c.path).chain(conflicted.iter()) {
+		if current.contains_key(path) && index.worktree_modified(path, &filters)? {
+			modified.push(path.to_string());
+		}
+	}
+	if !modified.is_empty() {
+		return Err(format!(
+			"local changes would be overwritten: {}",
+			modified.join(", ")
+		)
+		.into());
+	}
+	let in_the_way = untracked_in_the_way(&index, &result.tree)?;
+	if !in_the_way.is_empty() {
+		let paths = in_the_way.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+		return Err(format!("untracked files would be overwritten: {}", paths.join(", ")).into());
+	}
+
+	for change in &changes {
+		match &change.new {
+			Some(entry) => checkout_entry(&change.path, entry, &filters)?,
+			None => remove_entry(&change.path)?,
+		}
+	}
+	for conflict in &result.conflicts {
+		let (mode, content) = &conflict.worktree;
+		checkout_content(&conflict.path, *mode, content, &filters)?;
+	}
+	for (path, entry) in &untracked {
+		checkout_entry(path, entry, &filters)?;
+	}
+
+	match staged {
+		Some(staged) => {
+			for change in tree_changes(&current, &staged) {
+				match &change.new {
+					Some(entry) => {
+						let in_worktree = result.tree.get(&change.path) == Some(entry);
+						index.set_entry(change.path, entry, in_worktree)?;
+					},
+					None => index.remove(&change.path),
+				}
+			}
+		},
+		None => {
+			for change in changes.into_iter().filter(|c| c.old.is_none()) {
+				if let Some(entry) = &change.new {
+					index.set_entry(change.path, entry, true)?;
+				}
+			}
+		},
+	}
+	for conflict in &result.conflicts {
+		index.set_conflict(
+			conflict.path.clone(),
+			conflict.base.as_ref(),
+			conflict.ours.as_ref(),
+			conflict.theirs.as_ref(),
+		);
+	}
+	index.write()?;
+
+	if conflicted.is_empty() {
+		Ok(ApplyOutcome::Applied)
+	} else {
+		Ok(ApplyOutcome::Conflicted(conflicted.into_iter().collect()))
+	}
+}
+
+// Removes the stash from the stack. Returns its name and commit
+pub fn stash_drop(stash: Option<&str>) -> Result<Dropped, Box<dyn Error>> {
+	let n = stash_position(stash)?;
+	let commit = stash_commit(n)?;
+	let mut entries = read_reflog(STASH)?;
+	entries.remove(entries.len() - 1 - n);
+	match entries.last().and_then(|entry| entry.new.clone()) {
+		Some(newest) => {
+			write_reflog(STASH, &entries)?;
+			write_ref(STASH, &newest)?;
+		},
+		None => delete_ref(STASH)?,
+	}
+	Ok((stash_name(n), commit))
+}
+
+// Applies the stash and drops it, unless applying it left conflicts
+pub fn stash_pop(
+	stash: Option<&str>,
+	restore_index: bool,
+) -> Result<(ApplyOutcome, Option<Dropped>), Box<dyn Error>> {
+	match stash_apply(stash, restore_index)? {
+		ApplyOutcome::Applied => Ok((ApplyOutcome::Applied, Some(stash_drop(stash)?))),
+		conflicted => Ok((conflicted, None)),
+	}
+}
+
+pub fn stash_clear() -> Result<(), Box<dyn Error>> {
+	Ok(delete_ref(STASH)?)
+}
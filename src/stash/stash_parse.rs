@@ -0,0 +1,49 @@
+use docopt::Docopt;
+use serde::Deserialize;
+
+pub const USAGE: &str = "
+Usage:   unrusty stash [push] [-k] [-u] [-m <message>]
+         unrusty stash list
+         unrusty stash show [-p] [<stash>]
+         unrusty stash (apply | pop) [--index] [<stash>]
+         unrusty stash drop [<stash>]
+         unrusty stash clear
+         unrusty stash --help
+
+Options:
+         <stash>                      Stash to use, e.g. stash@{1} or 1, defaults to the newest
+         -m, --message=<message>      Describe the stash with this message
+         -k, --keep-index             Keep the staged changes in the index and the working tree
+         -u, --include-untracked      Stash untracked files too, and remove them
+         -p, --patch                  Show the changes as a patch instead of a diffstat
+         --index                      Also restore the staged changes in the index
+         -h, --help                   Shows this help message
+";
+
+#[derive(Deserialize, Debug)]
+pub struct Args {
+	pub cmd_list:               bool,
+	pub cmd_show:               bool,
+	pub cmd_apply:              bool,
+	pub cmd_pop:                bool,
+	pub cmd_drop:               bool,
+	pub cmd_clear:              bool,
+	pub arg_stash:              Option<String>,
+	pub flag_message:           Option<String>,
+	pub flag_keep_index:        bool,
+	pub flag_include_untracked: bool,
+	pub flag_patch:             bool,
+	pub flag_index:             bool,
+}
+
+impl Args {
+	pub fn from_cmd<'a, I, J>(argv_it: I) -> Args
+	where
+		I: IntoIterator<Item = &'a J>,
+		J: AsRef<str> + 'a,
+	{
+		Docopt::new(USAGE)
+			.and_then(|d| d.argv(argv_it).deserialize())
+			.unwrap_or_else(|e| e.exit())
+	}
+}
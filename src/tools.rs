@@ -1,4 +1,40 @@
+pub mod apply;
+pub mod archive;
+pub mod attributes;
+pub mod base85;
+pub mod bundle;
+pub mod commit;
+pub mod config;
+pub mod credential;
+pub mod date;
 pub mod db;
+pub mod diff;
+pub mod editor;
 pub mod failed;
+pub mod filter;
+pub mod glob;
+pub mod http;
+pub mod ignore;
 pub mod index;
+pub mod lfs;
+pub mod mail;
+pub mod merge;
+pub mod moved;
+pub mod object_format;
+pub mod pack;
+pub mod patch;
 pub mod path;
+pub mod pktline;
+pub mod reachable;
+pub mod reflog;
+pub mod refs;
+pub mod remote;
+pub mod rename;
+pub mod rev;
+pub mod tag;
+#[cfg(test)]
+pub mod testing;
+pub mod transport;
+pub mod tree;
+pub mod word_diff;
+pub mod worktree;
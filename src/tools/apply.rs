@@ -0,0 +1,585 @@
+// Patches in the format of git diff, parsed into the changes of each file and
+// applied to file contents. Hunks apply where their lines are found, at the
+// line numbers of the patch or moved by the lines added or removed above
+use crate::tools::{
+	base85::decode_line,
+	pack::{apply_delta, PackError},
+	path::RelativePathToBase,
+	tree::FileMode,
+};
+use flate2::read::ZlibDecoder;
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+use std::{
+	io::{self, Read},
+	path::{Component, Path, PathBuf},
+	str::from_utf8,
+};
+use thiserror::Error;
+use ApplyError::{
+	BinaryDataError, DeltaError, HunkError, InflateError, MalformedError, PreimageError,
+};
+
+#[derive(Error, Debug)]
+pub enum ApplyError {
+	#[error("Malformed patch at line {line}: {msg}")]
+	MalformedError { line: usize, msg: String },
+	#[error("Patch does not apply to {path}: hunk {hunk} does not match")]
+	HunkError { path: String, hunk: usize },
+	#[error("Patch does not apply to {path}: the file is not the one the patch was made for")]
+	PreimageError { path: String },
+	#[error("Patch has no data for binary file {path}")]
+	BinaryDataError { path: String },
+	#[error("{source:?}")]
+	InflateError { source: io::Error },
+	#[error("{source:?}")]
+	DeltaError { source: PackError },
+}
+
+// A group of changed lines. Lines keep their newline, except the last line
+// of a file that had none
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+	pub old_start: usize,
+	pub old_len:   usize,
+	pub new_start: usize,
+	pub new_len:   usize,
+	// ' ' for context, '-' for removed and '+' for added lines
+	pub lines:     Vec<(char, Vec<u8>)>,
+}
+
+impl Hunk {
+	// The lines the hunk expects, and the ones it leaves
+	fn images(&self) -> (Vec<&[u8]>, Vec<&[u8]>) {
+		let (mut old, mut new) = (Vec::new(), Vec::new());
+		for (kind, line) in &self.lines {
+			if *kind != '+' {
+				old.push(line.as_slice());
+			}
+			if *kind != '-' {
+				new.push(line.as_slice());
+			}
+		}
+		(old, new)
+	}
+
+	fn reverse(self) -> Hunk {
+		Hunk {
+			old_start: self.new_start,
+			old_len:   self.new_len,
+			new_start: self.old_start,
+			new_len:   self.old_len,
+			lines:     self
+				.lines
+				.into_iter()
+				.map(|(kind, line)| match kind {
+					'-' => ('+', line),
+					'+' => ('-', line),
+					kind => (kind, line),
+				})
+				.collect(),
+		}
+	}
+}
+
+// The data of a binary patch, inflated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BinaryPatch {
+	// The whole new content
+	Literal(Vec<u8>),
+	// Instructions to build the new content from the old one, as in packs
+	Delta(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Body {
+	Text(Vec<Hunk>),
+	// "GIT binary patch", with the data to reverse it if given
+	Binary {
+		forward: BinaryPatch,
+		reverse: Option<BinaryPatch>,
+	},
+	// "Binary files differ", which cannot be applied
+	BinaryWithoutData,
+}
+
+// The changes of one file. The old path is None for created files, the new
+// one for deleted files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePatch {
+	pub old_path: Option<RelativePathToBase>,
+	pub new_path: Option<RelativePathToBase>,
+	pub old_mode: Option<FileMode>,
+	pub new_mode: Option<FileMode>,
+	// Hashes of the index line, possibly abbreviated
+	pub old_hash: Option<String>,
+	pub new_hash: Option<String>,
+	// Copies keep their source, renames remove it
+	pub copy:     bool,
+	pub body:     Body,
+}
+
+impl FilePatch {
+	// The path to report the patch by
+	pub fn path(&self) -> &RelativePathToBase {
+		self.new_path
+			.as_ref()
+			.or(self.old_path.as_ref())
+			.expect("a file patch has a path")
+	}
+
+	// The patch that undoes this one. Undoing a copy deletes the copy, whose
+	// content the patch does not know in full
+	pub fn reverse(self) -> FilePatch {
+		if self.copy {
+			return FilePatch {
+				old_path: self.new_path,
+				new_path: None,
+				old_mode: self.new_mode,
+				new_mode: None,
+				old_hash: self.new_hash,
+				new_hash: None,
+				copy:     false,
+				body:     Body::Text(Vec::new()),
+			};
+		}
+		let body = match self.body {
+			Body::Text(hunks) => Body::Text(hunks.into_iter().map(Hunk::reverse).collect()),
+			Body::Binary {
+				forward,
+				reverse: Some(reverse),
+			} => Body::Binary {
+				forward: reverse,
+				reverse: Some(forward),
+			},
+			Body::Binary { reverse: None, .. } | Body::BinaryWithoutData => Body::BinaryWithoutData,
+		};
+		FilePatch {
+			old_path: self.new_path,
+			new_path: self.old_path,
+			old_mode: self.new_mode,
+			new_mode: self.old_mode,
+			old_hash: self.new_hash,
+			new_hash: self.old_hash,
+			copy: false,
+			body,
+		}
+	}
+}
+
+fn malformed(line: usize, msg: &str) -> ApplyError {
+	MalformedError {
+		line,
+		msg: msg.to_owned(),
+	}
+}
+
+// Lines of the patch with their newline, numbered from 1 for errors
+struct Lines<'a> {
+	lines: Vec<&'a [u8]>,
+	next:  usize,
+}
+
+impl<'a> Lines<'a> {
+	fn peek(&self) -> Option<&'a [u8]> {
+		self.lines.get(self.next).copied()
+	}
+
+	fn next(&mut self) -> Option<&'a [u8]> {
+		let line = self.peek();
+		self.next += 1;
+		line
+	}
+
+	fn number(&self) -> usize {
+		self.next
+	}
+}
+
+fn text(line: &[u8]) -> &[u8] {
+	let line = line.strip_suffix(b"\n").unwrap_or(line);
+	line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+// Paths of patches stay inside the working tree
+fn patch_path(name: &[u8], line: usize) -> Result<RelativePathToBase, ApplyError> {
+	let name = from_utf8(name).map_err(|_| malformed(line, "path is not valid UTF-8"))?;
+	let path = Path::new(name);
+	if name.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+		return Err(malformed(line, &format!("invalid path {:?}", name)));
+	}
+	Ok(RelativePathToBase::new(PathBuf::from(name)))
+}
+
+// The name of "--- a/<path>" or "+++ b/<path>", None for /dev/null
+fn side_path(
+	name: &[u8],
+	prefix: &[u8],
+	line: usize,
+) -> Result<Option<RelativePathToBase>, ApplyError> {
+	if name == b"/dev/null" {
+		return Ok(None);
+	}
+	let name = name.strip_prefix(prefix).unwrap_or(name);
+	// Some tools put a tab and a date after the name
+	let name = name.split(|&b| b == b'\t').next().unwrap_or(name);
+	patch_path(name, line).map(Some)
+}
+
+// The paths of "diff --git a/<old> b/<new>". Without a rename they are the
+// same, which tells where the names split when they contain spaces
+fn git_header_paths(
+	names: &[u8],
+	line: usize,
+) -> Result<(RelativePathToBase, RelativePathToBase), ApplyError> {
+	let names = names
+		.strip_prefix(b"a/")
+		.ok_or_else(|| malformed(line, "expected a/ before the old path"))?;
+	let splits = names
+		.windows(3)
+		.enumerate()
+		.filter(|(_, w)| *w == b" b/")
+		.map(|(i, _)| i)
+		.collect::<Vec<_>>();
+	let split = splits
+		.iter()
+		.find(|&&i| names[..i] == names[i + 3..])
+		.or(splits.first())
+		.ok_or_else(|| malformed(line, "expected b/ before the new path"))?;
+	Ok((
+		patch_path(&names[..*split], line)?,
+		patch_path(&names[split + 3..], line)?,
+	))
+}
+
+fn mode(repr: &[u8], line: usize) -> Result<FileMode, ApplyError> {
+	from_utf8(repr)
+		.ok()
+		.and_then(FileMode::from_octal)
+		.ok_or_else(|| malformed(line, "invalid file mode"))
+}
+
+fn number(repr: &[u8], line: usize) -> Result<usize, ApplyError> {
+	from_utf8(repr)
+		.ok()
+		.and_then(|n| n.parse().ok())
+		.ok_or_else(|| malformed(line, "invalid number"))
+}
+
+fn parse_hunk(lines: &mut Lines, header: &[u8]) -> Result<Hunk, ApplyError> {
+	lazy_static! {
+		static ref RE: Regex = Regex::new(r"^@@ -(\d+)(?:,(\d+))? \+(\d+)(?:,(\d+))? @@").unwrap();
+	}
+	let line = lines.number();
+	let capture = RE
+		.captures(header)
+		.ok_or_else(|| malformed(line, "invalid hunk header"))?;
+	let range = |start: usize, len: usize| -> Result<(usize, usize), ApplyError> {
+		let start = number(&capture[start], line)?;
+		let len = match capture.get(len) {
+			Some(len) => number(len.as_bytes(), line)?,
+			None => 1,
+		};
+		Ok((start, len))
+	};
+	let (old_start, old_len) = range(1, 2)?;
+	let (new_start, new_len) = range(3, 4)?;
+
+	let mut hunk = Hunk {
+		old_start,
+		old_len,
+		new_start,
+		new_len,
+		lines: Vec::new(),
+	};
+	let (mut old_left, mut new_left) = (old_len, new_len);
+	while old_left > 0 || new_left > 0 {
+		let content = lines
+			.next()
+			.ok_or_else(|| malformed(lines.number(), "hunk ends early"))?;
+		// Some editors drop the space of empty context lines
+		let (kind, content) = match content.split_first() {
+			Some((b'\n', _)) => (' ', content),
+			Some((&kind, rest)) => (kind as char, rest),
+			None => return Err(malformed(lines.number(), "hunk ends early")),
+		};
+		match kind {
+			' ' if old_left > 0 && new_left > 0 => {
+				old_left -= 1;
+				new_left -= 1;
+			},
+			'-' if old_left > 0 => old_left -= 1,
+			'+' if new_left > 0 => new_left -= 1,
+			_ => return Err(malformed(lines.number(), "unexpected line in hunk")),
+		}
+		hunk.lines.push((kind, content.to_vec()));
+		no_newline(lines, &mut hunk);
+	}
+	no_newline(lines, &mut hunk);
+	Ok(hunk)
+}
+
+// "\ No newline at end of file" removes the newline of the line before
+fn no_newline(lines: &mut Lines, hunk: &mut Hunk) {
+	if lines.peek().is_some_and(|l| l.starts_with(b"\\ ")) {
+		lines.next();
+		if let Some((_, last)) = hunk.lines.last_mut() {
+			if last.ends_with(b"\r\n") {
+				last.truncate(last.len() - 2);
+			} else if last.ends_with(b"\n") {
+				last.pop();
+			}
+		}
+	}
+}
+
+fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>, ApplyError> {
+	let mut out = Vec::with_capacity(size);
+	ZlibDecoder::new(data)
+		.read_to_end(&mut out)
+		.map_err(|e| InflateError { source: e })?;
+	Ok(out)
+}
+
+// "literal <size>" or "delta <size>" and the lines of data up to an empty line
+fn parse_binary_data(lines: &mut Lines) -> Result<Option<BinaryPatch>, ApplyError> {
+	let header = match lines.peek().map(text) {
+		Some(header) if header.starts_with(b"literal ") || header.starts_with(b"delta ") => header,
+		_ => return Ok(None),
+	};
+	lines.next();
+	let line = lines.number();
+	let (kind, size) = match header.iter().position(|&b| b == b' ') {
+		Some(i) => (&header[..i], number(&header[i + 1..], line)?),
+		None => return Err(malformed(line, "invalid binary patch header")),
+	};
+	let mut deflated = Vec::new();
+	loop {
+		let data = lines
+			.next()
+			.map(text)
+			.ok_or_else(|| malformed(lines.number(), "binary patch ends early"))?;
+		if data.is_empty() {
+			break;
+		}
+		deflated.extend(
+			decode_line(data).ok_or_else(|| malformed(lines.number(), "invalid binary data"))?,
+		);
+	}
+	let data = inflate(&deflated, size)?;
+	if data.len() != size {
+		return Err(malformed(line, "binary data has the wrong size"));
+	}
+	Ok(Some(match kind {
+		b"literal" => BinaryPatch::Literal(data),
+		_ => BinaryPatch::Delta(data),
+	}))
+}
+
+// The headers and body of one file, after its "diff --git" line
+fn parse_file(lines: &mut Lines, names: &[u8]) -> Result<FilePatch, ApplyError> {
+	let line = lines.number();
+	let (old_name, new_name) = git_header_paths(names, line)?;
+	let mut patch = FilePatch {
+		old_path: Some(old_name),
+		new_path: Some(new_name),
+		old_mode: None,
+		new_mode: None,
+		old_hash: None,
+		new_hash: None,
+		copy:     false,
+		body:     Body::Text(Vec::new()),
+	};
+
+	// Extended headers
+	while let Some(header) = lines.peek().map(text) {
+		let line = lines.number() + 1;
+		let field = |name: &[u8]| header.strip_prefix(name);
+		if let Some(repr) = field(b"old mode ") {
+			patch.old_mode = Some(mode(repr, line)?);
+		} else if let Some(repr) = field(b"new mode ") {
+			patch.new_mode = Some(mode(repr, line)?);
+		} else if let Some(repr) = field(b"deleted file mode ") {
+			patch.old_mode = Some(mode(repr, line)?);
+			patch.new_path = None;
+		} else if let Some(repr) = field(b"new file mode ") {
+			patch.new_mode = Some(mode(repr, line)?);
+			patch.old_path = None;
+		} else if let Some(name) = field(b"rename from ").or(field(b"copy from ")) {
+			patch.old_path = Some(patch_path(name, line)?);
+			patch.copy = header.starts_with(b"copy");
+		} else if let Some(name) = field(b"rename to ").or(field(b"copy to ")) {
+			patch.new_path = Some(patch_path(name, line)?);
+		} else if let Some(repr) = field(b"index ") {
+			let mut fields = repr.split(|&b| b == b' ');
+			let hashes = fields.next().unwrap_or_default();
+			let (old, new) = match hashes.windows(2).position(|w| w == b"..") {
+				Some(i) => (&hashes[..i], &hashes[i + 2..]),
+				None => return Err(malformed(line, "invalid index line")),
+			};
+			let hash = |h: &[u8]| from_utf8(h).ok().map(str::to_owned);
+			patch.old_hash = hash(old);
+			patch.new_hash = hash(new);
+			if let Some(repr) = fields.next() {
+				let mode = mode(repr, line)?;
+				patch.old_mode = Some(mode);
+				patch.new_mode = Some(mode);
+			}
+		} else if !(field(b"similarity index ").is_some()
+			|| field(b"dissimilarity index ").is_some())
+		{
+			break;
+		}
+		lines.next();
+	}
+
+	let header = lines.peek().map(text).unwrap_or_default();
+	if header.starts_with(b"--- ") {
+		lines.next();
+		let old = side_path(&header[4..], b"a/", lines.number())?;
+		let header = lines
+			.next()
+			.map(text)
+			.filter(|h| h.starts_with(b"+++ "))
+			.ok_or_else(|| malformed(lines.number(), "expected +++ after ---"))?;
+		let new = side_path(&header[4..], b"b/", lines.number())?;
+		// The side of a created or deleted file is missing
+		if old.is_none() {
+			patch.old_path = None;
+		}
+		if new.is_none() {
+			patch.new_path = None;
+		}
+		let mut hunks = Vec::new();
+		while let Some(header) = lines.peek().filter(|l| l.starts_with(b"@@ ")) {
+			lines.next();
+			hunks.push(parse_hunk(lines, header)?);
+		}
+		patch.body = Body::Text(hunks);
+	} else if header == b"GIT binary patch" {
+		lines.next();
+		let forward = parse_binary_data(lines)?
+			.ok_or_else(|| malformed(lines.number(), "binary patch without data"))?;
+		let reverse = parse_binary_data(lines)?;
+		patch.body = Body::Binary { forward, reverse };
+	} else if header.starts_with(b"Binary files ") {
+		lines.next();
+		patch.body = Body::BinaryWithoutData;
+	}
+	Ok(patch)
+}
+
+// The file patches of a patch in the format of git diff. Lines outside of the
+// patches, like the message of a mail, are ignored
+pub fn parse_patch(data: &[u8]) -> Result<Vec<FilePatch>, ApplyError> {
+	let mut lines = Lines {
+		lines: data.split_inclusive(|&b| b == b'\n').collect(),
+		next:  0,
+	};
+	let mut patches = Vec::new();
+	while let Some(line) = lines.next() {
+		if let Some(names) = text(line).strip_prefix(b"diff --git ") {
+			patches.push(parse_file(&mut lines, names)?);
+		}
+	}
+	Ok(patches)
+}
+
+// Where the lines expected by a hunk are found, searching from `expected`
+// outwards. Hunks starting at the first line must match there, hunks without
+// lines after their changes at the end
+fn find_hunk(
+	lines: &[&[u8]],
+	image: &[&[u8]],
+	expected: usize,
+	from: usize,
+	at_start: bool,
+	at_end: bool,
+) -> Option<usize> {
+	let matches = |pos: usize| {
+		pos >= from
+			&& pos + image.len() <= lines.len()
+			&& lines[pos..pos + image.len()] == *image
+			&& (!at_start || pos == 0)
+			&& (!at_end || pos + image.len() == lines.len())
+	};
+	let last = lines.len().saturating_sub(image.len());
+	(0..=last.max(expected))
+		.flat_map(|distance| {
+			let after = expected.checked_add(distance);
+			let before = expected.checked_sub(distance).filter(|_| distance > 0);
+			after.into_iter().chain(before)
+		})
+		.find(|&pos| matches(pos))
+}
+
+// The content after the hunks, or the number of the first one that does not
+// match
+pub fn apply_hunks(content: &[u8], hunks: &[Hunk]) -> Result<Vec<u8>, usize> {
+	let lines = content.split_inclusive(|&b| b == b'\n').collect::<Vec<_>>();
+	let mut out: Vec<&[u8]> = Vec::new();
+	// Lines of the content already handled, and the shift of line numbers by
+	// hunks matching elsewhere than their header says
+	let (mut done, mut offset) = (0, 0isize);
+	for (n, hunk) in hunks.iter().enumerate() {
+		let (old, new) = hunk.images();
+		let trailing = hunk
+			.lines
+			.iter()
+			.rev()
+			.take_while(|(k, _)| *k == ' ')
+			.count();
+		// A hunk without old lines starts after the line of its header
+		let start = if hunk.old_len == 0 {
+			hunk.old_start
+		} else {
+			hunk.old_start.saturating_sub(1)
+		};
+		let expected = (start as isize + offset).max(0) as usize;
+		let pos = find_hunk(
+			&lines,
+			&old,
+			expected,
+			done,
+			hunk.old_start <= 1,
+			trailing == 0 && hunk.old_len > 0,
+		)
+		.ok_or(n + 1)?;
+		offset += pos as isize - start as isize;
+		out.extend_from_slice(&lines[done..pos]);
+		out.extend_from_slice(&new);
+		done = pos + old.len();
+	}
+	out.extend_from_slice(&lines[done..]);
+	Ok(out.concat())
+}
+
+// The new content of a file from its old one
+pub fn apply_file(patch: &FilePatch, content: &[u8]) -> Result<Vec<u8>, ApplyError> {
+	let path = || patch.path().to_string();
+	match &patch.body {
+		Body::Text(hunks) => {
+			apply_hunks(content, hunks).map_err(|hunk| HunkError { path: path(), hunk })
+		},
+		Body::Binary { forward, .. } => match forward {
+			BinaryPatch::Literal(data) => Ok(data.clone()),
+			BinaryPatch::Delta(delta) => {
+				apply_delta(content, delta).map_err(|e| DeltaError { source: e })
+			},
+		},
+		Body::BinaryWithoutData => Err(BinaryDataError { path: path() }),
+	}
+}
+
+// Binary patches replace contents as a whole, so they are refused when the
+// file differs from the one they were made for
+pub fn check_preimage(patch: &FilePatch, hash: &str) -> Result<(), ApplyError> {
+	match (&patch.body, &patch.old_hash) {
+		(Body::Binary { .. }, Some(expected)) if !hash.starts_with(expected.as_str()) => {
+			Err(PreimageError {
+				path: patch.path().to_string(),
+			})
+		},
+		_ => Ok(()),
+	}
+}
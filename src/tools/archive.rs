@@ -0,0 +1,376 @@
+// Writers of tar and zip archives. Entries are written as they come, with
+// the same modification time. Tar archives follow ustar with pax headers for
+// what ustar can not hold, zip archives are plain zip without zip64
+use crate::tools::date::civil_from_days;
+use flate2::{write::DeflateEncoder, Compression, Crc};
+use std::{
+	convert::TryFrom,
+	io::{self, Write},
+};
+use thiserror::Error;
+use ArchiveError::{TooLargeError, WriteError};
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+	#[error("{source:?}")]
+	WriteError { source: io::Error },
+	#[error("Too large for a zip archive: {path}")]
+	TooLargeError { path: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+	File,
+	Executable,
+	Symlink,
+	Directory,
+}
+
+// Paths of directories end with a slash. The data of a symlink is its target
+#[derive(Debug)]
+pub struct ArchiveEntry {
+	pub path: String,
+	pub kind: EntryKind,
+	pub data: Vec<u8>,
+}
+
+const BLOCK_LEN: usize = 512;
+// Archives are padded to whole records like tar does
+const RECORD_LEN: usize = 20 * BLOCK_LEN;
+const USTAR_NAME_LEN: usize = 100;
+const GLOBAL_HEADER_NAME: &str = "pax_global_header";
+const EXTENDED_HEADER_NAME: &str = "pax_header";
+
+// Type flags of tar headers
+const TAR_FILE: u8 = b'0';
+const TAR_SYMLINK: u8 = b'2';
+const TAR_DIRECTORY: u8 = b'5';
+const TAR_EXTENDED: u8 = b'x';
+const TAR_GLOBAL: u8 = b'g';
+
+// Zip signatures and fields
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4b50;
+const ZIP_CENTRAL_HEADER: u32 = 0x0201_4b50;
+const ZIP_END: u32 = 0x0605_4b50;
+const ZIP_STORED: u16 = 0;
+const ZIP_DEFLATED: u16 = 8;
+const ZIP_UTF8_FLAG: u16 = 0x0800;
+// Made by unix, specification 2.0, so that the mode is honoured
+const ZIP_MADE_BY: u16 = (3 << 8) | 20;
+const ZIP_DIRECTORY_ATTRIBUTE: u32 = 0x10;
+
+impl EntryKind {
+	// Permissions like git archive with its default umask of 002
+	fn permissions(self) -> u32 {
+		match self {
+			EntryKind::File => 0o664,
+			EntryKind::Executable | EntryKind::Directory => 0o775,
+			EntryKind::Symlink => 0o777,
+		}
+	}
+
+	fn unix_mode(self) -> u32 {
+		let file_type = match self {
+			EntryKind::File | EntryKind::Executable => 0o100_000,
+			EntryKind::Symlink => 0o120_000,
+			EntryKind::Directory => 0o040_000,
+		};
+		file_type | self.permissions()
+	}
+}
+
+fn write_all(writer: &mut dyn Write, data: &[u8]) -> Result<(), ArchiveError> {
+	writer.write_all(data).map_err(|e| WriteError { source: e })
+}
+
+// Copies as much of `value` as fits in the field
+fn set_field(field: &mut [u8], value: &[u8]) {
+	let len = value.len().min(field.len());
+	field[..len].copy_from_slice(&value[..len]);
+}
+
+// Octal numbers fill their field but for a terminating NUL
+fn set_octal(field: &mut [u8], value: u64) {
+	let width = field.len() - 1;
+	set_field(
+		field,
+		format!("{:0>width$o}", value, width = width).as_bytes(),
+	);
+}
+
+// A pax record "<len> <key>=<value>\n" where len counts the whole record
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+	let rest = key.len() + value.len() + 3;
+	let mut len = rest + rest.to_string().len();
+	if len.to_string().len() > rest.to_string().len() {
+		len += 1;
+	}
+	let mut record = format!("{} {}=", len, key).into_bytes();
+	record.extend_from_slice(value);
+	record.push(b'\n');
+	record
+}
+
+pub struct TarWriter<W: Write> {
+	writer:  W,
+	mtime:   u64,
+	written: usize,
+}
+
+impl<W: Write> TarWriter<W> {
+	// A commit id is recorded in a pax global header, where git get-tar-commit-id
+	// finds it
+	pub fn new(writer: W, mtime: u64, commit: Option<&str>) -> Result<TarWriter<W>, ArchiveError> {
+		let mut tar = TarWriter {
+			writer,
+			mtime,
+			written: 0,
+		};
+		if let Some(commit) = commit {
+			let record = pax_record("comment", commit.as_bytes());
+			tar.write_header(GLOBAL_HEADER_NAME, TAR_GLOBAL, 0o666, record.len(), b"")?;
+			tar.write_data(&record)?;
+		}
+		Ok(tar)
+	}
+
+	fn write_raw(&mut self, data: &[u8]) -> Result<(), ArchiveError> {
+		write_all(&mut self.writer, data)?;
+		self.written += data.len();
+		Ok(())
+	}
+
+	// Data padded to whole blocks
+	fn write_data(&mut self, data: &[u8]) -> Result<(), ArchiveError> {
+		self.write_raw(data)?;
+		let padding = (BLOCK_LEN - data.len() % BLOCK_LEN) % BLOCK_LEN;
+		self.write_raw(&vec![0; padding])
+	}
+
+	fn write_header(
+		&mut self,
+		name: &str,
+		type_flag: u8,
+		mode: u32,
+		size: usize,
+		link: &[u8],
+	) -> Result<(), ArchiveError> {
+		let mut header = [0u8; BLOCK_LEN];
+		set_field(&mut header[0..100], name.as_bytes());
+		set_octal(&mut header[100..108], u64::from(mode));
+		set_octal(&mut header[108..116], 0);
+		set_octal(&mut header[116..124], 0);
+		set_octal(&mut header[124..136], size as u64);
+		set_octal(&mut header[136..148], self.mtime);
+		header[156] = type_flag;
+		set_field(&mut header[157..257], link);
+		set_field(&mut header[257..265], b"ustar\x0000");
+		set_field(&mut header[265..297], b"root");
+		set_field(&mut header[297..329], b"root");
+		set_octal(&mut header[329..337], 0);
+		set_octal(&mut header[337..345], 0);
+		// The checksum is computed with its own field as spaces
+		header[148..156].copy_from_slice(b"        ");
+		let checksum = header.iter().map(|&b| u64::from(b)).sum::<u64>();
+		set_field(
+			&mut header[148..156],
+			format!("{:06o}\0 ", checksum).as_bytes(),
+		);
+		self.write_raw(&header)
+	}
+
+	pub fn add(&mut self, entry: &ArchiveEntry) -> Result<(), ArchiveError> {
+		// Names and link targets too long for ustar go to a pax header
+		let mut extended = Vec::new();
+		if entry.path.len() > USTAR_NAME_LEN {
+			extended.extend(pax_record("path", entry.path.as_bytes()));
+		}
+		if entry.kind == EntryKind::Symlink && entry.data.len() > USTAR_NAME_LEN {
+			extended.extend(pax_record("linkpath", &entry.data));
+		}
+		if !extended.is_empty() {
+			self.write_header(
+				EXTENDED_HEADER_NAME,
+				TAR_EXTENDED,
+				0o666,
+				extended.len(),
+				b"",
+			)?;
+			self.write_data(&extended)?;
+		}
+
+		let mode = entry.kind.permissions();
+		match entry.kind {
+			EntryKind::File | EntryKind::Executable => {
+				self.write_header(&entry.path, TAR_FILE, mode, entry.data.len(), b"")?;
+				self.write_data(&entry.data)
+			},
+			EntryKind::Symlink => self.write_header(&entry.path, TAR_SYMLINK, mode, 0, &entry.data),
+			EntryKind::Directory => self.write_header(&entry.path, TAR_DIRECTORY, mode, 0, b""),
+		}
+	}
+
+	// Two empty blocks end the archive, which is then padded to a record
+	pub fn finish(mut self) -> Result<W, ArchiveError> {
+		self.write_raw(&[0; 2 * BLOCK_LEN])?;
+		let padding = (RECORD_LEN - self.written % RECORD_LEN) % RECORD_LEN;
+		self.write_raw(&vec![0; padding])?;
+		Ok(self.writer)
+	}
+}
+
+// Time and date as MS-DOS has them, the earliest being 1980
+fn dos_time(seconds: u64) -> (u16, u16) {
+	let days = (seconds / 86400) as i64;
+	let (year, month, day) = civil_from_days(days);
+	if year < 1980 {
+		return (0, (1 << 5) | 1);
+	}
+	let seconds_of_day = seconds % 86400;
+	let (hour, minute, second) = (
+		seconds_of_day / 3600,
+		seconds_of_day / 60 % 60,
+		seconds_of_day % 60,
+	);
+	let time = (hour << 11) | (minute << 5) | (second / 2);
+	let date = (((year - 1980).min(127) as u64) << 9) | ((month as u64) << 5) | day as u64;
+	(time as u16, date as u16)
+}
+
+pub struct ZipWriter<W: Write> {
+	writer:  W,
+	time:    u16,
+	date:    u16,
+	offset:  u64,
+	central: Vec<u8>,
+	entries: u16,
+}
+
+fn push_u16(buffer: &mut Vec<u8>, value: u16) {
+	buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+	buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+impl<W: Write> ZipWriter<W> {
+	pub fn new(writer: W, mtime: u64) -> ZipWriter<W> {
+		let (time, date) = dos_time(mtime);
+		ZipWriter {
+			writer,
+			time,
+			date,
+			offset: 0,
+			central: Vec::new(),
+			entries: 0,
+		}
+	}
+
+	fn too_large(path: &str) -> ArchiveError {
+		TooLargeError {
+			path: path.to_owned(),
+		}
+	}
+
+	// Data is deflated unless that does not make it smaller
+	pub fn add(&mut self, entry: &ArchiveEntry) -> Result<(), ArchiveError> {
+		let too_large = || Self::too_large(&entry.path);
+		let mut crc = Crc::new();
+		crc.update(&entry.data);
+		let mut method = ZIP_STORED;
+		let mut compressed = None;
+		if matches!(entry.kind, EntryKind::File | EntryKind::Executable) && !entry.data.is_empty() {
+			let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+			let deflated = encoder
+				.write_all(&entry.data)
+				.and_then(|_| encoder.finish())
+				.map_err(|e| WriteError { source: e })?;
+			if deflated.len() < entry.data.len() {
+				method = ZIP_DEFLATED;
+				compressed = Some(deflated);
+			}
+		}
+		let data = compressed.as_deref().unwrap_or(&entry.data);
+		let flags = if entry.path.is_ascii() {
+			0
+		} else {
+			ZIP_UTF8_FLAG
+		};
+		let version = if method == ZIP_DEFLATED || entry.kind == EntryKind::Directory {
+			20
+		} else {
+			10
+		};
+		let name_len = u16::try_from(entry.path.len()).map_err(|_| too_large())?;
+		let size = u32::try_from(entry.data.len()).map_err(|_| too_large())?;
+		let compressed_size = u32::try_from(data.len()).map_err(|_| too_large())?;
+		let offset = u32::try_from(self.offset).map_err(|_| too_large())?;
+
+		// Fields shared by the local and the central header
+		let mut common = Vec::new();
+		push_u16(&mut common, version);
+		push_u16(&mut common, flags);
+		push_u16(&mut common, method);
+		push_u16(&mut common, self.time);
+		push_u16(&mut common, self.date);
+		push_u32(&mut common, crc.sum());
+		push_u32(&mut common, compressed_size);
+		push_u32(&mut common, size);
+		push_u16(&mut common, name_len);
+		push_u16(&mut common, 0);
+
+		let mut local = Vec::new();
+		push_u32(&mut local, ZIP_LOCAL_HEADER);
+		local.extend_from_slice(&common);
+		local.extend_from_slice(entry.path.as_bytes());
+		write_all(&mut self.writer, &local)?;
+		write_all(&mut self.writer, data)?;
+		self.offset += (local.len() + data.len()) as u64;
+
+		let mut attributes = entry.kind.unix_mode() << 16;
+		if entry.kind == EntryKind::Directory {
+			attributes |= ZIP_DIRECTORY_ATTRIBUTE;
+		}
+		push_u32(&mut self.central, ZIP_CENTRAL_HEADER);
+		push_u16(&mut self.central, ZIP_MADE_BY);
+		self.central.extend_from_slice(&common);
+		push_u16(&mut self.central, 0);
+		push_u16(&mut self.central, 0);
+		push_u16(&mut self.central, 0);
+		push_u32(&mut self.central, attributes);
+		push_u32(&mut self.central, offset);
+		self.central.extend_from_slice(entry.path.as_bytes());
+		self.entries = self.entries.checked_add(1).ok_or_else(too_large)?;
+		Ok(())
+	}
+
+	// Writes the central directory, whose end record holds the comment
+	pub fn finish(mut self, comment: Option<&str>) -> Result<W, ArchiveError> {
+		let comment = comment.unwrap_or("");
+		let too_large = || Self::too_large("central directory");
+		let mut end = Vec::new();
+		push_u32(&mut end, ZIP_END);
+		push_u16(&mut end, 0);
+		push_u16(&mut end, 0);
+		push_u16(&mut end, self.entries);
+		push_u16(&mut end, self.entries);
+		push_u32(
+			&mut end,
+			u32::try_from(self.central.len()).map_err(|_| too_large())?,
+		);
+		push_u32(
+			&mut end,
+			u32::try_from(self.offset).map_err(|_| too_large())?,
+		);
+		push_u16(
+			&mut end,
+			u16::try_from(comment.len()).map_err(|_| too_large())?,
+		);
+		end.extend_from_slice(comment.as_bytes());
+		let central = std::mem::take(&mut self.central);
+		write_all(&mut self.writer, &central)?;
+		write_all(&mut self.writer, &end)?;
+		Ok(self.writer)
+	}
+}
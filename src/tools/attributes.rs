@@ -0,0 +1,146 @@
+use crate::tools::{
+	glob::wildmatch,
+	path::{root_path_required, PathError, RelativePathToBase},
+};
+use std::{collections::HashMap, fs, io, io::ErrorKind, path::Path};
+use thiserror::Error;
+use AttributesError::{NoRepoError, ReadError};
+
+#[derive(Error, Debug)]
+pub enum AttributesError {
+	#[error("{source:?}")]
+	NoRepoError { source: PathError },
+	#[error("{source:?}")]
+	ReadError { source: io::Error },
+}
+
+// Read from the repository root, in this order. Later lines take precedence
+const ATTRIBUTES_FILES: [&str; 2] = [".gitattributes", ".unrustyattributes"];
+
+// "attr", "-attr", "attr=value", and "!attr" which resets it to unspecified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrState {
+	Set,
+	Unset,
+	Value(String),
+	Unspecified,
+}
+
+struct AttributesLine {
+	pattern:    String,
+	// Patterns containing a slash match the whole path, others the file name
+	anchored:   bool,
+	// In order, the last one of a name wins
+	attributes: Vec<(String, AttrState)>,
+}
+
+// Attribute rules with the syntax of .gitattributes files, including the
+// "[attr]name ..." macro definitions
+pub struct Attributes {
+	lines: Vec<AttributesLine>,
+}
+
+fn parse_state(item: &str) -> (String, AttrState) {
+	if let Some(name) = item.strip_prefix('-') {
+		(name.to_owned(), AttrState::Unset)
+	} else if let Some(name) = item.strip_prefix('!') {
+		(name.to_owned(), AttrState::Unspecified)
+	} else if let Some((name, value)) = item.split_once('=') {
+		(name.to_owned(), AttrState::Value(value.to_owned()))
+	} else {
+		(item.to_owned(), AttrState::Set)
+	}
+}
+
+// Setting a macro sets the attributes it stands for, before itself
+fn expand(
+	items: &[&str],
+	macros: &HashMap<String, Vec<(String, AttrState)>>,
+) -> Vec<(String, AttrState)> {
+	let mut attributes = Vec::new();
+	for item in items {
+		let (name, state) = parse_state(item);
+		if state == AttrState::Set {
+			if let Some(expansion) = macros.get(&name) {
+				attributes.extend(expansion.iter().cloned());
+			}
+		}
+		attributes.push((name, state));
+	}
+	attributes
+}
+
+impl AttributesLine {
+	fn matches(&self, path: &str) -> bool {
+		let text = if self.anchored {
+			path
+		} else {
+			path.rsplit('/').next().unwrap_or(path)
+		};
+		wildmatch(self.pattern.as_bytes(), text.as_bytes())
+	}
+}
+
+impl Attributes {
+	pub fn read() -> Result<Attributes, AttributesError> {
+		let root = root_path_required().map_err(|e| NoRepoError { source: e })?;
+		let mut macros = HashMap::new();
+		macros.insert(
+			"binary".to_owned(),
+			expand(&["-diff", "-merge", "-text"], &HashMap::new()),
+		);
+		let mut lines = Vec::new();
+		for name in ATTRIBUTES_FILES.iter() {
+			let content = match fs::read_to_string(root.join(name)) {
+				Ok(content) => content,
+				Err(e) if e.kind() == ErrorKind::NotFound => continue,
+				Err(e) => return Err(ReadError { source: e }),
+			};
+			for line in content.lines() {
+				let mut items = line.split_whitespace();
+				let pattern = match items.next() {
+					Some(pattern) if !pattern.starts_with('#') => pattern,
+					_ => continue,
+				};
+				let items = items.collect::<Vec<_>>();
+				if let Some(name) = pattern.strip_prefix("[attr]") {
+					let expansion = expand(&items, &macros);
+					macros.insert(name.to_owned(), expansion);
+				} else if !pattern.starts_with('!') {
+					// Negative patterns are not allowed in attribute files
+					lines.push(AttributesLine {
+						pattern:    pattern.trim_start_matches('/').to_owned(),
+						anchored:   pattern.contains('/'),
+						attributes: expand(&items, &macros),
+					});
+				}
+			}
+		}
+		Ok(Attributes { lines })
+	}
+
+	// The state of attribute `name` for a file, from the last line matching
+	// the path that mentions it
+	// Whether the file is one the attributes are read from
+	pub fn is_attributes_file(path: &RelativePathToBase) -> bool {
+		ATTRIBUTES_FILES
+			.iter()
+			.any(|name| path.as_path() == Path::new(name))
+	}
+
+	pub fn get(&self, path: &RelativePathToBase, name: &str) -> AttrState {
+		let path = path.to_string();
+		self.lines
+			.iter()
+			.rev()
+			.filter(|line| line.matches(&path))
+			.find_map(|line| {
+				line.attributes
+					.iter()
+					.rev()
+					.find(|(n, _)| n == name)
+					.map(|(_, state)| state.clone())
+			})
+			.unwrap_or(AttrState::Unspecified)
+	}
+}
@@ -0,0 +1,66 @@
+// The base85 encoding of git binary patches. Every line holds up to LINE_LEN
+// bytes of data, with their number as a letter in front: A-Z for 1 to 26,
+// a-z for 27 to 52
+const ALPHABET: &[u8; 85] =
+	b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+const LINE_LEN: usize = 52;
+
+fn digit(c: u8) -> Option<u32> {
+	ALPHABET.iter().position(|&a| a == c).map(|i| i as u32)
+}
+
+fn length_letter(len: usize) -> char {
+	match len {
+		1..=26 => (b'A' + (len - 1) as u8) as char,
+		_ => (b'a' + (len - 27) as u8) as char,
+	}
+}
+
+fn letter_length(letter: u8) -> Option<usize> {
+	match letter {
+		b'A'..=b'Z' => Some(usize::from(letter - b'A') + 1),
+		b'a'..=b'z' => Some(usize::from(letter - b'a') + 27),
+		_ => None,
+	}
+}
+
+// The lines of `data`, each ending with a newline
+pub fn encode_lines(data: &[u8]) -> String {
+	let mut out = String::new();
+	for line in data.chunks(LINE_LEN) {
+		out.push(length_letter(line.len()));
+		for group in line.chunks(4) {
+			// The last group is padded with zeros
+			let mut bytes = [0; 4];
+			bytes[..group.len()].copy_from_slice(group);
+			let mut value = u32::from_be_bytes(bytes);
+			let mut digits = [0; 5];
+			for d in digits.iter_mut().rev() {
+				*d = ALPHABET[(value % 85) as usize];
+				value /= 85;
+			}
+			out.extend(digits.iter().map(|&d| d as char));
+		}
+		out.push('\n');
+	}
+	out
+}
+
+// The data of one line, without its newline. None if it is malformed
+pub fn decode_line(line: &[u8]) -> Option<Vec<u8>> {
+	let (&letter, digits) = line.split_first()?;
+	let len = letter_length(letter)?;
+	if digits.len() != len.div_ceil(4) * 5 {
+		return None;
+	}
+	let mut data = Vec::with_capacity(len + 3);
+	for group in digits.chunks(5) {
+		let mut value: u32 = 0;
+		for &c in group {
+			value = value.checked_mul(85)?.checked_add(digit(c)?)?;
+		}
+		data.extend_from_slice(&value.to_be_bytes());
+	}
+	data.truncate(len);
+	Some(data)
+}
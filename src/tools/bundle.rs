@@ -0,0 +1,127 @@
+// Bundles carry history as a single file: a header listing the commits the
+// receiving repository must already have and the refs the bundle provides,
+// then a pack of the objects. Version 3 adds capabilities to the header
+use crate::tools::{db::ObjectRepr, object_format::ObjectFormat};
+use std::{
+	convert::TryFrom,
+	fs,
+	io::{self, Write},
+	path::Path,
+};
+use thiserror::Error;
+use BundleError::{MalformedError, ReadError, UnsupportedError, WriteError};
+
+#[derive(Error, Debug)]
+pub enum BundleError {
+	#[error("{source:?}")]
+	ReadError { source: io::Error },
+	#[error("{source:?}")]
+	WriteError { source: io::Error },
+	#[error("Malformed bundle: {msg}")]
+	MalformedError { msg: String },
+	#[error("Unsupported bundle: {msg}")]
+	UnsupportedError { msg: String },
+}
+
+const V2_SIGNATURE: &str = "# v2 git bundle";
+const V3_SIGNATURE: &str = "# v3 git bundle";
+const OBJECT_FORMAT: &str = "object-format";
+
+// Objects are given as (object, name). Prerequisites name the commit they
+// stand for, which is informational only
+#[derive(Debug, Default)]
+pub struct Bundle {
+	pub prerequisites: Vec<(ObjectRepr, String)>,
+	pub refs:          Vec<(ObjectRepr, String)>,
+	pub pack:          Vec<u8>,
+	pub object_format: ObjectFormat,
+}
+
+fn malformed(msg: &str) -> BundleError {
+	MalformedError {
+		msg: msg.to_owned(),
+	}
+}
+
+fn parse_object(hash: &str) -> Result<ObjectRepr, BundleError> {
+	ObjectRepr::try_from(hash).map_err(|_| malformed(&format!("invalid object {:?}", hash)))
+}
+
+impl Bundle {
+	pub fn read(path: &Path) -> Result<Bundle, BundleError> {
+		let content = fs::read(path).map_err(|e| ReadError { source: e })?;
+		Bundle::parse(&content)
+	}
+
+	// The header is text up to the first empty line, the pack follows
+	pub fn parse(content: &[u8]) -> Result<Bundle, BundleError> {
+		let mut bundle = Bundle::default();
+		let mut pos = 0;
+		let mut first = true;
+		loop {
+			let end = content[pos..]
+				.iter()
+				.position(|&b| b == b'\n')
+				.ok_or_else(|| malformed("truncated header"))?;
+			let line = std::str::from_utf8(&content[pos..pos + end])
+				.map_err(|_| malformed("header is not text"))?;
+			pos += end + 1;
+			if first {
+				if line != V2_SIGNATURE && line != V3_SIGNATURE {
+					return Err(malformed("no bundle signature"));
+				}
+				first = false;
+				continue;
+			}
+			if line.is_empty() {
+				break;
+			}
+			if let Some(capability) = line.strip_prefix('@') {
+				let format = capability
+					.split_once('=')
+					.filter(|(name, _)| *name == OBJECT_FORMAT)
+					.and_then(|(_, value)| ObjectFormat::from_name(value).ok());
+				match format {
+					Some(format) => bundle.object_format = format,
+					_ => {
+						return Err(UnsupportedError {
+							msg: format!("capability {}", capability),
+						})
+					},
+				}
+			} else if let Some(prerequisite) = line.strip_prefix('-') {
+				let (hash, comment) = prerequisite.split_once(' ').unwrap_or((prerequisite, ""));
+				bundle
+					.prerequisites
+					.push((parse_object(hash)?, comment.to_owned()));
+			} else {
+				let (hash, name) = line
+					.split_once(' ')
+					.ok_or_else(|| malformed(&format!("invalid ref line {:?}", line)))?;
+				bundle.refs.push((parse_object(hash)?, name.to_owned()));
+			}
+		}
+		bundle.pack = content[pos..].to_vec();
+		Ok(bundle)
+	}
+
+	// Written as version 2, which every reader understands, unless the objects
+	// are not SHA-1 ones. Version 3 then names their format
+	pub fn write(&self, path: &Path) -> Result<(), BundleError> {
+		let mut content = match self.object_format {
+			ObjectFormat::Sha1 => format!("{}\n", V2_SIGNATURE),
+			format => format!("{}\n@{}={}\n", V3_SIGNATURE, OBJECT_FORMAT, format),
+		};
+		for (object_repr, comment) in &self.prerequisites {
+			content.push_str(&format!("-{} {}\n", object_repr, comment));
+		}
+		for (object_repr, name) in &self.refs {
+			content.push_str(&format!("{} {}\n", object_repr, name));
+		}
+		content.push('\n');
+		let mut f = fs::File::create(path).map_err(|e| WriteError { source: e })?;
+		f.write_all(content.as_bytes())
+			.and_then(|_| f.write_all(&self.pack))
+			.map_err(|e| WriteError { source: e })
+	}
+}
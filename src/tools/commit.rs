@@ -0,0 +1,228 @@
+use crate::tools::{
+	config::{Config, ConfigError},
+	db::{insert, read, DbError, ObjectRepr, ObjectType},
+	tree::{flatten_tree, FlatTree, TreeError},
+};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+	convert::TryFrom,
+	env, fs,
+	str::from_utf8,
+	time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+use CommitError::{
+	ConfigReadError, IdentityError, MalformedError, NotACommitError, ObjectError, TreeReadError,
+};
+
+#[derive(Error, Debug)]
+pub enum CommitError {
+	#[error("{source:?}")]
+	ObjectError { source: DbError },
+	#[error("Object {hash} is not a commit")]
+	NotACommitError { hash: String },
+	#[error("Malformed commit: {msg}")]
+	MalformedError { msg: String },
+	#[error("{source:?}")]
+	TreeReadError { source: TreeError },
+	#[error("{source:?}")]
+	ConfigReadError { source: ConfigError },
+	#[error(
+		"No identity, set user.name and user.email in the config or UNRUSTY_AUTHOR_NAME and \
+		 UNRUSTY_AUTHOR_EMAIL"
+	)]
+	IdentityError,
+}
+
+#[derive(Debug, Clone)]
+pub struct Commit {
+	pub tree:      ObjectRepr,
+	pub parents:   Vec<ObjectRepr>,
+	pub author:    String,
+	pub committer: String,
+	pub message:   String,
+}
+
+const AUTHOR_NAME_VAR: &str = "UNRUSTY_AUTHOR_NAME";
+const AUTHOR_EMAIL_VAR: &str = "UNRUSTY_AUTHOR_EMAIL";
+
+// "Name <email> <seconds since epoch> <timezone>" as used for author and
+// committer. Times are always recorded in UTC
+pub fn signature_now() -> Result<String, CommitError> {
+	let (name, email) = configured_identity()?;
+	Ok(signature(
+		&name.ok_or(IdentityError)?,
+		&email.ok_or(IdentityError)?,
+	))
+}
+
+// Like signature_now, but without a configured identity it falls back to the
+// login name and "<login>@<host name>" like git does. Reflog entries must not
+// keep refs from being updated
+pub fn reflog_signature_now() -> Result<String, CommitError> {
+	let (name, email) = configured_identity()?;
+	let login = env::var("USER")
+		.or_else(|_| env::var("USERNAME"))
+		.unwrap_or_else(|_| "unknown".to_owned());
+	let email = email.unwrap_or_else(|| format!("{}@{}", login, host_name()));
+	Ok(signature(&name.unwrap_or(login), &email))
+}
+
+// The name and email of the environment, else of the config
+fn configured_identity() -> Result<(Option<String>, Option<String>), CommitError> {
+	let config = Config::read().map_err(|e| ConfigReadError { source: e })?;
+	Ok((
+		env::var(AUTHOR_NAME_VAR).ok().or(config.user.name),
+		env::var(AUTHOR_EMAIL_VAR).ok().or(config.user.email),
+	))
+}
+
+fn host_name() -> String {
+	fs::read_to_string("/proc/sys/kernel/hostname")
+		.ok()
+		.or_else(|| env::var("HOSTNAME").ok())
+		.map(|name| name.trim().to_owned())
+		.filter(|name| !name.is_empty())
+		.unwrap_or_else(|| "localhost".to_owned())
+}
+
+fn signature(name: &str, email: &str) -> String {
+	let seconds = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0);
+	format!("{} <{}> {} +0000", name, email, seconds)
+}
+
+// Whether a signature of a commit or tag has the format of signature_now
+pub fn is_valid_signature(signature: &str) -> bool {
+	lazy_static! {
+		static ref RE: Regex = Regex::new(r"\A[^<>\n]*<[^<>\n]*> \d+ [+-]\d{4}\z").unwrap();
+	}
+	RE.is_match(signature)
+}
+
+impl Commit {
+	// A commit authored and committed now by the configured user
+	pub fn new(
+		tree: ObjectRepr,
+		parents: Vec<ObjectRepr>,
+		message: &str,
+	) -> Result<Commit, CommitError> {
+		let signature = signature_now()?;
+		let mut message = message.to_owned();
+		if !message.ends_with('\n') {
+			message.push('\n');
+		}
+		Ok(Commit {
+			tree,
+			parents,
+			author: signature.clone(),
+			committer: signature,
+			message,
+		})
+	}
+
+	// A commit committed now by the configured user, keeping the author of
+	// another commit, e.g. when replaying it
+	pub fn with_author(
+		tree: ObjectRepr,
+		parents: Vec<ObjectRepr>,
+		author: &str,
+		message: &str,
+	) -> Result<Commit, CommitError> {
+		let mut commit = Commit::new(tree, parents, message)?;
+		commit.author = author.to_owned();
+		Ok(commit)
+	}
+
+	pub fn parse(data: &[u8]) -> Result<Commit, CommitError> {
+		let text = from_utf8(data).map_err(|_| malformed("not valid UTF-8"))?;
+		let (headers, message) = match text.find("\n\n") {
+			Some(index) => (&text[..index], &text[(index + 2)..]),
+			None => (text.trim_end_matches('\n'), ""),
+		};
+
+		let mut tree = None;
+		let mut parents = Vec::new();
+		let mut author = None;
+		let mut committer = None;
+		for line in headers.lines() {
+			// Continuation lines of multi-line headers such as gpgsig
+			if line.starts_with(' ') {
+				continue;
+			}
+			let (key, value) = match line.find(' ') {
+				Some(index) => (&line[..index], &line[(index + 1)..]),
+				None => return Err(malformed(&format!("header line {:?}", line))),
+			};
+			match key {
+				"tree" => tree = Some(parse_hash(value)?),
+				"parent" => parents.push(parse_hash(value)?),
+				"author" => author = Some(value.to_owned()),
+				"committer" => committer = Some(value.to_owned()),
+				_ => {},
+			}
+		}
+
+		Ok(Commit {
+			tree: tree.ok_or_else(|| malformed("missing tree"))?,
+			parents,
+			author: author.ok_or_else(|| malformed("missing author"))?,
+			committer: committer.ok_or_else(|| malformed("missing committer"))?,
+			message: message.to_owned(),
+		})
+	}
+
+	pub fn serialize(&self) -> Vec<u8> {
+		let mut text = format!("tree {}\n", self.tree);
+		for parent in &self.parents {
+			text.push_str(&format!("parent {}\n", parent));
+		}
+		text.push_str(&format!("author {}\n", self.author));
+		text.push_str(&format!("committer {}\n", self.committer));
+		text.push('\n');
+		text.push_str(&self.message);
+		text.into_bytes()
+	}
+
+	pub fn read(object_repr: &ObjectRepr) -> Result<Commit, CommitError> {
+		let object = read(object_repr).map_err(|e| ObjectError { source: e })?;
+		match object.object_type {
+			ObjectType::Commit => Commit::parse(&object.data),
+			_ => Err(NotACommitError {
+				hash: object_repr.to_string(),
+			}),
+		}
+	}
+
+	pub fn write(&self) -> Result<ObjectRepr, CommitError> {
+		insert(&self.serialize(), ObjectType::Commit).map_err(|e| ObjectError { source: e })
+	}
+}
+
+// The first line of a commit message
+pub fn subject(message: &str) -> String {
+	message.lines().next().unwrap_or("").to_owned()
+}
+
+// The files of a commit, none without a commit, e.g. for an unborn HEAD
+pub fn commit_tree(commit: Option<&ObjectRepr>) -> Result<FlatTree, CommitError> {
+	match commit {
+		Some(commit) => {
+			flatten_tree(&Commit::read(commit)?.tree).map_err(|e| TreeReadError { source: e })
+		},
+		None => Ok(FlatTree::new()),
+	}
+}
+
+fn parse_hash(value: &str) -> Result<ObjectRepr, CommitError> {
+	ObjectRepr::try_from(value).map_err(|_| malformed(&format!("invalid hash {:?}", value)))
+}
+
+fn malformed(msg: &str) -> CommitError {
+	MalformedError {
+		msg: msg.to_owned(),
+	}
+}
@@ -0,0 +1,134 @@
+use crate::tools::path::{vcs_path_required, PathError};
+use serde::{Deserialize, Serialize};
+use serde_yaml::{from_reader, to_writer};
+use std::{collections::BTreeMap, error::Error, fs::File, io::ErrorKind};
+use thiserror::Error;
+use ConfigError::{NoRepoError, ReadError, WriteError};
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+	#[error("{source:?}")]
+	NoRepoError { source: PathError },
+	#[error("Failed reading config: {source:?}")]
+	ReadError { source: Box<dyn Error> },
+	#[error("Failed writing config: {source:?}")]
+	WriteError { source: Box<dyn Error> },
+}
+
+const CONFIG_FILE: &str = "config";
+
+// Repository configuration, stored as YAML in the VCS directory. Every
+// section is optional so that a partial (or missing) file is valid
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+	pub user:       UserConfig,
+	pub merge:      MergeConfig,
+	// By name of the remote, e.g. origin
+	pub remote:     BTreeMap<String, RemoteConfig>,
+	// By name of the local branch, e.g. master
+	pub branch:     BTreeMap<String, BranchConfig>,
+	pub credential: CredentialConfig,
+	pub core:       CoreConfig,
+	// Clean and smudge commands by name of the driver, as set by the filter
+	// attribute
+	pub filter:     BTreeMap<String, FilterConfig>,
+	pub extensions: ExtensionsConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct UserConfig {
+	pub name:  Option<String>,
+	pub email: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct MergeConfig {
+	// merge, diff3 or zdiff3
+	pub conflict_style: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct RemoteConfig {
+	pub url:   String,
+	// Refspecs such as "+refs/heads/*:refs/remotes/origin/*"
+	pub fetch: Vec<String>,
+}
+
+// The upstream of a local branch
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct BranchConfig {
+	pub remote: Option<String>,
+	// Ref on the remote, e.g. refs/heads/master
+	pub merge:  Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct CredentialConfig {
+	// As in git, e.g. store for git-credential-store, or a shell command
+	// prefixed with !
+	pub helper: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct CoreConfig {
+	// true, false or input: line endings of files without a text attribute
+	pub autocrlf: Option<String>,
+	// lf, crlf or native: line endings of text files in the working tree
+	pub eol:      Option<String>,
+}
+
+// Shell commands filtering the content on stdin to stdout, "%f" stands for
+// the path of the file
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct FilterConfig {
+	// From the working tree to the repository
+	pub clean:    Option<String>,
+	// From the repository to the working tree
+	pub smudge:   Option<String>,
+	// Failing commands are errors instead of leaving the content as it is
+	pub required: bool,
+}
+
+// Settings that change how the repository is stored, git's extensions.*
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ExtensionsConfig {
+	// sha1 or sha256, set at init. Unset means sha1
+	pub object_format: Option<String>,
+}
+
+impl Config {
+	pub fn read() -> Result<Config, ConfigError> {
+		let path = vcs_path_required(CONFIG_FILE).map_err(|e| NoRepoError { source: e })?;
+		let f = match File::open(path) {
+			Ok(f) => f,
+			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+			Err(e) => {
+				return Err(ReadError {
+					source: Box::new(e),
+				})
+			},
+		};
+		from_reader(f).map_err(|e| ReadError {
+			source: Box::new(e),
+		})
+	}
+
+	pub fn write(&self) -> Result<(), ConfigError> {
+		let path = vcs_path_required(CONFIG_FILE).map_err(|e| NoRepoError { source: e })?;
+		let f = File::create(path).map_err(|e| WriteError {
+			source: Box::new(e),
+		})?;
+		to_writer(f, self).map_err(|e| WriteError {
+			source: Box::new(e),
+		})
+	}
+}
@@ -0,0 +1,133 @@
+// Credentials for HTTP remotes come from a helper speaking git's credential
+// protocol: it reads key=value lines describing the server on stdin and
+// answers with the username and password
+use crate::tools::config::Config;
+use std::{
+	env,
+	io::{self, Write},
+	path::Path,
+	process::{Command, Output, Stdio},
+};
+use thiserror::Error;
+use url::Url;
+use CredentialError::{HelperFailedError, HelperRunError};
+
+#[derive(Error, Debug)]
+pub enum CredentialError {
+	#[error("{source:?}")]
+	HelperRunError { source: io::Error },
+	#[error("Credential helper {helper:?} failed")]
+	HelperFailedError { helper: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct Credential {
+	pub username: String,
+	pub password: String,
+}
+
+const HELPER_VAR: &str = "UNRUSTY_CREDENTIAL_HELPER";
+
+// The helper of the environment, else of the config. Clones happen outside
+// any repository, so a missing config is not an error
+pub fn credential_helper() -> Option<String> {
+	env::var(HELPER_VAR)
+		.ok()
+		.or_else(|| Config::read().ok().and_then(|c| c.credential.helper))
+}
+
+// Like git: a shell command after !, a path, or the suffix of a
+// git-credential-* program
+fn helper_command(helper: &str, action: &str) -> String {
+	match helper.strip_prefix('!') {
+		Some(command) => format!("{} {}", command, action),
+		None if Path::new(helper).is_absolute() => format!("{} {}", helper, action),
+		None => format!("git-credential-{} {}", helper, action),
+	}
+}
+
+fn describe(url: &Url, credential: Option<&Credential>) -> String {
+	let mut description = format!(
+		"protocol={}\nhost={}",
+		url.scheme(),
+		url.host_str().unwrap_or("")
+	);
+	if let Some(port) = url.port() {
+		description.push_str(&format!(":{}", port));
+	}
+	description.push('\n');
+	if let Some(credential) = credential {
+		description.push_str(&format!(
+			"username={}\npassword={}\n",
+			credential.username, credential.password
+		));
+	} else if !url.username().is_empty() {
+		description.push_str(&format!("username={}\n", url.username()));
+	}
+	description.push('\n');
+	description
+}
+
+// Only the answer to get matters, like git the status of the other actions
+// is ignored. Helpers need not read the description, e.g. those that only
+// print a password
+fn run_helper(helper: &str, action: &str, input: &str) -> Result<Output, CredentialError> {
+	let mut child = Command::new("sh")
+		.arg("-c")
+		.arg(helper_command(helper, action))
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.spawn()
+		.map_err(|e| HelperRunError { source: e })?;
+	if let Some(mut stdin) = child.stdin.take() {
+		match stdin.write_all(input.as_bytes()) {
+			Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+				return Err(HelperRunError { source: e })
+			},
+			_ => {},
+		}
+	}
+	child
+		.wait_with_output()
+		.map_err(|e| HelperRunError { source: e })
+}
+
+// Asks the helper for the credentials of the server of `url`. None if it
+// knows no password
+pub fn fill_credential(helper: &str, url: &Url) -> Result<Option<Credential>, CredentialError> {
+	let output = run_helper(helper, "get", &describe(url, None))?;
+	if !output.status.success() {
+		return Err(HelperFailedError {
+			helper: helper.to_owned(),
+		});
+	}
+	let answer = String::from_utf8_lossy(&output.stdout);
+	let value = |key: &str| {
+		answer
+			.lines()
+			.find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+			.map(str::to_owned)
+	};
+	Ok(value("password").map(|password| Credential {
+		username: value("username").unwrap_or_else(|| url.username().to_owned()),
+		password,
+	}))
+}
+
+// Tells the helper that the credentials worked, so that it may store them
+pub fn approve_credential(
+	helper: &str,
+	url: &Url,
+	credential: &Credential,
+) -> Result<(), CredentialError> {
+	run_helper(helper, "store", &describe(url, Some(credential))).map(|_| ())
+}
+
+// Tells the helper that the credentials were refused
+pub fn reject_credential(
+	helper: &str,
+	url: &Url,
+	credential: &Credential,
+) -> Result<(), CredentialError> {
+	run_helper(helper, "erase", &describe(url, Some(credential))).map(|_| ())
+}
@@ -0,0 +1,219 @@
+use std::{
+	convert::TryFrom,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+
+fn unit_seconds(unit: &str) -> Option<u64> {
+	let unit = unit.strip_suffix('s').unwrap_or(unit);
+	match unit {
+		"second" => Some(1),
+		"minute" => Some(MINUTE),
+		"hour" => Some(HOUR),
+		"day" => Some(DAY),
+		"week" => Some(7 * DAY),
+		"month" => Some(30 * DAY),
+		"year" => Some(365 * DAY),
+		_ => None,
+	}
+}
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar, see
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = year.div_euclid(400);
+	let year_of_era = year - era * 400;
+	let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	era * 146_097 + day_of_era - 719_468
+}
+
+// Inverse of days_from_civil, as (year, month, day)
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+	let days = days + 719_468;
+	let era = days.div_euclid(146_097);
+	let day_of_era = days - era * 146_097;
+	let year_of_era =
+		(day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let month_index = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+	let month = if month_index < 10 {
+		month_index + 3
+	} else {
+		month_index - 9
+	};
+	let year = year_of_era + era * 400 + i64::from(month <= 2);
+	(year, month, day)
+}
+
+// "YYYY-MM-DD", optionally followed by " HH:MM" or " HH:MM:SS", in UTC
+fn parse_iso(repr: &str) -> Option<SystemTime> {
+	let (date, time) = match repr.split_once([' ', 'T']) {
+		Some((date, time)) => (date, Some(time)),
+		None => (repr, None),
+	};
+	let date = date
+		.split('-')
+		.map(|n| n.parse::<i64>().ok())
+		.collect::<Option<Vec<_>>>()?;
+	let (year, month, day) = match date[..] {
+		[year, month, day] if (1..=12).contains(&month) && (1..=31).contains(&day) => {
+			(year, month, day)
+		},
+		_ => return None,
+	};
+	let time = match time {
+		Some(time) => time
+			.split(':')
+			.map(|n| n.parse::<u64>().ok())
+			.collect::<Option<Vec<_>>>()?,
+		None => Vec::new(),
+	};
+	let seconds = match time[..] {
+		[] => 0,
+		[hours, minutes] if hours < 24 && minutes < 60 => hours * HOUR + minutes * MINUTE,
+		[hours, minutes, seconds] if hours < 24 && minutes < 60 && seconds < 60 => {
+			hours * HOUR + minutes * MINUTE + seconds
+		},
+		_ => return None,
+	};
+	let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+	Some(UNIX_EPOCH + Duration::from_secs(days * DAY + seconds))
+}
+
+// Dates as accepted by options like --expire: "now", "never", "yesterday",
+// relative dates such as "2.weeks.ago" or "3 days ago", seconds since the
+// epoch, or an ISO date
+pub fn parse_date(repr: &str) -> Option<SystemTime> {
+	let repr = repr.trim();
+	match repr {
+		"now" | "all" => return Some(SystemTime::now()),
+		"never" => return Some(UNIX_EPOCH),
+		"yesterday" => return SystemTime::now().checked_sub(Duration::from_secs(DAY)),
+		_ => {},
+	}
+	if let Some(relative) = repr.strip_suffix("ago") {
+		let parts = relative
+			.split(['.', ' '])
+			.filter(|p| !p.is_empty())
+			.collect::<Vec<_>>();
+		return match parts[..] {
+			[count, unit] => {
+				let seconds = count.parse::<u64>().ok()? * unit_seconds(unit)?;
+				SystemTime::now().checked_sub(Duration::from_secs(seconds))
+			},
+			_ => None,
+		};
+	}
+	if repr.chars().all(|c| c.is_ascii_digit()) && !repr.is_empty() {
+		return repr
+			.parse::<u64>()
+			.ok()
+			.map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds));
+	}
+	parse_iso(repr)
+}
+
+// The time of a signature "Name <email> <seconds> <timezone>"
+pub fn signature_time(signature: &str) -> Option<SystemTime> {
+	signature
+		.rsplit(' ')
+		.nth(1)
+		.and_then(|seconds| seconds.parse::<u64>().ok())
+		.map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+// Seconds east of UTC of a time zone "+HHMM" or "-HHMM"
+fn zone_offset(zone: &str) -> Option<i64> {
+	let sign = match zone.as_bytes().first()? {
+		b'+' => 1,
+		b'-' => -1,
+		_ => return None,
+	};
+	if zone.len() != 5 {
+		return None;
+	}
+	let hours = zone.get(1..3)?.parse::<i64>().ok()?;
+	let minutes = zone.get(3..5)?.parse::<i64>().ok()?;
+	Some(sign * (hours * 3600 + minutes * 60))
+}
+
+// Seconds since the epoch in the time zone of a signature, and the zone
+fn signature_local_time(signature: &str) -> Option<(i64, &str)> {
+	let mut fields = signature.rsplit(' ');
+	let zone = fields.next()?;
+	let seconds = fields.next()?.parse::<i64>().ok()?;
+	Some((seconds + zone_offset(zone)?, zone))
+}
+
+// "YYYY-MM-DD HH:MM:SS +ZZZZ" of a signature, in the time zone it was made in
+pub fn format_signature_date(signature: &str) -> Option<String> {
+	let (local, zone) = signature_local_time(signature)?;
+	let (year, month, day) = civil_from_days(local.div_euclid(DAY as i64));
+	let time = local.rem_euclid(DAY as i64);
+	Some(format!(
+		"{:04}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+		year,
+		month,
+		day,
+		time / 3600,
+		time % 3600 / 60,
+		time % 60,
+		zone
+	))
+}
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+	"Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// "Mon, 2 Jan 2006 15:04:05 +ZZZZ" of a signature as in the Date header of
+// mails, in the time zone it was made in
+pub fn format_rfc2822_date(signature: &str) -> Option<String> {
+	let (local, zone) = signature_local_time(signature)?;
+	let days = local.div_euclid(DAY as i64);
+	let (year, month, day) = civil_from_days(days);
+	let time = local.rem_euclid(DAY as i64);
+	Some(format!(
+		"{}, {} {} {} {:02}:{:02}:{:02} {}",
+		WEEKDAYS[days.rem_euclid(7) as usize],
+		day,
+		MONTHS[(month - 1) as usize],
+		year,
+		time / 3600,
+		time % 3600 / 60,
+		time % 60,
+		zone
+	))
+}
+
+// The seconds since the epoch and the time zone of a Date header of a mail,
+// the day of the week is optional
+pub fn parse_rfc2822_date(repr: &str) -> Option<(i64, String)> {
+	let repr = repr.split_once(',').map_or(repr, |(_, rest)| rest);
+	let fields = repr.split_whitespace().collect::<Vec<_>>();
+	let (day, month, year, time, zone) = match fields[..] {
+		[day, month, year, time, zone, ..] => (day, month, year, time, zone),
+		_ => return None,
+	};
+	let day = day.parse::<i64>().ok()?;
+	let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? as i64 + 1;
+	let year = year.parse::<i64>().ok()?;
+	let time = time
+		.split(':')
+		.map(|n| n.parse::<i64>().ok())
+		.collect::<Option<Vec<_>>>()?;
+	let seconds = match time[..] {
+		[hours, minutes] => hours * 3600 + minutes * 60,
+		[hours, minutes, seconds] => hours * 3600 + minutes * 60 + seconds,
+		_ => return None,
+	};
+	let local = days_from_civil(year, month, day) * DAY as i64 + seconds;
+	Some((local - zone_offset(zone)?, zone.to_owned()))
+}
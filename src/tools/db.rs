@@ -1,4 +1,7 @@
-use crate::tools::path::{db_path, PathError};
+use crate::tools::{
+	object_format::ObjectFormat,
+	path::{db_path, objects_dir_required, PathError},
+};
 use flate2::{
 	write::{ZlibDecoder, ZlibEncoder},
 	Compression,
@@ -6,12 +9,11 @@ use flate2::{
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize, Serializer};
-use sha1::{Digest, Sha1};
 use std::{
 	convert::{From, TryFrom},
 	fmt::{self, Display, Formatter},
 	fs::{self, remove_file, File},
-	io::{self, ErrorKind, Write},
+	io::{self, Write},
 	path::{Path, PathBuf},
 	str::from_utf8,
 };
@@ -36,11 +38,12 @@ pub enum DbError {
 	NoRootError,
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ObjectType {
 	Blob,
 	Tree,
 	Commit,
+	Tag,
 	Invalid,
 }
 
@@ -49,11 +52,13 @@ pub struct Object {
 	pub object_type: ObjectType,
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Hash)]
 pub struct ObjectRepr {
 	hash: String,
 }
 
+const SHORT_HASH_LEN: usize = 7;
+
 ////////////////////////////////////////////
 // READ
 ////////////////////////////////////////////
@@ -66,17 +71,25 @@ pub fn read_with_invalid(
 	object_repr: &ObjectRepr,
 	allow_invalid_type: bool,
 ) -> Result<Object, DbError> {
+	let decoded = read_store(object_repr)?;
+	parse_store(&decoded, allow_invalid_type)
+}
+
+// The decompressed content of the object file, header included
+pub fn read_store(object_repr: &ObjectRepr) -> Result<Vec<u8>, DbError> {
 	let path = object_repr.path().map_err(|_| NoRootError)?;
 	let content = fs::read(path).map_err(|e| ReadError { source: e })?;
-	let mut decoded = Vec::new();
-	let mut z = ZlibDecoder::new(decoded);
+	let mut z = ZlibDecoder::new(Vec::new());
 	z.write_all(&content[..])
 		.map_err(|e| ReadError { source: e })?;
-	decoded = z.finish().map_err(|e| ReadError { source: e })?;
+	z.finish().map_err(|e| ReadError { source: e })
+}
 
-	if let Some(index) = decoded.iter().position(|&x| x == b'\0') {
-		let header = &decoded[0..index];
-		let data = &decoded[(index + 1)..];
+// Splits the decompressed content of an object file into header and data
+pub fn parse_store(store: &[u8], allow_invalid_type: bool) -> Result<Object, DbError> {
+	if let Some(index) = store.iter().position(|&x| x == b'\0') {
+		let header = &store[0..index];
+		let data = &store[(index + 1)..];
 		read_prepared(header, data, allow_invalid_type)
 	} else {
 		Err(read_error_factory("No null byte in object"))
@@ -84,10 +97,10 @@ pub fn read_with_invalid(
 }
 
 fn read_prepared(header: &[u8], data: &[u8], allow_invalid: bool) -> Result<Object, DbError> {
-	let header = from_utf8(&header).map_err(|e| read_error_factory(&format!("{:?}", e)))?;
+	let header = from_utf8(header).map_err(|e| read_error_factory(&format!("{:?}", e)))?;
 
 	lazy_static! {
-		static ref RE: Regex = Regex::new(r"\A([a-z]*) ([1-9]\d*)\z").unwrap();
+		static ref RE: Regex = Regex::new(r"\A([a-z]*) (0|[1-9]\d*)\z").unwrap();
 	}
 	let capture = RE
 		.captures(header)
@@ -114,7 +127,7 @@ fn read_prepared(header: &[u8], data: &[u8], allow_invalid: bool) -> Result<Obje
 
 fn read_error_factory(msg: &str) -> DbError {
 	ReadError {
-		source: io::Error::new(ErrorKind::Other, msg),
+		source: io::Error::other(msg),
 	}
 }
 
@@ -123,11 +136,11 @@ fn read_error_factory(msg: &str) -> DbError {
 ////////////////////////////////////////////
 
 pub fn simulate_insert_file(path: &Path, object_type: ObjectType) -> Result<ObjectRepr, DbError> {
-	insert_file_with_simulate(&path, object_type, true)
+	insert_file_with_simulate(path, object_type, true)
 }
 
 pub fn insert_file(path: &Path, object_type: ObjectType) -> Result<ObjectRepr, DbError> {
-	insert_file_with_simulate(&path, object_type, false)
+	insert_file_with_simulate(path, object_type, false)
 }
 
 pub fn simulate_insert(content: &[u8], object_type: ObjectType) -> Result<ObjectRepr, DbError> {
@@ -143,7 +156,7 @@ pub fn insert_file_with_simulate(
 	object_type: ObjectType,
 	simulate: bool,
 ) -> Result<ObjectRepr, DbError> {
-	let content = fs::read(&path).map_err(|e| InputReadError { source: e })?;
+	let content = fs::read(path).map_err(|e| InputReadError { source: e })?;
 	insert_with_simulate(&content, object_type, simulate)
 }
 
@@ -174,10 +187,9 @@ fn get_store(content: &[u8], object_type: ObjectType) -> Vec<u8> {
 	header
 }
 
-fn get_object_repr(store: &[u8]) -> ObjectRepr {
-	let mut hasher = Sha1::new();
-	hasher.input(&store);
-	ObjectRepr::try_from(hex::encode(hasher.result()).as_str()).unwrap()
+pub fn get_object_repr(store: &[u8]) -> ObjectRepr {
+	let digest = ObjectFormat::current().digest(store);
+	ObjectRepr::try_from(hex::encode(digest).as_str()).unwrap()
 }
 
 fn write_object(store: &[u8], writer: &mut dyn Write) -> Result<(), io::Error> {
@@ -197,6 +209,33 @@ pub fn delete(object_repr: &ObjectRepr) -> Result<(), DbError> {
 	remove_file(&path).map_err(|e| DeleteError { source: e })
 }
 
+////////////////////////////////////////////
+// LIST
+////////////////////////////////////////////
+
+// All objects in the database, sorted by hash
+pub fn list_objects() -> Result<Vec<ObjectRepr>, DbError> {
+	let dir = objects_dir_required().map_err(|_| NoRootError)?;
+	let entries = match fs::read_dir(&dir) {
+		Ok(entries) => entries,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(ReadError { source: e }),
+	};
+	let mut objects = Vec::new();
+	for entry in entries {
+		let entry = entry.map_err(|e| ReadError { source: e })?;
+		if let Some(object_repr) = entry
+			.file_name()
+			.to_str()
+			.and_then(|name| ObjectRepr::try_from(name).ok())
+		{
+			objects.push(object_repr);
+		}
+	}
+	objects.sort();
+	Ok(objects)
+}
+
 ////////////////////////////////////////////
 // TYPE IMPLEMENTATIONS
 ////////////////////////////////////////////
@@ -207,6 +246,7 @@ impl Display for ObjectType {
 			ObjectType::Blob => write!(f, "blob"),
 			ObjectType::Tree => write!(f, "tree"),
 			ObjectType::Commit => write!(f, "commit"),
+			ObjectType::Tag => write!(f, "tag"),
 			ObjectType::Invalid => write!(f, "invalid"),
 		}
 	}
@@ -218,6 +258,7 @@ impl From<&str> for ObjectType {
 			"blob" => ObjectType::Blob,
 			"tree" => ObjectType::Tree,
 			"commit" => ObjectType::Commit,
+			"tag" => ObjectType::Tag,
 			_ => ObjectType::Invalid,
 		}
 	}
@@ -233,6 +274,7 @@ impl ObjectType {
 			ObjectType::Blob,
 			ObjectType::Commit,
 			ObjectType::Tree,
+			ObjectType::Tag,
 			ObjectType::Invalid,
 		]
 		.iter()
@@ -249,11 +291,12 @@ impl Object {
 impl TryFrom<&str> for ObjectRepr {
 	type Error = ();
 
+	// SHA-1 or SHA-256 digests
 	fn try_from(hash: &str) -> Result<Self, Self::Error> {
 		lazy_static! {
-			static ref RE: Regex = Regex::new(r"\A[\d|[a-f]]{40}\z").unwrap();
+			static ref RE: Regex = Regex::new(r"\A([\da-f]{40}|[\da-f]{64})\z").unwrap();
 		}
-		if RE.is_match(&hash) {
+		if RE.is_match(hash) {
 			Ok(ObjectRepr {
 				hash: String::from(hash),
 			})
@@ -277,6 +320,20 @@ impl ObjectRepr {
 	pub fn hash(&self) -> &str {
 		self.hash.as_str()
 	}
+
+	// Abbreviation for display, like git's default
+	pub fn short(&self) -> &str {
+		&self.hash[..SHORT_HASH_LEN]
+	}
+
+	// Tree objects store hashes in binary form
+	pub fn from_bytes(bytes: &[u8]) -> Result<ObjectRepr, ()> {
+		ObjectRepr::try_from(hex::encode(bytes).as_str())
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		hex::decode(&self.hash).unwrap()
+	}
 }
 
 // needed as we want to check that the hash is correct by calling
@@ -0,0 +1,199 @@
+// Line based diffing with the algorithm from Myers, "An O(ND) Difference
+// Algorithm and Its Variations"
+
+// Splits after every newline, the last line may lack one
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+	let mut lines = Vec::new();
+	let mut start = 0;
+	for (i, &b) in data.iter().enumerate() {
+		if b == b'\n' {
+			lines.push(&data[start..=i]);
+			start = i + 1;
+		}
+	}
+	if start < data.len() {
+		lines.push(&data[start..]);
+	}
+	lines
+}
+
+// Pairs (i, j) of equal lines a[i] == b[j] forming a longest common
+// subsequence, in increasing order
+pub fn matching_lines<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+	// Strip common prefix and suffix, they are cheap and very common
+	let prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+	let suffix = a[prefix..]
+		.iter()
+		.rev()
+		.zip(b[prefix..].iter().rev())
+		.take_while(|(x, y)| x == y)
+		.count();
+
+	let mut matches = (0..prefix).map(|i| (i, i)).collect::<Vec<_>>();
+	let middle = myers(
+		&a[prefix..(a.len() - suffix)],
+		&b[prefix..(b.len() - suffix)],
+	);
+	matches.extend(middle.into_iter().map(|(i, j)| (i + prefix, j + prefix)));
+	matches.extend((0..suffix).map(|s| (a.len() - suffix + s, b.len() - suffix + s)));
+	matches
+}
+
+fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+	let n = a.len() as isize;
+	let m = b.len() as isize;
+	let max = (n + m) as usize;
+	if max == 0 {
+		return Vec::new();
+	}
+	// v[k + offset] is the furthest x reached on diagonal k = x - y
+	let offset = max as isize + 1;
+	let mut v = vec![0isize; 2 * max + 3];
+	let mut trace = Vec::new();
+
+	'outer: for d in 0..=(max as isize) {
+		trace.push(v.clone());
+		let mut k = -d;
+		while k <= d {
+			let idx = (k + offset) as usize;
+			let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+				v[idx + 1]
+			} else {
+				v[idx - 1] + 1
+			};
+			let mut y = x - k;
+			while x < n && y < m && a[x as usize] == b[y as usize] {
+				x += 1;
+				y += 1;
+			}
+			v[idx] = x;
+			if x >= n && y >= m {
+				trace.push(v.clone());
+				break 'outer;
+			}
+			k += 2;
+		}
+	}
+
+	// Walk back through the trace to recover the diagonals, i.e. the matches
+	let mut matches = Vec::new();
+	let (mut x, mut y) = (n, m);
+	for d in (1..(trace.len() as isize - 1)).rev() {
+		let v = &trace[d as usize];
+		let k = x - y;
+		let idx = (k + offset) as usize;
+		let prev_k = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+			k + 1
+		} else {
+			k - 1
+		};
+		let prev_x = v[(prev_k + offset) as usize];
+		let prev_y = prev_x - prev_k;
+		while x > prev_x && y > prev_y {
+			x -= 1;
+			y -= 1;
+			matches.push((x as usize, y as usize));
+		}
+		x = prev_x;
+		y = prev_y;
+	}
+	while x > 0 && y > 0 {
+		x -= 1;
+		y -= 1;
+		matches.push((x as usize, y as usize));
+	}
+	matches.reverse();
+	matches
+}
+
+// A line of a hunk: ' ' for context, '-' for removed, '+' for added
+pub type HunkLine<'a> = (char, &'a [u8]);
+
+// A group of changes with their context. Starts are 1-based, a range without
+// lines starts at the line before it like in unified diffs
+pub struct Hunk<'a> {
+	pub old_start: usize,
+	pub old_len:   usize,
+	pub new_start: usize,
+	pub new_len:   usize,
+	pub lines:     Vec<HunkLine<'a>>,
+}
+
+// The lines of both sides as context, removals and additions, removals
+// first where lines were replaced
+fn edit_script<'a>(a: &[&'a [u8]], b: &[&'a [u8]]) -> Vec<HunkLine<'a>> {
+	let mut script = Vec::new();
+	let (mut i, mut j) = (0, 0);
+	for (mi, mj) in matching_lines(a, b)
+		.into_iter()
+		.chain(std::iter::once((a.len(), b.len())))
+	{
+		script.extend(a[i..mi].iter().map(|l| ('-', *l)));
+		script.extend(b[j..mj].iter().map(|l| ('+', *l)));
+		if mi < a.len() {
+			script.push((' ', a[mi]));
+		}
+		i = mi + 1;
+		j = mj + 1;
+	}
+	script
+}
+
+// Changes between the lines of a and b, with `context` unchanged lines around
+// them. Changes separated by at most twice the context share a hunk
+pub fn hunks<'a>(a: &[&'a [u8]], b: &[&'a [u8]], context: usize) -> Vec<Hunk<'a>> {
+	let script = edit_script(a, b);
+	let changes = script
+		.iter()
+		.enumerate()
+		.filter(|(_, (kind, _))| *kind != ' ')
+		.map(|(n, _)| n)
+		.collect::<Vec<_>>();
+
+	let mut hunks = Vec::new();
+	let mut n = 0;
+	while n < changes.len() {
+		let first = changes[n];
+		let mut last = first;
+		while n + 1 < changes.len() && changes[n + 1] - last <= 2 * context + 1 {
+			n += 1;
+			last = changes[n];
+		}
+		n += 1;
+		let start = first.saturating_sub(context);
+		let end = (last + context + 1).min(script.len());
+
+		// Lines of each side before the hunk
+		let before = |side: char| {
+			script[..start]
+				.iter()
+				.filter(|(kind, _)| *kind == ' ' || *kind == side)
+				.count()
+		};
+		let lines = script[start..end].to_vec();
+		let len = |side: char| {
+			lines
+				.iter()
+				.filter(|(kind, _)| *kind == ' ' || *kind == side)
+				.count()
+		};
+		let (old_len, new_len) = (len('-'), len('+'));
+		let (old_before, new_before) = (before('-'), before('+'));
+		hunks.push(Hunk {
+			old_start: if old_len == 0 {
+				old_before
+			} else {
+				old_before + 1
+			},
+			old_len,
+			new_start: if new_len == 0 {
+				new_before
+			} else {
+				new_before + 1
+			},
+			new_len,
+			lines,
+		});
+	}
+	hunks
+}
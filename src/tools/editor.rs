@@ -0,0 +1,57 @@
+// Messages and todo lists are edited by the user in an editor run on a file,
+// like git does with $EDITOR
+use std::{
+	env, io,
+	path::Path,
+	process::{Command, ExitStatus},
+};
+use thiserror::Error;
+use EditorError::{EditorFailedError, EditorRunError};
+
+#[derive(Error, Debug)]
+pub enum EditorError {
+	#[error("{source:?}")]
+	EditorRunError { source: io::Error },
+	#[error("Editor {editor:?} failed with {status}")]
+	EditorFailedError { editor: String, status: ExitStatus },
+}
+
+const EDITOR_VAR: &str = "UNRUSTY_EDITOR";
+const DEFAULT_EDITOR: &str = "vi";
+
+// The editor of UNRUSTY_EDITOR, else of EDITOR, else vi
+pub fn editor() -> String {
+	env::var(EDITOR_VAR)
+		.or_else(|_| env::var("EDITOR"))
+		.ok()
+		.filter(|e| !e.is_empty())
+		.unwrap_or_else(|| DEFAULT_EDITOR.to_owned())
+}
+
+// Lets the user edit the file. The editor is a shell command, so it may carry
+// arguments, e.g. "code --wait"
+pub fn edit_file(path: &Path) -> Result<(), EditorError> {
+	let editor = editor();
+	let status = Command::new("sh")
+		.arg("-c")
+		.arg(format!("{} \"$@\"", editor))
+		.arg(&editor)
+		.arg(path)
+		.status()
+		.map_err(|e| EditorRunError { source: e })?;
+	if status.success() {
+		Ok(())
+	} else {
+		Err(EditorFailedError { editor, status })
+	}
+}
+
+// Drops the lines starting with # and surrounding blank lines
+pub fn strip_comments(text: &str) -> String {
+	let lines = text
+		.lines()
+		.filter(|l| !l.starts_with('#'))
+		.collect::<Vec<_>>()
+		.join("\n");
+	lines.trim().to_owned()
+}
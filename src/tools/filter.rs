@@ -0,0 +1,295 @@
+use crate::tools::{
+	attributes::{AttrState, Attributes, AttributesError},
+	config::{Config, ConfigError, CoreConfig, FilterConfig},
+	db::{simulate_insert, DbError, ObjectType},
+	lfs::{resolve, store, LfsError, Pointer, LFS_FILTER},
+	path::RelativePathToBase,
+};
+use log::warn;
+use std::{
+	collections::BTreeMap,
+	io::{self, Write},
+	process::{Command, Stdio},
+	thread,
+};
+use thiserror::Error;
+use FilterError::{AttributesReadError, CommandError, ConfigReadError, LfsStoreError, ObjectError};
+
+#[derive(Error, Debug)]
+pub enum FilterError {
+	#[error("{source:?}")]
+	AttributesReadError { source: AttributesError },
+	#[error("{source:?}")]
+	ConfigReadError { source: ConfigError },
+	#[error("{source:?}")]
+	ObjectError { source: DbError },
+	#[error("{source:?}")]
+	LfsStoreError { source: LfsError },
+	#[error("Failed running filter {command:?}: {source:?}")]
+	CommandError { command: String, source: io::Error },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Eol {
+	Lf,
+	Crlf,
+}
+
+// How the line endings of a file are converted, with those it has in the
+// working tree. The repository always holds LF
+#[derive(Debug, Clone, Copy)]
+enum Text {
+	Binary,
+	Text(Eol),
+	// Only when the content does not look binary
+	Auto(Eol),
+}
+
+// Conversions between the content of files in the working tree and in the
+// repository, as the attributes and the configuration ask for
+pub struct Filters {
+	attributes: Attributes,
+	core:       CoreConfig,
+	drivers:    BTreeMap<String, FilterConfig>,
+}
+
+// Like git: NUL bytes, lone CRs or many control characters
+fn is_binary(content: &[u8]) -> bool {
+	let mut printable = 0;
+	let mut nonprintable = 0;
+	for (i, &c) in content.iter().enumerate() {
+		match c {
+			0 => return true,
+			b'\r' if content.get(i + 1) != Some(&b'\n') => return true,
+			b'\n' | b'\r' | b'\t' | 0x08 | 0x1b | 0x0c => printable += 1,
+			c if c < 0x20 || c == 0x7f => nonprintable += 1,
+			_ => printable += 1,
+		}
+	}
+	printable / 128 < nonprintable
+}
+
+fn crlf_to_lf(content: &[u8]) -> Vec<u8> {
+	let mut out = Vec::with_capacity(content.len());
+	for (i, &c) in content.iter().enumerate() {
+		if c != b'\r' || content.get(i + 1) != Some(&b'\n') {
+			out.push(c);
+		}
+	}
+	out
+}
+
+// LFs already preceded by a CR are left alone
+fn lf_to_crlf(content: &[u8]) -> Vec<u8> {
+	let mut out = Vec::with_capacity(content.len());
+	for (i, &c) in content.iter().enumerate() {
+		if c == b'\n' && (i == 0 || content[i - 1] != b'\r') {
+			out.push(b'\r');
+		}
+		out.push(c);
+	}
+	out
+}
+
+// Replaces every "$Id$" and "$Id: ...$" within a line by `with`
+fn replace_ident(content: &[u8], with: &[u8]) -> Vec<u8> {
+	let mut out = Vec::with_capacity(content.len());
+	let mut i = 0;
+	while i < content.len() {
+		if content[i..].starts_with(b"$Id") {
+			let rest = &content[(i + 3)..];
+			let end = match rest.first() {
+				Some(b'$') => Some(0),
+				Some(b':') => rest
+					.iter()
+					.position(|&c| c == b'$' || c == b'\n')
+					.filter(|&end| rest[end] == b'$'),
+				_ => None,
+			};
+			if let Some(end) = end {
+				out.extend_from_slice(with);
+				i += 3 + end + 1;
+				continue;
+			}
+		}
+		out.push(content[i]);
+		i += 1;
+	}
+	out
+}
+
+fn shell_quote(text: &str) -> String {
+	format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+// Feeds the content to the command and returns what it printed
+fn run_driver(command: &str, path: &RelativePathToBase, content: Vec<u8>) -> io::Result<Vec<u8>> {
+	let command = command.replace("%f", &shell_quote(&path.to_string()));
+	let mut child = Command::new("sh")
+		.arg("-c")
+		.arg(command)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.spawn()?;
+	// Written from another thread so that a full stdout cannot block it
+	let mut stdin = child.stdin.take().expect("stdin is piped");
+	let writer = thread::spawn(move || stdin.write_all(&content));
+	let output = child.wait_with_output()?;
+	writer
+		.join()
+		.unwrap_or_else(|_| Err(io::ErrorKind::BrokenPipe.into()))?;
+	if output.status.success() {
+		Ok(output.stdout)
+	} else {
+		Err(io::Error::other(format!("exited with {}", output.status)))
+	}
+}
+
+// Large files go to the store, the repository gets a pointer. Pointers, e.g.
+// of files whose content is not in the store, are kept as they are
+fn lfs_clean(content: Vec<u8>) -> Result<Vec<u8>, LfsError> {
+	if Pointer::parse(&content).is_some() {
+		return Ok(content);
+	}
+	Ok(store(&content)?.format().into_bytes())
+}
+
+// Pointers whose content is not in the store can not be checked out
+fn lfs_smudge(content: Vec<u8>) -> Result<Vec<u8>, LfsError> {
+	match resolve(&content)? {
+		Some(resolved) => Ok(resolved),
+		None => match Pointer::parse(&content) {
+			Some(pointer) => Err(LfsError::MissingError { oid: pointer.oid }),
+			None => Ok(content),
+		},
+	}
+}
+
+impl Filters {
+	pub fn read() -> Result<Filters, FilterError> {
+		let config = Config::read().map_err(|e| ConfigReadError { source: e })?;
+		Ok(Filters {
+			attributes: Attributes::read().map_err(|e| AttributesReadError { source: e })?,
+			core:       config.core,
+			drivers:    config.filter,
+		})
+	}
+
+	// Text files get CRLF with core.autocrlf, or as core.eol says
+	fn default_eol(&self) -> Eol {
+		match (self.core.autocrlf.as_deref(), self.core.eol.as_deref()) {
+			(Some("true"), _) | (Some("false") | None, Some("crlf")) => Eol::Crlf,
+			_ => Eol::Lf,
+		}
+	}
+
+	fn text(&self, path: &RelativePathToBase) -> Text {
+		let eol = match self.attributes.get(path, "eol") {
+			AttrState::Value(eol) if eol == "lf" => Some(Eol::Lf),
+			AttrState::Value(eol) if eol == "crlf" => Some(Eol::Crlf),
+			_ => None,
+		};
+		// crlf is the older name of text, "crlf=input" converts on the way in only
+		let state = match self.attributes.get(path, "text") {
+			AttrState::Unspecified => match self.attributes.get(path, "crlf") {
+				AttrState::Value(value) if value == "input" => return Text::Text(Eol::Lf),
+				state => state,
+			},
+			state => state,
+		};
+		match state {
+			AttrState::Set => Text::Text(eol.unwrap_or_else(|| self.default_eol())),
+			AttrState::Unset => Text::Binary,
+			AttrState::Value(value) if value == "auto" => {
+				Text::Auto(eol.unwrap_or_else(|| self.default_eol()))
+			},
+			_ => match (eol, self.core.autocrlf.as_deref()) {
+				(Some(eol), _) => Text::Text(eol),
+				(None, Some("true")) => Text::Auto(Eol::Crlf),
+				(None, Some("input")) => Text::Auto(Eol::Lf),
+				(None, _) => Text::Binary,
+			},
+		}
+	}
+
+	// Without commands configured for it, the lfs driver is the built-in one
+	fn driver(
+		&self,
+		path: &RelativePathToBase,
+		command: impl Fn(&FilterConfig) -> Option<&String>,
+		builtin_lfs: impl Fn(Vec<u8>) -> Result<Vec<u8>, LfsError>,
+		content: Vec<u8>,
+	) -> Result<Vec<u8>, FilterError> {
+		let name = match self.attributes.get(path, "filter") {
+			AttrState::Value(name) => name,
+			_ => return Ok(content),
+		};
+		let driver = match self.drivers.get(&name) {
+			Some(driver) => driver,
+			None if name == LFS_FILTER => {
+				return builtin_lfs(content).map_err(|e| LfsStoreError { source: e })
+			},
+			None => return Ok(content),
+		};
+		let command = match command(driver) {
+			Some(command) => command,
+			None => return Ok(content),
+		};
+		match run_driver(command, path, content.clone()) {
+			Ok(filtered) => Ok(filtered),
+			Err(e) if driver.required => Err(CommandError {
+				command: command.clone(),
+				source:  e,
+			}),
+			Err(e) => {
+				warn!("filter {:?} failed for {}: {}", command, path, e);
+				Ok(content)
+			},
+		}
+	}
+
+	// From the working tree to the repository: the filter driver, then line
+	// endings, then idents
+	pub fn clean(
+		&self,
+		path: &RelativePathToBase,
+		content: Vec<u8>,
+	) -> Result<Vec<u8>, FilterError> {
+		let content = self.driver(path, |d| d.clean.as_ref(), lfs_clean, content)?;
+		let content = match self.text(path) {
+			Text::Text(_) => crlf_to_lf(&content),
+			Text::Auto(_) if !is_binary(&content) => crlf_to_lf(&content),
+			_ => content,
+		};
+		match self.attributes.get(path, "ident") {
+			AttrState::Set => Ok(replace_ident(&content, b"$Id$")),
+			_ => Ok(content),
+		}
+	}
+
+	// From the repository to the working tree, the other way round. Idents
+	// get the hash of the blob
+	pub fn smudge(
+		&self,
+		path: &RelativePathToBase,
+		content: Vec<u8>,
+	) -> Result<Vec<u8>, FilterError> {
+		let content = match self.attributes.get(path, "ident") {
+			AttrState::Set => {
+				let hash = simulate_insert(&content, ObjectType::Blob)
+					.map_err(|e| ObjectError { source: e })?;
+				replace_ident(&content, format!("$Id: {} $", hash).as_bytes())
+			},
+			_ => content,
+		};
+		let content = match self.text(path) {
+			Text::Text(Eol::Crlf) => lf_to_crlf(&content),
+			// Content that already has CRs was not normalized, it is left alone
+			Text::Auto(Eol::Crlf) if !is_binary(&content) && !content.contains(&b'\r') => {
+				lf_to_crlf(&content)
+			},
+			_ => content,
+		};
+		self.driver(path, |d| d.smudge.as_ref(), lfs_smudge, content)
+	}
+}
@@ -0,0 +1,93 @@
+// Shell style pattern matching as used by git for ignore and attribute files.
+// `*` and `?` do not match a slash, `**` between slashes matches any number
+// of directories, `[...]` matches a character class
+
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+	match pattern.split_first() {
+		None => text.is_empty(),
+		Some((b'*', rest)) => {
+			if rest.first() == Some(&b'*') {
+				match_double_star(&rest[1..], text)
+			} else {
+				// Single star: any run of characters within one path component
+				(0..=text.len())
+					.take_while(|&i| i == 0 || text[i - 1] != b'/')
+					.any(|i| wildmatch(rest, &text[i..]))
+			}
+		},
+		Some((b'?', rest)) => match text.split_first() {
+			Some((&c, text_rest)) if c != b'/' => wildmatch(rest, text_rest),
+			_ => false,
+		},
+		Some((b'[', rest)) => match (text.split_first(), match_class(rest)) {
+			(Some((&c, text_rest)), Some((class, after))) if c != b'/' => {
+				class.contains(c) && wildmatch(after, text_rest)
+			},
+			// An unterminated class is matched literally
+			(Some((b'[', text_rest)), None) => wildmatch(rest, text_rest),
+			_ => false,
+		},
+		Some((b'\\', rest)) if !rest.is_empty() => match text.split_first() {
+			Some((&c, text_rest)) if c == rest[0] => wildmatch(&rest[1..], text_rest),
+			_ => false,
+		},
+		Some((&p, rest)) => match text.split_first() {
+			Some((&c, text_rest)) if c == p => wildmatch(rest, text_rest),
+			_ => false,
+		},
+	}
+}
+
+// `rest` is the pattern after `**`
+fn match_double_star(rest: &[u8], text: &[u8]) -> bool {
+	match rest.split_first() {
+		// "**/" also matches no directory at all
+		Some((b'/', after)) => {
+			wildmatch(after, text)
+				|| (0..text.len())
+					.filter(|&i| text[i] == b'/')
+					.any(|i| wildmatch(after, &text[(i + 1)..]))
+		},
+		_ => (0..=text.len()).any(|i| wildmatch(rest, &text[i..])),
+	}
+}
+
+struct CharClass {
+	negated: bool,
+	ranges:  Vec<(u8, u8)>,
+}
+
+impl CharClass {
+	fn contains(&self, c: u8) -> bool {
+		self.ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != self.negated
+	}
+}
+
+// Parses the class after `[`, returns it with the rest of the pattern
+fn match_class(pattern: &[u8]) -> Option<(CharClass, &[u8])> {
+	let (negated, mut i) = match pattern.first() {
+		Some(b'!') | Some(b'^') => (true, 1),
+		_ => (false, 0),
+	};
+	let mut ranges = Vec::new();
+	let mut first = true;
+	while i < pattern.len() {
+		let mut c = pattern[i];
+		if c == b']' && !first {
+			return Some((CharClass { negated, ranges }, &pattern[(i + 1)..]));
+		}
+		first = false;
+		if c == b'\\' && i + 1 < pattern.len() {
+			i += 1;
+			c = pattern[i];
+		}
+		if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']' {
+			ranges.push((c, pattern[i + 2]));
+			i += 3;
+		} else {
+			ranges.push((c, c));
+			i += 1;
+		}
+	}
+	None
+}
@@ -0,0 +1,676 @@
+// Client of git's smart HTTP protocol, version 2. The capabilities are
+// discovered with a GET of info/refs, then each command is a POST to
+// git-upload-pack whose response is read as pkt-lines
+use crate::tools::{
+	credential::{
+		approve_credential, credential_helper, fill_credential, reject_credential, Credential,
+		CredentialError,
+	},
+	db::ObjectRepr,
+	object_format::ObjectFormat,
+	pktline::{
+		read_packet, write_line, write_packet, Packet, PktLineError, AGENT, BAND_DATA,
+		BAND_PROGRESS,
+	},
+	refs::{RefValue, HEAD},
+	rev::is_safe_ref_name,
+	transport::RemoteRefs,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{
+	collections::BTreeMap,
+	convert::TryFrom,
+	io::{self, Read, Write},
+};
+use thiserror::Error;
+use url::Url;
+use HttpError::{
+	AuthenticationError, CredentialHelperError, InvalidUrlError, PacketError, ProtocolError,
+	RequestError,
+};
+
+#[derive(Error, Debug)]
+pub enum HttpError {
+	#[error("{url} is not a valid URL")]
+	InvalidUrlError { url: String },
+	#[error("{source}")]
+	RequestError { source: Box<ureq::Error> },
+	#[error("Authentication failed for {url}")]
+	AuthenticationError { url: String },
+	#[error("{source:?}")]
+	CredentialHelperError { source: CredentialError },
+	#[error("{source:?}")]
+	PacketError { source: PktLineError },
+	#[error("Protocol error: {msg}")]
+	ProtocolError { msg: String },
+}
+
+const SERVICE: &str = "git-upload-pack";
+const PROTOCOL_HEADER: &str = "Git-Protocol";
+const PROTOCOL_VERSION: &str = "version=2";
+
+// A remote reached over HTTP, with the credentials that worked so far
+pub struct HttpRemote {
+	url:               Url,
+	agent:             ureq::Agent,
+	helper:            Option<String>,
+	credential:        Option<Credential>,
+	// Whether the credentials came from the helper and still await approval
+	unapproved:        bool,
+	// As advertised, SHA-1 unless the server says otherwise
+	pub object_format: ObjectFormat,
+}
+
+pub fn is_http_url(url: &str) -> bool {
+	url.starts_with("http://") || url.starts_with("https://")
+}
+
+fn protocol_error(msg: &str) -> HttpError {
+	ProtocolError {
+		msg: msg.to_owned(),
+	}
+}
+
+fn read_packet_from(reader: &mut dyn Read) -> Result<Option<Packet>, HttpError> {
+	read_packet(reader).map_err(|e| PacketError { source: e })
+}
+
+impl HttpRemote {
+	// Connects to the server and checks that it speaks protocol v2. Credentials
+	// in the URL are used for authentication but not kept in it
+	pub fn connect(url: &str) -> Result<HttpRemote, HttpError> {
+		let invalid = || InvalidUrlError {
+			url: url.to_owned(),
+		};
+		let mut parsed = Url::parse(url.trim_end_matches('/')).map_err(|_| invalid())?;
+		let credential = parsed.password().map(|password| Credential {
+			username: parsed.username().to_owned(),
+			password: password.to_owned(),
+		});
+		parsed.set_password(None).map_err(|_| invalid())?;
+		let mut remote = HttpRemote {
+			url: parsed,
+			agent: ureq::AgentBuilder::new().user_agent(AGENT).build(),
+			helper: credential_helper(),
+			credential,
+			unapproved: false,
+			object_format: ObjectFormat::Sha1,
+		};
+		remote.discover()?;
+		Ok(remote)
+	}
+
+	// The URL without credentials
+	pub fn url(&self) -> String {
+		self.url.to_string()
+	}
+
+	fn endpoint(&self, path: &str) -> String {
+		format!("{}/{}", self.url.as_str().trim_end_matches('/'), path)
+	}
+
+	// Sends a request, asking the helper for credentials once the server
+	// requires them. The helper learns whether they worked
+	fn request(&mut self, path: &str, body: Option<&[u8]>) -> Result<ureq::Response, HttpError> {
+		loop {
+			let mut request = match body {
+				Some(_) => self
+					.agent
+					.post(&self.endpoint(path))
+					.set(
+						"Content-Type",
+						&format!("application/x-{}-request", SERVICE),
+					)
+					.set("Accept", &format!("application/x-{}-result", SERVICE)),
+				None => self.agent.get(&self.endpoint(path)),
+			}
+			.set(PROTOCOL_HEADER, PROTOCOL_VERSION);
+			if let Some(credential) = &self.credential {
+				let token =
+					STANDARD.encode(format!("{}:{}", credential.username, credential.password));
+				request = request.set("Authorization", &format!("Basic {}", token));
+			}
+			let result = match body {
+				Some(body) => request.send_bytes(body),
+				None => request.call(),
+			};
+			match result {
+				Ok(response) => {
+					if let (true, Some(helper), Some(credential)) =
+						(self.unapproved, &self.helper, &self.credential)
+					{
+						approve_credential(helper, &self.url, credential)
+							.map_err(|e| CredentialHelperError { source: e })?;
+						self.unapproved = false;
+					}
+					return Ok(response);
+				},
+				Err(ureq::Error::Status(401, _)) => {
+					let helper = match (&self.helper, &self.credential) {
+						(Some(helper), None) => helper.clone(),
+						(Some(helper), Some(credential)) if self.unapproved => {
+							reject_credential(helper, &self.url, credential)
+								.map_err(|e| CredentialHelperError { source: e })?;
+							return Err(AuthenticationError { url: self.url() });
+						},
+						_ => return Err(AuthenticationError { url: self.url() }),
+					};
+					self.credential = fill_credential(&helper, &self.url)
+						.map_err(|e| CredentialHelperError { source: e })?;
+					if self.credential.is_none() {
+						return Err(AuthenticationError { url: self.url() });
+					}
+					self.unapproved = true;
+				},
+				Err(e) => {
+					return Err(RequestError {
+						source: Box::new(e),
+					})
+				},
+			}
+		}
+	}
+
+	// Servers that are not smart, or that only speak older versions, are
+	// refused. Smart servers may start with a service line
+	fn discover(&mut self) -> Result<(), HttpError> {
+		let response = self.request(&format!("info/refs?service={}", SERVICE), None)?;
+		if response.content_type() != format!("application/x-{}-advertisement", SERVICE) {
+			return Err(protocol_error(
+				"the server does not support the smart protocol",
+			));
+		}
+		let mut reader = response.into_reader();
+		let mut capabilities = Vec::new();
+		while let Some(packet) = read_packet_from(&mut reader)? {
+			match packet.text() {
+				Some(line) if line.starts_with("# service=") => {
+					read_packet_from(&mut reader)?;
+				},
+				Some(line) => capabilities.push(line.to_owned()),
+				None => break,
+			}
+		}
+		if capabilities.first().map(String::as_str) != Some("version 2") {
+			return Err(protocol_error(
+				"the server does not support protocol version 2",
+			));
+		}
+		for command in ["ls-refs", "fetch"].iter() {
+			if !capabilities
+				.iter()
+				.any(|c| c == command || c.starts_with(&format!("{}=", command)))
+			{
+				return Err(protocol_error(&format!(
+					"the server does not support {}",
+					command
+				)));
+			}
+		}
+		if let Some(name) = capabilities
+			.iter()
+			.find_map(|c| c.strip_prefix("object-format="))
+		{
+			self.object_format = ObjectFormat::from_name(name).map_err(|_| {
+				protocol_error(&format!("the server uses unknown object format {}", name))
+			})?;
+		}
+		Ok(())
+	}
+
+	fn command(
+		&mut self,
+		command: &str,
+		arguments: &[String],
+	) -> Result<Box<dyn Read + Send + Sync>, HttpError> {
+		let mut body = Vec::new();
+		let mut lines = vec![format!("command={}", command), format!("agent={}", AGENT)];
+		// Servers assume SHA-1 unless told otherwise
+		if self.object_format != ObjectFormat::Sha1 {
+			lines.push(format!("object-format={}", self.object_format));
+		}
+		let write = |body: &mut Vec<u8>| -> Result<(), PktLineError> {
+			for line in lines.iter() {
+				write_line(body, line)?;
+			}
+			write_packet(body, &Packet::Delim)?;
+			for argument in arguments {
+				write_line(body, argument)?;
+			}
+			write_packet(body, &Packet::Flush)
+		};
+		write(&mut body).map_err(|e| PacketError { source: e })?;
+		Ok(self.request(SERVICE, Some(&body))?.into_reader())
+	}
+
+	// HEAD, the refs and what annotated tags peel to
+	pub fn ls_refs(&mut self) -> Result<RemoteRefs, HttpError> {
+		let arguments = [
+			"symrefs",
+			"peel",
+			"unborn",
+			"ref-prefix HEAD",
+			"ref-prefix refs/",
+		]
+		.iter()
+		.map(|a| a.to_string())
+		.collect::<Vec<_>>();
+		let mut reader = self.command("ls-refs", &arguments)?;
+		let mut refs = RemoteRefs {
+			head:   None,
+			refs:   Vec::new(),
+			peeled: BTreeMap::new(),
+		};
+		while let Some(packet) = read_packet_from(&mut reader)? {
+			let line = match packet.text() {
+				Some(line) => line.to_owned(),
+				None => break,
+			};
+			let mut fields = line.split(' ');
+			let (object, name) = match (fields.next(), fields.next()) {
+				(Some(object), Some(name)) => (object, name.to_owned()),
+				_ => return Err(protocol_error(&format!("invalid ref {:?}", line))),
+			};
+			let object_repr = ObjectRepr::try_from(object).ok();
+			let mut target = None;
+			for attribute in fields {
+				if let Some(t) = attribute.strip_prefix("symref-target:") {
+					target = Some(t.to_owned());
+				} else if let Some(peeled) = attribute.strip_prefix("peeled:") {
+					let peeled = ObjectRepr::try_from(peeled)
+						.map_err(|_| protocol_error(&format!("invalid ref {:?}", line)))?;
+					refs.peeled.insert(name.clone(), peeled);
+				}
+			}
+			// Names become paths of the local repository
+			let is_valid = |name: &str| name.starts_with("refs/") && is_safe_ref_name(name);
+			if !(name == HEAD || is_valid(&name)) || !target.as_deref().is_none_or(is_valid) {
+				return Err(protocol_error(&format!("invalid ref name in {:?}", line)));
+			}
+			match (name == HEAD, object_repr, target) {
+				(true, _, Some(target)) => refs.head = Some(RefValue::Symbolic(target)),
+				(true, Some(object_repr), None) => refs.head = Some(RefValue::Direct(object_repr)),
+				(false, Some(object_repr), _) => refs.refs.push((name, object_repr)),
+				_ => return Err(protocol_error(&format!("invalid ref {:?}", line))),
+			}
+		}
+		Ok(refs)
+	}
+
+	// A pack of the objects reachable from `wants` but not from `haves`.
+	// Negotiation is a single round: the haves are sent along with done.
+	// Progress messages of the server are shown on stderr
+	pub fn fetch_pack(
+		&mut self,
+		wants: &[ObjectRepr],
+		haves: &[ObjectRepr],
+	) -> Result<Vec<u8>, HttpError> {
+		let mut arguments = vec!["ofs-delta".to_owned()];
+		arguments.extend(wants.iter().map(|w| format!("want {}", w)));
+		arguments.extend(haves.iter().map(|h| format!("have {}", h)));
+		arguments.push("done".to_owned());
+		let mut reader = self.command("fetch", &arguments)?;
+
+		// Sections other than the packfile end with a delimiter
+		loop {
+			match read_packet_from(&mut reader)? {
+				Some(packet) if packet.text() == Some("packfile") => break,
+				Some(packet) => {
+					if let Some(error) = packet.text().and_then(|l| l.strip_prefix("ERR ")) {
+						return Err(protocol_error(error));
+					}
+				},
+				None => return Err(protocol_error("no packfile in the response")),
+			}
+		}
+		let mut pack = Vec::new();
+		let mut stderr = io::stderr();
+		while let Some(packet) = read_packet_from(&mut reader)? {
+			let data = match packet {
+				Packet::Data(data) => data,
+				_ => break,
+			};
+			match data.split_first() {
+				Some((&BAND_DATA, data)) => pack.extend_from_slice(data),
+				Some((&BAND_PROGRESS, message)) => {
+					stderr.write_all(message).ok();
+				},
+				Some((_, message)) => {
+					return Err(protocol_error(&String::from_utf8_lossy(message)))
+				},
+				None => {},
+			}
+		}
+		Ok(pack)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		tools::{
+			db::{simulate_insert, ObjectType},
+			pack::read_pack,
+			pktline::read_until_flush,
+			refs::update_ref,
+			testing::{commit, lock, TempRepo},
+		},
+		upload_pack::upload_pack_do::upload_pack,
+	};
+	use std::{
+		collections::HashMap,
+		env, fs,
+		io::{BufRead, BufReader},
+		net::{TcpListener, TcpStream},
+		path::PathBuf,
+		slice, thread,
+	};
+
+	struct Request {
+		method:  String,
+		target:  String,
+		// By lowercase name
+		headers: HashMap<String, String>,
+		body:    Vec<u8>,
+	}
+
+	struct Response {
+		status:       u16,
+		content_type: String,
+		body:         Vec<u8>,
+	}
+
+	fn read_request(reader: &mut dyn BufRead) -> io::Result<Request> {
+		let mut line = String::new();
+		reader.read_line(&mut line)?;
+		let mut parts = line.split_whitespace().map(str::to_owned);
+		let (method, target) = match (parts.next(), parts.next()) {
+			(Some(method), Some(target)) => (method, target),
+			_ => return Err(io::ErrorKind::InvalidData.into()),
+		};
+		let mut headers = HashMap::new();
+		loop {
+			line.clear();
+			reader.read_line(&mut line)?;
+			match line.trim_end().split_once(':') {
+				Some((name, value)) => {
+					headers.insert(name.to_lowercase(), value.trim().to_owned());
+				},
+				None => break,
+			}
+		}
+		let length = headers
+			.get("content-length")
+			.map_or(Ok(0), |length| length.parse())
+			.map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+		let mut body = vec![0; length];
+		reader.read_exact(&mut body)?;
+		Ok(Request {
+			method,
+			target,
+			headers,
+			body,
+		})
+	}
+
+	fn handle(stream: TcpStream, respond: &dyn Fn(Request) -> Response) -> io::Result<()> {
+		let request = read_request(&mut BufReader::new(stream.try_clone()?))?;
+		let response = respond(request);
+		let mut stream = stream;
+		let reason = match response.status {
+			200 => "OK",
+			401 => "Unauthorized\r\nWWW-Authenticate: Basic realm=\"test\"",
+			_ => "Error",
+		};
+		write!(
+			stream,
+			"HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+			response.status,
+			reason,
+			response.content_type,
+			response.body.len()
+		)?;
+		stream.write_all(&response.body)
+	}
+
+	// A server on a free local port answering every request with `respond`,
+	// one request per connection. Clients may hang up before the end of the
+	// response, e.g. on 401, which does not stop the server. Returns its URL
+	fn serve(respond: impl Fn(Request) -> Response + Send + 'static) -> String {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				match stream {
+					Ok(stream) => handle(stream, &respond).unwrap_or(()),
+					Err(_) => break,
+				}
+			}
+		});
+		url
+	}
+
+	// Answers like git http-backend with protocol v2: upload-pack runs for each
+	// request, advertising its capabilities to GETs and answering the command
+	// of POSTs
+	fn backend(root: PathBuf) -> impl Fn(Request) -> Response {
+		move |request| {
+			assert_eq!(
+				request.headers.get("git-protocol").map(String::as_str),
+				Some("version=2")
+			);
+			let mut output = Vec::new();
+			// Errors are sent to the client as ERR packets
+			upload_pack(&root, &mut &request.body[..], &mut output).ok();
+			let (kind, body) = match request.method.as_str() {
+				"GET" => {
+					assert_eq!(request.target, "/repo/info/refs?service=git-upload-pack");
+					("advertisement", output)
+				},
+				_ => {
+					assert_eq!(request.target, "/repo/git-upload-pack");
+					let mut rest = &output[..];
+					read_until_flush(&mut rest).unwrap();
+					("result", rest.to_vec())
+				},
+			};
+			Response {
+				status: 200,
+				content_type: format!("application/x-git-upload-pack-{}", kind),
+				body,
+			}
+		}
+	}
+
+	// Refuses requests without the Basic credentials of user and secret
+	fn authenticated(root: PathBuf) -> impl Fn(Request) -> Response {
+		let backend = backend(root);
+		move |request| {
+			let expected = format!("Basic {}", STANDARD.encode("user:secret"));
+			if request.headers.get("authorization") == Some(&expected) {
+				backend(request)
+			} else {
+				Response {
+					status:       401,
+					content_type: "text/plain".to_owned(),
+					body:         b"authentication required".to_vec(),
+				}
+			}
+		}
+	}
+
+	fn remote_repo(object_format: ObjectFormat) -> (TempRepo, ObjectRepr, ObjectRepr) {
+		let repo = TempRepo::new(object_format);
+		let (first, second) = repo.run(|| {
+			let first = commit(&[("a.txt", "a\n")], &[])?;
+			let second = commit(&[("a.txt", "b\n")], slice::from_ref(&first))?;
+			update_ref("refs/heads/master", &second, "test")?;
+			Ok((first, second))
+		});
+		(repo, first, second)
+	}
+
+	fn hashes(pack: &[u8]) -> Vec<ObjectRepr> {
+		read_pack(pack)
+			.unwrap()
+			.into_iter()
+			.map(|(object_type, data)| simulate_insert(&data, object_type).unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn lists_refs_and_fetches_packs() {
+		let _lock = lock();
+		let (repo, first, second) = remote_repo(ObjectFormat::Sha1);
+		let url = serve(backend(repo.path.clone()));
+		let mut remote = HttpRemote::connect(&format!("{}/repo/", url)).unwrap();
+		assert_eq!(remote.object_format, ObjectFormat::Sha1);
+
+		let refs = remote.ls_refs().unwrap();
+		assert_eq!(
+			refs.head,
+			Some(RefValue::Symbolic("refs/heads/master".to_owned()))
+		);
+		assert_eq!(
+			refs.refs,
+			vec![("refs/heads/master".to_owned(), second.clone())]
+		);
+
+		// Both commits, trees and blobs, then only those of the second commit
+		let all = hashes(&remote.fetch_pack(slice::from_ref(&second), &[]).unwrap());
+		assert_eq!(all.len(), 6);
+		assert!(all.contains(&first));
+		let missing = hashes(
+			&remote
+				.fetch_pack(slice::from_ref(&second), slice::from_ref(&first))
+				.unwrap(),
+		);
+		assert_eq!(missing.len(), 3);
+		assert!(missing.contains(&second) && !missing.contains(&first));
+	}
+
+	#[test]
+	fn refuses_unsafe_ref_names() {
+		let _lock = lock();
+		let (repo, _, second) = remote_repo(ObjectFormat::Sha1);
+		fs::write(
+			repo.path.join(".unrusty/packed-refs"),
+			format!("{} refs/heads/../../../escaped\n", second),
+		)
+		.unwrap();
+		let url = serve(backend(repo.path.clone()));
+		let mut remote = HttpRemote::connect(&format!("{}/repo", url)).unwrap();
+		match remote.ls_refs() {
+			Err(ProtocolError { msg }) => assert!(msg.starts_with("invalid ref name")),
+			result => panic!("unexpected {:?}", result.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn uses_the_advertised_object_format() {
+		let _lock = lock();
+		let (repo, _, second) = remote_repo(ObjectFormat::Sha256);
+		let url = serve(backend(repo.path.clone()));
+		let mut remote = HttpRemote::connect(&format!("{}/repo", url)).unwrap();
+		assert_eq!(remote.object_format, ObjectFormat::Sha256);
+		assert_eq!(remote.ls_refs().unwrap().refs[0].1, second);
+	}
+
+	#[test]
+	fn reports_errors_of_the_server() {
+		let _lock = lock();
+		let (repo, ..) = remote_repo(ObjectFormat::Sha1);
+		let url = serve(backend(repo.path.clone()));
+		let mut remote = HttpRemote::connect(&format!("{}/repo", url)).unwrap();
+		let unknown = simulate_insert(b"unknown", ObjectType::Blob).unwrap();
+		match remote.fetch_pack(slice::from_ref(&unknown), &[]) {
+			Err(ProtocolError { msg }) => {
+				assert_eq!(msg, format!("upload-pack: not our ref {}", unknown))
+			},
+			result => panic!("unexpected {:?}", result.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn authenticates_with_the_credentials_of_the_url() {
+		let _lock = lock();
+		let (repo, ..) = remote_repo(ObjectFormat::Sha1);
+		let url = serve(authenticated(repo.path.clone()));
+		let with_credentials =
+			|credentials: &str| url.replace("://", &format!("://{}@", credentials));
+
+		let remote = HttpRemote::connect(&format!("{}/repo", with_credentials("user:secret")));
+		let mut remote = remote.unwrap();
+		assert!(!remote.url().contains("secret"));
+		assert_eq!(remote.ls_refs().unwrap().refs.len(), 1);
+
+		for credentials in ["user:wrong", "user"].iter() {
+			let result = HttpRemote::connect(&format!("{}/repo", with_credentials(credentials)));
+			assert!(matches!(result, Err(AuthenticationError { .. })));
+		}
+	}
+
+	#[test]
+	fn asks_the_helper_for_credentials() {
+		let _lock = lock();
+		let (repo, ..) = remote_repo(ObjectFormat::Sha1);
+		let url = serve(authenticated(repo.path.clone()));
+		let log = repo.path.join("helper.log");
+		let helper = |password: &str| {
+			format!(
+				"!f() {{ if [ \"$1\" = get ]; then echo username=user; echo password={}; else \
+				 echo \"$1\" >> '{}'; fi; }}; f",
+				password,
+				log.display()
+			)
+		};
+
+		env::set_var("UNRUSTY_CREDENTIAL_HELPER", helper("secret"));
+		let accepted = HttpRemote::connect(&format!("{}/repo", url));
+		env::set_var("UNRUSTY_CREDENTIAL_HELPER", helper("wrong"));
+		let refused = HttpRemote::connect(&format!("{}/repo", url));
+		env::remove_var("UNRUSTY_CREDENTIAL_HELPER");
+
+		assert!(accepted.is_ok());
+		assert!(matches!(refused, Err(AuthenticationError { .. })));
+		assert_eq!(fs::read_to_string(&log).unwrap(), "store\nerase\n");
+	}
+
+	#[test]
+	fn refuses_servers_without_protocol_v2() {
+		let _lock = lock();
+		let dumb = serve(|_| Response {
+			status:       200,
+			content_type: "text/plain".to_owned(),
+			body:         b"0000000000000000000000000000000000000000 HEAD\n".to_vec(),
+		});
+		let v0 = serve(|_| {
+			let mut body = Vec::new();
+			write_line(&mut body, "# service=git-upload-pack").unwrap();
+			write_packet(&mut body, &Packet::Flush).unwrap();
+			write_line(
+				&mut body,
+				&format!("{} HEAD\0multi_ack", ObjectFormat::Sha1.null_hash()),
+			)
+			.unwrap();
+			write_packet(&mut body, &Packet::Flush).unwrap();
+			Response {
+				status: 200,
+				content_type: "application/x-git-upload-pack-advertisement".to_owned(),
+				body,
+			}
+		});
+		for (url, msg) in [
+			(dumb, "the server does not support the smart protocol"),
+			(v0, "the server does not support protocol version 2"),
+		]
+		.iter()
+		{
+			match HttpRemote::connect(&format!("{}/repo", url)) {
+				Err(ProtocolError { msg: error }) => assert_eq!(error, *msg),
+				result => panic!("unexpected {:?}", result.map(|_| ())),
+			}
+		}
+	}
+}
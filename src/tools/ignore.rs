@@ -0,0 +1,109 @@
+use crate::tools::{
+	glob::wildmatch,
+	path::{root_path_required, PathError, RelativePathToBase},
+};
+use std::{fs, io, io::ErrorKind, path::Path};
+use thiserror::Error;
+use IgnoreError::{NoRepoError, ReadError};
+
+#[derive(Error, Debug)]
+pub enum IgnoreError {
+	#[error("{source:?}")]
+	NoRepoError { source: PathError },
+	#[error("{source:?}")]
+	ReadError { source: io::Error },
+}
+
+// Read from the repository root, in this order. Later patterns take precedence
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".unrustyignore"];
+
+struct IgnorePattern {
+	pattern:  String,
+	negated:  bool,
+	dir_only: bool,
+	// Patterns containing a slash match the whole path, others the file name
+	anchored: bool,
+}
+
+// Ignore rules with the syntax of .gitignore files
+pub struct Ignore {
+	patterns: Vec<IgnorePattern>,
+}
+
+impl IgnorePattern {
+	fn parse(line: &str) -> Option<IgnorePattern> {
+		let line = line.trim_end_matches([' ', '\r']);
+		if line.is_empty() || line.starts_with('#') {
+			return None;
+		}
+		let (negated, line) = match line.strip_prefix('!') {
+			Some(rest) => (true, rest),
+			None => (false, line.strip_prefix('\\').unwrap_or(line)),
+		};
+		let (dir_only, line) = match line.strip_suffix('/') {
+			Some(rest) => (true, rest),
+			None => (false, line),
+		};
+		let anchored = line.contains('/');
+		Some(IgnorePattern {
+			pattern: line.trim_start_matches('/').to_owned(),
+			negated,
+			dir_only,
+			anchored,
+		})
+	}
+
+	fn matches(&self, path: &str, is_dir: bool) -> bool {
+		if self.dir_only && !is_dir {
+			return false;
+		}
+		let text = if self.anchored {
+			path
+		} else {
+			path.rsplit('/').next().unwrap_or(path)
+		};
+		wildmatch(self.pattern.as_bytes(), text.as_bytes())
+	}
+}
+
+impl Ignore {
+	pub fn read() -> Result<Ignore, IgnoreError> {
+		let root = root_path_required().map_err(|e| NoRepoError { source: e })?;
+		let mut patterns = Vec::new();
+		for name in IGNORE_FILES.iter() {
+			let content = match fs::read_to_string(root.join(name)) {
+				Ok(content) => content,
+				Err(e) if e.kind() == ErrorKind::NotFound => continue,
+				Err(e) => return Err(ReadError { source: e }),
+			};
+			patterns.extend(content.lines().filter_map(IgnorePattern::parse));
+		}
+		Ok(Ignore { patterns })
+	}
+
+	// The last matching pattern decides
+	fn matches(&self, path: &str, is_dir: bool) -> bool {
+		self.patterns
+			.iter()
+			.rev()
+			.find(|p| p.matches(path, is_dir))
+			.is_some_and(|p| !p.negated)
+	}
+
+	// A file inside an ignored directory is ignored as well, whatever the
+	// patterns say about the file itself
+	pub fn is_ignored(&self, path: &RelativePathToBase, is_dir: bool) -> bool {
+		let path = path.to_string();
+		let mut prefix = String::new();
+		for component in Path::new(&path).parent().into_iter().flat_map(|p| p.iter()) {
+			if !prefix.is_empty() {
+				prefix.push('/');
+			}
+			prefix.push_str(&component.to_string_lossy());
+			if self.matches(&prefix, true) {
+				return true;
+			}
+		}
+		self.matches(&path, is_dir)
+	}
+}
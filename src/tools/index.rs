@@ -1,13 +1,22 @@
 use crate::tools::{
-	db::{insert_file, DbError, ObjectRepr, ObjectType},
+	db::{insert, simulate_insert, ObjectRepr, ObjectType},
+	filter::Filters,
 	path::{index_path_required, RelativePathToBase},
+	tree::{FileMode, FlatEntry, FlatTree},
+	worktree::read_worktree_entry,
 };
 use log::info;
 use serde::{Deserialize, Serialize};
-use serde_yaml::{from_reader, to_writer};
+use serde_yaml::{from_str, to_writer};
 use std::{
-	collections::HashMap, convert::TryFrom, error::Error, fs, fs::File, io, path::Path,
-	time::SystemTime,
+	collections::BTreeMap,
+	convert::TryFrom,
+	error::Error,
+	fs,
+	fs::File,
+	io,
+	path::Path,
+	time::{SystemTime, UNIX_EPOCH},
 };
 
 use thiserror::Error;
@@ -25,15 +34,24 @@ pub enum IndexError {
 	MetadataError { source: io::Error },
 }
 
+// Sorted by path, so that iterating and writing the index is deterministic
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Index {
-	entries: HashMap<RelativePathToBase, IndexEntry>,
+	entries: BTreeMap<IndexKey, IndexEntry>,
+}
+
+// A path has either one Regular entry or, while conflicted, one entry for
+// each stage it exists in
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IndexKey {
+	pub path:  RelativePathToBase,
+	pub stage: MergeStatus,
 }
 
 // CommonAncestor, Head and MergeHead occur during a merge conflict.
 // They allow to quickly see the different versions of a file:
 // from the common ancestor, the HEAD, and the MERGE_HEAD
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum MergeStatus {
 	// check acccess rights
 	Regular,
@@ -45,10 +63,27 @@ pub enum MergeStatus {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct IndexEntry {
 	metadata: Metadata,
-	status:   MergeStatus,
 	// flags: u32, // change to struct of bools
 	// index: u32, // Find out for what this is used!
 	hash:     ObjectRepr,
+	#[serde(default)]
+	mode:     FileMode,
+}
+
+// The index as written before entries were keyed by stage: one entry per
+// path, with its stage in the entry. Converted when read
+#[derive(Deserialize)]
+struct PathKeyedIndex {
+	entries: BTreeMap<RelativePathToBase, PathKeyedEntry>,
+}
+
+#[derive(Deserialize)]
+struct PathKeyedEntry {
+	metadata: Metadata,
+	status:   MergeStatus,
+	hash:     ObjectRepr,
+	#[serde(default)]
+	mode:     FileMode,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -64,7 +99,7 @@ pub struct Metadata {
 
 impl Metadata {
 	fn new(path: &Path) -> Result<Metadata, IndexError> {
-		let metadata = fs::symlink_metadata(&path).map_err(|e| MetadataError { source: e })?;
+		let metadata = fs::symlink_metadata(path).map_err(|e| MetadataError { source: e })?;
 
 		Ok(Metadata {
 			// Assume we are on a platform that has this metadata
@@ -77,30 +112,84 @@ impl Metadata {
 			size:   metadata.len(),
 		})
 	}
+
+	// Never equal to the metadata of a file on disk, forces rehashing on the next
+	// add. Used when the entry does not come from the file in the worktree
+	fn unknown() -> Metadata {
+		Metadata {
+			c_time: UNIX_EPOCH,
+			m_time: UNIX_EPOCH,
+			size:   0,
+		}
+	}
+}
+
+impl MergeStatus {
+	// Stage number as shown by git
+	pub fn number(self) -> u8 {
+		match self {
+			MergeStatus::Regular => 0,
+			MergeStatus::CommonAncestor => 1,
+			MergeStatus::Head => 2,
+			MergeStatus::MergeHead => 3,
+		}
+	}
+
+	// Which stage stands for a conflicted path where only one version can be
+	// used, e.g. in the working tree
+	fn preference(self) -> u8 {
+		match self {
+			MergeStatus::Regular => 0,
+			MergeStatus::Head => 1,
+			MergeStatus::MergeHead => 2,
+			MergeStatus::CommonAncestor => 3,
+		}
+	}
+}
+
+impl IndexKey {
+	pub fn new(path: RelativePathToBase, stage: MergeStatus) -> IndexKey {
+		IndexKey { path, stage }
+	}
 }
 
 impl IndexEntry {
-	fn new(status: MergeStatus, hash: ObjectRepr, metadata: Metadata) -> IndexEntry {
+	fn new(hash: ObjectRepr, mode: FileMode, metadata: Metadata) -> IndexEntry {
 		// Deal with ce_mode and ce_flag creation in add_cacheinfo
 		IndexEntry {
 			metadata,
-			status,
 			// flags: 0,
 			// index: 0,
 			hash,
+			mode,
 		}
 	}
 
 	fn changed(&self, new_metadata: &Metadata) -> bool {
 		self.metadata != *new_metadata
 	}
+
+	pub fn hash(&self) -> &ObjectRepr {
+		&self.hash
+	}
+
+	pub fn mode(&self) -> FileMode {
+		self.mode
+	}
+
+	pub fn flat_entry(&self) -> FlatEntry {
+		FlatEntry {
+			mode: self.mode,
+			hash: self.hash.clone(),
+		}
+	}
 }
 
 // Creating index
 impl Index {
 	pub fn create_at_path(path: &Path) -> Result<(), IndexError> {
 		let index = Index::new();
-		Self::write_at_path_helper(&index, &path)
+		Self::write_at_path_helper(&index, path)
 	}
 
 	pub fn create() -> Result<(), Box<dyn Error>> {
@@ -110,21 +199,37 @@ impl Index {
 
 	pub fn new() -> Index {
 		Index {
-			entries: HashMap::new(),
+			entries: BTreeMap::new(),
 		}
 	}
 }
 
 // Reading index
 impl Index {
+	// Indexes in the earlier format keyed by path are accepted as well
 	pub fn read_at_path(path: &Path) -> Result<Index, IndexError> {
-		let f = File::open(path).map_err(|e| ReadError {
-			source: Box::new(e),
-		})?;
-		let index = from_reader(f).map_err(|e| ReadError {
+		let content = fs::read_to_string(path).map_err(|e| ReadError {
 			source: Box::new(e),
 		})?;
-		Ok(index)
+		let error = match from_str::<Index>(&content) {
+			Ok(index) => return Ok(index),
+			Err(e) => e,
+		};
+		match from_str::<PathKeyedIndex>(&content) {
+			Ok(old) => Ok(Index {
+				entries: old
+					.entries
+					.into_iter()
+					.map(|(path, entry)| {
+						let key = IndexKey::new(path, entry.status);
+						(key, IndexEntry::new(entry.hash, entry.mode, entry.metadata))
+					})
+					.collect(),
+			}),
+			Err(_) => Err(ReadError {
+				source: Box::new(error),
+			}),
+		}
 	}
 
 	pub fn read() -> Result<Index, Box<dyn Error>> {
@@ -162,32 +267,186 @@ impl Index {
 		metadata: Metadata,
 		status: MergeStatus,
 		key: RelativePathToBase,
-	) -> Result<(), DbError> {
-		let hash = insert_file(path, ObjectType::Blob)?;
+		filters: &Filters,
+	) -> Result<(), Box<dyn Error>> {
+		let (mode, content) = read_worktree_entry(&key, filters)?
+			.ok_or_else(|| format!("{:?} does not exist", path))?;
+		let hash = insert(&content, ObjectType::Blob)?;
 		info!("hash for {:?} in index is now {:?}", path, hash.hash());
-		let entry = IndexEntry::new(status, hash, metadata);
-		self.entries.insert(key, entry);
+		let entry = IndexEntry::new(hash, mode, metadata);
+		// Adding a conflicted file as Regular marks it as resolved
+		if status == MergeStatus::Regular {
+			self.remove(&key);
+		}
+		self.entries.insert(IndexKey::new(key, status), entry);
 		Ok(())
 	}
 
-	pub fn add_change(&mut self, status: MergeStatus, path: &Path) -> Result<(), Box<dyn Error>> {
+	pub fn add_change(
+		&mut self,
+		status: MergeStatus,
+		path: &Path,
+		filters: &Filters,
+	) -> Result<(), Box<dyn Error>> {
 		let key = RelativePathToBase::try_from(path)?;
 		let metadata = Metadata::new(path)?;
 
-		match self.entries.get(&key) {
+		match self.entries.get(&IndexKey::new(key.clone(), status)) {
 			Some(entry) => {
-				if entry.changed(&metadata) {
+				if entry.changed(&metadata) || self.is_conflicted(&key) {
 					info!("updating {:?} in index", path);
-					self.add_change_helper(path, metadata, status, key)?;
+					self.add_change_helper(path, metadata, status, key, filters)?;
 				} else {
 					info!("file {:?} in index is up to date", path);
 				}
 			},
 			None => {
 				info!("adding {:?} to index", path);
-				self.add_change_helper(path, metadata, status, key)?;
+				self.add_change_helper(path, metadata, status, key, filters)?;
 			},
 		}
 		Ok(())
 	}
 }
+
+// Entries that do not come from `add`, e.g. when restoring from a tree
+impl Index {
+	// If `in_worktree` is set, the file on disk is known to have the content of
+	// the entry and its metadata is recorded
+	pub fn set_entry(
+		&mut self,
+		key: RelativePathToBase,
+		entry: &FlatEntry,
+		in_worktree: bool,
+	) -> Result<(), Box<dyn Error>> {
+		let metadata = if in_worktree {
+			Metadata::new(&key.absolute()?)?
+		} else {
+			Metadata::unknown()
+		};
+		let entry = IndexEntry::new(entry.hash.clone(), entry.mode, metadata);
+		self.remove(&key);
+		self.entries
+			.insert(IndexKey::new(key, MergeStatus::Regular), entry);
+		Ok(())
+	}
+
+	// Removes all stages of the path
+	pub fn remove(&mut self, key: &RelativePathToBase) {
+		let stages = self.stages(key).map(|(k, _)| k.clone()).collect::<Vec<_>>();
+		for stage in stages {
+			self.entries.remove(&stage);
+		}
+	}
+
+	// Records the versions of a path that could not be merged, replacing the
+	// Regular entry
+	pub fn set_conflict(
+		&mut self,
+		key: RelativePathToBase,
+		base: Option<&FlatEntry>,
+		ours: Option<&FlatEntry>,
+		theirs: Option<&FlatEntry>,
+	) {
+		self.remove(&key);
+		let stages = [
+			(MergeStatus::CommonAncestor, base),
+			(MergeStatus::Head, ours),
+			(MergeStatus::MergeHead, theirs),
+		];
+		for (status, entry) in stages.iter() {
+			if let Some(entry) = entry {
+				let entry = IndexEntry::new(entry.hash.clone(), entry.mode, Metadata::unknown());
+				self.entries
+					.insert(IndexKey::new(key.clone(), *status), entry);
+			}
+		}
+	}
+}
+
+// Querying index
+impl Index {
+	// All entries, sorted by path and then stage
+	pub fn entries(&self) -> impl Iterator<Item = (&IndexKey, &IndexEntry)> {
+		self.entries.iter()
+	}
+
+	fn stages<'a>(
+		&'a self,
+		key: &RelativePathToBase,
+	) -> impl Iterator<Item = (&'a IndexKey, &'a IndexEntry)> {
+		let first = IndexKey::new(key.clone(), MergeStatus::Regular);
+		let last = IndexKey::new(key.clone(), MergeStatus::MergeHead);
+		self.entries.range(first..=last)
+	}
+
+	pub fn is_conflicted(&self, key: &RelativePathToBase) -> bool {
+		self.stages(key)
+			.any(|(k, _)| k.stage != MergeStatus::Regular)
+	}
+
+	// The entry of the path, or the preferred stage if the path is conflicted
+	pub fn get(&self, key: &RelativePathToBase) -> Option<&IndexEntry> {
+		self.stages(key)
+			.min_by_key(|(k, _)| k.stage.preference())
+			.map(|(_, entry)| entry)
+	}
+
+	// Conflicted paths, each once
+	pub fn conflicts(&self) -> Vec<&RelativePathToBase> {
+		let mut conflicts = self
+			.entries
+			.keys()
+			.filter(|key| key.stage != MergeStatus::Regular)
+			.map(|key| &key.path)
+			.collect::<Vec<_>>();
+		conflicts.dedup();
+		conflicts
+	}
+
+	// Whether the file in the working tree differs from the index. Files whose
+	// metadata changed are rehashed to rule out touched but unmodified files.
+	// Changing the mode does not change the recorded metadata, so it is
+	// compared first
+	pub fn worktree_modified(
+		&self,
+		key: &RelativePathToBase,
+		filters: &Filters,
+	) -> Result<bool, Box<dyn Error>> {
+		let entry = match self.get(key) {
+			// Submodules are not looked into
+			Some(entry) if entry.mode != FileMode::Gitlink => entry,
+			_ => return Ok(false),
+		};
+		let path = key.absolute()?;
+		let metadata = match Metadata::new(&path) {
+			Ok(metadata) => metadata,
+			Err(_) => return Ok(true),
+		};
+		if FileMode::from_metadata(&fs::symlink_metadata(&path)?) != entry.mode {
+			return Ok(true);
+		}
+		if !entry.changed(&metadata) {
+			return Ok(false);
+		}
+		match read_worktree_entry(key, filters)? {
+			Some((_, content)) => Ok(simulate_insert(&content, ObjectType::Blob)? != entry.hash),
+			None => Ok(true),
+		}
+	}
+
+	// One entry per path, conflicted paths are represented by their preferred
+	// stage
+	pub fn flat_tree(&self) -> FlatTree {
+		let mut flat = FlatTree::new();
+		for key in self.entries.keys() {
+			if flat.contains_key(&key.path) {
+				continue;
+			}
+			if let Some(entry) = self.get(&key.path) {
+				flat.insert(key.path.clone(), entry.flat_entry());
+			}
+		}
+		flat
+	}
+}
@@ -0,0 +1,235 @@
+use crate::tools::{
+	db::{read, ObjectRepr, ObjectType},
+	path::{vcs_path_required, PathError},
+	reachable::{reachable_from, roots, ReachableError},
+};
+use sha2::{Digest, Sha256};
+use std::{
+	collections::BTreeMap,
+	fs, io,
+	io::ErrorKind,
+	path::{Path, PathBuf},
+	str::from_utf8,
+};
+use thiserror::Error;
+use LfsError::{CorruptError, NoRepoError, ReachabilityError, ReadError, WriteError};
+
+#[derive(Error, Debug)]
+pub enum LfsError {
+	#[error("{source:?}")]
+	NoRepoError { source: PathError },
+	#[error("{source:?}")]
+	ReadError { source: io::Error },
+	#[error("{source:?}")]
+	WriteError { source: io::Error },
+	#[error("{source:?}")]
+	ReachabilityError { source: ReachableError },
+	#[error("Large file {oid} is not in the store")]
+	MissingError { oid: String },
+	#[error("Large file {oid} in the store does not match its pointer")]
+	CorruptError { oid: String },
+}
+
+// Large files are kept whole in this directory of the VCS directory, named by
+// the SHA-256 of their content. The repository only holds pointers to them
+const LFS_DIR: &str = "lfs/objects";
+// Name of the filter driver that moves files to the store, as set by the
+// "filter=lfs" attribute
+pub const LFS_FILTER: &str = "lfs";
+const POINTER_VERSION: &str = "https://git-lfs.github.com/spec/v1";
+// Blobs larger than this are never pointers
+const POINTER_MAX_LEN: usize = 1024;
+
+// The blob stored instead of a large file, in the format of git-lfs:
+// "version <url>", "oid sha256:<hash>" and "size <bytes>" lines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pointer {
+	pub oid:  String,
+	pub size: u64,
+}
+
+pub fn lfs_hash(content: &[u8]) -> String {
+	let mut hasher = Sha256::new();
+	hasher.input(content);
+	hex::encode(hasher.result())
+}
+
+fn is_oid(oid: &str) -> bool {
+	oid.len() == 64
+		&& oid
+			.bytes()
+			.all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+impl Pointer {
+	pub fn parse(data: &[u8]) -> Option<Pointer> {
+		if data.len() > POINTER_MAX_LEN || !data.ends_with(b"\n") {
+			return None;
+		}
+		let mut lines = from_utf8(data).ok()?.lines();
+		if lines.next()? != format!("version {}", POINTER_VERSION) {
+			return None;
+		}
+		let (mut oid, mut size) = (None, None);
+		for line in lines {
+			let (key, value) = line.split_once(' ')?;
+			match key {
+				"oid" => oid = value.strip_prefix("sha256:").filter(|o| is_oid(o)),
+				"size" => size = value.parse().ok(),
+				// Extensions of git-lfs are not used here but are valid
+				_ => {},
+			}
+		}
+		Some(Pointer {
+			oid:  oid?.to_owned(),
+			size: size?,
+		})
+	}
+
+	pub fn format(&self) -> String {
+		format!(
+			"version {}\noid sha256:{}\nsize {}\n",
+			POINTER_VERSION, self.oid, self.size
+		)
+	}
+
+	pub fn path(&self) -> Result<PathBuf, LfsError> {
+		lfs_object_path(&self.oid)
+	}
+}
+
+pub fn lfs_object_path(oid: &str) -> Result<PathBuf, LfsError> {
+	vcs_path_required(&format!("{}/{}", LFS_DIR, oid)).map_err(|e| NoRepoError { source: e })
+}
+
+// Puts the content in the store, unless it is already there
+pub fn store(content: &[u8]) -> Result<Pointer, LfsError> {
+	let pointer = Pointer {
+		oid:  lfs_hash(content),
+		size: content.len() as u64,
+	};
+	let path = pointer.path()?;
+	if !path.is_file() {
+		let dir = path.parent().expect("store files are in a directory");
+		fs::create_dir_all(dir).map_err(|e| WriteError { source: e })?;
+		// Written aside first, so that a partial file never has the name of
+		// its content
+		let partial = path.with_extension("partial");
+		fs::write(&partial, content).map_err(|e| WriteError { source: e })?;
+		fs::rename(&partial, &path).map_err(|e| WriteError { source: e })?;
+	}
+	Ok(pointer)
+}
+
+// Puts the file of another store in this one, unless it is already there.
+// Files are never changed, so they can be shared as hard links
+pub fn import(pointer: &Pointer, from: &Path, hardlink: bool) -> Result<(), LfsError> {
+	let path = pointer.path()?;
+	if path.is_file() {
+		return Ok(());
+	}
+	let dir = path.parent().expect("store files are in a directory");
+	fs::create_dir_all(dir).map_err(|e| WriteError { source: e })?;
+	if hardlink && fs::hard_link(from, &path).is_ok() {
+		return Ok(());
+	}
+	let partial = path.with_extension("partial");
+	fs::copy(from, &partial).map_err(|e| WriteError { source: e })?;
+	fs::rename(&partial, &path).map_err(|e| WriteError { source: e })
+}
+
+// The content a blob points to. None if it is not a pointer, or if the
+// content is not in the store. Content that does not match the pointer is
+// an error
+pub fn resolve(data: &[u8]) -> Result<Option<Vec<u8>>, LfsError> {
+	let pointer = match Pointer::parse(data) {
+		Some(pointer) => pointer,
+		None => return Ok(None),
+	};
+	let content = match fs::read(pointer.path()?) {
+		Ok(content) => content,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(ReadError { source: e }),
+	};
+	if content.len() as u64 != pointer.size || lfs_hash(&content) != pointer.oid {
+		return Err(CorruptError { oid: pointer.oid });
+	}
+	Ok(Some(content))
+}
+
+// The names of the files in the store, sorted
+pub fn list_lfs_objects() -> Result<Vec<String>, LfsError> {
+	let dir = vcs_path_required(LFS_DIR).map_err(|e| NoRepoError { source: e })?;
+	let entries = match fs::read_dir(&dir) {
+		Ok(entries) => entries,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(ReadError { source: e }),
+	};
+	let mut oids = Vec::new();
+	for entry in entries {
+		let entry = entry.map_err(|e| ReadError { source: e })?;
+		if let Some(name) = entry.file_name().to_str() {
+			oids.push(name.to_owned());
+		}
+	}
+	oids.sort();
+	Ok(oids)
+}
+
+// Pointers among the reachable blobs, by oid. These keep their content in
+// the store alive
+pub fn reachable_pointers() -> Result<BTreeMap<String, Pointer>, LfsError> {
+	let roots = roots().map_err(|e| ReachabilityError { source: e })?;
+	let reachable = reachable_from(roots)
+		.map_err(|e| ReachabilityError { source: e })?
+		.reachable;
+	Ok(pointers(reachable))
+}
+
+// Pointers among the blobs of the objects, by oid. Objects that can not be
+// read are skipped
+pub fn pointers(objects: impl IntoIterator<Item = ObjectRepr>) -> BTreeMap<String, Pointer> {
+	let mut pointers = BTreeMap::new();
+	for object_repr in objects {
+		let object = match read(&object_repr) {
+			Ok(object) if object.object_type == ObjectType::Blob => object,
+			_ => continue,
+		};
+		if let Some(pointer) = Pointer::parse(&object.data) {
+			pointers.insert(pointer.oid.clone(), pointer);
+		}
+	}
+	pointers
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tools::{
+		object_format::ObjectFormat,
+		testing::{lock, TempRepo},
+	};
+
+	#[test]
+	fn resolves_pointers_to_matching_content_only() {
+		let _lock = lock();
+		let repo = TempRepo::new(ObjectFormat::Sha1);
+		repo.run(|| {
+			let pointer = store(b"large\n")?;
+			let data = pointer.format().into_bytes();
+			assert_eq!(resolve(&data)?, Some(b"large\n".to_vec()));
+			assert_eq!(resolve(b"large\n")?, None);
+
+			let missing = store(b"missing\n")?;
+			fs::remove_file(missing.path()?)?;
+			assert_eq!(resolve(missing.format().as_bytes())?, None);
+
+			for content in [&b"other\n"[..], b"large\nlarge\n"].iter() {
+				fs::remove_file(pointer.path()?)?;
+				fs::write(pointer.path()?, content)?;
+				assert!(matches!(resolve(&data), Err(CorruptError { .. })));
+			}
+			Ok(())
+		});
+	}
+}
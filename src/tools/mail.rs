@@ -0,0 +1,360 @@
+// Commits as mails in the mbox format of git format-patch, and mails back to
+// the author, message and patch of a commit as git am takes them
+use crate::tools::date::{format_rfc2822_date, parse_rfc2822_date};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{
+	str::from_utf8,
+	time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+use MailError::{HeaderError, NoPatchError};
+
+#[derive(Error, Debug)]
+pub enum MailError {
+	#[error("Malformed mail: {msg}")]
+	HeaderError { msg: String },
+	#[error("Mail has no patch")]
+	NoPatchError,
+}
+
+// Lines starting mails in an mbox, with a date that mail readers ignore
+const MBOX_FROM: &str = "Mon Sep 17 00:00:00 2001";
+// Characters that make a name be quoted in a From header
+const SPECIALS: &str = "()<>[]:;@\\,.\"";
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+	// "Name <email> <seconds> <timezone>", dated now without a Date header
+	pub author:  String,
+	pub message: String,
+	pub patch:   Vec<u8>,
+}
+
+// "=?UTF-8?q?...?=" for text that is not plain ASCII
+fn encode_word(text: &str) -> String {
+	if text.is_ascii() {
+		return text.to_owned();
+	}
+	let mut encoded = "=?UTF-8?q?".to_owned();
+	for b in text.bytes() {
+		match b {
+			b' ' => encoded.push('_'),
+			b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'!' | b'*' | b'+' | b'-' | b'/' => {
+				encoded.push(b as char)
+			},
+			b => encoded.push_str(&format!("={:02X}", b)),
+		}
+	}
+	encoded.push_str("?=");
+	encoded
+}
+
+fn from_hex(digits: &[u8]) -> Option<u8> {
+	u8::from_str_radix(from_utf8(digits).ok()?, 16).ok()
+}
+
+fn decode_quoted_printable(text: &[u8], underscore_is_space: bool) -> Vec<u8> {
+	let mut out = Vec::with_capacity(text.len());
+	let mut i = 0;
+	while i < text.len() {
+		match text[i] {
+			b'=' if text[i + 1..].starts_with(b"\n") => i += 2,
+			b'=' if text[i + 1..].starts_with(b"\r\n") => i += 3,
+			b'=' if i + 3 <= text.len() => {
+				match from_hex(&text[i + 1..i + 3]) {
+					Some(b) => out.push(b),
+					None => out.extend_from_slice(&text[i..i + 3]),
+				}
+				i += 3;
+			},
+			b'_' if underscore_is_space => {
+				out.push(b' ');
+				i += 1;
+			},
+			b => {
+				out.push(b);
+				i += 1;
+			},
+		}
+	}
+	out
+}
+
+// Decodes the "=?charset?q|b?...?=" words of a header. Only UTF-8 and ASCII
+// are understood, other charsets are kept as they are
+fn decode_header(value: &str) -> String {
+	let mut out = String::new();
+	let mut rest = value;
+	// Whitespace between encoded words is dropped
+	let mut after_word = false;
+	while let Some(start) = rest.find("=?") {
+		let word = &rest[start + 2..];
+		let parts = word.splitn(3, '?').collect::<Vec<_>>();
+		let decoded = match parts[..] {
+			[charset, encoding, text] if text.contains("?=") => {
+				let text = &text[..text.find("?=").unwrap()];
+				let data = match encoding {
+					"q" | "Q" => Some(decode_quoted_printable(text.as_bytes(), true)),
+					"b" | "B" => STANDARD.decode(text).ok(),
+					_ => None,
+				};
+				let known = ["utf-8", "us-ascii"].contains(&charset.to_lowercase().as_str());
+				data.filter(|_| known)
+					.and_then(|d| String::from_utf8(d).ok())
+					.map(|d| (d, charset.len() + encoding.len() + text.len() + 6))
+			},
+			_ => None,
+		};
+		let gap = &rest[..start];
+		match decoded {
+			Some((decoded, len)) => {
+				if !(after_word && gap.trim().is_empty()) {
+					out.push_str(gap);
+				}
+				out.push_str(&decoded);
+				rest = &rest[start + len..];
+				after_word = true;
+			},
+			None => {
+				out.push_str(&rest[..start + 2]);
+				rest = &rest[start + 2..];
+				after_word = false;
+			},
+		}
+	}
+	out.push_str(rest);
+	out
+}
+
+// Names with special characters are quoted
+fn format_address(name: &str, email: &str) -> String {
+	if !name.is_ascii() {
+		format!("{} <{}>", encode_word(name), email)
+	} else if name.chars().any(|c| SPECIALS.contains(c)) {
+		format!(
+			"\"{}\" <{}>",
+			name.replace('\\', "\\\\").replace('"', "\\\""),
+			email
+		)
+	} else {
+		format!("{} <{}>", name, email)
+	}
+}
+
+fn parse_address(value: &str) -> Option<(String, String)> {
+	let value = decode_header(value);
+	let (name, email) = match (value.rfind('<'), value.rfind('>')) {
+		(Some(start), Some(end)) if start < end => (value[..start].trim(), &value[start + 1..end]),
+		// A bare address names its author by it
+		_ => (value.trim(), value.trim()),
+	};
+	let name = match name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) {
+		Some(quoted) => quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+		None => name.to_owned(),
+	};
+	let name = if name.is_empty() {
+		email.to_owned()
+	} else {
+		name
+	};
+	Some((name, email.trim().to_owned())).filter(|(_, email)| !email.is_empty())
+}
+
+// The name and email of a signature
+fn signature_identity(signature: &str) -> Option<(&str, &str)> {
+	let start = signature.find('<')?;
+	let end = signature.find('>')?;
+	Some((signature[..start].trim(), &signature[start + 1..end]))
+}
+
+// The subject of a commit message, its first paragraph on one line, and the
+// rest of the message
+pub fn split_message(message: &str) -> (String, String) {
+	let message = message.trim_start_matches('\n');
+	let (subject, body) = message.split_once("\n\n").unwrap_or((message, ""));
+	let subject = subject.lines().map(str::trim).collect::<Vec<_>>().join(" ");
+	(subject, body.trim_matches('\n').to_owned())
+}
+
+// One commit as a mail: "From <hash>", the headers, the message and the patch
+// after a "---" line. `prefix` is put before the subject, as "PATCH 1/2"
+pub fn format_mail(hash: &str, author: &str, message: &str, prefix: &str, patch: &[u8]) -> Vec<u8> {
+	let (subject, body) = split_message(message);
+	let (name, email) = signature_identity(author).unwrap_or(("", ""));
+	let mut mail = format!("From {} {}\n", hash, MBOX_FROM);
+	mail.push_str(&format!("From: {}\n", format_address(name, email)));
+	if let Some(date) = format_rfc2822_date(author) {
+		mail.push_str(&format!("Date: {}\n", date));
+	}
+	mail.push_str(&format!(
+		"Subject: {}\n",
+		encode_word(&format!("[{}] {}", prefix, subject))
+	));
+	if !body.is_ascii() || !patch.is_ascii() {
+		mail.push_str("MIME-Version: 1.0\n");
+		mail.push_str("Content-Type: text/plain; charset=UTF-8\n");
+		mail.push_str("Content-Transfer-Encoding: 8bit\n");
+	}
+	mail.push('\n');
+	if !body.is_empty() {
+		mail.push_str(&body);
+		mail.push('\n');
+	}
+	mail.push_str("---\n");
+	let mut mail = mail.into_bytes();
+	mail.extend_from_slice(patch);
+	mail
+}
+
+// The mails of an mbox, each starting with a "From " line
+pub fn split_mbox(data: &[u8]) -> Vec<&[u8]> {
+	let mut starts = Vec::new();
+	let mut previous_empty = true;
+	let mut offset = 0;
+	for line in data.split_inclusive(|&b| b == b'\n') {
+		if previous_empty && line.starts_with(b"From ") {
+			starts.push(offset);
+		}
+		previous_empty = line == b"\n" || line == b"\r\n";
+		offset += line.len();
+	}
+	if starts.first() != Some(&0) {
+		// A single mail without a "From " line
+		starts.insert(0, 0);
+	}
+	starts.push(data.len());
+	starts
+		.windows(2)
+		.map(|w| &data[w[0]..w[1]])
+		.filter(|mail| !mail.iter().all(u8::is_ascii_whitespace))
+		.collect()
+}
+
+// Headers of a mail with continuation lines joined, and the body
+fn split_headers(mail: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+	let mut headers: Vec<(String, String)> = Vec::new();
+	let mut offset = 0;
+	for line in mail.split_inclusive(|&b| b == b'\n') {
+		offset += line.len();
+		let text = String::from_utf8_lossy(line);
+		let text = text.trim_end_matches(['\n', '\r']);
+		if text.is_empty() {
+			break;
+		}
+		if text.starts_with([' ', '\t']) {
+			if let Some((_, value)) = headers.last_mut() {
+				value.push(' ');
+				value.push_str(text.trim());
+			}
+		} else if let Some((name, value)) = text.split_once(':') {
+			headers.push((name.trim().to_lowercase(), value.trim().to_owned()));
+		}
+	}
+	(headers, &mail[offset.min(mail.len())..])
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+	headers
+		.iter()
+		.find(|(n, _)| n == name)
+		.map(|(_, value)| value.as_str())
+}
+
+// Subjects lose "Re:" and "[PATCH ...]" in front
+fn clean_subject(subject: &str) -> String {
+	let mut subject = subject.trim();
+	loop {
+		if subject.len() >= 3 && subject[..3].eq_ignore_ascii_case("re:") {
+			subject = subject[3..].trim_start();
+		} else if let Some(end) = subject.strip_prefix('[').and_then(|s| s.find(']')) {
+			subject = subject[end + 2..].trim_start();
+		} else {
+			return subject.to_owned();
+		}
+	}
+}
+
+// Where the patch starts: a "---" line, or the diff itself
+fn patch_start(lines: &[&[u8]]) -> Option<usize> {
+	lines.iter().position(|line| {
+		let text = line.strip_suffix(b"\n").unwrap_or(line);
+		let text = text.strip_suffix(b"\r").unwrap_or(text);
+		text == b"---" || text.starts_with(b"diff -") || text.starts_with(b"Index: ")
+	})
+}
+
+pub fn parse_mail(mail: &[u8]) -> Result<Mail, MailError> {
+	let mail = match mail.starts_with(b"From ") {
+		true => mail
+			.iter()
+			.position(|&b| b == b'\n')
+			.map_or(&[][..], |i| &mail[i + 1..]),
+		false => mail,
+	};
+	let (mut headers, body) = split_headers(mail);
+	let body = match header(&headers, "content-transfer-encoding").map(str::to_lowercase) {
+		Some(encoding) if encoding == "quoted-printable" => decode_quoted_printable(body, false),
+		Some(encoding) if encoding == "base64" => {
+			let text = body
+				.iter()
+				.copied()
+				.filter(|b| !b.is_ascii_whitespace())
+				.collect::<Vec<_>>();
+			STANDARD.decode(text).map_err(|_| HeaderError {
+				msg: "invalid base64 body".to_owned(),
+			})?
+		},
+		_ => body.to_vec(),
+	};
+
+	let lines = body.split_inclusive(|&b| b == b'\n').collect::<Vec<_>>();
+	let start = patch_start(&lines).ok_or(NoPatchError)?;
+	let message_lines = &lines[..start];
+	// Headers at the start of the body take the place of those of the mail,
+	// e.g. when the mail was sent by someone else than the author
+	let in_body = message_lines.concat();
+	let (body_headers, rest) = split_headers(&in_body);
+	let known = ["from", "subject", "date"];
+	let has_body_headers = !body_headers.is_empty()
+		&& body_headers
+			.iter()
+			.all(|(name, _)| known.contains(&name.as_str()));
+	let message_body = if has_body_headers {
+		for (name, value) in body_headers {
+			headers.retain(|(n, _)| *n != name);
+			headers.push((name, value));
+		}
+		String::from_utf8_lossy(rest).into_owned()
+	} else {
+		String::from_utf8_lossy(&in_body).into_owned()
+	};
+	let patch = lines[start..].concat();
+
+	let subject = clean_subject(&decode_header(header(&headers, "subject").unwrap_or("")));
+	let message_body = message_body.replace("\r\n", "\n");
+	let message_body = message_body.trim_matches('\n');
+	let message = if message_body.is_empty() {
+		format!("{}\n", subject)
+	} else {
+		format!("{}\n\n{}\n", subject, message_body)
+	};
+	let (name, email) = header(&headers, "from")
+		.and_then(parse_address)
+		.ok_or_else(|| HeaderError {
+			msg: "no author in the From header".to_owned(),
+		})?;
+	let (seconds, zone) = header(&headers, "date")
+		.and_then(parse_rfc2822_date)
+		.unwrap_or_else(|| {
+			let now = SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map_or(0, |d| d.as_secs() as i64);
+			(now, "+0000".to_owned())
+		});
+	let author = format!("{} <{}> {} {}", name, email, seconds, zone);
+	Ok(Mail {
+		author,
+		message,
+		patch,
+	})
+}
@@ -0,0 +1,576 @@
+use crate::tools::{
+	commit::{Commit, CommitError},
+	db::{insert, read, DbError, ObjectRepr, ObjectType},
+	diff::{matching_lines, split_lines},
+	path::RelativePathToBase,
+	tree::{FileMode, FlatEntry, FlatTree},
+};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use thiserror::Error;
+use MergeError::{CommitReadError, ObjectError};
+
+#[derive(Error, Debug)]
+pub enum MergeError {
+	#[error("{source:?}")]
+	ObjectError { source: DbError },
+	#[error("{source:?}")]
+	CommitReadError { source: CommitError },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictStyle {
+	// Only ours and theirs, common lines at both ends moved out of the conflict
+	Merge,
+	// Ours, base and theirs, as they are
+	Diff3,
+	// Like diff3, with common lines of ours and theirs moved out of the conflict
+	Zdiff3,
+}
+
+impl ConflictStyle {
+	pub fn from_name(name: &str) -> Option<ConflictStyle> {
+		match name {
+			"merge" => Some(ConflictStyle::Merge),
+			"diff3" => Some(ConflictStyle::Diff3),
+			"zdiff3" => Some(ConflictStyle::Zdiff3),
+			_ => None,
+		}
+	}
+}
+
+// Names written after the conflict markers
+pub struct MergeLabels<'a> {
+	pub base:   &'a str,
+	pub ours:   &'a str,
+	pub theirs: &'a str,
+}
+
+pub struct ContentMerge {
+	pub content:   Vec<u8>,
+	pub conflicts: usize,
+}
+
+// A path that could not be merged. The stages are the versions in the common
+// ancestor, HEAD and MERGE_HEAD, `worktree` is what to leave in the working
+// tree for the user to resolve
+#[derive(Debug, Clone)]
+pub struct Conflict {
+	pub path:     RelativePathToBase,
+	pub base:     Option<FlatEntry>,
+	pub ours:     Option<FlatEntry>,
+	pub theirs:   Option<FlatEntry>,
+	pub worktree: (FileMode, Vec<u8>),
+}
+
+pub struct TreeMerge {
+	// Merged entries of all paths without conflict
+	pub tree:      FlatTree,
+	pub conflicts: Vec<Conflict>,
+}
+
+////////////////////////////////////////////
+// MERGE BASE
+////////////////////////////////////////////
+
+struct History {
+	parents: HashMap<ObjectRepr, Vec<ObjectRepr>>,
+}
+
+impl History {
+	fn new() -> History {
+		History {
+			parents: HashMap::new(),
+		}
+	}
+
+	fn parents(&mut self, commit: &ObjectRepr) -> Result<Vec<ObjectRepr>, MergeError> {
+		if let Some(parents) = self.parents.get(commit) {
+			return Ok(parents.clone());
+		}
+		let parents = Commit::read(commit)
+			.map_err(|e| CommitReadError { source: e })?
+			.parents;
+		self.parents.insert(commit.clone(), parents.clone());
+		Ok(parents)
+	}
+
+	// The commit and all commits reachable from it
+	fn ancestors(&mut self, commit: &ObjectRepr) -> Result<HashSet<ObjectRepr>, MergeError> {
+		let mut seen = HashSet::new();
+		let mut queue = VecDeque::new();
+		queue.push_back(commit.clone());
+		while let Some(c) = queue.pop_front() {
+			if seen.insert(c.clone()) {
+				queue.extend(self.parents(&c)?);
+			}
+		}
+		Ok(seen)
+	}
+}
+
+pub fn is_ancestor(ancestor: &ObjectRepr, commit: &ObjectRepr) -> Result<bool, MergeError> {
+	Ok(History::new().ancestors(commit)?.contains(ancestor))
+}
+
+// Best common ancestors: common ancestors that are not ancestors of another
+// common ancestor. Criss-cross merges can have several of them
+pub fn merge_bases(a: &ObjectRepr, b: &ObjectRepr) -> Result<Vec<ObjectRepr>, MergeError> {
+	let mut history = History::new();
+	let ancestors_a = history.ancestors(a)?;
+
+	// Walk from b, stopping at the first common commits on every path
+	let mut candidates = BTreeSet::new();
+	let mut seen = HashSet::new();
+	let mut queue = VecDeque::new();
+	queue.push_back(b.clone());
+	while let Some(c) = queue.pop_front() {
+		if !seen.insert(c.clone()) {
+			continue;
+		}
+		if ancestors_a.contains(&c) {
+			candidates.insert(c);
+		} else {
+			queue.extend(history.parents(&c)?);
+		}
+	}
+
+	let mut bases = Vec::new();
+	for candidate in &candidates {
+		let mut redundant = false;
+		for other in candidates.iter().filter(|o| *o != candidate) {
+			if history.ancestors(other)?.contains(candidate) {
+				redundant = true;
+				break;
+			}
+		}
+		if !redundant {
+			bases.push(candidate.clone());
+		}
+	}
+	Ok(bases)
+}
+
+// Commits reachable from `head` but from none of `upstreams`, parents before
+// their children
+pub fn commits_between(
+	upstreams: &[ObjectRepr],
+	head: &ObjectRepr,
+) -> Result<Vec<ObjectRepr>, MergeError> {
+	let mut history = History::new();
+	let mut excluded = HashSet::new();
+	for upstream in upstreams {
+		excluded.extend(history.ancestors(upstream)?);
+	}
+	// Depth-first from head, a commit is emitted once its parents are
+	let mut commits = Vec::new();
+	let mut emitted = HashSet::new();
+	let mut stack = vec![(head.clone(), false)];
+	while let Some((commit, parents_done)) = stack.pop() {
+		if excluded.contains(&commit) || emitted.contains(&commit) {
+			continue;
+		}
+		if parents_done {
+			emitted.insert(commit.clone());
+			commits.push(commit);
+			continue;
+		}
+		stack.push((commit.clone(), true));
+		// First parents are walked first
+		for parent in history.parents(&commit)?.into_iter().rev() {
+			stack.push((parent, false));
+		}
+	}
+	Ok(commits)
+}
+
+////////////////////////////////////////////
+// CONTENT MERGE
+////////////////////////////////////////////
+
+fn push_lines(out: &mut Vec<u8>, lines: &[&[u8]]) {
+	for line in lines {
+		out.extend_from_slice(line);
+	}
+}
+
+// Markers need to start on a new line even if the last line lacks a newline
+fn push_marker(out: &mut Vec<u8>, marker: &str, label: Option<&str>) {
+	if !out.is_empty() && !out.ends_with(b"\n") {
+		out.push(b'\n');
+	}
+	out.extend_from_slice(marker.as_bytes());
+	if let Some(label) = label {
+		out.push(b' ');
+		out.extend_from_slice(label.as_bytes());
+	}
+	out.push(b'\n');
+}
+
+fn push_conflict(
+	out: &mut Vec<u8>,
+	base: &[&[u8]],
+	ours: &[&[u8]],
+	theirs: &[&[u8]],
+	labels: &MergeLabels,
+	style: ConflictStyle,
+) {
+	let (prefix, suffix) = match style {
+		ConflictStyle::Diff3 => (0, 0),
+		ConflictStyle::Merge | ConflictStyle::Zdiff3 => {
+			let prefix = ours
+				.iter()
+				.zip(theirs.iter())
+				.take_while(|(o, t)| o == t)
+				.count();
+			let suffix = ours[prefix..]
+				.iter()
+				.rev()
+				.zip(theirs[prefix..].iter().rev())
+				.take_while(|(o, t)| o == t)
+				.count();
+			(prefix, suffix)
+		},
+	};
+
+	push_lines(out, &ours[..prefix]);
+	push_marker(out, "<<<<<<<", Some(labels.ours));
+	push_lines(out, &ours[prefix..(ours.len() - suffix)]);
+	if style != ConflictStyle::Merge {
+		push_marker(out, "|||||||", Some(labels.base));
+		push_lines(out, base);
+	}
+	push_marker(out, "=======", None);
+	push_lines(out, &theirs[prefix..(theirs.len() - suffix)]);
+	push_marker(out, ">>>>>>>", Some(labels.theirs));
+	push_lines(out, &ours[(ours.len() - suffix)..]);
+}
+
+// Three-way merge of the lines of a file, see Khanna, Kunal and Pierce, "A
+// Formal Investigation of Diff3"
+pub fn merge_content(
+	base: &[u8],
+	ours: &[u8],
+	theirs: &[u8],
+	labels: &MergeLabels,
+	style: ConflictStyle,
+) -> ContentMerge {
+	let b = split_lines(base);
+	let o = split_lines(ours);
+	let t = split_lines(theirs);
+
+	// For each base line, the matching line in ours and theirs
+	let mut to_ours = vec![None; b.len()];
+	for (i, j) in matching_lines(&b, &o) {
+		to_ours[i] = Some(j);
+	}
+	let mut to_theirs = vec![None; b.len()];
+	for (i, k) in matching_lines(&b, &t) {
+		to_theirs[i] = Some(k);
+	}
+
+	let mut out = Vec::new();
+	let mut conflicts = 0;
+	let (mut i, mut j, mut k) = (0, 0, 0);
+	loop {
+		// Stable chunk: lines unchanged on both sides
+		let mut m = 0;
+		while i + m < b.len() && to_ours[i + m] == Some(j + m) && to_theirs[i + m] == Some(k + m) {
+			m += 1;
+		}
+		if m > 0 {
+			push_lines(&mut out, &b[i..(i + m)]);
+			i += m;
+			j += m;
+			k += m;
+			continue;
+		}
+
+		// Unstable chunk: up to the next base line kept on both sides
+		let next = (i..b.len()).find(|&l| to_ours[l].is_some() && to_theirs[l].is_some());
+		let (end_b, end_o, end_t) = match next {
+			Some(l) => (l, to_ours[l].unwrap(), to_theirs[l].unwrap()),
+			None => (b.len(), o.len(), t.len()),
+		};
+		let (bc, oc, tc) = (&b[i..end_b], &o[j..end_o], &t[k..end_t]);
+		if oc == tc || tc == bc {
+			push_lines(&mut out, oc);
+		} else if oc == bc {
+			push_lines(&mut out, tc);
+		} else {
+			conflicts += 1;
+			push_conflict(&mut out, bc, oc, tc, labels, style);
+		}
+		i = end_b;
+		j = end_o;
+		k = end_t;
+		if next.is_none() {
+			break;
+		}
+	}
+	ContentMerge {
+		content: out,
+		conflicts,
+	}
+}
+
+////////////////////////////////////////////
+// TREE MERGE
+////////////////////////////////////////////
+
+fn blob_content(entry: Option<&FlatEntry>) -> Result<Vec<u8>, MergeError> {
+	match entry {
+		Some(entry) => Ok(read(&entry.hash)
+			.map_err(|e| ObjectError { source: e })?
+			.data),
+		None => Ok(Vec::new()),
+	}
+}
+
+fn is_file(entry: &FlatEntry) -> bool {
+	matches!(entry.mode, FileMode::Regular | FileMode::Executable)
+}
+
+// The mode changed on at most one side, otherwise ours wins
+fn merge_mode(base: Option<&FlatEntry>, ours: &FlatEntry, theirs: &FlatEntry) -> FileMode {
+	match base {
+		Some(base) if base.mode == ours.mode => theirs.mode,
+		_ => ours.mode,
+	}
+}
+
+fn merge_path(
+	path: &RelativePathToBase,
+	base: Option<&FlatEntry>,
+	ours: Option<&FlatEntry>,
+	theirs: Option<&FlatEntry>,
+	labels: &MergeLabels,
+	style: ConflictStyle,
+) -> Result<Result<Option<FlatEntry>, Conflict>, MergeError> {
+	if ours == theirs || base == theirs {
+		return Ok(Ok(ours.cloned()));
+	}
+	if base == ours {
+		return Ok(Ok(theirs.cloned()));
+	}
+
+	let conflict = |worktree| Conflict {
+		path: path.clone(),
+		base: base.cloned(),
+		ours: ours.cloned(),
+		theirs: theirs.cloned(),
+		worktree,
+	};
+	match (ours, theirs) {
+		(Some(o), Some(t)) if is_file(o) && is_file(t) && base.is_none_or(is_file) => {
+			let merged = merge_content(
+				&blob_content(base)?,
+				&blob_content(ours)?,
+				&blob_content(theirs)?,
+				labels,
+				style,
+			);
+			let mode = merge_mode(base, o, t);
+			if merged.conflicts > 0 {
+				Ok(Err(conflict((mode, merged.content))))
+			} else {
+				let hash = insert(&merged.content, ObjectType::Blob)
+					.map_err(|e| ObjectError { source: e })?;
+				Ok(Ok(Some(FlatEntry { mode, hash })))
+			}
+		},
+		// Symlinks, modify/delete or add/add of different kinds: keep what is
+		// left of ours in the working tree, or theirs if ours is deleted
+		(Some(o), _) => Ok(Err(conflict((o.mode, blob_content(ours)?)))),
+		(None, Some(t)) => Ok(Err(conflict((t.mode, blob_content(theirs)?)))),
+		(None, None) => Ok(Ok(None)),
+	}
+}
+
+pub fn merge_trees(
+	base: &FlatTree,
+	ours: &FlatTree,
+	theirs: &FlatTree,
+	labels: &MergeLabels,
+	style: ConflictStyle,
+) -> Result<TreeMerge, MergeError> {
+	let paths = base
+		.keys()
+		.chain(ours.keys())
+		.chain(theirs.keys())
+		.collect::<BTreeSet<_>>();
+
+	let mut tree = FlatTree::new();
+	let mut conflicts = Vec::new();
+	for path in paths {
+		match merge_path(
+			path,
+			base.get(path),
+			ours.get(path),
+			theirs.get(path),
+			labels,
+			style,
+		)? {
+			Ok(Some(entry)) => {
+				tree.insert(path.clone(), entry);
+			},
+			Ok(None) => {},
+			Err(conflict) => conflicts.push(conflict),
+		}
+	}
+	Ok(TreeMerge { tree, conflicts })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tools::{
+		object_format::ObjectFormat,
+		testing::{lock, TempRepo},
+	};
+	use std::path::PathBuf;
+
+	const LABELS: MergeLabels = MergeLabels {
+		base:   "base",
+		ours:   "ours",
+		theirs: "theirs",
+	};
+
+	fn merged(base: &str, ours: &str, theirs: &str, style: ConflictStyle) -> (String, usize) {
+		let merge = merge_content(
+			base.as_bytes(),
+			ours.as_bytes(),
+			theirs.as_bytes(),
+			&LABELS,
+			style,
+		);
+		(String::from_utf8(merge.content).unwrap(), merge.conflicts)
+	}
+
+	fn path(name: &str) -> RelativePathToBase {
+		RelativePathToBase::new(PathBuf::from(name))
+	}
+
+	fn tree(files: &[(&str, &str)]) -> Result<FlatTree, MergeError> {
+		let mut tree = FlatTree::new();
+		for (name, content) in files {
+			let hash = insert(content.as_bytes(), ObjectType::Blob)
+				.map_err(|e| ObjectError { source: e })?;
+			tree.insert(
+				path(name),
+				FlatEntry {
+					mode: FileMode::Regular,
+					hash,
+				},
+			);
+		}
+		Ok(tree)
+	}
+
+	#[test]
+	fn merges_changes_to_different_lines() {
+		let base = "a\nb\nc\nd\n";
+		assert_eq!(
+			merged(base, "A\nb\nc\nd\n", "a\nb\nc\nD\n", ConflictStyle::Merge),
+			("A\nb\nc\nD\n".to_owned(), 0)
+		);
+		// The same change on both sides, or on one side only
+		assert_eq!(
+			merged(base, "a\nB\nc\nd\n", "a\nB\nc\nd\n", ConflictStyle::Merge),
+			("a\nB\nc\nd\n".to_owned(), 0)
+		);
+		assert_eq!(
+			merged(base, base, "a\nc\nd\ne\n", ConflictStyle::Merge),
+			("a\nc\nd\ne\n".to_owned(), 0)
+		);
+	}
+
+	#[test]
+	fn marks_conflicts_in_each_style() {
+		let (base, ours, theirs) = ("a\nb\nc\n", "a\nx\nB\ny\nc\n", "a\nx\nT\ny\nc\n");
+		assert_eq!(
+			merged(base, ours, theirs, ConflictStyle::Merge),
+			(
+				"a\nx\n<<<<<<< ours\nB\n=======\nT\n>>>>>>> theirs\ny\nc\n".to_owned(),
+				1
+			)
+		);
+		assert_eq!(
+			merged(base, ours, theirs, ConflictStyle::Diff3),
+			(
+				"a\n<<<<<<< ours\nx\nB\ny\n||||||| base\nb\n=======\nx\nT\ny\n>>>>>>> theirs\nc\n"
+					.to_owned(),
+				1
+			)
+		);
+		assert_eq!(
+			merged(base, ours, theirs, ConflictStyle::Zdiff3),
+			(
+				"a\nx\n<<<<<<< ours\nB\n||||||| base\nb\n=======\nT\n>>>>>>> theirs\ny\nc\n"
+					.to_owned(),
+				1
+			)
+		);
+	}
+
+	#[test]
+	fn starts_markers_on_a_new_line() {
+		assert_eq!(
+			merged("", "a", "b", ConflictStyle::Merge),
+			(
+				"<<<<<<< ours\na\n=======\nb\n>>>>>>> theirs\n".to_owned(),
+				1
+			)
+		);
+	}
+
+	#[test]
+	fn merges_trees() {
+		let _lock = lock();
+		let repo = TempRepo::new(ObjectFormat::Sha1);
+		repo.run(|| {
+			let base = tree(&[("a", "a\n-\nb\n"), ("b", "b\n"), ("c", "c\n")])?;
+			let ours = tree(&[("a", "A\n-\nb\n"), ("c", "c\n"), ("d", "d\n")])?;
+			let theirs = tree(&[("a", "a\n-\nB\n"), ("b", "b\n"), ("c", "C\n")])?;
+			let merge = merge_trees(&base, &ours, &theirs, &LABELS, ConflictStyle::Merge)?;
+			assert!(merge.conflicts.is_empty());
+			assert_eq!(
+				merge.tree,
+				tree(&[("a", "A\n-\nB\n"), ("c", "C\n"), ("d", "d\n")])?
+			);
+			Ok(())
+		});
+	}
+
+	#[test]
+	fn reports_content_and_delete_modify_conflicts() {
+		let _lock = lock();
+		let repo = TempRepo::new(ObjectFormat::Sha1);
+		repo.run(|| {
+			let base = tree(&[("a", "a\n"), ("b", "b\n"), ("c", "c\n")])?;
+			let ours = tree(&[("a", "ours\n"), ("c", "c\n")])?;
+			let theirs = tree(&[("a", "theirs\n"), ("b", "B\n"), ("c", "c\n")])?;
+			let merge = merge_trees(&base, &ours, &theirs, &LABELS, ConflictStyle::Merge)?;
+			assert_eq!(merge.tree, tree(&[("c", "c\n")])?);
+			assert_eq!(merge.conflicts.len(), 2);
+
+			let content = &merge.conflicts[0];
+			assert_eq!(content.path, path("a"));
+			assert_eq!(content.base.as_ref(), base.get(&path("a")));
+			assert_eq!(
+				content.worktree,
+				(
+					FileMode::Regular,
+					b"<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\n".to_vec()
+				)
+			);
+
+			// Deleted in ours, theirs is left to resolve
+			let deleted = &merge.conflicts[1];
+			assert_eq!(deleted.path, path("b"));
+			assert_eq!(deleted.ours, None);
+			assert_eq!(deleted.theirs.as_ref(), theirs.get(&path("b")));
+			assert_eq!(deleted.worktree, (FileMode::Regular, b"B\n".to_vec()));
+			Ok(())
+		});
+	}
+}
@@ -0,0 +1,154 @@
+// Lines removed in one place of a diff and added in another, in the styles of
+// git diff --color-moved. Moved lines are found across all files, in blocks
+// that follow the same lines on the other side
+use crate::tools::patch::PatchLine;
+use std::collections::HashMap;
+
+// Blocks with fewer letters and digits are too common to count as moved
+const MIN_ALNUM_COUNT: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMoved {
+	// Every moved line, without blocks
+	Plain,
+	// Blocks of moved lines
+	Blocks,
+	// Blocks, with alternating colors where one follows another
+	Zebra,
+}
+
+impl ColorMoved {
+	pub fn from_name(name: &str) -> Option<ColorMoved> {
+		match name {
+			"plain" => Some(ColorMoved::Plain),
+			"blocks" => Some(ColorMoved::Blocks),
+			"zebra" | "default" => Some(ColorMoved::Zebra),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Moved {
+	No,
+	Moved,
+	// Moved, in a block right after another one
+	Alternate,
+}
+
+// Sign and content of removed and added lines
+fn changed_line(line: &PatchLine) -> Option<(bool, &[u8])> {
+	match line {
+		PatchLine::Removed(content) => Some((false, content)),
+		PatchLine::Added(content) => Some((true, content)),
+		_ => None,
+	}
+}
+
+// Whether the block ending before `end` is long enough to count as moved,
+// if not its lines are unmarked
+fn keep_block(
+	lines: &[PatchLine],
+	marks: &mut [Moved],
+	end: usize,
+	length: usize,
+	mode: ColorMoved,
+) -> bool {
+	if mode == ColorMoved::Plain {
+		return length > 0;
+	}
+	let alnum = lines[end - length..end]
+		.iter()
+		.filter_map(changed_line)
+		.flat_map(|(_, content)| content.iter())
+		.filter(|b| b.is_ascii_alphanumeric())
+		.count();
+	if alnum >= MIN_ALNUM_COUNT {
+		return true;
+	}
+	for mark in &mut marks[end - length..end] {
+		*mark = Moved::No;
+	}
+	false
+}
+
+pub fn mark_moved(lines: &[PatchLine], mode: ColorMoved) -> Vec<Moved> {
+	// Lines of each sign by content, and the line following each one with the
+	// same sign
+	let mut by_content: [HashMap<&[u8], Vec<usize>>; 2] = [HashMap::new(), HashMap::new()];
+	let mut next = vec![None; lines.len()];
+	for (n, line) in lines.iter().enumerate() {
+		if let Some((sign, content)) = changed_line(line) {
+			by_content[sign as usize]
+				.entry(content)
+				.or_default()
+				.push(n);
+			if lines
+				.get(n + 1)
+				.and_then(changed_line)
+				.is_some_and(|(s, _)| s == sign)
+			{
+				next[n] = Some(n + 1);
+			}
+		}
+	}
+
+	let mut marks = vec![Moved::No; lines.len()];
+	// Lines on the other side the current block may be following
+	let mut candidates = Vec::new();
+	let (mut block_length, mut flipped, mut block_sign) = (0, false, None);
+	let mut n = 0;
+	while n < lines.len() {
+		let changed = changed_line(&lines[n]);
+		let matches = changed.and_then(|(sign, content)| by_content[!sign as usize].get(content));
+		let (sign, content, matches) = match (changed, matches) {
+			(Some((sign, content)), Some(matches)) => (sign, content, matches),
+			_ => {
+				// A block too short may still hold one starting on its second line
+				if !keep_block(lines, &mut marks, n, block_length, mode) && block_length > 1 {
+					n -= block_length - 1;
+				} else {
+					n += 1;
+				}
+				candidates.clear();
+				block_length = 0;
+				flipped = false;
+				continue;
+			},
+		};
+		if mode == ColorMoved::Plain {
+			marks[n] = Moved::Moved;
+			n += 1;
+			continue;
+		}
+
+		candidates = candidates
+			.iter()
+			.filter_map(|&c: &usize| next[c])
+			.filter(|&c| changed_line(&lines[c]).is_some_and(|(_, other)| other == content))
+			.collect();
+		if candidates.is_empty() {
+			let kept = keep_block(lines, &mut marks, n, block_length, mode);
+			if !kept && block_length > 1 {
+				n -= block_length - 1;
+				block_length = 0;
+				flipped = false;
+				block_sign = None;
+				continue;
+			}
+			candidates = matches.clone();
+			flipped = kept && block_sign == Some(sign) && !flipped;
+			block_sign = Some(sign);
+			block_length = 0;
+		}
+		block_length += 1;
+		marks[n] = if flipped && mode == ColorMoved::Zebra {
+			Moved::Alternate
+		} else {
+			Moved::Moved
+		};
+		n += 1;
+	}
+	keep_block(lines, &mut marks, n, block_length, mode);
+	marks
+}
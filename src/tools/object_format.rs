@@ -0,0 +1,131 @@
+use crate::tools::{
+	config::{Config, ConfigError},
+	path::root_path,
+};
+use lazy_static::lazy_static;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::{
+	fmt::{self, Display, Formatter},
+	sync::Mutex,
+};
+use thiserror::Error;
+use ObjectFormatError::{ConfigReadError, ConfigWriteError, UnknownFormatError};
+
+#[derive(Error, Debug)]
+pub enum ObjectFormatError {
+	#[error("{source:?}")]
+	ConfigReadError { source: ConfigError },
+	#[error("{source:?}")]
+	ConfigWriteError { source: ConfigError },
+	#[error("Unknown object format {name:?}")]
+	UnknownFormatError { name: String },
+}
+
+// The hash function naming objects, chosen when the repository is created.
+// Repositories without one configured use SHA-1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjectFormat {
+	#[default]
+	Sha1,
+	Sha256,
+}
+
+lazy_static! {
+	// Of the repository of the current directory, loaded when a command starts
+	// and whenever it switches to another repository, e.g. to fetch from it
+	static ref CURRENT: Mutex<ObjectFormat> = Mutex::new(ObjectFormat::Sha1);
+}
+
+impl ObjectFormat {
+	pub fn from_name(name: &str) -> Result<ObjectFormat, ObjectFormatError> {
+		match name {
+			"sha1" => Ok(ObjectFormat::Sha1),
+			"sha256" => Ok(ObjectFormat::Sha256),
+			_ => Err(UnknownFormatError {
+				name: name.to_owned(),
+			}),
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			ObjectFormat::Sha1 => "sha1",
+			ObjectFormat::Sha256 => "sha256",
+		}
+	}
+
+	// Length of a digest in bytes, as in trees and packs
+	pub fn hash_len(self) -> usize {
+		match self {
+			ObjectFormat::Sha1 => 20,
+			ObjectFormat::Sha256 => 32,
+		}
+	}
+
+	// Length of a digest in hex, as in object names
+	pub fn hex_len(self) -> usize {
+		self.hash_len() * 2
+	}
+
+	pub fn digest(self, data: &[u8]) -> Vec<u8> {
+		match self {
+			ObjectFormat::Sha1 => Sha1::digest(data).to_vec(),
+			ObjectFormat::Sha256 => Sha256::digest(data).to_vec(),
+		}
+	}
+
+	// Stands for no object, e.g. in reflogs and ref updates
+	pub fn null_hash(self) -> String {
+		"0".repeat(self.hex_len())
+	}
+
+	// The format of the repository of the current directory
+	pub fn read() -> Result<ObjectFormat, ObjectFormatError> {
+		let config = Config::read().map_err(|e| ConfigReadError { source: e })?;
+		match config.extensions.object_format {
+			Some(name) => ObjectFormat::from_name(&name),
+			None => Ok(ObjectFormat::Sha1),
+		}
+	}
+
+	// Reads the format of the repository of the current directory and makes
+	// it the current one. Outside of a repository it is SHA-1
+	pub fn load() -> Result<ObjectFormat, ObjectFormatError> {
+		let format = match root_path() {
+			Ok(Some(_)) => ObjectFormat::read()?,
+			_ => ObjectFormat::Sha1,
+		};
+		format.make_current();
+		Ok(format)
+	}
+
+	// The format objects are hashed with, as last loaded. Objects are hashed
+	// often, so the config is not read again
+	pub fn current() -> ObjectFormat {
+		*CURRENT.lock().expect("the object format is not poisoned")
+	}
+
+	pub fn make_current(self) {
+		*CURRENT.lock().expect("the object format is not poisoned") = self;
+	}
+
+	// Records the format in the config of the repository of the current
+	// directory, before any object is hashed in it
+	pub fn write(self) -> Result<(), ObjectFormatError> {
+		let mut config = Config::read().map_err(|e| ConfigReadError { source: e })?;
+		config.extensions.object_format = match self {
+			ObjectFormat::Sha1 => None,
+			ObjectFormat::Sha256 => Some(self.name().to_owned()),
+		};
+		config.write().map_err(|e| ConfigWriteError { source: e })?;
+		self.make_current();
+		Ok(())
+	}
+}
+
+impl Display for ObjectFormat {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "{}", self.name())
+	}
+}
@@ -0,0 +1,488 @@
+// Packfiles as exchanged over the wire: a header, the objects and a hash of
+// everything before it, with the hash function of the object format. Objects
+// are written whole, but read packs may store objects as deltas against other
+// objects of the pack or of the database
+use crate::tools::{
+	db::{read, simulate_insert, DbError, ObjectRepr, ObjectType},
+	object_format::ObjectFormat,
+};
+use flate2::{write::ZlibEncoder, Compression, Decompress, FlushDecompress, Status};
+use std::{
+	collections::HashMap,
+	convert::TryInto,
+	io::{self, Write},
+};
+use thiserror::Error;
+use PackError::{CorruptError, ObjectReadError, WriteError};
+
+#[derive(Error, Debug)]
+pub enum PackError {
+	#[error("Corrupt pack: {msg}")]
+	CorruptError { msg: String },
+	#[error("{source:?}")]
+	ObjectReadError { source: DbError },
+	#[error("{source:?}")]
+	WriteError { source: io::Error },
+}
+
+const SIGNATURE: &[u8] = b"PACK";
+const VERSION: u32 = 2;
+
+// Type codes of pack entries
+const COMMIT: u8 = 1;
+const TREE: u8 = 2;
+const BLOB: u8 = 3;
+const TAG: u8 = 4;
+const OFS_DELTA: u8 = 6;
+const REF_DELTA: u8 = 7;
+
+// An entry before its delta is resolved
+enum Entry {
+	Whole(ObjectType, Vec<u8>),
+	OfsDelta(usize, Vec<u8>),
+	RefDelta(ObjectRepr, Vec<u8>),
+}
+
+fn corrupt(msg: &str) -> PackError {
+	CorruptError {
+		msg: msg.to_owned(),
+	}
+}
+
+fn type_code(object_type: ObjectType) -> u8 {
+	match object_type {
+		ObjectType::Commit => COMMIT,
+		ObjectType::Tree => TREE,
+		ObjectType::Blob => BLOB,
+		ObjectType::Tag => TAG,
+		ObjectType::Invalid => panic!("invalid objects can not be packed"),
+	}
+}
+
+// The type and size header of an entry: 3 bits of type and 4 bits of size,
+// then 7 bits of size per byte while the high bit is set
+fn entry_header(code: u8, size: usize) -> Vec<u8> {
+	let mut header = Vec::new();
+	let mut byte = (code << 4) | (size & 0x0f) as u8;
+	let mut rest = size >> 4;
+	while rest != 0 {
+		header.push(byte | 0x80);
+		byte = (rest & 0x7f) as u8;
+		rest >>= 7;
+	}
+	header.push(byte);
+	header
+}
+
+// Writes objects of the database as a pack
+pub fn write_pack(writer: &mut dyn Write, objects: &[ObjectRepr]) -> Result<(), PackError> {
+	let mut pack = Vec::new();
+	pack.extend_from_slice(SIGNATURE);
+	pack.extend_from_slice(&VERSION.to_be_bytes());
+	pack.extend_from_slice(&(objects.len() as u32).to_be_bytes());
+	for object_repr in objects {
+		let object = read(object_repr).map_err(|e| ObjectReadError { source: e })?;
+		pack.extend(entry_header(
+			type_code(object.object_type),
+			object.data.len(),
+		));
+		let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+		encoder
+			.write_all(&object.data)
+			.map_err(|e| WriteError { source: e })?;
+		pack.extend(encoder.finish().map_err(|e| WriteError { source: e })?);
+	}
+	let checksum = ObjectFormat::current().digest(&pack);
+	pack.extend_from_slice(&checksum);
+	writer
+		.write_all(&pack)
+		.map_err(|e| WriteError { source: e })
+}
+
+// Reads a variable length integer of 7 bits per byte, least significant first
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<usize, PackError> {
+	let mut value = 0;
+	let mut shift = 0;
+	loop {
+		let byte = *data.get(*pos).ok_or_else(|| corrupt("truncated size"))?;
+		*pos += 1;
+		value |= ((byte & 0x7f) as usize) << shift;
+		shift += 7;
+		if byte & 0x80 == 0 {
+			return Ok(value);
+		}
+		if shift > 63 {
+			return Err(corrupt("size too large"));
+		}
+	}
+}
+
+// Offsets of ofs-delta bases are big endian, and each continuation adds one
+// so that encodings are unique
+fn read_offset(data: &[u8], pos: &mut usize) -> Result<usize, PackError> {
+	let mut byte = *data.get(*pos).ok_or_else(|| corrupt("truncated offset"))?;
+	*pos += 1;
+	let mut value = (byte & 0x7f) as usize;
+	while byte & 0x80 != 0 {
+		byte = *data.get(*pos).ok_or_else(|| corrupt("truncated offset"))?;
+		*pos += 1;
+		value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+	}
+	Ok(value)
+}
+
+// Decompresses the zlib stream at `pos`, which must inflate to `size` bytes
+fn inflate(data: &[u8], pos: &mut usize, size: usize) -> Result<Vec<u8>, PackError> {
+	let mut decompress = Decompress::new(true);
+	let mut out = Vec::with_capacity(size + 1);
+	loop {
+		let consumed = decompress.total_in() as usize;
+		let status = decompress
+			.decompress_vec(&data[*pos + consumed..], &mut out, FlushDecompress::Finish)
+			.map_err(|e| corrupt(&e.to_string()))?;
+		match status {
+			Status::StreamEnd => break,
+			_ if out.len() > size => return Err(corrupt("object larger than announced")),
+			_ if decompress.total_in() as usize == consumed && out.len() < out.capacity() => {
+				return Err(corrupt("truncated object"))
+			},
+			_ => out.reserve(1),
+		}
+	}
+	if out.len() != size {
+		return Err(corrupt("object size differs from announced"));
+	}
+	*pos += decompress.total_in() as usize;
+	Ok(out)
+}
+
+// Rebuilds an object from its base and a delta: the sizes of base and result,
+// then instructions either copying a range of the base or inserting new data
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, PackError> {
+	let mut pos = 0;
+	if read_varint(delta, &mut pos)? != base.len() {
+		return Err(corrupt("delta base size mismatch"));
+	}
+	let size = read_varint(delta, &mut pos)?;
+	let mut result = Vec::with_capacity(size);
+	while let Some(&instruction) = delta.get(pos) {
+		pos += 1;
+		if instruction & 0x80 != 0 {
+			// Bits 0-3 select offset bytes, bits 4-6 size bytes
+			let mut fields = [0usize; 2];
+			for bit in 0..7 {
+				if instruction & (1 << bit) != 0 {
+					let byte = *delta.get(pos).ok_or_else(|| corrupt("truncated delta"))?;
+					pos += 1;
+					let (field, shift) = if bit < 4 { (0, bit) } else { (1, bit - 4) };
+					fields[field] |= (byte as usize) << (8 * shift);
+				}
+			}
+			let [offset, mut length] = fields;
+			if length == 0 {
+				length = 0x10000;
+			}
+			let copied = base
+				.get(offset..offset + length)
+				.ok_or_else(|| corrupt("delta copies beyond its base"))?;
+			result.extend_from_slice(copied);
+		} else if instruction != 0 {
+			let inserted = delta
+				.get(pos..pos + instruction as usize)
+				.ok_or_else(|| corrupt("truncated delta"))?;
+			result.extend_from_slice(inserted);
+			pos += instruction as usize;
+		} else {
+			return Err(corrupt("reserved delta instruction"));
+		}
+	}
+	if result.len() != size {
+		return Err(corrupt("delta result size mismatch"));
+	}
+	Ok(result)
+}
+
+fn object_type_from_code(code: u8) -> Result<ObjectType, PackError> {
+	match code {
+		COMMIT => Ok(ObjectType::Commit),
+		TREE => Ok(ObjectType::Tree),
+		BLOB => Ok(ObjectType::Blob),
+		TAG => Ok(ObjectType::Tag),
+		_ => Err(corrupt(&format!("unknown entry type {}", code))),
+	}
+}
+
+// Parses the entries of a pack, keyed by their offset
+fn read_entries(pack: &[u8]) -> Result<Vec<(usize, Entry)>, PackError> {
+	let format = ObjectFormat::current();
+	let hash_len = format.hash_len();
+	if pack.len() < 12 + hash_len || &pack[..4] != SIGNATURE {
+		return Err(corrupt("no pack signature"));
+	}
+	let (content, checksum) = pack.split_at(pack.len() - hash_len);
+	if format.digest(content) != checksum {
+		return Err(corrupt("checksum mismatch"));
+	}
+	let version = u32::from_be_bytes(pack[4..8].try_into().unwrap());
+	if version != VERSION && version != 3 {
+		return Err(corrupt(&format!("unsupported version {}", version)));
+	}
+	let count = u32::from_be_bytes(pack[8..12].try_into().unwrap());
+
+	let mut entries = Vec::new();
+	let mut pos = 12;
+	for _ in 0..count {
+		let offset = pos;
+		let first = *content.get(pos).ok_or_else(|| corrupt("truncated entry"))?;
+		pos += 1;
+		let code = (first >> 4) & 0x07;
+		let mut size = (first & 0x0f) as usize;
+		if first & 0x80 != 0 {
+			size |= read_varint(content, &mut pos)? << 4;
+		}
+		let entry = match code {
+			OFS_DELTA => {
+				let base = offset
+					.checked_sub(read_offset(content, &mut pos)?)
+					.ok_or_else(|| corrupt("delta base before the pack"))?;
+				Entry::OfsDelta(base, inflate(content, &mut pos, size)?)
+			},
+			REF_DELTA => {
+				let base = content
+					.get(pos..pos + hash_len)
+					.and_then(|bytes| ObjectRepr::from_bytes(bytes).ok())
+					.ok_or_else(|| corrupt("truncated delta base"))?;
+				pos += hash_len;
+				Entry::RefDelta(base, inflate(content, &mut pos, size)?)
+			},
+			code => Entry::Whole(
+				object_type_from_code(code)?,
+				inflate(content, &mut pos, size)?,
+			),
+		};
+		entries.push((offset, entry));
+	}
+	if pos != content.len() {
+		return Err(corrupt("trailing data"));
+	}
+	Ok(entries)
+}
+
+// The objects of a pack with their deltas resolved. Bases of ref-deltas that
+// are not in the pack are read from the database, as in thin packs
+pub fn read_pack(pack: &[u8]) -> Result<Vec<(ObjectType, Vec<u8>)>, PackError> {
+	let entries = read_entries(pack)?;
+	let mut by_offset: HashMap<usize, (ObjectType, Vec<u8>)> = HashMap::new();
+	let mut by_hash: HashMap<ObjectRepr, usize> = HashMap::new();
+	let resolved = |offset: usize,
+	                object: (ObjectType, Vec<u8>),
+	                by_offset: &mut HashMap<_, _>,
+	                by_hash: &mut HashMap<_, _>| {
+		let object_repr =
+			simulate_insert(&object.1, object.0).map_err(|e| ObjectReadError { source: e })?;
+		by_hash.insert(object_repr, offset);
+		by_offset.insert(offset, object);
+		Ok::<(), PackError>(())
+	};
+
+	// Deltas are resolved once their base is, which may take several passes
+	let mut pending = Vec::new();
+	for (offset, entry) in entries {
+		match entry {
+			Entry::Whole(object_type, data) => {
+				resolved(offset, (object_type, data), &mut by_offset, &mut by_hash)?
+			},
+			entry => pending.push((offset, entry)),
+		}
+	}
+	while !pending.is_empty() {
+		let count = pending.len();
+		let mut unresolved = Vec::new();
+		for (offset, entry) in pending {
+			let base = match &entry {
+				Entry::OfsDelta(base, _) => by_offset.get(base).cloned(),
+				Entry::RefDelta(base, _) => match by_hash.get(base) {
+					Some(base) => by_offset.get(base).cloned(),
+					None => read(base)
+						.ok()
+						.map(|object| (object.object_type, object.data)),
+				},
+				Entry::Whole(..) => unreachable!(),
+			};
+			match (base, &entry) {
+				(Some((object_type, base)), Entry::OfsDelta(_, delta))
+				| (Some((object_type, base)), Entry::RefDelta(_, delta)) => {
+					let data = apply_delta(&base, delta)?;
+					resolved(offset, (object_type, data), &mut by_offset, &mut by_hash)?;
+				},
+				_ => unresolved.push((offset, entry)),
+			}
+		}
+		if unresolved.len() == count {
+			return Err(corrupt("delta base missing"));
+		}
+		pending = unresolved;
+	}
+
+	let mut objects = by_offset.into_iter().collect::<Vec<_>>();
+	objects.sort_by_key(|(offset, _)| *offset);
+	Ok(objects.into_iter().map(|(_, object)| object).collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tools::{
+		db::insert,
+		reachable::objects_between,
+		testing::{commit, lock, TempRepo},
+	};
+
+	const BASE: &[u8] = b"hello world\n";
+	const RESULT: &[u8] = b"hello there\n";
+
+	// Turns BASE into RESULT: copies "hello ", inserts "there", copies "\n"
+	fn delta() -> Vec<u8> {
+		let mut delta = vec![BASE.len() as u8, RESULT.len() as u8];
+		delta.extend_from_slice(&[0x91, 0, 6]);
+		delta.push(5);
+		delta.extend_from_slice(b"there");
+		delta.extend_from_slice(&[0x91, 11, 1]);
+		delta
+	}
+
+	fn deflate(data: &[u8]) -> Vec<u8> {
+		let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+		encoder.write_all(data).unwrap();
+		encoder.finish().unwrap()
+	}
+
+	fn whole(object_type: ObjectType, data: &[u8]) -> Vec<u8> {
+		let mut entry = entry_header(type_code(object_type), data.len());
+		entry.extend(deflate(data));
+		entry
+	}
+
+	// Distances below 128 fit the first byte of the offset
+	fn ofs_delta(distance: usize, delta: &[u8]) -> Vec<u8> {
+		assert!(distance < 0x80);
+		let mut entry = entry_header(OFS_DELTA, delta.len());
+		entry.push(distance as u8);
+		entry.extend(deflate(delta));
+		entry
+	}
+
+	fn ref_delta(base: &ObjectRepr, delta: &[u8]) -> Vec<u8> {
+		let mut entry = entry_header(REF_DELTA, delta.len());
+		entry.extend(base.to_bytes());
+		entry.extend(deflate(delta));
+		entry
+	}
+
+	fn pack_of(entries: &[Vec<u8>]) -> Vec<u8> {
+		let mut pack = SIGNATURE.to_vec();
+		pack.extend_from_slice(&VERSION.to_be_bytes());
+		pack.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+		for entry in entries {
+			pack.extend_from_slice(entry);
+		}
+		let checksum = ObjectFormat::current().digest(&pack);
+		pack.extend(checksum);
+		pack
+	}
+
+	fn blob(data: &[u8]) -> (ObjectType, Vec<u8>) {
+		(ObjectType::Blob, data.to_vec())
+	}
+
+	#[test]
+	fn encodes_sizes_in_entry_headers() {
+		assert_eq!(entry_header(BLOB, 5), vec![0x35]);
+		assert_eq!(entry_header(COMMIT, 0x123), vec![0x93, 0x12]);
+		let mut pos = 1;
+		assert_eq!(read_varint(&[0x93, 0x12], &mut pos).unwrap(), 0x12);
+	}
+
+	#[test]
+	fn applies_deltas() {
+		assert_eq!(apply_delta(BASE, &delta()).unwrap(), RESULT);
+		assert!(apply_delta(b"other base", &delta()).is_err());
+		let mut beyond = vec![BASE.len() as u8, 4, 0x91, 10, 4];
+		assert!(apply_delta(BASE, &beyond).is_err());
+		beyond[4] = 2;
+		assert!(apply_delta(BASE, &beyond).is_err());
+	}
+
+	#[test]
+	fn reads_ofs_deltas() {
+		let _lock = lock();
+		let base = whole(ObjectType::Blob, BASE);
+		let distance = base.len();
+		let pack = pack_of(&[base, ofs_delta(distance, &delta())]);
+		assert_eq!(read_pack(&pack).unwrap(), vec![blob(BASE), blob(RESULT)]);
+	}
+
+	#[test]
+	fn reads_ref_deltas_before_their_base() {
+		let _lock = lock();
+		let base_repr = simulate_insert(BASE, ObjectType::Blob).unwrap();
+		let pack = pack_of(&[
+			ref_delta(&base_repr, &delta()),
+			whole(ObjectType::Blob, BASE),
+		]);
+		assert_eq!(read_pack(&pack).unwrap(), vec![blob(RESULT), blob(BASE)]);
+	}
+
+	#[test]
+	fn reads_ref_deltas_against_the_database() {
+		let _lock = lock();
+		let repo = TempRepo::new(ObjectFormat::Sha1);
+		let objects = repo.run(|| {
+			let base_repr = insert(BASE, ObjectType::Blob)?;
+			Ok(read_pack(&pack_of(&[ref_delta(&base_repr, &delta())]))?)
+		});
+		assert_eq!(objects, vec![blob(RESULT)]);
+	}
+
+	#[test]
+	fn refuses_deltas_without_base() {
+		let _lock = lock();
+		let base_repr = simulate_insert(b"unknown", ObjectType::Blob).unwrap();
+		let pack = pack_of(&[ref_delta(&base_repr, &delta())]);
+		assert!(matches!(read_pack(&pack), Err(CorruptError { .. })));
+	}
+
+	#[test]
+	fn refuses_corrupt_packs() {
+		let _lock = lock();
+		let mut pack = pack_of(&[whole(ObjectType::Blob, BASE)]);
+		assert!(read_pack(&pack[..pack.len() - 1]).is_err());
+		let last = pack.len() - 1;
+		pack[last] ^= 1;
+		assert!(matches!(read_pack(&pack), Err(CorruptError { .. })));
+		assert!(read_pack(b"PACK").is_err());
+	}
+
+	#[test]
+	fn reads_the_packs_it_writes() {
+		let _lock = lock();
+		for format in [ObjectFormat::Sha1, ObjectFormat::Sha256].iter() {
+			let repo = TempRepo::new(*format);
+			let (objects, read_back) = repo.run(|| {
+				let first = commit(&[("a.txt", "a\n")], &[])?;
+				let second = commit(&[("a.txt", "a\n"), ("b/c.txt", "c\n")], &[first])?;
+				let objects = objects_between(&[second], &[])?;
+				let mut pack = Vec::new();
+				write_pack(&mut pack, &objects)?;
+				let read_back = read_pack(&pack)?
+					.into_iter()
+					.map(|(object_type, data)| simulate_insert(&data, object_type))
+					.collect::<Result<Vec<_>, _>>()?;
+				Ok((objects, read_back))
+			});
+			// Two commits, two trees for the root and one for b, and two blobs
+			assert_eq!(objects.len(), 7);
+			assert_eq!(read_back, objects);
+		}
+	}
+}
@@ -0,0 +1,847 @@
+// Differences between trees in the formats of git diff: unified patches with
+// git's extended headers, optionally colored or word by word, diffstats and
+// lists of changed files
+use crate::tools::{
+	base85::encode_lines,
+	db::{read, DbError, ObjectRepr},
+	diff::{hunks, split_lines},
+	moved::{mark_moved, ColorMoved, Moved},
+	object_format::ObjectFormat,
+	path::RelativePathToBase,
+	tree::{FileMode, FlatEntry, FlatTree},
+	word_diff::{write_word_diff, WordDiff},
+};
+use flate2::{write::ZlibEncoder, Compression};
+use std::{
+	collections::{BTreeSet, HashMap},
+	io::{self, Write},
+};
+use thiserror::Error;
+use PatchError::{CompressError, ObjectError};
+
+#[derive(Error, Debug)]
+pub enum PatchError {
+	#[error("{source:?}")]
+	ObjectError { source: DbError },
+	#[error("{source:?}")]
+	CompressError { source: io::Error },
+}
+
+const CONTEXT_LINES: usize = 3;
+const NULL_SHORT_HASH: &str = "0000000";
+// Like git, a NUL in the first bytes marks binary content
+const BINARY_CHECK_LEN: usize = 8000;
+const NO_NEWLINE: &str = "\\ No newline at end of file";
+// Hunk headers show the function a hunk is in, cut to this many bytes
+const FUNCNAME_LEN: usize = 80;
+// Diffstats fit this many columns, like git's without a terminal
+pub const STAT_WIDTH: usize = 80;
+const BINARY_LABEL: &str = "Bin";
+
+pub const RESET: &str = "\x1b[m";
+pub const BOLD: &str = "\x1b[1m";
+pub const RED: &str = "\x1b[31m";
+pub const GREEN: &str = "\x1b[32m";
+pub const CYAN: &str = "\x1b[36m";
+const MOVED_OLD: &str = "\x1b[1;35m";
+const MOVED_OLD_ALTERNATE: &str = "\x1b[1;34m";
+const MOVED_NEW: &str = "\x1b[1;36m";
+const MOVED_NEW_ALTERNATE: &str = "\x1b[1;33m";
+
+// A path that differs between two trees, None for the side it is missing in.
+// Renamed and copied files have the old entry at the path of their source
+#[derive(Debug, Clone)]
+pub struct FileChange {
+	pub path:   RelativePathToBase,
+	pub old:    Option<FlatEntry>,
+	pub new:    Option<FlatEntry>,
+	pub source: Option<Source>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Source {
+	pub path:       RelativePathToBase,
+	// Copies keep their source, renames replace it
+	pub copy:       bool,
+	// Percentage of the content kept
+	pub similarity: usize,
+}
+
+impl FileChange {
+	// The path of the old entry
+	pub fn old_path(&self) -> &RelativePathToBase {
+		self.source.as_ref().map_or(&self.path, |s| &s.path)
+	}
+
+	// The letter of git diff --name-status
+	pub fn status(&self) -> char {
+		match (&self.source, &self.old, &self.new) {
+			(Some(source), _, _) if source.copy => 'C',
+			(Some(_), _, _) => 'R',
+			(None, None, _) => 'A',
+			(None, _, None) => 'D',
+			(None, Some(old), Some(new)) if kind(old.mode) != kind(new.mode) => 'T',
+			_ => 'M',
+		}
+	}
+}
+
+// Modes of the same kind of entry, differing at most in the executable bit
+fn kind(mode: FileMode) -> FileMode {
+	match mode {
+		FileMode::Executable => FileMode::Regular,
+		mode => mode,
+	}
+}
+
+// Contents of blobs, from the database unless they were hashed from the
+// working tree without being stored
+#[derive(Debug, Default)]
+pub struct Blobs {
+	unstored: HashMap<ObjectRepr, Vec<u8>>,
+}
+
+impl Blobs {
+	pub fn new() -> Blobs {
+		Blobs::default()
+	}
+
+	pub fn add_unstored(&mut self, hash: ObjectRepr, data: Vec<u8>) {
+		self.unstored.insert(hash, data);
+	}
+
+	pub fn is_unstored(&self, hash: &ObjectRepr) -> bool {
+		self.unstored.contains_key(hash)
+	}
+
+	// Submodules are shown by the commit they point to
+	pub fn content(&self, entry: Option<&FlatEntry>) -> Result<Vec<u8>, PatchError> {
+		match entry {
+			Some(entry) if entry.mode == FileMode::Gitlink => {
+				Ok(format!("Subproject commit {}\n", entry.hash).into_bytes())
+			},
+			Some(entry) => match self.unstored.get(&entry.hash) {
+				Some(data) => Ok(data.clone()),
+				None => Ok(read(&entry.hash)
+					.map_err(|e| ObjectError { source: e })?
+					.data),
+			},
+			None => Ok(Vec::new()),
+		}
+	}
+}
+
+// Paths whose entries differ, sorted
+pub fn tree_changes(old: &FlatTree, new: &FlatTree) -> Vec<FileChange> {
+	old.keys()
+		.chain(new.keys())
+		.collect::<BTreeSet<_>>()
+		.into_iter()
+		.filter(|path| old.get(*path) != new.get(*path))
+		.map(|path| FileChange {
+			path:   path.clone(),
+			old:    old.get(path).cloned(),
+			new:    new.get(path).cloned(),
+			source: None,
+		})
+		.collect()
+}
+
+fn is_binary(data: &[u8]) -> bool {
+	data[..data.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+fn short_hash(entry: Option<&FlatEntry>) -> &str {
+	entry.map_or(NULL_SHORT_HASH, |e| e.hash.short())
+}
+
+// A line of a patch. Patches are built as lines first and formatted once all
+// are known, as moved lines are found across files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchLine {
+	// Extended headers, from "diff --git" to "+++"
+	Header(String),
+	// "@@ -a,b +c,d @@" and the function the hunk is in
+	HunkHeader(String, Option<Vec<u8>>),
+	// Lines with their newline, the last one may lack it
+	Context(Vec<u8>),
+	Removed(Vec<u8>),
+	Added(Vec<u8>),
+	// "Binary files differ", or the lines of a binary patch
+	Binary(String),
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PatchOptions {
+	pub color:       bool,
+	pub color_moved: Option<ColorMoved>,
+	pub word_diff:   Option<WordDiff>,
+	// Binary files as patches that can be applied, instead of "Binary files
+	// differ"
+	pub binary:      bool,
+}
+
+// The line before `start` that looks like the start of a function, like git
+// by default: one starting with a letter, '_' or '$'
+fn funcname(lines: &[&[u8]], start: usize) -> Option<Vec<u8>> {
+	let line = lines[..start].iter().rev().find(|l| {
+		l.first()
+			.is_some_and(|&b| b.is_ascii_alphabetic() || b == b'_' || b == b'$')
+	})?;
+	let line = &line[..line.len().min(FUNCNAME_LEN)];
+	let end = line
+		.iter()
+		.rposition(|b| !b.is_ascii_whitespace())
+		.map_or(0, |i| i + 1);
+	Some(line[..end].to_vec())
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>, PatchError> {
+	let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+	encoder
+		.write_all(data)
+		.and_then(|_| encoder.finish())
+		.map_err(|e| CompressError { source: e })
+}
+
+// "GIT binary patch" with the new content, and the old one to reverse it.
+// Contents are given as a whole, which git calls literal, never as deltas
+fn binary_patch(old: &[u8], new: &[u8]) -> Result<Vec<PatchLine>, PatchError> {
+	let mut lines = vec![PatchLine::Binary("GIT binary patch".to_owned())];
+	for data in [new, old] {
+		lines.push(PatchLine::Binary(format!("literal {}", data.len())));
+		lines.extend(
+			encode_lines(&deflate(data)?)
+				.lines()
+				.map(|line| PatchLine::Binary(line.to_owned())),
+		);
+		lines.push(PatchLine::Binary(String::new()));
+	}
+	Ok(lines)
+}
+
+// The patch of one path, starting with "diff --git". Binary files are given
+// as binary patches if `binary` is set
+pub fn patch_lines(
+	change: &FileChange,
+	blobs: &Blobs,
+	binary: bool,
+) -> Result<Vec<PatchLine>, PatchError> {
+	let (old, new) = (change.old.as_ref(), change.new.as_ref());
+	let (old_path, path) = (change.old_path(), &change.path);
+	let changed = old.map(|e| &e.hash) != new.map(|e| &e.hash);
+	let (old_data, new_data) = if changed {
+		(blobs.content(old)?, blobs.content(new)?)
+	} else {
+		(Vec::new(), Vec::new())
+	};
+	let binary_data = is_binary(&old_data) || is_binary(&new_data);
+	// Binary patches name blobs in full, the old one is checked before applying
+	let full_hashes = binary && binary_data;
+	let hash = |entry: Option<&FlatEntry>| match entry {
+		Some(entry) if full_hashes => entry.hash.hash().to_owned(),
+		None if full_hashes => ObjectFormat::current().null_hash(),
+		entry => short_hash(entry).to_owned(),
+	};
+
+	let mut lines = vec![PatchLine::Header(format!(
+		"diff --git a/{} b/{}",
+		old_path, path
+	))];
+	let mut header = |line: String| lines.push(PatchLine::Header(line));
+	let hashes = format!("{}..{}", hash(old), hash(new));
+	match (old, new) {
+		(None, Some(new)) => {
+			header(format!("new file mode {}", new.mode.octal()));
+			header(format!("index {}", hashes));
+		},
+		(Some(old), None) => {
+			header(format!("deleted file mode {}", old.mode.octal()));
+			header(format!("index {}", hashes));
+		},
+		(Some(old), Some(new)) => {
+			if old.mode != new.mode {
+				header(format!("old mode {}", old.mode.octal()));
+				header(format!("new mode {}", new.mode.octal()));
+			}
+			if let Some(source) = &change.source {
+				let verb = if source.copy { "copy" } else { "rename" };
+				header(format!("similarity index {}%", source.similarity));
+				header(format!("{} from {}", verb, old_path));
+				header(format!("{} to {}", verb, path));
+			}
+			if old.hash != new.hash && old.mode == new.mode {
+				header(format!("index {} {}", hashes, old.mode.octal()));
+			} else if old.hash != new.hash {
+				header(format!("index {}", hashes));
+			}
+		},
+		(None, None) => {},
+	}
+	if !changed {
+		return Ok(lines);
+	}
+
+	let old_name = old.map_or_else(|| "/dev/null".to_owned(), |_| format!("a/{}", old_path));
+	let new_name = new.map_or_else(|| "/dev/null".to_owned(), |_| format!("b/{}", path));
+	if full_hashes {
+		lines.extend(binary_patch(&old_data, &new_data)?);
+		return Ok(lines);
+	}
+	if binary_data {
+		lines.push(PatchLine::Binary(format!(
+			"Binary files {} and {} differ",
+			old_name, new_name
+		)));
+		return Ok(lines);
+	}
+	let (old_lines, new_lines) = (split_lines(&old_data), split_lines(&new_data));
+	let hunks = hunks(&old_lines, &new_lines, CONTEXT_LINES);
+	// Empty files added or deleted have no lines to show
+	if !hunks.is_empty() {
+		lines.push(PatchLine::Header(format!("--- {}", old_name)));
+		lines.push(PatchLine::Header(format!("+++ {}", new_name)));
+	}
+	for hunk in hunks {
+		let range = |start: usize, len: usize| {
+			if len == 1 {
+				start.to_string()
+			} else {
+				format!("{},{}", start, len)
+			}
+		};
+		// Index of the first old line of the hunk
+		let first = if hunk.old_len == 0 {
+			hunk.old_start
+		} else {
+			hunk.old_start - 1
+		};
+		lines.push(PatchLine::HunkHeader(
+			format!(
+				"@@ -{} +{} @@",
+				range(hunk.old_start, hunk.old_len),
+				range(hunk.new_start, hunk.new_len)
+			),
+			funcname(&old_lines, first),
+		));
+		for (kind, line) in hunk.lines {
+			lines.push(match kind {
+				'-' => PatchLine::Removed(line.to_vec()),
+				'+' => PatchLine::Added(line.to_vec()),
+				_ => PatchLine::Context(line.to_vec()),
+			});
+		}
+	}
+	Ok(lines)
+}
+
+fn push_str(out: &mut Vec<u8>, s: &str) {
+	out.extend_from_slice(s.as_bytes());
+}
+
+// A line of the patch with its sign, and the marker for a missing newline
+fn write_line(out: &mut Vec<u8>, sign: &str, content: &[u8], color: Option<&str>) {
+	let text = content.strip_suffix(b"\n").unwrap_or(content);
+	match color {
+		// Added lines color their sign apart
+		Some(color) if sign == "+" && !text.is_empty() => {
+			push_str(out, &format!("{}+{}{}", color, RESET, color));
+			out.extend_from_slice(text);
+			push_str(out, RESET);
+		},
+		Some(color) => {
+			push_str(out, color);
+			push_str(out, sign);
+			out.extend_from_slice(text);
+			push_str(out, RESET);
+		},
+		None => {
+			push_str(out, sign);
+			out.extend_from_slice(text);
+		},
+	}
+	out.push(b'\n');
+	if text.len() == content.len() {
+		push_str(out, NO_NEWLINE);
+		if color.is_some() {
+			push_str(out, RESET);
+		}
+		out.push(b'\n');
+	}
+}
+
+// Lines other than removed and added ones, the same in all styles but for
+// context lines of word diffs
+fn write_other_line(out: &mut Vec<u8>, line: &PatchLine, options: &PatchOptions) {
+	let color = options.color || options.word_diff == Some(WordDiff::Color);
+	match line {
+		PatchLine::Header(header) if color => {
+			push_str(out, &format!("{}{}{}\n", BOLD, header, RESET))
+		},
+		PatchLine::Header(header) => push_str(out, &format!("{}\n", header)),
+		PatchLine::HunkHeader(range, func) => {
+			push_str(out, if color { CYAN } else { "" });
+			push_str(out, range);
+			if color {
+				push_str(out, RESET);
+			}
+			if let Some(func) = func {
+				push_str(out, if color { " \x1b[m" } else { " " });
+				out.extend_from_slice(func);
+				if color {
+					push_str(out, RESET);
+				}
+			}
+			out.push(b'\n');
+		},
+		PatchLine::Binary(message) => push_str(out, &format!("{}\n", message)),
+		PatchLine::Context(content) => match options.word_diff {
+			Some(WordDiff::Porcelain) => {
+				push_str(out, " ");
+				out.extend_from_slice(content.strip_suffix(b"\n").unwrap_or(content));
+				push_str(out, "\n~\n");
+			},
+			Some(word_diff) => {
+				out.extend_from_slice(content.strip_suffix(b"\n").unwrap_or(content));
+				if word_diff == WordDiff::Color {
+					push_str(out, RESET);
+				}
+				out.push(b'\n');
+			},
+			None => write_line(out, " ", content, color.then_some("")),
+		},
+		PatchLine::Removed(_) | PatchLine::Added(_) => {},
+	}
+}
+
+// Runs of removed and added lines are shown together, word by word
+fn write_word_diff_patch(
+	out: &mut Vec<u8>,
+	lines: &[PatchLine],
+	options: &PatchOptions,
+	mode: WordDiff,
+) {
+	let (mut old, mut new) = (Vec::new(), Vec::new());
+	for line in lines {
+		match line {
+			PatchLine::Removed(content) | PatchLine::Added(content) => {
+				let text = if matches!(line, PatchLine::Removed(_)) {
+					&mut old
+				} else {
+					&mut new
+				};
+				text.extend_from_slice(content);
+				if !content.ends_with(b"\n") {
+					text.push(b'\n');
+				}
+				continue;
+			},
+			_ => {},
+		}
+		if !old.is_empty() || !new.is_empty() {
+			write_word_diff(out, &old, &new, mode);
+			old.clear();
+			new.clear();
+		}
+		write_other_line(out, line, options);
+	}
+	if !old.is_empty() || !new.is_empty() {
+		write_word_diff(out, &old, &new, mode);
+	}
+}
+
+// Patches of all changes, one after the other
+pub fn write_patches(
+	changes: &[FileChange],
+	blobs: &Blobs,
+	options: &PatchOptions,
+) -> Result<Vec<u8>, PatchError> {
+	let mut lines = Vec::new();
+	for change in changes {
+		lines.extend(patch_lines(change, blobs, options.binary)?);
+	}
+
+	let mut out = Vec::new();
+	if let Some(mode) = options.word_diff {
+		write_word_diff_patch(&mut out, &lines, options, mode);
+		return Ok(out);
+	}
+	let moved = match options.color_moved {
+		Some(mode) => mark_moved(&lines, mode),
+		None => vec![Moved::No; lines.len()],
+	};
+	let color = |normal, moved_color, alternate, moved| match (options.color, moved) {
+		(false, _) => None,
+		(true, Moved::No) => Some(normal),
+		(true, Moved::Moved) => Some(moved_color),
+		(true, Moved::Alternate) => Some(alternate),
+	};
+	for (line, moved) in lines.iter().zip(moved) {
+		match line {
+			PatchLine::Removed(content) => write_line(
+				&mut out,
+				"-",
+				content,
+				color(RED, MOVED_OLD, MOVED_OLD_ALTERNATE, moved),
+			),
+			PatchLine::Added(content) => write_line(
+				&mut out,
+				"+",
+				content,
+				color(GREEN, MOVED_NEW, MOVED_NEW_ALTERNATE, moved),
+			),
+			line => write_other_line(&mut out, line, options),
+		}
+	}
+	Ok(out)
+}
+
+// Added and removed lines of a file, or the sizes of binary files before and
+// after
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counts {
+	Lines(usize, usize),
+	Binary(usize, usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct FileStat {
+	pub name:   String,
+	pub counts: Counts,
+}
+
+fn counts(change: &FileChange, blobs: &Blobs) -> Result<Counts, PatchError> {
+	let (old_data, new_data) = (
+		blobs.content(change.old.as_ref())?,
+		blobs.content(change.new.as_ref())?,
+	);
+	if is_binary(&old_data) || is_binary(&new_data) {
+		return Ok(Counts::Binary(old_data.len(), new_data.len()));
+	}
+	let (old_lines, new_lines) = (split_lines(&old_data), split_lines(&new_data));
+	let (mut added, mut removed) = (0, 0);
+	for (kind, _) in hunks(&old_lines, &new_lines, 0)
+		.iter()
+		.flat_map(|hunk| hunk.lines.iter())
+	{
+		match kind {
+			'+' => added += 1,
+			'-' => removed += 1,
+			_ => {},
+		}
+	}
+	Ok(Counts::Lines(added, removed))
+}
+
+pub fn file_stats(changes: &[FileChange], blobs: &Blobs) -> Result<Vec<FileStat>, PatchError> {
+	changes
+		.iter()
+		.map(|change| {
+			Ok(FileStat {
+				name:   display_name(change),
+				counts: counts(change, blobs)?,
+			})
+		})
+		.collect()
+}
+
+fn plural(n: usize, singular: &str, plural: &str) -> String {
+	format!("{} {}", n, if n == 1 { singular } else { plural })
+}
+
+// "old => new" for renames and copies, with the directories both share
+// outside of braces: "dir/{old => new}/file"
+pub fn display_name(change: &FileChange) -> String {
+	let (old, new) = (change.old_path().to_string(), change.path.to_string());
+	if change.source.is_none() {
+		return new;
+	}
+	// Common parts end and start at a slash
+	let prefix = old
+		.char_indices()
+		.zip(new.chars())
+		.take_while(|((_, a), b)| a == b)
+		.filter(|((_, a), _)| *a == '/')
+		.map(|((i, _), _)| i + 1)
+		.last()
+		.unwrap_or(0);
+	// The slash ending the prefix may also start the suffix
+	let max_suffix = old.len().min(new.len()) - prefix.saturating_sub(1);
+	let suffix = old
+		.bytes()
+		.rev()
+		.zip(new.bytes().rev())
+		.take(max_suffix)
+		.take_while(|(a, b)| a == b)
+		.enumerate()
+		.filter(|(_, (a, _))| *a == b'/')
+		.map(|(i, _)| i + 1)
+		.last()
+		.unwrap_or(0);
+	if prefix == 0 && suffix == 0 {
+		return format!("{} => {}", old, new);
+	}
+	format!(
+		"{}{{{} => {}}}{}",
+		&new[..prefix],
+		&old[prefix..(old.len() - suffix).max(prefix)],
+		&new[prefix..(new.len() - suffix).max(prefix)],
+		&new[new.len() - suffix..]
+	)
+}
+
+// The status letter and the paths, renames and copies with their score and
+// both paths
+fn status_and_paths(change: &FileChange) -> String {
+	match &change.source {
+		Some(source) => format!(
+			"{}{:03}\t{}\t{}",
+			change.status(),
+			source.similarity,
+			source.path,
+			change.path
+		),
+		None => format!("{}\t{}", change.status(), change.path),
+	}
+}
+
+// "<status>\t<path>" for each change, like git diff --name-status
+pub fn name_status(changes: &[FileChange]) -> String {
+	changes
+		.iter()
+		.map(|change| format!("{}\n", status_and_paths(change)))
+		.collect()
+}
+
+// The path of each change, the new one of renames and copies
+pub fn name_only(changes: &[FileChange]) -> String {
+	changes
+		.iter()
+		.map(|change| format!("{}\n", change.path))
+		.collect()
+}
+
+// Files created, deleted, renamed, copied or whose mode changed, like git
+// diff --summary
+pub fn summary(changes: &[FileChange]) -> String {
+	let mut out = String::new();
+	for change in changes {
+		let (old, new) = (change.old.as_ref(), change.new.as_ref());
+		if let Some(source) = &change.source {
+			let verb = if source.copy { "copy" } else { "rename" };
+			out.push_str(&format!(
+				" {} {} ({}%)\n",
+				verb,
+				display_name(change),
+				source.similarity
+			));
+		}
+		match (old, new) {
+			(None, Some(new)) => out.push_str(&format!(
+				" create mode {} {}\n",
+				new.mode.octal(),
+				change.path
+			)),
+			(Some(old), None) => out.push_str(&format!(
+				" delete mode {} {}\n",
+				old.mode.octal(),
+				change.path
+			)),
+			// Renames and copies already named the file
+			(Some(old), Some(new)) if old.mode != new.mode => {
+				out.push_str(&format!(
+					" mode change {} => {}",
+					old.mode.octal(),
+					new.mode.octal()
+				));
+				if change.source.is_none() {
+					out.push_str(&format!(" {}", change.path));
+				}
+				out.push('\n');
+			},
+			_ => {},
+		}
+	}
+	out
+}
+
+// ":<old mode> <new mode> <old hash> <new hash> <status>\t<path>" for each
+// change, like git diff --raw. Contents of the working tree are not hashed
+// by git and show as zeros
+pub fn raw(changes: &[FileChange], blobs: &Blobs) -> String {
+	let mode = |entry: Option<&FlatEntry>| entry.map_or("000000", |e| e.mode.octal());
+	let hash = |entry: Option<&FlatEntry>| match entry {
+		Some(entry) if blobs.is_unstored(&entry.hash) => NULL_SHORT_HASH.to_owned(),
+		entry => short_hash(entry).to_owned(),
+	};
+	changes
+		.iter()
+		.map(|change| {
+			let (old, new) = (change.old.as_ref(), change.new.as_ref());
+			format!(
+				":{:0>6} {:0>6} {} {} {}\n",
+				mode(old),
+				mode(new),
+				hash(old),
+				hash(new),
+				status_and_paths(change)
+			)
+		})
+		.collect()
+}
+
+// "<added>\t<removed>\t<path>" for each change, "-" for binary files
+pub fn numstat(stats: &[FileStat]) -> String {
+	stats
+		.iter()
+		.map(|stat| match stat.counts {
+			Counts::Lines(added, removed) => format!("{}\t{}\t{}\n", added, removed, stat.name),
+			Counts::Binary(..) => format!("-\t-\t{}\n", stat.name),
+		})
+		.collect()
+}
+
+// " <n> files changed, <n> insertions(+), <n> deletions(-)", binary files
+// count as changed without lines
+pub fn shortstat(stats: &[FileStat]) -> String {
+	let (mut insertions, mut deletions) = (0, 0);
+	for stat in stats {
+		if let Counts::Lines(added, removed) = stat.counts {
+			insertions += added;
+			deletions += removed;
+		}
+	}
+	let mut summary = format!(" {} changed", plural(stats.len(), "file", "files"));
+	if insertions > 0 || deletions == 0 {
+		summary.push_str(&format!(
+			", {}(+)",
+			plural(insertions, "insertion", "insertions")
+		));
+	}
+	if deletions > 0 || insertions == 0 {
+		summary.push_str(&format!(
+			", {}(-)",
+			plural(deletions, "deletion", "deletions")
+		));
+	}
+	summary.push('\n');
+	summary
+}
+
+// Bar length of `n` changed lines, when the bars are scaled down
+fn scale(n: usize, width: usize, max_change: usize) -> usize {
+	if n == 0 {
+		0
+	} else {
+		1 + n * (width - 1) / max_change
+	}
+}
+
+fn colored(text: &str, color: &str, enabled: bool) -> String {
+	if enabled && !text.is_empty() {
+		format!("{}{}{}", color, text, RESET)
+	} else {
+		text.to_owned()
+	}
+}
+
+// " <path> | <n> ++--" for each change and the summary line, like git diff
+// --stat. Names and bars share the width as git shares it
+pub fn diffstat(stats: &[FileStat], width: usize, color: bool) -> String {
+	let name_len = |name: &str| name.chars().count();
+	let max_len = stats.iter().map(|s| name_len(&s.name)).max().unwrap_or(0);
+	let max_change = stats
+		.iter()
+		.filter_map(|s| match s.counts {
+			Counts::Lines(added, removed) => Some(added + removed),
+			Counts::Binary(..) => None,
+		})
+		.max()
+		.unwrap_or(0);
+	// "Bin <old> -> <new> bytes"
+	let bin_width = stats
+		.iter()
+		.filter_map(|s| match s.counts {
+			Counts::Binary(old, new) => Some(14 + old.to_string().len() + new.to_string().len()),
+			Counts::Lines(..) => None,
+		})
+		.max()
+		.unwrap_or(0);
+	let has_binary = stats.iter().any(|s| matches!(s.counts, Counts::Binary(..)));
+	let number_width =
+		max_change
+			.to_string()
+			.len()
+			.max(if has_binary { BINARY_LABEL.len() } else { 0 });
+
+	let width = width.max(16 + 6 + number_width);
+	let mut graph_width = if max_change + 4 > bin_width {
+		max_change
+	} else {
+		bin_width - 4
+	};
+	let mut name_width = max_len;
+	if name_width + number_width + 6 + graph_width > width {
+		if graph_width > (width * 3 / 8).saturating_sub(number_width + 6) {
+			graph_width = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+		}
+		if name_width > width - number_width - 6 - graph_width {
+			name_width = width - number_width - 6 - graph_width;
+		} else {
+			graph_width = width - number_width - 6 - name_width;
+		}
+	}
+
+	let mut out = String::new();
+	for stat in stats {
+		// Long names keep their end, from a slash on if there is one
+		let mut name = stat.name.as_str();
+		let mut prefix = "";
+		let mut len = name_width;
+		if name_len(name) > name_width {
+			prefix = "...";
+			len = name_width.saturating_sub(3);
+			let skip = name_len(name) - len;
+			name = &name[name.char_indices().nth(skip).map_or(name.len(), |(i, _)| i)..];
+			if let Some(slash) = name.find('/') {
+				name = &name[slash..];
+			}
+		}
+		let padding = len.saturating_sub(name_len(name));
+		out.push_str(&format!(" {}{}{} | ", prefix, name, " ".repeat(padding)));
+		match stat.counts {
+			Counts::Binary(0, 0) => out.push_str(&format!("{:>number_width$}\n", BINARY_LABEL)),
+			Counts::Binary(old, new) => out.push_str(&format!(
+				"{:>number_width$} {} -> {} bytes\n",
+				BINARY_LABEL,
+				colored(&old.to_string(), RED, color),
+				colored(&new.to_string(), GREEN, color)
+			)),
+			Counts::Lines(added, removed) => {
+				let (mut add, mut del) = (added, removed);
+				if graph_width <= max_change {
+					let mut total = scale(add + del, graph_width, max_change);
+					if total < 2 && add > 0 && del > 0 {
+						total = 2;
+					}
+					if add < del {
+						add = scale(add, graph_width, max_change);
+						del = total - add;
+					} else {
+						del = scale(del, graph_width, max_change);
+						add = total - del;
+					}
+				}
+				out.push_str(&format!(
+					"{:>number_width$}{}{}{}\n",
+					added + removed,
+					if added + removed > 0 { " " } else { "" },
+					colored(&"+".repeat(add), GREEN, color),
+					colored(&"-".repeat(del), RED, color)
+				));
+			},
+		}
+	}
+	out.push_str(&shortstat(stats));
+	out
+}
@@ -5,9 +5,11 @@ use std::{
 	cmp::Eq,
 	convert::TryFrom,
 	env::current_dir,
+	fmt::{self, Display, Formatter},
+	fs,
 	hash::Hash,
 	io,
-	path::{Path, PathBuf},
+	path::{Component, Path, PathBuf},
 };
 use thiserror::Error;
 use PathError::{IllegalPathError, NoRepoError, NotInRepoError};
@@ -26,6 +28,16 @@ const VCS_DIR: &str = ".unrusty";
 const INDEX_FILE: &str = "index";
 const OBJECTS_DIR: &str = "objects";
 
+// Names that may not appear in paths of the working tree, as what is written
+// there would end up in a VCS directory. Like git, case and the trailing dots
+// and spaces Windows drops are ignored
+pub fn is_reserved_name(name: &str) -> bool {
+	let name = name.trim_end_matches(['.', ' ']);
+	[VCS_DIR, ".git"]
+		.iter()
+		.any(|r| name.eq_ignore_ascii_case(r))
+}
+
 pub fn vcs_dir_from_base(path: &Path) -> PathBuf {
 	path.join(Path::new(VCS_DIR))
 }
@@ -41,7 +53,7 @@ pub fn root_path() -> Result<Option<PathBuf>, PathError> {
 		.map_err(|e| IllegalPathError { source: e })?;
 
 	for parent in path.ancestors() {
-		if is_repo_root_dir(&parent) {
+		if is_repo_root_dir(parent) {
 			return Ok(Some(parent.to_path_buf()));
 		}
 	}
@@ -56,8 +68,14 @@ pub fn root_path_required() -> Result<PathBuf, PathError> {
 }
 
 pub fn index_path_required() -> Result<PathBuf, PathError> {
+	vcs_path_required(INDEX_FILE)
+}
+
+// Path of a file or directory inside the VCS directory, e.g. HEAD or
+// refs/heads/master
+pub fn vcs_path_required(name: &str) -> Result<PathBuf, PathError> {
 	let mut root = root_path_required()?;
-	root.push(PathBuf::from(&format!("{}/{}", VCS_DIR, INDEX_FILE,)));
+	root.push(PathBuf::from(&format!("{}/{}", VCS_DIR, name)));
 	Ok(root)
 }
 
@@ -74,22 +92,120 @@ fn relative_path(path: &Path) -> Result<PathBuf, PathError> {
 
 pub fn db_path(object: &ObjectRepr) -> Result<PathBuf, PathError> {
 	let hash = object.hash();
-	assert!(hash.len() == 40);
+	assert!(hash.len() == 40 || hash.len() == 64);
 	let rel_path = PathBuf::from(format!("{}/{}/{}", VCS_DIR, OBJECTS_DIR, &hash));
 	let mut base = root_path_required()?;
 	base.push(rel_path);
 	Ok(base)
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub fn objects_dir_required() -> Result<PathBuf, PathError> {
+	vcs_path_required(OBJECTS_DIR)
+}
+
+// Files below a directory as slash separated paths relative to it, sorted.
+// A missing directory has no files
+pub fn list_files_below(dir: &Path) -> io::Result<Vec<String>> {
+	let mut files = Vec::new();
+	list_files_below_helper(dir, "", &mut files)?;
+	files.sort();
+	Ok(files)
+}
+
+fn list_files_below_helper(dir: &Path, prefix: &str, files: &mut Vec<String>) -> io::Result<()> {
+	let entries = match fs::read_dir(dir) {
+		Ok(entries) => entries,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+		Err(e) => return Err(e),
+	};
+	for entry in entries {
+		let entry = entry?;
+		let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+		if entry.file_type()?.is_dir() {
+			list_files_below_helper(&entry.path(), &format!("{}/", name), files)?;
+		} else {
+			files.push(name);
+		}
+	}
+	Ok(())
+}
+
+// Resolves `.` and `..` without touching the file system, so that paths of
+// deleted files can still be located in the repo
+fn normalize(path: &Path) -> PathBuf {
+	let mut normalized = PathBuf::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => {},
+			Component::ParentDir => {
+				normalized.pop();
+			},
+			c => normalized.push(c.as_os_str()),
+		}
+	}
+	normalized
+}
+
+fn relative_path_lexical(path: &Path) -> Result<PathBuf, PathError> {
+	let cwd = current_dir()
+		.and_then(|p| p.canonicalize())
+		.map_err(|e| IllegalPathError { source: e })?;
+	let path = normalize(&cwd.join(path));
+	let base = root_path_required()?;
+	match path.strip_prefix(&base) {
+		Ok(p) => Ok(p.to_path_buf()),
+		Err(_) => Err(NotInRepoError { base, file: path }),
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
 pub struct RelativePathToBase {
 	path: PathBuf,
 }
 
+impl RelativePathToBase {
+	// For paths that are already relative to the base, e.g. from a tree object
+	pub fn new(path: PathBuf) -> RelativePathToBase {
+		RelativePathToBase { path }
+	}
+
+	// Like try_from, but does not require the file to exist
+	pub fn from_user_path(path: &Path) -> Result<RelativePathToBase, PathError> {
+		relative_path_lexical(path).map(|p| RelativePathToBase { path: p })
+	}
+
+	pub fn as_path(&self) -> &Path {
+		&self.path
+	}
+
+	pub fn absolute(&self) -> Result<PathBuf, PathError> {
+		Ok(root_path_required()?.join(&self.path))
+	}
+
+	// Whether the path stays below the base and outside of VCS directories
+	pub fn is_safe(&self) -> bool {
+		self.path.components().all(|c| match c {
+			Component::Normal(name) => !is_reserved_name(&name.to_string_lossy()),
+			_ => false,
+		})
+	}
+
+	// The empty path is the base itself and contains everything
+	pub fn contains(&self, other: &RelativePathToBase) -> bool {
+		other.path.starts_with(&self.path)
+	}
+}
+
 impl TryFrom<&Path> for RelativePathToBase {
 	type Error = PathError;
 
 	fn try_from(path: &Path) -> Result<Self, Self::Error> {
-		relative_path(&path).map(|p| RelativePathToBase { path: p })
+		relative_path(path).map(|p| RelativePathToBase { path: p })
+	}
+}
+
+impl Display for RelativePathToBase {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "{}", self.path.display())
 	}
 }
@@ -0,0 +1,224 @@
+// Framing of the git wire protocol. Each packet starts with its length,
+// header included, as four hex digits. Lengths 0 to 2 are special packets
+use std::io::{self, Read, Write};
+use thiserror::Error;
+use PktLineError::{InvalidLengthError, ReadError, WriteError};
+
+#[derive(Error, Debug)]
+pub enum PktLineError {
+	#[error("{source:?}")]
+	ReadError { source: io::Error },
+	#[error("{source:?}")]
+	WriteError { source: io::Error },
+	#[error("Invalid packet length {length:?}")]
+	InvalidLengthError { length: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+	Data(Vec<u8>),
+	// Ends a message
+	Flush,
+	// Separates the sections of a message
+	Delim,
+	// Ends a response in stateless connections
+	ResponseEnd,
+}
+
+// How the server introduces itself to clients
+pub const AGENT: &str = concat!("unrusty/", env!("CARGO_PKG_VERSION"));
+
+const HEADER_LEN: usize = 4;
+pub const MAX_PACKET_LEN: usize = 65520;
+pub const MAX_DATA_LEN: usize = MAX_PACKET_LEN - HEADER_LEN;
+
+// Side-band channels of packfile data
+pub const BAND_DATA: u8 = 1;
+pub const BAND_PROGRESS: u8 = 2;
+
+impl Packet {
+	// The payload of a data packet, without the trailing newline
+	pub fn text(&self) -> Option<&str> {
+		match self {
+			Packet::Data(data) => std::str::from_utf8(data)
+				.ok()
+				.map(|s| s.strip_suffix('\n').unwrap_or(s)),
+			_ => None,
+		}
+	}
+}
+
+// The next packet, or None at the end of the input
+pub fn read_packet(reader: &mut dyn Read) -> Result<Option<Packet>, PktLineError> {
+	let mut header = [0; HEADER_LEN];
+	match reader.read_exact(&mut header) {
+		Ok(()) => {},
+		Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+		Err(e) => return Err(ReadError { source: e }),
+	}
+	let length_error = || InvalidLengthError {
+		length: String::from_utf8_lossy(&header).into_owned(),
+	};
+	let length = std::str::from_utf8(&header)
+		.ok()
+		.and_then(|h| usize::from_str_radix(h, 16).ok())
+		.ok_or_else(length_error)?;
+	let packet = match length {
+		0 => Packet::Flush,
+		1 => Packet::Delim,
+		2 => Packet::ResponseEnd,
+		length if length <= HEADER_LEN || length > MAX_PACKET_LEN => return Err(length_error()),
+		length => {
+			let mut data = vec![0; length - HEADER_LEN];
+			reader
+				.read_exact(&mut data)
+				.map_err(|e| ReadError { source: e })?;
+			Packet::Data(data)
+		},
+	};
+	Ok(Some(packet))
+}
+
+// Packets up to the next flush packet, the flush excluded
+pub fn read_until_flush(reader: &mut dyn Read) -> Result<Vec<Packet>, PktLineError> {
+	let mut packets = Vec::new();
+	while let Some(packet) = read_packet(reader)? {
+		if packet == Packet::Flush {
+			break;
+		}
+		packets.push(packet);
+	}
+	Ok(packets)
+}
+
+pub fn write_packet(writer: &mut dyn Write, packet: &Packet) -> Result<(), PktLineError> {
+	let result = match packet {
+		Packet::Flush => writer.write_all(b"0000"),
+		Packet::Delim => writer.write_all(b"0001"),
+		Packet::ResponseEnd => writer.write_all(b"0002"),
+		Packet::Data(data) => {
+			assert!(data.len() <= MAX_DATA_LEN);
+			writer
+				.write_all(format!("{:04x}", data.len() + HEADER_LEN).as_bytes())
+				.and_then(|_| writer.write_all(data))
+		},
+	};
+	result.map_err(|e| WriteError { source: e })
+}
+
+// Writes a line of text as a data packet
+pub fn write_line(writer: &mut dyn Write, line: &str) -> Result<(), PktLineError> {
+	write_packet(writer, &Packet::Data(format!("{}\n", line).into_bytes()))
+}
+
+// Writes data on a side-band channel, split into as many packets as needed
+pub fn write_band(writer: &mut dyn Write, band: u8, data: &[u8]) -> Result<(), PktLineError> {
+	for chunk in data.chunks(MAX_DATA_LEN - 1) {
+		let mut packet = vec![band];
+		packet.extend_from_slice(chunk);
+		write_packet(writer, &Packet::Data(packet))?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn read_all(mut input: &[u8]) -> Vec<Packet> {
+		let mut packets = Vec::new();
+		while let Some(packet) = read_packet(&mut input).unwrap() {
+			packets.push(packet);
+		}
+		packets
+	}
+
+	#[test]
+	fn writes_lengths_including_the_header() {
+		let mut output = Vec::new();
+		write_line(&mut output, "version 2").unwrap();
+		write_packet(&mut output, &Packet::Delim).unwrap();
+		write_packet(&mut output, &Packet::ResponseEnd).unwrap();
+		write_packet(&mut output, &Packet::Flush).unwrap();
+		assert_eq!(output, b"000eversion 2\n000100020000");
+	}
+
+	#[test]
+	fn reads_what_it_writes() {
+		let packets = vec![
+			Packet::Data(b"command=fetch\n".to_vec()),
+			Packet::Delim,
+			Packet::Data(vec![0, 1, 2, 255]),
+			Packet::ResponseEnd,
+			Packet::Flush,
+		];
+		let mut output = Vec::new();
+		for packet in &packets {
+			write_packet(&mut output, packet).unwrap();
+		}
+		assert_eq!(read_all(&output), packets);
+		assert_eq!(packets[0].text(), Some("command=fetch"));
+		assert_eq!(packets[1].text(), None);
+	}
+
+	#[test]
+	fn stops_reading_at_flush() {
+		let mut input: &[u8] = b"0008one\n0008two\n00000009three\n";
+		let packets = read_until_flush(&mut input).unwrap();
+		assert_eq!(
+			packets,
+			vec![
+				Packet::Data(b"one\n".to_vec()),
+				Packet::Data(b"two\n".to_vec())
+			]
+		);
+		assert_eq!(input, b"0009three\n");
+	}
+
+	#[test]
+	fn ends_at_the_end_of_the_input() {
+		assert_eq!(read_all(b""), vec![]);
+		assert!(read_until_flush(&mut &b""[..]).unwrap().is_empty());
+	}
+
+	#[test]
+	fn refuses_invalid_lengths() {
+		for input in [&b"0003"[..], b"0004", b"zzzz", b"fff1"].iter() {
+			let result = read_packet(&mut &input[..]);
+			assert!(
+				matches!(result, Err(InvalidLengthError { .. })),
+				"{:?}",
+				input
+			);
+		}
+	}
+
+	#[test]
+	fn refuses_truncated_packets() {
+		assert!(matches!(
+			read_packet(&mut &b"000ashort"[..]),
+			Err(ReadError { .. })
+		));
+	}
+
+	#[test]
+	fn splits_bands_into_packets() {
+		let data = vec![7; MAX_DATA_LEN * 2];
+		let mut output = Vec::new();
+		write_band(&mut output, BAND_DATA, &data).unwrap();
+		let packets = read_all(&output);
+		assert_eq!(packets.len(), 3);
+		let mut received = Vec::new();
+		for packet in packets {
+			match packet {
+				Packet::Data(packet) => {
+					assert!(packet.len() <= MAX_DATA_LEN);
+					assert_eq!(packet[0], BAND_DATA);
+					received.extend_from_slice(&packet[1..]);
+				},
+				packet => panic!("unexpected {:?}", packet),
+			}
+		}
+		assert_eq!(received, data);
+	}
+}
@@ -0,0 +1,199 @@
+use crate::tools::{
+	commit::Commit,
+	db::{read, DbError, Object, ObjectRepr, ObjectType},
+	index::Index,
+	path::{list_files_below, vcs_path_required},
+	reflog::{list_reflogs, read_reflog, ReflogError},
+	refs::{
+		list_refs, read_ref, RefError, CHERRY_PICK_HEAD, FETCH_HEAD, HEAD, MERGE_HEAD, ORIG_HEAD,
+		REVERT_HEAD,
+	},
+	tag::Tag,
+	tree::{FileMode, Tree},
+};
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	convert::TryFrom,
+	error::Error,
+	fs, io,
+};
+use thiserror::Error;
+use ReachableError::{
+	IndexReadError, NoRepoError, ObjectReadError, RefReadError, ReflogReadError, StateReadError,
+};
+
+#[derive(Error, Debug)]
+pub enum ReachableError {
+	#[error("No unrusty root")]
+	NoRepoError,
+	#[error("{source:?}")]
+	RefReadError { source: RefError },
+	#[error("{source:?}")]
+	ReflogReadError { source: ReflogError },
+	#[error("{source:?}")]
+	IndexReadError { source: Box<dyn Error> },
+	#[error("{source:?}")]
+	ObjectReadError { source: DbError },
+	#[error("{source:?}")]
+	StateReadError { source: io::Error },
+}
+
+// An object reference, with the type the referencing side expects if known
+pub type Reference = (Option<ObjectType>, ObjectRepr);
+
+// Objects reachable from the roots. Objects that are referenced but absent
+// from the database are missing, corrupt objects are reachable but their
+// references can not be followed
+#[derive(Debug, Default)]
+pub struct Reachability {
+	pub reachable: BTreeSet<ObjectRepr>,
+	pub missing:   BTreeMap<ObjectRepr, Option<ObjectType>>,
+}
+
+// Pseudo refs that keep objects alive besides the refs below refs/
+const PSEUDO_REFS: [&str; 6] = [
+	HEAD,
+	ORIG_HEAD,
+	MERGE_HEAD,
+	CHERRY_PICK_HEAD,
+	REVERT_HEAD,
+	FETCH_HEAD,
+];
+
+// State of the commands in progress: the sequencer of cherry-pick and revert,
+// rebase and am. FETCH_HEAD lists more than the ref it stands for
+const STATE_PATHS: [&str; 4] = ["sequencer", "rebase-merge", "rebase-apply", FETCH_HEAD];
+
+// The objects whose full hash appears in the files of the state. Hashes of
+// objects that are not in the database, e.g. in mails, are skipped
+fn state_objects() -> Result<Vec<ObjectRepr>, ReachableError> {
+	let mut objects = Vec::new();
+	for name in STATE_PATHS.iter() {
+		let path = vcs_path_required(name).map_err(|_| NoRepoError)?;
+		let files = if path.is_dir() {
+			list_files_below(&path)
+				.map_err(|e| StateReadError { source: e })?
+				.into_iter()
+				.map(|file| path.join(file))
+				.collect()
+		} else {
+			vec![path]
+		};
+		for file in files {
+			let content = match fs::read(&file) {
+				Ok(content) => content,
+				Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+				Err(e) => return Err(StateReadError { source: e }),
+			};
+			for word in String::from_utf8_lossy(&content).split(|c: char| !c.is_ascii_hexdigit()) {
+				if let Ok(object_repr) = ObjectRepr::try_from(word) {
+					if object_repr.path().map_err(|_| NoRepoError)?.is_file() {
+						objects.push(object_repr);
+					}
+				}
+			}
+		}
+	}
+	Ok(objects)
+}
+
+// Everything that keeps objects alive: refs, pseudo refs, reflogs, the
+// commits named by commands in progress, e.g. those left to rebase, and the
+// index. Submodule commits in the index live in another repository
+pub fn roots() -> Result<Vec<Reference>, ReachableError> {
+	let mut roots = Vec::new();
+	for name in PSEUDO_REFS.iter() {
+		if let Some(object_repr) = read_ref(name).map_err(|e| RefReadError { source: e })? {
+			roots.push((None, object_repr));
+		}
+	}
+	let refs = list_refs().map_err(|e| RefReadError { source: e })?;
+	roots.extend(refs.into_iter().map(|(_, object_repr)| (None, object_repr)));
+	// Previous values of refs, until the log entries expire
+	for name in list_reflogs().map_err(|e| ReflogReadError { source: e })? {
+		for entry in read_reflog(&name).map_err(|e| ReflogReadError { source: e })? {
+			roots.extend(entry.old.into_iter().chain(entry.new).map(|r| (None, r)));
+		}
+	}
+	roots.extend(state_objects()?.into_iter().map(|r| (None, r)));
+
+	let index = Index::read().map_err(|e| IndexReadError { source: e })?;
+	for (_, entry) in index.entries() {
+		if entry.mode() != FileMode::Gitlink {
+			roots.push((Some(ObjectType::Blob), entry.hash().clone()));
+		}
+	}
+	Ok(roots)
+}
+
+// The objects an object points to. Objects that do not parse point nowhere
+pub fn references(object: &Object) -> Vec<Reference> {
+	match object.object_type {
+		ObjectType::Tree => Tree::parse_structure(&object.data)
+			.map(|tree| {
+				tree.entries
+					.into_iter()
+					.filter(|e| e.mode != FileMode::Gitlink)
+					.map(|e| (Some(e.mode.object_type()), e.hash))
+					.collect()
+			})
+			.unwrap_or_default(),
+		ObjectType::Commit => Commit::parse(&object.data)
+			.map(|commit| {
+				let mut references = vec![(Some(ObjectType::Tree), commit.tree)];
+				references.extend(
+					commit
+						.parents
+						.into_iter()
+						.map(|p| (Some(ObjectType::Commit), p)),
+				);
+				references
+			})
+			.unwrap_or_default(),
+		ObjectType::Tag => Tag::parse(&object.data)
+			.map(|tag| vec![(Some(tag.object_type), tag.object)])
+			.unwrap_or_default(),
+		_ => Vec::new(),
+	}
+}
+
+pub fn reachable_from(roots: Vec<Reference>) -> Result<Reachability, ReachableError> {
+	let mut reachability = Reachability::default();
+	let mut pending = roots;
+	while let Some((object_type, object_repr)) = pending.pop() {
+		if reachability.reachable.contains(&object_repr)
+			|| reachability.missing.contains_key(&object_repr)
+		{
+			continue;
+		}
+		if !object_repr.path().map_err(|_| NoRepoError)?.is_file() {
+			reachability.missing.insert(object_repr, object_type);
+			continue;
+		}
+		if let Ok(object) = read(&object_repr) {
+			pending.extend(references(&object));
+		}
+		reachability.reachable.insert(object_repr);
+	}
+	Ok(reachability)
+}
+
+// Objects reachable from `wants` that are not reachable from `haves`, as
+// needed by a repository that has `haves`. All of them must be present
+pub fn objects_between(
+	wants: &[ObjectRepr],
+	haves: &[ObjectRepr],
+) -> Result<Vec<ObjectRepr>, ReachableError> {
+	let roots = haves.iter().map(|h| (None, h.clone())).collect();
+	let excluded = reachable_from(roots)?.reachable;
+	let mut seen = BTreeSet::new();
+	let mut pending = wants.to_vec();
+	while let Some(object_repr) = pending.pop() {
+		if excluded.contains(&object_repr) || !seen.insert(object_repr.clone()) {
+			continue;
+		}
+		let object = read(&object_repr).map_err(|e| ObjectReadError { source: e })?;
+		pending.extend(references(&object).into_iter().map(|(_, r)| r));
+	}
+	Ok(seen.into_iter().collect())
+}
@@ -0,0 +1,206 @@
+use crate::tools::{
+	commit::{reflog_signature_now, CommitError},
+	date::signature_time,
+	db::ObjectRepr,
+	object_format::ObjectFormat,
+	path::{list_files_below, vcs_path_required, PathError},
+	refs::{HEAD, STASH},
+};
+use log::warn;
+use std::{
+	convert::TryFrom,
+	fmt::{self, Display, Formatter},
+	fs::{self, OpenOptions},
+	io::{self, ErrorKind, Write},
+	path::PathBuf,
+	time::SystemTime,
+};
+use thiserror::Error;
+use ReflogError::{IdentityError, MalformedError, NoRepoError, ReadError, WriteError};
+
+#[derive(Error, Debug)]
+pub enum ReflogError {
+	#[error("{source:?}")]
+	NoRepoError { source: PathError },
+	#[error("{source:?}")]
+	ReadError { source: io::Error },
+	#[error("{source:?}")]
+	WriteError { source: io::Error },
+	#[error("Malformed reflog of {name}")]
+	MalformedError { name: String },
+	#[error("{source:?}")]
+	IdentityError { source: CommitError },
+}
+
+const LOGS_DIR: &str = "logs";
+// Refs whose updates are logged even without an existing log, like git with
+// core.logAllRefUpdates
+const LOGGED_PREFIXES: [&str; 2] = ["refs/heads/", "refs/remotes/"];
+
+// One update of a ref. Line format:
+// "<old hash> <new hash> <name> <<email>> <timestamp> <timezone>\t<message>"
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+	pub old:       Option<ObjectRepr>,
+	pub new:       Option<ObjectRepr>,
+	pub signature: String,
+	pub message:   String,
+}
+
+fn log_path(name: &str) -> Result<PathBuf, ReflogError> {
+	vcs_path_required(&format!("{}/{}", LOGS_DIR, name)).map_err(|e| NoRepoError { source: e })
+}
+
+fn format_hash(object_repr: &Option<ObjectRepr>) -> String {
+	object_repr
+		.as_ref()
+		.map_or_else(|| ObjectFormat::current().null_hash(), |r| r.to_string())
+}
+
+fn parse_hash(hash: &str) -> Result<Option<ObjectRepr>, ()> {
+	// The null hash stands for a ref that did not exist before or after the
+	// update
+	if hash == ObjectFormat::current().null_hash() {
+		Ok(None)
+	} else {
+		ObjectRepr::try_from(hash).map(Some)
+	}
+}
+
+impl ReflogEntry {
+	fn parse(line: &str) -> Option<ReflogEntry> {
+		let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+		let mut parts = head.splitn(3, ' ');
+		let old = parse_hash(parts.next()?).ok()?;
+		let new = parse_hash(parts.next()?).ok()?;
+		Some(ReflogEntry {
+			old,
+			new,
+			signature: parts.next()?.to_owned(),
+			message: message.to_owned(),
+		})
+	}
+
+	// When the update happened, taken from the signature
+	pub fn time(&self) -> Option<SystemTime> {
+		signature_time(&self.signature)
+	}
+}
+
+impl Display for ReflogEntry {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		writeln!(
+			f,
+			"{} {} {}\t{}",
+			format_hash(&self.old),
+			format_hash(&self.new),
+			self.signature,
+			self.message
+		)
+	}
+}
+
+pub fn should_log(name: &str) -> Result<bool, ReflogError> {
+	Ok(name == HEAD
+		|| name == STASH
+		|| LOGGED_PREFIXES.iter().any(|p| name.starts_with(p))
+		|| log_path(name)?.is_file())
+}
+
+// Appends an entry to the log of the ref, if updates of the ref are logged.
+// The ref has already moved, so failures are only warned about
+pub fn append_reflog(
+	name: &str,
+	old: Option<&ObjectRepr>,
+	new: Option<&ObjectRepr>,
+	message: &str,
+) {
+	if let Err(e) = try_append_reflog(name, old, new, message) {
+		warn!("Failed writing reflog of {}: {}", name, e);
+	}
+}
+
+fn try_append_reflog(
+	name: &str,
+	old: Option<&ObjectRepr>,
+	new: Option<&ObjectRepr>,
+	message: &str,
+) -> Result<(), ReflogError> {
+	if !should_log(name)? {
+		return Ok(());
+	}
+	let entry = ReflogEntry {
+		old:       old.cloned(),
+		new:       new.cloned(),
+		signature: reflog_signature_now().map_err(|e| IdentityError { source: e })?,
+		// Entries are single lines
+		message:   message.lines().next().unwrap_or("").to_owned(),
+	};
+	let path = log_path(name)?;
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent).map_err(|e| WriteError { source: e })?;
+	}
+	let mut f = OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(&path)
+		.map_err(|e| WriteError { source: e })?;
+	f.write_all(entry.to_string().as_bytes())
+		.map_err(|e| WriteError { source: e })
+}
+
+// Entries of the log of the ref, oldest first. A ref without log has none
+pub fn read_reflog(name: &str) -> Result<Vec<ReflogEntry>, ReflogError> {
+	let content = match fs::read_to_string(log_path(name)?) {
+		Ok(content) => content,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(ReadError { source: e }),
+	};
+	content
+		.lines()
+		.filter(|l| !l.is_empty())
+		.map(|l| {
+			ReflogEntry::parse(l).ok_or_else(|| MalformedError {
+				name: name.to_owned(),
+			})
+		})
+		.collect()
+}
+
+// Replaces the whole log of the ref, e.g. after expiring entries
+pub fn write_reflog(name: &str, entries: &[ReflogEntry]) -> Result<(), ReflogError> {
+	let content = entries.iter().map(|e| e.to_string()).collect::<String>();
+	let path = log_path(name)?;
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent).map_err(|e| WriteError { source: e })?;
+	}
+	fs::write(path, content).map_err(|e| WriteError { source: e })
+}
+
+pub fn delete_reflog(name: &str) -> Result<(), ReflogError> {
+	match fs::remove_file(log_path(name)?) {
+		Err(e) if e.kind() != ErrorKind::NotFound => Err(WriteError { source: e }),
+		_ => Ok(()),
+	}
+}
+
+// Names of all refs with a log, sorted
+pub fn list_reflogs() -> Result<Vec<String>, ReflogError> {
+	let dir = vcs_path_required(LOGS_DIR).map_err(|e| NoRepoError { source: e })?;
+	list_files_below(&dir).map_err(|e| ReadError { source: e })
+}
+
+// Removes the entries recorded before `before`, returns how many
+pub fn expire_reflog(name: &str, before: SystemTime, dry_run: bool) -> Result<usize, ReflogError> {
+	let entries = read_reflog(name)?;
+	let kept = entries
+		.iter()
+		.filter(|e| e.time().is_none_or(|t| t >= before))
+		.cloned()
+		.collect::<Vec<_>>();
+	let expired = entries.len() - kept.len();
+	if !dry_run && expired > 0 {
+		write_reflog(name, &kept)?;
+	}
+	Ok(expired)
+}
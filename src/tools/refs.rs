@@ -0,0 +1,280 @@
+use crate::tools::{
+	db::ObjectRepr,
+	path::{list_files_below, vcs_path_required, PathError},
+	reflog::{append_reflog, delete_reflog},
+	rev::is_safe_ref_name,
+};
+use log::warn;
+use std::{collections::BTreeMap, convert::TryFrom, fs, io, io::ErrorKind};
+use thiserror::Error;
+use RefError::{MalformedError, NoRepoError, ReadError, UnsafeNameError, WriteError};
+
+#[derive(Error, Debug)]
+pub enum RefError {
+	#[error("{source:?}")]
+	NoRepoError { source: PathError },
+	#[error("{source:?}")]
+	ReadError { source: io::Error },
+	#[error("{source:?}")]
+	WriteError { source: io::Error },
+	#[error("Malformed ref {name}")]
+	MalformedError { name: String },
+	#[error("Unsafe ref name {name}")]
+	UnsafeNameError { name: String },
+}
+
+pub const HEAD: &str = "HEAD";
+pub const ORIG_HEAD: &str = "ORIG_HEAD";
+pub const MERGE_HEAD: &str = "MERGE_HEAD";
+pub const CHERRY_PICK_HEAD: &str = "CHERRY_PICK_HEAD";
+pub const REVERT_HEAD: &str = "REVERT_HEAD";
+// The refs of the last fetch, one per line as
+// "<hash>\t[not-for-merge]\t<what>". As a ref it stands for the first one
+pub const FETCH_HEAD: &str = "FETCH_HEAD";
+// Its log is the stack of stashes, the ref itself the newest
+pub const STASH: &str = "refs/stash";
+pub const DEFAULT_BRANCH: &str = "refs/heads/master";
+const REFS_DIR: &str = "refs";
+const PACKED_REFS: &str = "packed-refs";
+const PACKED_REFS_LOCK: &str = "packed-refs.lock";
+const PACKED_REFS_HEADER: &str = "# pack-refs with: peeled fully-peeled sorted \n";
+const SYMREF_PREFIX: &str = "ref: ";
+// Guards against cycles of symbolic refs
+const MAX_SYMREF_DEPTH: usize = 5;
+
+// Content of a ref file, either a hash or a pointer to another ref
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefValue {
+	Direct(ObjectRepr),
+	Symbolic(String),
+}
+
+// A ref in the packed-refs file. Annotated tags also record the object they
+// peel to, so that listing tags does not need to read every tag object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedRef {
+	pub object: ObjectRepr,
+	pub peeled: Option<ObjectRepr>,
+}
+
+// Loose refs take precedence over packed ones
+pub fn read_ref_raw(name: &str) -> Result<Option<RefValue>, RefError> {
+	if let Some(value) = read_loose_ref(name)? {
+		return Ok(Some(value));
+	}
+	if !name.starts_with(&format!("{}/", REFS_DIR)) {
+		return Ok(None);
+	}
+	Ok(read_packed_refs()?
+		.remove(name)
+		.map(|packed| RefValue::Direct(packed.object)))
+}
+
+pub fn read_loose_ref(name: &str) -> Result<Option<RefValue>, RefError> {
+	let path = vcs_path_required(name).map_err(|e| NoRepoError { source: e })?;
+	if path.is_dir() {
+		return Ok(None);
+	}
+	let content = match fs::read_to_string(&path) {
+		Ok(content) => content,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(ReadError { source: e }),
+	};
+	let content = match name {
+		FETCH_HEAD => content.split('\t').next().unwrap_or(""),
+		_ => content.trim_end(),
+	};
+	// A fetch that got nothing leaves FETCH_HEAD empty
+	if content.is_empty() && name == FETCH_HEAD {
+		return Ok(None);
+	}
+	if let Some(target) = content.strip_prefix(SYMREF_PREFIX) {
+		Ok(Some(RefValue::Symbolic(target.to_owned())))
+	} else {
+		ObjectRepr::try_from(content)
+			.map(|r| Some(RefValue::Direct(r)))
+			.map_err(|_| MalformedError {
+				name: name.to_owned(),
+			})
+	}
+}
+
+// Follows symbolic refs. Returns None if the ref, or the ref it points to,
+// does not exist, e.g. HEAD of a repository without commits
+pub fn read_ref(name: &str) -> Result<Option<ObjectRepr>, RefError> {
+	let mut name = name.to_owned();
+	for _ in 0..MAX_SYMREF_DEPTH {
+		match read_ref_raw(&name)? {
+			Some(RefValue::Direct(object_repr)) => return Ok(Some(object_repr)),
+			Some(RefValue::Symbolic(target)) => name = target,
+			None => return Ok(None),
+		}
+	}
+	Err(MalformedError { name })
+}
+
+// The branch HEAD points to, None if HEAD is detached
+pub fn current_branch() -> Result<Option<String>, RefError> {
+	match read_ref_raw(HEAD)? {
+		Some(RefValue::Symbolic(target)) => Ok(Some(target)),
+		_ => Ok(None),
+	}
+}
+
+// Names come from other repositories too, so ones that could leave the refs
+// are refused before they become paths
+fn ensure_safe_name(name: &str) -> Result<(), RefError> {
+	if is_safe_ref_name(name) {
+		Ok(())
+	} else {
+		Err(UnsafeNameError {
+			name: name.to_owned(),
+		})
+	}
+}
+
+fn write_ref_content(name: &str, content: &str) -> Result<(), RefError> {
+	ensure_safe_name(name)?;
+	let path = vcs_path_required(name).map_err(|e| NoRepoError { source: e })?;
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent).map_err(|e| WriteError { source: e })?;
+	}
+	fs::write(&path, content).map_err(|e| WriteError { source: e })
+}
+
+pub fn write_symbolic_ref(name: &str, target: &str) -> Result<(), RefError> {
+	ensure_safe_name(target)?;
+	write_ref_content(name, &format!("{}{}\n", SYMREF_PREFIX, target))
+}
+
+// Overwrites the ref itself, even if it is symbolic
+pub fn write_ref(name: &str, object_repr: &ObjectRepr) -> Result<(), RefError> {
+	write_ref_content(name, &format!("{}\n", object_repr))
+}
+
+// Follows symbolic refs and updates the ref at the end of the chain, e.g. the
+// current branch when updating HEAD. The update is logged for every ref in the
+// chain
+pub fn update_ref(name: &str, object_repr: &ObjectRepr, message: &str) -> Result<(), RefError> {
+	let old = read_ref(name)?;
+	let mut chain = vec![name.to_owned()];
+	for _ in 0..MAX_SYMREF_DEPTH {
+		let last = chain.last().unwrap().clone();
+		match read_ref_raw(&last)? {
+			Some(RefValue::Symbolic(target)) => chain.push(target),
+			_ => {
+				write_ref(&last, object_repr)?;
+				for name in &chain {
+					append_reflog(name, old.as_ref(), Some(object_repr), message);
+				}
+				return Ok(());
+			},
+		}
+	}
+	Err(MalformedError {
+		name: name.to_owned(),
+	})
+}
+
+// Deletes only the file of the ref, the ref stays visible if it is packed
+pub fn delete_loose_ref(name: &str) -> Result<(), RefError> {
+	let path = vcs_path_required(name).map_err(|e| NoRepoError { source: e })?;
+	match fs::remove_file(&path) {
+		Err(e) if e.kind() != ErrorKind::NotFound => Err(WriteError { source: e }),
+		_ => Ok(()),
+	}
+}
+
+pub fn delete_ref(name: &str) -> Result<(), RefError> {
+	delete_loose_ref(name)?;
+	let mut packed = read_packed_refs()?;
+	if packed.remove(name).is_some() {
+		write_packed_refs(&packed)?;
+	}
+	// The ref is gone, a stale log is only warned about
+	if let Err(e) = delete_reflog(name) {
+		warn!("Failed deleting reflog of {}: {}", name, e);
+	}
+	Ok(())
+}
+
+// Names of the refs stored as files below refs/, sorted
+pub fn list_loose_refs() -> Result<Vec<String>, RefError> {
+	let dir = vcs_path_required(REFS_DIR).map_err(|e| NoRepoError { source: e })?;
+	let names = list_files_below(&dir).map_err(|e| ReadError { source: e })?;
+	Ok(names
+		.into_iter()
+		.map(|name| format!("{}/{}", REFS_DIR, name))
+		.collect())
+}
+
+// All refs below refs/, loose and packed, with the objects they point to,
+// sorted by name. Symbolic refs that point nowhere are left out
+pub fn list_refs() -> Result<Vec<(String, ObjectRepr)>, RefError> {
+	let mut refs = BTreeMap::new();
+	for (name, packed) in read_packed_refs()? {
+		refs.insert(name, packed.object);
+	}
+	for name in list_loose_refs()? {
+		if let Some(object_repr) = read_ref(&name)? {
+			refs.insert(name, object_repr);
+		}
+	}
+	Ok(refs.into_iter().collect())
+}
+
+// Format: a header, then "<hash> <name>" lines sorted by name, each
+// optionally followed by "^<peeled hash>"
+pub fn read_packed_refs() -> Result<BTreeMap<String, PackedRef>, RefError> {
+	let path = vcs_path_required(PACKED_REFS).map_err(|e| NoRepoError { source: e })?;
+	let content = match fs::read_to_string(&path) {
+		Ok(content) => content,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+		Err(e) => return Err(ReadError { source: e }),
+	};
+	let malformed = || MalformedError {
+		name: PACKED_REFS.to_owned(),
+	};
+	let mut refs = BTreeMap::new();
+	let mut last = None;
+	for line in content.lines() {
+		if line.starts_with('#') || line.is_empty() {
+			continue;
+		}
+		if let Some(peeled) = line.strip_prefix('^') {
+			let packed: &mut PackedRef = last
+				.as_ref()
+				.and_then(|name| refs.get_mut(name))
+				.ok_or_else(malformed)?;
+			packed.peeled = Some(ObjectRepr::try_from(peeled).map_err(|_| malformed())?);
+			continue;
+		}
+		let (hash, name) = line.split_once(' ').ok_or_else(malformed)?;
+		let object = ObjectRepr::try_from(hash).map_err(|_| malformed())?;
+		refs.insert(
+			name.to_owned(),
+			PackedRef {
+				object,
+				peeled: None,
+			},
+		);
+		last = Some(name.to_owned());
+	}
+	Ok(refs)
+}
+
+// Replaces the packed-refs file. The new content is written to a lock file
+// first and renamed, so that readers never see a partial file
+pub fn write_packed_refs(refs: &BTreeMap<String, PackedRef>) -> Result<(), RefError> {
+	let path = vcs_path_required(PACKED_REFS).map_err(|e| NoRepoError { source: e })?;
+	let lock = vcs_path_required(PACKED_REFS_LOCK).map_err(|e| NoRepoError { source: e })?;
+	let mut content = String::from(PACKED_REFS_HEADER);
+	for (name, packed) in refs {
+		content.push_str(&format!("{} {}\n", packed.object, name));
+		if let Some(peeled) = &packed.peeled {
+			content.push_str(&format!("^{}\n", peeled));
+		}
+	}
+	fs::write(&lock, content).map_err(|e| WriteError { source: e })?;
+	fs::rename(&lock, &path).map_err(|e| WriteError { source: e })
+}
@@ -0,0 +1,188 @@
+use crate::tools::{
+	config::{Config, ConfigError},
+	http::is_http_url,
+	refs::{current_branch, RefError},
+};
+use std::path::Path;
+use thiserror::Error;
+use RemoteError::{ConfigReadError, InvalidRefspecError, RefReadError, UnknownError};
+
+#[derive(Error, Debug)]
+pub enum RemoteError {
+	#[error("{source:?}")]
+	ConfigReadError { source: ConfigError },
+	#[error("{source:?}")]
+	RefReadError { source: RefError },
+	#[error("{name} is neither a remote nor a repository path")]
+	UnknownError { name: String },
+	#[error("Invalid refspec {spec:?}")]
+	InvalidRefspecError { spec: String },
+}
+
+pub const DEFAULT_REMOTE: &str = "origin";
+pub const HEADS_PREFIX: &str = "refs/heads/";
+pub const TAGS_PREFIX: &str = "refs/tags/";
+const WILDCARD: char = '*';
+
+// Maps refs of one repository to refs of another, e.g.
+// "+refs/heads/*:refs/remotes/origin/*". A leading + allows updates that are
+// not fast-forwards, an empty source deletes the destination
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refspec {
+	pub force: bool,
+	pub src:   String,
+	pub dst:   Option<String>,
+}
+
+// A configured remote, or a repository path or URL given directly
+#[derive(Debug, Clone)]
+pub struct Remote {
+	pub name:  Option<String>,
+	pub url:   String,
+	pub fetch: Vec<Refspec>,
+}
+
+// Where the branches of a remote are tracked, e.g. refs/remotes/origin/
+pub fn remote_tracking_prefix(remote: &str) -> String {
+	format!("refs/remotes/{}/", remote)
+}
+
+pub fn default_fetch_refspec(remote: &str) -> String {
+	format!(
+		"+{}{}:{}{}",
+		HEADS_PREFIX,
+		WILDCARD,
+		remote_tracking_prefix(remote),
+		WILDCARD
+	)
+}
+
+// What a single wildcard in `pattern` stands for in `name`, or the empty
+// string if `pattern` has no wildcard and equals `name`
+fn match_pattern<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+	match pattern.split_once(WILDCARD) {
+		Some((prefix, suffix)) => name
+			.strip_prefix(prefix)
+			.and_then(|rest| rest.strip_suffix(suffix))
+			.filter(|matched| !matched.is_empty()),
+		None if pattern == name => Some(""),
+		None => None,
+	}
+}
+
+impl Refspec {
+	pub fn parse(spec: &str) -> Option<Refspec> {
+		let (force, spec) = match spec.strip_prefix('+') {
+			Some(spec) => (true, spec),
+			None => (false, spec),
+		};
+		let (src, dst) = match spec.split_once(':') {
+			Some((src, dst)) => (src, Some(dst.to_owned())),
+			None => (spec, None),
+		};
+		let wildcards = |s: &str| s.matches(WILDCARD).count();
+		let dst_wildcards = dst.as_deref().map_or(wildcards(src), wildcards);
+		if wildcards(src) > 1
+			|| wildcards(src) != dst_wildcards
+			|| (src.is_empty() && dst.is_none())
+		{
+			return None;
+		}
+		Some(Refspec {
+			force,
+			src: src.to_owned(),
+			dst,
+		})
+	}
+
+	pub fn is_wildcard(&self) -> bool {
+		self.src.contains(WILDCARD)
+	}
+
+	// Short sources stand for refs/<name>, refs/tags/<name> or
+	// refs/heads/<name>, whichever of `names` comes first in this order, like
+	// git. Unknown short names are kept as they are
+	pub fn qualify(&self, names: &[&str]) -> Refspec {
+		let mut refspec = self.clone();
+		if self.is_wildcard() || self.src.is_empty() || self.src.starts_with("refs/") {
+			return refspec;
+		}
+		let candidates = [
+			format!("refs/{}", self.src),
+			format!("{}{}", TAGS_PREFIX, self.src),
+			format!("{}{}", HEADS_PREFIX, self.src),
+		];
+		if let Some(name) = candidates.iter().find(|c| names.contains(&c.as_str())) {
+			refspec.src = name.clone();
+		}
+		refspec
+	}
+
+	// Whether the ref `name` of the source side matches, even if the refspec
+	// has no destination
+	pub fn matches(&self, name: &str) -> bool {
+		match_pattern(&self.src, name).is_some()
+	}
+
+	// The destination of the ref `name` of the source side, if it matches
+	pub fn map(&self, name: &str) -> Option<String> {
+		let matched = match_pattern(&self.src, name)?;
+		self.dst
+			.as_ref()
+			.map(|dst| dst.replacen(WILDCARD, matched, 1))
+	}
+
+	// The source of the ref `name` of the destination side, if it matches
+	pub fn reverse_map(&self, name: &str) -> Option<String> {
+		let matched = match_pattern(self.dst.as_ref()?, name)?;
+		Some(self.src.replacen(WILDCARD, matched, 1))
+	}
+}
+
+pub fn parse_refspecs(specs: &[String]) -> Result<Vec<Refspec>, RemoteError> {
+	specs
+		.iter()
+		.map(|spec| Refspec::parse(spec).ok_or_else(|| InvalidRefspecError { spec: spec.clone() }))
+		.collect()
+}
+
+// Remote names are used in ref names and must not contain slashes
+pub fn is_valid_remote_name(name: &str) -> bool {
+	!name.is_empty()
+		&& name != "."
+		&& name != ".."
+		&& !name.contains(|c: char| c == '/' || c.is_whitespace() || c == WILDCARD)
+}
+
+pub fn resolve_remote(name_or_url: &str) -> Result<Remote, RemoteError> {
+	let config = Config::read().map_err(|e| ConfigReadError { source: e })?;
+	if let Some(remote) = config.remote.get(name_or_url) {
+		return Ok(Remote {
+			name:  Some(name_or_url.to_owned()),
+			url:   remote.url.clone(),
+			fetch: parse_refspecs(&remote.fetch)?,
+		});
+	}
+	if Path::new(name_or_url).exists() || is_http_url(name_or_url) {
+		return Ok(Remote {
+			name:  None,
+			url:   name_or_url.to_owned(),
+			fetch: Vec::new(),
+		});
+	}
+	Err(UnknownError {
+		name: name_or_url.to_owned(),
+	})
+}
+
+// The remote the current branch tracks, else origin
+pub fn default_remote_name() -> Result<String, RemoteError> {
+	let config = Config::read().map_err(|e| ConfigReadError { source: e })?;
+	let branch = current_branch().map_err(|e| RefReadError { source: e })?;
+	Ok(branch
+		.as_deref()
+		.and_then(|b| b.strip_prefix(HEADS_PREFIX))
+		.and_then(|b| config.branch.get(b))
+		.and_then(|b| b.remote.clone())
+		.unwrap_or_else(|| DEFAULT_REMOTE.to_owned()))
+}
@@ -0,0 +1,290 @@
+// Rename and copy detection between the changes of two trees. Files are
+// paired by the similarity of their contents, estimated like git does from
+// the bytes of the chunks both contain
+use crate::tools::{
+	db::ObjectRepr,
+	patch::{Blobs, FileChange, PatchError, Source},
+	path::RelativePathToBase,
+	tree::{FileMode, FlatEntry, FlatTree},
+};
+use std::collections::{HashMap, HashSet};
+
+pub const DEFAULT_THRESHOLD: usize = 50;
+// Scores are finer than the percentages shown, like in git
+const MAX_SCORE: usize = 60000;
+// Chunks end at newlines or after this many bytes
+const CHUNK_LEN: usize = 64;
+// Inexact detection compares every pair of candidates, beyond this many on
+// either side only exact renames are found
+const RENAME_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RenameOptions {
+	pub renames:       bool,
+	// Copies from files changed in the same diff
+	pub copies:        bool,
+	// Copies from any file of the old tree
+	pub copies_harder: bool,
+	// Minimum similarity in percent
+	pub threshold:     usize,
+}
+
+impl Default for RenameOptions {
+	fn default() -> RenameOptions {
+		RenameOptions {
+			renames:       true,
+			copies:        false,
+			copies_harder: false,
+			threshold:     DEFAULT_THRESHOLD,
+		}
+	}
+}
+
+impl RenameOptions {
+	pub fn none() -> RenameOptions {
+		RenameOptions {
+			renames: false,
+			..RenameOptions::default()
+		}
+	}
+}
+
+// A similarity score as given to -M or -C: "50%", or digits after an implied
+// decimal point, so that "5" and "50" both mean 50%
+pub fn parse_score(repr: &str) -> Option<usize> {
+	if repr.is_empty() {
+		return Some(DEFAULT_THRESHOLD);
+	}
+	if let Some(percent) = repr.strip_suffix('%') {
+		return percent.parse::<usize>().ok().filter(|p| *p <= 100);
+	}
+	if !repr.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	let digits = format!("{:0<3}", repr);
+	digits[..3].parse::<usize>().ok().map(|n| n / 10)
+}
+
+fn chunk_sizes(data: &[u8]) -> HashMap<&[u8], usize> {
+	let mut sizes = HashMap::new();
+	let mut start = 0;
+	for (i, &b) in data.iter().enumerate() {
+		if b == b'\n' || i + 1 - start == CHUNK_LEN {
+			*sizes.entry(&data[start..=i]).or_insert(0) += i + 1 - start;
+			start = i + 1;
+		}
+	}
+	if start < data.len() {
+		*sizes.entry(&data[start..]).or_insert(0) += data.len() - start;
+	}
+	sizes
+}
+
+// Share of the larger content found in the other one, out of MAX_SCORE
+fn score(a: &[u8], b: &[u8]) -> usize {
+	let larger = a.len().max(b.len());
+	if larger == 0 {
+		return MAX_SCORE;
+	}
+	let (a_chunks, b_chunks) = (chunk_sizes(a), chunk_sizes(b));
+	let shared = b_chunks
+		.iter()
+		.map(|(chunk, size)| a_chunks.get(chunk).map_or(0, |a_size| (*a_size).min(*size)))
+		.sum::<usize>();
+	shared * MAX_SCORE / larger
+}
+
+// Entries that can be paired: files and symlinks, not submodules
+fn is_candidate(entry: &FlatEntry) -> bool {
+	matches!(
+		entry.mode,
+		FileMode::Regular | FileMode::Executable | FileMode::Symlink
+	)
+}
+
+fn same_kind(a: &FlatEntry, b: &FlatEntry) -> bool {
+	(a.mode == FileMode::Symlink) == (b.mode == FileMode::Symlink)
+}
+
+struct Contents<'a> {
+	blobs: &'a Blobs,
+	cache: HashMap<ObjectRepr, Vec<u8>>,
+}
+
+impl Contents<'_> {
+	fn get(&mut self, entry: &FlatEntry) -> Result<&[u8], PatchError> {
+		if !self.cache.contains_key(&entry.hash) {
+			let data = self.blobs.content(Some(entry))?;
+			self.cache.insert(entry.hash.clone(), data);
+		}
+		Ok(&self.cache[&entry.hash])
+	}
+
+	// None when the pair cannot reach the threshold percentage
+	fn score(
+		&mut self,
+		old: &FlatEntry,
+		new: &FlatEntry,
+		threshold: usize,
+	) -> Result<Option<usize>, PatchError> {
+		if old.hash == new.hash {
+			return Ok(Some(MAX_SCORE));
+		}
+		let old_len = self.get(old)?.len();
+		let new_len = self.get(new)?.len();
+		let (smaller, larger) = (old_len.min(new_len), old_len.max(new_len));
+		// Empty files are alike but say nothing about where a file came from
+		if smaller == 0 || smaller * 100 < threshold * larger {
+			return Ok(None);
+		}
+		let old_data = self.get(old)?.to_vec();
+		let score = score(&old_data, self.get(new)?);
+		Ok(Some(score).filter(|s| s * 100 >= threshold * MAX_SCORE))
+	}
+}
+
+// Replaces pairs of deleted and added files by renames, and marks added
+// files as copies of files of the old tree, as the options ask for
+pub fn detect_renames(
+	changes: Vec<FileChange>,
+	old_tree: &FlatTree,
+	blobs: &Blobs,
+	options: &RenameOptions,
+) -> Result<Vec<FileChange>, PatchError> {
+	let copies = options.copies || options.copies_harder;
+	if !options.renames && !copies {
+		return Ok(changes);
+	}
+	let mut contents = Contents {
+		blobs,
+		cache: HashMap::new(),
+	};
+	let deleted = (0..changes.len())
+		.filter(|&i| changes[i].new.is_none() && changes[i].old.as_ref().is_some_and(is_candidate))
+		.collect::<Vec<_>>();
+	let added = (0..changes.len())
+		.filter(|&i| changes[i].old.is_none() && changes[i].new.as_ref().is_some_and(is_candidate))
+		.collect::<Vec<_>>();
+
+	// Source of every paired added file, and the deleted files renamed. Whether
+	// a file is a copy is decided once all are paired
+	let mut sources: HashMap<usize, (FlatEntry, Source)> = HashMap::new();
+	let mut renamed = HashSet::new();
+	let pair = |d: usize, score: usize| {
+		let old = changes[d].old.clone().unwrap();
+		let source = Source {
+			path:       changes[d].path.clone(),
+			copy:       false,
+			similarity: score * 100 / MAX_SCORE,
+		};
+		(old, source)
+	};
+
+	// Exact renames first, they are cheap and certain
+	for &a in added.iter().filter(|_| options.renames) {
+		let new = changes[a].new.as_ref().unwrap();
+		let found = deleted.iter().find(|&&d| {
+			let old = changes[d].old.as_ref().unwrap();
+			!renamed.contains(&d) && old.hash == new.hash && same_kind(old, new)
+		});
+		if let Some(&d) = found {
+			renamed.insert(d);
+			sources.insert(a, pair(d, MAX_SCORE));
+		}
+	}
+
+	if options.renames && deleted.len().max(added.len()) <= RENAME_LIMIT {
+		let mut scores = Vec::new();
+		for &a in added.iter().filter(|a| !sources.contains_key(a)) {
+			for &d in deleted.iter().filter(|d| !renamed.contains(d)) {
+				let (old, new) = (
+					changes[d].old.as_ref().unwrap(),
+					changes[a].new.as_ref().unwrap(),
+				);
+				if !same_kind(old, new) {
+					continue;
+				}
+				if let Some(score) = contents.score(old, new, options.threshold)? {
+					scores.push((score, a, d));
+				}
+			}
+		}
+		// Best pairs first, ties by path order
+		scores.sort_by(|x, y| y.0.cmp(&x.0).then(x.1.cmp(&y.1)).then(x.2.cmp(&y.2)));
+		for (score, a, d) in scores {
+			if !sources.contains_key(&a) && !renamed.contains(&d) {
+				renamed.insert(d);
+				sources.insert(a, pair(d, score));
+			}
+		}
+	}
+	if copies {
+		// Files of the old tree that were changed or deleted, or all of them
+		let candidates = if options.copies_harder {
+			old_tree
+				.iter()
+				.filter(|(_, e)| is_candidate(e))
+				.map(|(p, e)| (p.clone(), e.clone()))
+				.collect::<Vec<_>>()
+		} else {
+			changes
+				.iter()
+				.filter_map(|c| match (&c.old, c.source.is_none()) {
+					(Some(old), true) if is_candidate(old) => Some((c.path.clone(), old.clone())),
+					_ => None,
+				})
+				.collect()
+		};
+		if candidates.len().max(added.len()) <= RENAME_LIMIT {
+			let unpaired = added
+				.iter()
+				.copied()
+				.filter(|a| !sources.contains_key(a))
+				.collect::<Vec<_>>();
+			for a in unpaired {
+				let new = changes[a].new.as_ref().unwrap();
+				let mut best: Option<(usize, &(RelativePathToBase, FlatEntry))> = None;
+				for candidate in candidates.iter().filter(|(_, old)| same_kind(old, new)) {
+					if let Some(score) = contents.score(&candidate.1, new, options.threshold)? {
+						if best.is_none_or(|(s, _)| score > s) {
+							best = Some((score, candidate));
+						}
+					}
+				}
+				if let Some((score, (path, old))) = best {
+					let source = Source {
+						path:       path.clone(),
+						copy:       false,
+						similarity: score * 100 / MAX_SCORE,
+					};
+					sources.insert(a, (old.clone(), source));
+				}
+			}
+		}
+	}
+
+	// Like git, a deleted file is renamed to the last file made from it and
+	// copied to the others
+	let deleted_paths = deleted
+		.iter()
+		.map(|&d| changes[d].path.clone())
+		.collect::<HashSet<_>>();
+	let mut last_use = HashMap::new();
+	for (&a, (_, source)) in &sources {
+		let last = last_use.entry(source.path.clone()).or_insert(a);
+		*last = (*last).max(a);
+	}
+	let mut result = Vec::new();
+	for (i, mut change) in changes.into_iter().enumerate() {
+		if change.new.is_none() && last_use.contains_key(&change.path) {
+			continue;
+		}
+		if let Some((old, mut source)) = sources.remove(&i) {
+			source.copy = !deleted_paths.contains(&source.path) || last_use[&source.path] != i;
+			change.old = Some(old);
+			change.source = Some(source);
+		}
+		result.push(change);
+	}
+	Ok(result)
+}
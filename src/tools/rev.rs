@@ -0,0 +1,279 @@
+use crate::tools::{
+	commit::{Commit, CommitError},
+	date::parse_date,
+	db::{list_objects, read, DbError, Object, ObjectRepr, ObjectType},
+	reflog::{read_reflog, ReflogError},
+	refs::{current_branch, read_ref, read_ref_raw, RefError, HEAD},
+	tag::{Tag, TagError},
+};
+use std::convert::TryFrom;
+use thiserror::Error;
+use RevError::{
+	AmbiguousError, CommitReadError, NoParentError, ObjectError, RefReadError, ReflogEntryError,
+	ReflogReadError, TagReadError, UnknownError, WrongTypeError,
+};
+
+#[derive(Error, Debug)]
+pub enum RevError {
+	#[error("Unknown revision {rev}")]
+	UnknownError { rev: String },
+	#[error("Short hash {rev} is ambiguous")]
+	AmbiguousError { rev: String },
+	#[error("{hash} can not be used as a {expected}")]
+	WrongTypeError {
+		hash:     String,
+		expected: ObjectType,
+	},
+	#[error("{source:?}")]
+	RefReadError { source: RefError },
+	#[error("{source:?}")]
+	ObjectError { source: DbError },
+	#[error("{source:?}")]
+	CommitReadError { source: CommitError },
+	#[error("{source:?}")]
+	TagReadError { source: TagError },
+	#[error("Log for {rev} does not go back that far")]
+	ReflogEntryError { rev: String },
+	#[error("{source:?}")]
+	ReflogReadError { source: ReflogError },
+	#[error("{rev} has no such parent")]
+	NoParentError { rev: String },
+}
+
+// Guards against cycles of tags pointing at tags
+const MAX_TAG_DEPTH: usize = 10;
+
+const MIN_SHORT_HASH: usize = 4;
+
+// Same lookup order as git, see https://git-scm.com/docs/gitrevisions
+fn ref_candidates(rev: &str) -> Vec<String> {
+	let mut candidates = vec![];
+	// Other files in the repository directory, e.g. "config" or "index", are
+	// not refs, so a bare name is either in refs/ or a pseudo ref like HEAD
+	if rev.starts_with("refs/") || is_pseudo_ref_name(rev) {
+		candidates.push(rev.to_owned());
+	}
+	candidates.extend([
+		format!("refs/{}", rev),
+		format!("refs/tags/{}", rev),
+		format!("refs/heads/{}", rev),
+		format!("refs/remotes/{}", rev),
+		format!("refs/remotes/{}/HEAD", rev),
+	]);
+	candidates
+}
+
+// HEAD, ORIG_HEAD, MERGE_HEAD, FETCH_HEAD and the like
+fn is_pseudo_ref_name(rev: &str) -> bool {
+	!rev.is_empty() && rev.chars().all(|c| c.is_ascii_uppercase() || c == '_')
+}
+
+fn is_hex(rev: &str) -> bool {
+	rev.chars()
+		.all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+pub fn resolve_short_hash(rev: &str) -> Result<Option<ObjectRepr>, RevError> {
+	if rev.len() < MIN_SHORT_HASH || !is_hex(rev) {
+		return Ok(None);
+	}
+	let mut matching = list_objects()
+		.map_err(|e| ObjectError { source: e })?
+		.into_iter()
+		.filter(|o| o.hash().starts_with(rev));
+	match (matching.next(), matching.next()) {
+		(Some(object_repr), None) => Ok(Some(object_repr)),
+		(Some(_), Some(_)) => Err(AmbiguousError {
+			rev: rev.to_owned(),
+		}),
+		_ => Ok(None),
+	}
+}
+
+// refs must not escape the VCS directory
+pub fn is_safe_ref_name(rev: &str) -> bool {
+	!rev.is_empty()
+		&& !rev
+			.split('/')
+			.any(|c| c.is_empty() || c == "." || c == "..")
+}
+
+// The full name of the ref a short name stands for, e.g. refs/heads/master
+// for master
+pub fn expand_ref_name(rev: &str) -> Result<Option<String>, RevError> {
+	if !is_safe_ref_name(rev) {
+		return Ok(None);
+	}
+	for candidate in ref_candidates(rev) {
+		if read_ref_raw(&candidate)
+			.map_err(|e| RefReadError { source: e })?
+			.is_some()
+		{
+			return Ok(Some(candidate));
+		}
+	}
+	Ok(None)
+}
+
+// "<ref>@{<selector>}" split into ref and selector
+pub fn split_reflog_selector(rev: &str) -> Option<(&str, &str)> {
+	let rest = rev.strip_suffix('}')?;
+	let index = rest.rfind("@{")?;
+	Some((&rest[..index], &rest[(index + 2)..]))
+}
+
+// The ref a reflog selector refers to, the current branch if none is given
+pub fn reflog_ref_name(name: &str) -> Result<String, RevError> {
+	if name.is_empty() || name == "@" {
+		let branch = current_branch().map_err(|e| RefReadError { source: e })?;
+		return Ok(branch.unwrap_or_else(|| HEAD.to_owned()));
+	}
+	expand_ref_name(name)?.ok_or_else(|| UnknownError {
+		rev: name.to_owned(),
+	})
+}
+
+// "<ref>@{<n>}" is the value of the ref n updates ago, "<ref>@{<date>}" the
+// value it had at that date
+fn resolve_reflog_selector(rev: &str, name: &str, selector: &str) -> Result<ObjectRepr, RevError> {
+	let entries =
+		read_reflog(&reflog_ref_name(name)?).map_err(|e| ReflogReadError { source: e })?;
+	let value = if let Ok(n) = selector.parse::<usize>() {
+		match entries.iter().rev().nth(n) {
+			Some(entry) => entry.new.clone(),
+			// One further back than the log is the value before the oldest entry
+			None if n == entries.len() => entries.first().and_then(|e| e.old.clone()),
+			None => None,
+		}
+	} else {
+		let date = parse_date(selector).ok_or_else(|| UnknownError {
+			rev: rev.to_owned(),
+		})?;
+		match entries
+			.iter()
+			.rev()
+			.find(|e| e.time().is_some_and(|t| t <= date))
+		{
+			Some(entry) => entry.new.clone(),
+			// Before the log starts, the oldest known value
+			None => entries.first().and_then(|e| e.old.clone()),
+		}
+	};
+	value.ok_or_else(|| ReflogEntryError {
+		rev: rev.to_owned(),
+	})
+}
+
+// Ancestry suffixes: ~n follows first parents n times, ^n takes the nth
+// parent, ^0 the commit itself. Both default to 1
+fn resolve_ancestry(rev: &str, base: ObjectRepr, suffixes: &str) -> Result<ObjectRepr, RevError> {
+	let no_parent = || NoParentError {
+		rev: rev.to_owned(),
+	};
+	let mut object_repr = base;
+	let mut rest = suffixes;
+	while let Some(operator) = rest.chars().next() {
+		let digits = rest[1..]
+			.find(|c: char| !c.is_ascii_digit())
+			.map_or(rest.len(), |i| i + 1);
+		let n = match &rest[1..digits] {
+			"" => 1,
+			n => n.parse::<usize>().map_err(|_| no_parent())?,
+		};
+		rest = &rest[digits..];
+		let commit = peel_to_commit(&object_repr)?;
+		let parents = |commit: &ObjectRepr| -> Result<Vec<ObjectRepr>, RevError> {
+			let object = read(commit).map_err(|e| ObjectError { source: e })?;
+			Commit::parse(&object.data)
+				.map(|c| c.parents)
+				.map_err(|e| CommitReadError { source: e })
+		};
+		object_repr = match (operator, n) {
+			('^', 0) => commit,
+			('^', n) => parents(&commit)?
+				.into_iter()
+				.nth(n - 1)
+				.ok_or_else(no_parent)?,
+			('~', n) => {
+				let mut commit = commit;
+				for _ in 0..n {
+					commit = parents(&commit)?.into_iter().next().ok_or_else(no_parent)?;
+				}
+				commit
+			},
+			_ => return Err(no_parent()),
+		};
+	}
+	Ok(object_repr)
+}
+
+pub fn resolve_revision(rev: &str) -> Result<ObjectRepr, RevError> {
+	if let Some(index) = rev.find(['~', '^']).filter(|&i| i > 0) {
+		let base = resolve_revision(&rev[..index])?;
+		return resolve_ancestry(rev, base, &rev[index..]);
+	}
+	if let Ok(object_repr) = ObjectRepr::try_from(rev) {
+		return Ok(object_repr);
+	}
+	if let Some((name, selector)) = split_reflog_selector(rev) {
+		return resolve_reflog_selector(rev, name, selector);
+	}
+	// "@" alone is a shortcut for HEAD
+	let rev = if rev == "@" { HEAD } else { rev };
+	if is_safe_ref_name(rev) {
+		for candidate in ref_candidates(rev) {
+			if let Some(object_repr) =
+				read_ref(&candidate).map_err(|e| RefReadError { source: e })?
+			{
+				return Ok(object_repr);
+			}
+		}
+	}
+	resolve_short_hash(rev)?.ok_or_else(|| UnknownError {
+		rev: rev.to_owned(),
+	})
+}
+
+// Follows annotated tags to the object they point at
+pub fn peel_tags(object_repr: &ObjectRepr) -> Result<(ObjectRepr, Object), RevError> {
+	let mut object_repr = object_repr.clone();
+	for _ in 0..MAX_TAG_DEPTH {
+		let object = read(&object_repr).map_err(|e| ObjectError { source: e })?;
+		if object.object_type != ObjectType::Tag {
+			return Ok((object_repr, object));
+		}
+		object_repr = Tag::parse(&object.data)
+			.map_err(|e| TagReadError { source: e })?
+			.object;
+	}
+	Err(WrongTypeError {
+		hash:     object_repr.to_string(),
+		expected: ObjectType::Tag,
+	})
+}
+
+// A commit stands for its tree wherever a tree is expected
+pub fn peel_to_tree(object_repr: &ObjectRepr) -> Result<ObjectRepr, RevError> {
+	let (object_repr, object) = peel_tags(object_repr)?;
+	match object.object_type {
+		ObjectType::Tree => Ok(object_repr),
+		ObjectType::Commit => Commit::parse(&object.data)
+			.map(|c| c.tree)
+			.map_err(|e| CommitReadError { source: e }),
+		_ => Err(WrongTypeError {
+			hash:     object_repr.to_string(),
+			expected: ObjectType::Tree,
+		}),
+	}
+}
+
+pub fn peel_to_commit(object_repr: &ObjectRepr) -> Result<ObjectRepr, RevError> {
+	let (object_repr, object) = peel_tags(object_repr)?;
+	match object.object_type {
+		ObjectType::Commit => Ok(object_repr),
+		_ => Err(WrongTypeError {
+			hash:     object_repr.to_string(),
+			expected: ObjectType::Commit,
+		}),
+	}
+}
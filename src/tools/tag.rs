@@ -0,0 +1,62 @@
+use crate::tools::db::{ObjectRepr, ObjectType};
+use std::{convert::TryFrom, str::from_utf8};
+use thiserror::Error;
+use TagError::MalformedError;
+
+#[derive(Error, Debug)]
+pub enum TagError {
+	#[error("Malformed tag: {msg}")]
+	MalformedError { msg: String },
+}
+
+// Annotated tag object
+#[derive(Debug, Clone)]
+pub struct Tag {
+	pub object:      ObjectRepr,
+	pub object_type: ObjectType,
+	pub tagger:      Option<String>,
+}
+
+impl Tag {
+	// Format: "object <hash>\ntype <type>\ntag <name>\n[tagger <signature>\n]"
+	// followed by an empty line and the message
+	pub fn parse(data: &[u8]) -> Result<Tag, TagError> {
+		let text = from_utf8(data).map_err(|_| malformed("not valid UTF-8"))?;
+		let headers = match text.find("\n\n") {
+			Some(index) => &text[..index],
+			None => text.trim_end_matches('\n'),
+		};
+		let mut lines = headers.lines();
+		let mut header = |key: &str| {
+			lines
+				.next()
+				.and_then(|l| l.strip_prefix(key))
+				.and_then(|l| l.strip_prefix(' '))
+				.ok_or_else(|| malformed(&format!("missing {}", key)))
+		};
+
+		let object = header("object")?;
+		let object = ObjectRepr::try_from(object)
+			.map_err(|_| malformed(&format!("invalid hash {:?}", object)))?;
+		let object_type = ObjectType::from(header("type")?);
+		if !object_type.is_valid() {
+			return Err(malformed("invalid type"));
+		}
+		if header("tag")?.is_empty() {
+			return Err(malformed("empty name"));
+		}
+		// Old tags were created without a tagger
+		let tagger = header("tagger").ok().map(|t| t.to_owned());
+		Ok(Tag {
+			object,
+			object_type,
+			tagger,
+		})
+	}
+}
+
+fn malformed(msg: &str) -> TagError {
+	MalformedError {
+		msg: msg.to_owned(),
+	}
+}
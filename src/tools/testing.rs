@@ -0,0 +1,92 @@
+// Repositories for tests. Commands find the repository from the current
+// directory and hash with the current object format, both global to the
+// process, so tests using repositories hold a lock while they run
+use crate::{
+	init::init_do::init_at,
+	tools::{
+		db::{insert, ObjectRepr, ObjectType},
+		object_format::ObjectFormat,
+		path::RelativePathToBase,
+		transport::in_repository,
+		tree::{write_flat_tree, FileMode, FlatEntry, FlatTree},
+	},
+};
+use lazy_static::lazy_static;
+use std::{
+	env,
+	error::Error,
+	fs,
+	path::PathBuf,
+	process,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Mutex, MutexGuard, PoisonError,
+	},
+};
+
+lazy_static! {
+	static ref LOCK: Mutex<()> = Mutex::new(());
+}
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// A failed test leaves the lock poisoned, which does not matter to the others
+pub fn lock() -> MutexGuard<'static, ()> {
+	LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// An empty repository in a directory of its own, removed when dropped
+pub struct TempRepo {
+	pub path: PathBuf,
+}
+
+impl TempRepo {
+	pub fn new(object_format: ObjectFormat) -> TempRepo {
+		let path = env::temp_dir().join(format!(
+			"unrusty-test-{}-{}",
+			process::id(),
+			COUNT.fetch_add(1, Ordering::SeqCst)
+		));
+		fs::create_dir_all(&path).expect("the test directory is created");
+		let path = path.canonicalize().expect("the test directory exists");
+		in_repository(&path, || init_at(&path, object_format)).expect("the repository is created");
+		TempRepo { path }
+	}
+
+	// Runs `f` in the repository
+	pub fn run<T>(&self, f: impl FnOnce() -> Result<T, Box<dyn Error>>) -> T {
+		in_repository(&self.path, f).expect("running in the repository succeeds")
+	}
+}
+
+impl Drop for TempRepo {
+	fn drop(&mut self) {
+		fs::remove_dir_all(&self.path).ok();
+	}
+}
+
+// Stores a commit of the files, given by path and content, with a fixed
+// author and date
+pub fn commit(
+	files: &[(&str, &str)],
+	parents: &[ObjectRepr],
+) -> Result<ObjectRepr, Box<dyn Error>> {
+	let mut tree = FlatTree::new();
+	for (path, content) in files {
+		tree.insert(
+			RelativePathToBase::new(PathBuf::from(path)),
+			FlatEntry {
+				mode: FileMode::Regular,
+				hash: insert(content.as_bytes(), ObjectType::Blob)?,
+			},
+		);
+	}
+	let mut content = format!("tree {}\n", write_flat_tree(&tree)?);
+	for parent in parents {
+		content.push_str(&format!("parent {}\n", parent));
+	}
+	content.push_str("author A U Thor <author@example.com> 1700000000 +0000\n");
+	content.push_str("committer A U Thor <author@example.com> 1700000000 +0000\n");
+	content.push_str("\ncommit\n");
+	Ok(insert(content.as_bytes(), ObjectType::Commit)?)
+}
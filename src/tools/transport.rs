@@ -0,0 +1,392 @@
+// Exchange of refs and objects with another repository, on the local file
+// system or over HTTP. Repository functions act on the repository of the
+// current directory, so a local repository is accessed by switching into it
+use crate::tools::{
+	db::{insert, read, ObjectRepr, ObjectType},
+	http::{is_http_url, HttpRemote},
+	lfs::{import, pointers},
+	merge::is_ancestor,
+	object_format::ObjectFormat,
+	pack::read_pack,
+	path::{objects_dir_required, vcs_dir_from_base},
+	reachable::references,
+	refs::{list_refs, read_ref, read_ref_raw, RefValue, HEAD},
+	remote::{HEADS_PREFIX, TAGS_PREFIX},
+	rev::{is_safe_ref_name, peel_tags},
+};
+use log::warn;
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	env,
+	error::Error,
+	fmt::{self, Display, Formatter},
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+// What the other repository advertises. Annotated tags also come with the
+// object they peel to
+#[derive(Debug, Clone)]
+pub struct RemoteRefs {
+	pub head:   Option<RefValue>,
+	pub refs:   Vec<(String, ObjectRepr)>,
+	pub peeled: BTreeMap<String, ObjectRepr>,
+}
+
+// Where fetch and clone get refs and objects from
+pub enum Source {
+	Local(PathBuf),
+	Http(Box<HttpRemote>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateStatus {
+	New,
+	UpToDate,
+	FastForward,
+	Forced,
+	Deleted,
+	// Fetched without a ref to store it in, only into FETCH_HEAD
+	Fetched,
+	Rejected(String),
+}
+
+// The update of one ref as reported by fetch and push
+#[derive(Debug, Clone)]
+pub struct RefUpdate {
+	pub src:    String,
+	pub dst:    String,
+	pub old:    Option<ObjectRepr>,
+	pub new:    Option<ObjectRepr>,
+	pub status: UpdateStatus,
+}
+
+// Runs `f` with the current directory set to `root`, and restores it
+pub fn in_repository<T>(
+	root: &Path,
+	f: impl FnOnce() -> Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+	let previous = env::current_dir()?;
+	let previous_format = ObjectFormat::current();
+	env::set_current_dir(root)?;
+	let result = match ObjectFormat::load() {
+		Ok(_) => f(),
+		Err(e) => Err(e.into()),
+	};
+	env::set_current_dir(previous)?;
+	previous_format.make_current();
+	result
+}
+
+// The root of the repository at `path`, which may also be its VCS directory
+pub fn find_repository(path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+	let path = path
+		.canonicalize()
+		.map_err(|e| format!("{}: {}", path.display(), e))?;
+	for candidate in [Some(path.as_path()), path.parent()].iter().flatten() {
+		if vcs_dir_from_base(candidate).is_dir() {
+			return Ok(candidate.to_path_buf());
+		}
+	}
+	Err(format!("{} is not a repository", path.display()).into())
+}
+
+// Ref names of another repository become paths in this one, those that could
+// point outside of the refs are left out
+fn without_unsafe_names(remote: RemoteRefs) -> RemoteRefs {
+	let safe = |name: &String| {
+		let safe = is_safe_ref_name(name);
+		if !safe {
+			warn!("ignoring the ref {:?} with an unsafe name", name);
+		}
+		safe
+	};
+	RemoteRefs {
+		head:   remote.head.filter(|head| match head {
+			RefValue::Symbolic(target) => safe(target),
+			RefValue::Direct(_) => true,
+		}),
+		refs:   remote
+			.refs
+			.into_iter()
+			.filter(|(name, _)| safe(name))
+			.collect(),
+		peeled: remote
+			.peeled
+			.into_iter()
+			.filter(|(name, _)| is_safe_ref_name(name))
+			.collect(),
+	}
+}
+
+pub fn remote_refs(root: &Path) -> Result<RemoteRefs, Box<dyn Error>> {
+	in_repository(root, || {
+		let refs = list_refs()?;
+		let mut peeled = BTreeMap::new();
+		for (name, object_repr) in refs
+			.iter()
+			.filter(|(name, _)| name.starts_with(TAGS_PREFIX))
+		{
+			let (target, _) = peel_tags(object_repr)?;
+			if &target != object_repr {
+				peeled.insert(name.clone(), target);
+			}
+		}
+		Ok(without_unsafe_names(RemoteRefs {
+			head: read_ref_raw(HEAD)?,
+			refs,
+			peeled,
+		}))
+	})
+}
+
+// Objects reachable from `wants` in the repository at `from` and missing in
+// the current one. Objects the current repository has are assumed complete,
+// so the walk does not descend into them
+pub fn objects_to_transfer(
+	from: &Path,
+	wants: &[ObjectRepr],
+) -> Result<Vec<ObjectRepr>, Box<dyn Error>> {
+	let local_objects = objects_dir_required()?;
+	in_repository(from, || {
+		let mut seen = BTreeSet::new();
+		let mut pending = wants.to_vec();
+		while let Some(object_repr) = pending.pop() {
+			if local_objects.join(object_repr.hash()).is_file() || !seen.insert(object_repr.clone())
+			{
+				continue;
+			}
+			let object = read(&object_repr)?;
+			pending.extend(references(&object).into_iter().map(|(_, r)| r));
+		}
+		Ok(seen.into_iter().collect())
+	})
+}
+
+// Copies object files between object directories. Objects never change, so
+// they can be shared as hard links if both are on the same file system
+pub fn copy_objects(
+	from_objects: &Path,
+	to_objects: &Path,
+	objects: &[ObjectRepr],
+	hardlinks: bool,
+) -> io::Result<()> {
+	fs::create_dir_all(to_objects)?;
+	for object_repr in objects {
+		let from = from_objects.join(object_repr.hash());
+		let to = to_objects.join(object_repr.hash());
+		if to.is_file() {
+			continue;
+		}
+		if !hardlinks || fs::hard_link(&from, &to).is_err() {
+			fs::copy(&from, &to)?;
+		}
+	}
+	Ok(())
+}
+
+// Copies the large files that pointers among `objects` refer to from the store
+// of the repository at `from`. Files it does not have either are left out, they
+// fail to check out
+pub fn copy_lfs_objects(
+	from: &Path,
+	objects: &[ObjectRepr],
+	hardlinks: bool,
+) -> Result<(), Box<dyn Error>> {
+	let files = in_repository(from, || {
+		let mut files = Vec::new();
+		for pointer in pointers(objects.iter().cloned()).into_values() {
+			let path = pointer.path()?;
+			if path.is_file() {
+				files.push((pointer, path));
+			}
+		}
+		Ok(files)
+	})?;
+	for (pointer, path) in files {
+		import(&pointer, &path, hardlinks)?;
+	}
+	Ok(())
+}
+
+impl Source {
+	// An HTTP remote for http(s) URLs, else a repository path
+	pub fn open(url: &str) -> Result<Source, Box<dyn Error>> {
+		if is_http_url(url) {
+			Ok(Source::Http(Box::new(HttpRemote::connect(url)?)))
+		} else {
+			Ok(Source::Local(find_repository(url.as_ref())?))
+		}
+	}
+
+	// The URL to record in the config
+	pub fn url(&self) -> String {
+		match self {
+			Source::Local(root) => root.to_string_lossy().into_owned(),
+			Source::Http(remote) => remote.url(),
+		}
+	}
+
+	// The hash function naming the objects of the other repository
+	pub fn object_format(&self) -> Result<ObjectFormat, Box<dyn Error>> {
+		match self {
+			Source::Local(root) => in_repository(root, || Ok(ObjectFormat::read()?)),
+			Source::Http(remote) => Ok(remote.object_format),
+		}
+	}
+
+	pub fn refs(&mut self) -> Result<RemoteRefs, Box<dyn Error>> {
+		match self {
+			Source::Local(root) => remote_refs(root),
+			Source::Http(remote) => Ok(without_unsafe_names(remote.ls_refs()?)),
+		}
+	}
+
+	// Fetches the objects reachable from `wants` that the current repository
+	// lacks. Local objects may be hard linked, HTTP remotes are told the refs
+	// of the current repository to leave out what they reach
+	pub fn fetch_objects(
+		&mut self,
+		wants: &[ObjectRepr],
+		hardlinks: bool,
+	) -> Result<(), Box<dyn Error>> {
+		let (format, local_format) = (self.object_format()?, ObjectFormat::current());
+		if format != local_format {
+			return Err(format!(
+				"The other repository uses {} objects, this one {}",
+				format, local_format
+			)
+			.into());
+		}
+		match self {
+			Source::Local(root) => {
+				let root_objects = in_repository(root, || Ok(objects_dir_required()?))?;
+				let objects = objects_to_transfer(root, wants)?;
+				copy_objects(&root_objects, &objects_dir_required()?, &objects, hardlinks)?;
+				copy_lfs_objects(root, &objects, hardlinks)?;
+			},
+			Source::Http(remote) => {
+				let local_objects = objects_dir_required()?;
+				let wants = wants
+					.iter()
+					.filter(|w| !local_objects.join(w.hash()).is_file())
+					.cloned()
+					.collect::<BTreeSet<_>>();
+				if wants.is_empty() {
+					return Ok(());
+				}
+				let mut haves = list_refs()?
+					.into_iter()
+					.map(|(_, object_repr)| object_repr)
+					.chain(read_ref(HEAD)?)
+					.collect::<Vec<_>>();
+				haves.sort();
+				haves.dedup();
+				let wants = wants.into_iter().collect::<Vec<_>>();
+				let pack = remote.fetch_pack(&wants, &haves)?;
+				for (object_type, data) in read_pack(&pack)? {
+					insert(&data, object_type)?;
+				}
+			},
+		}
+		Ok(())
+	}
+}
+
+fn is_commit(object_repr: &ObjectRepr) -> Result<bool, Box<dyn Error>> {
+	Ok(read(object_repr)?.object_type == ObjectType::Commit)
+}
+
+// How a ref of the current repository would move from `old` to `new`. Moves
+// that lose commits, and moves of tags, are rejected unless forced. Both
+// objects must be present
+pub fn classify_update(
+	name: &str,
+	old: Option<&ObjectRepr>,
+	new: Option<&ObjectRepr>,
+	force: bool,
+) -> Result<UpdateStatus, Box<dyn Error>> {
+	Ok(match (old, new) {
+		(None, None) => UpdateStatus::UpToDate,
+		(None, Some(_)) => UpdateStatus::New,
+		(Some(_), None) => UpdateStatus::Deleted,
+		(Some(old), Some(new)) if old == new => UpdateStatus::UpToDate,
+		// Tags are not expected to move, not even forward
+		(Some(_), Some(_)) if name.starts_with(TAGS_PREFIX) => {
+			if force {
+				UpdateStatus::Forced
+			} else {
+				UpdateStatus::Rejected("would clobber existing tag".to_owned())
+			}
+		},
+		(Some(old), Some(new)) if is_commit(old)? && is_commit(new)? && is_ancestor(old, new)? => {
+			UpdateStatus::FastForward
+		},
+		_ if force => UpdateStatus::Forced,
+		_ => UpdateStatus::Rejected("non-fast-forward".to_owned()),
+	})
+}
+
+impl RefUpdate {
+	pub fn is_rejected(&self) -> bool {
+		matches!(self.status, UpdateStatus::Rejected(_))
+	}
+}
+
+fn short_ref_name(name: &str) -> &str {
+	[HEADS_PREFIX, TAGS_PREFIX, "refs/remotes/"]
+		.iter()
+		.find_map(|prefix| name.strip_prefix(prefix))
+		.unwrap_or(name)
+}
+
+fn short_hash(object_repr: &Option<ObjectRepr>) -> &str {
+	object_repr.as_ref().map_or("", |r| r.short())
+}
+
+// One line in the style of git, e.g.
+// "   64234ca..dd2fc06  master -> origin/master"
+impl Display for RefUpdate {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		let kind = if self.dst.starts_with(TAGS_PREFIX)
+			|| (self.status == UpdateStatus::Fetched && self.src.starts_with(TAGS_PREFIX))
+		{
+			"tag"
+		} else {
+			"branch"
+		};
+		let (flag, summary, note) = match &self.status {
+			UpdateStatus::New => ('*', format!("[new {}]", kind), String::new()),
+			UpdateStatus::UpToDate => ('=', "[up to date]".to_owned(), String::new()),
+			UpdateStatus::FastForward => (
+				' ',
+				format!("{}..{}", short_hash(&self.old), short_hash(&self.new)),
+				String::new(),
+			),
+			UpdateStatus::Forced => (
+				'+',
+				format!("{}...{}", short_hash(&self.old), short_hash(&self.new)),
+				" (forced update)".to_owned(),
+			),
+			UpdateStatus::Deleted => ('-', "[deleted]".to_owned(), String::new()),
+			UpdateStatus::Fetched => ('*', kind.to_owned(), String::new()),
+			UpdateStatus::Rejected(reason) => {
+				('!', "[rejected]".to_owned(), format!(" ({})", reason))
+			},
+		};
+		let src = if self.src.is_empty() {
+			"(none)"
+		} else {
+			short_ref_name(&self.src)
+		};
+		write!(
+			f,
+			" {} {:<17} {} -> {}{}",
+			flag,
+			summary,
+			src,
+			short_ref_name(&self.dst),
+			note
+		)
+	}
+}
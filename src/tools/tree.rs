@@ -0,0 +1,287 @@
+use crate::tools::{
+	db::{insert, read, DbError, ObjectRepr, ObjectType},
+	object_format::ObjectFormat,
+	path::{is_reserved_name, RelativePathToBase},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::BTreeMap,
+	fs::Metadata,
+	path::{Component, Path},
+	str::from_utf8,
+};
+use thiserror::Error;
+use TreeError::{MalformedError, NotATreeError, ObjectError};
+
+#[derive(Error, Debug)]
+pub enum TreeError {
+	#[error("{source:?}")]
+	ObjectError { source: DbError },
+	#[error("Object {hash} is not a tree")]
+	NotATreeError { hash: String },
+	#[error("Malformed tree: {msg}")]
+	MalformedError { msg: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileMode {
+	#[default]
+	Regular,
+	Executable,
+	Symlink,
+	Directory,
+	Gitlink,
+}
+
+#[derive(Debug, Clone)]
+pub struct TreeEntry {
+	pub mode: FileMode,
+	pub name: String,
+	pub hash: ObjectRepr,
+}
+
+#[derive(Debug, Clone)]
+pub struct Tree {
+	pub entries: Vec<TreeEntry>,
+}
+
+// A tree with all subtrees expanded, keyed by the path of each file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlatEntry {
+	pub mode: FileMode,
+	pub hash: ObjectRepr,
+}
+
+pub type FlatTree = BTreeMap<RelativePathToBase, FlatEntry>;
+
+impl FileMode {
+	pub fn from_octal(repr: &str) -> Option<FileMode> {
+		match repr {
+			"100644" | "100664" => Some(FileMode::Regular),
+			"100755" => Some(FileMode::Executable),
+			"120000" => Some(FileMode::Symlink),
+			"40000" | "040000" => Some(FileMode::Directory),
+			"160000" => Some(FileMode::Gitlink),
+			_ => None,
+		}
+	}
+
+	pub fn octal(self) -> &'static str {
+		match self {
+			FileMode::Regular => "100644",
+			FileMode::Executable => "100755",
+			FileMode::Symlink => "120000",
+			FileMode::Directory => "40000",
+			FileMode::Gitlink => "160000",
+		}
+	}
+
+	// Type of the object an entry with this mode points to
+	pub fn object_type(self) -> ObjectType {
+		match self {
+			FileMode::Directory => ObjectType::Tree,
+			FileMode::Gitlink => ObjectType::Commit,
+			_ => ObjectType::Blob,
+		}
+	}
+
+	#[cfg(unix)]
+	pub fn from_metadata(metadata: &Metadata) -> FileMode {
+		use std::os::unix::fs::PermissionsExt;
+		if metadata.file_type().is_symlink() {
+			FileMode::Symlink
+		} else if metadata.permissions().mode() & 0o111 != 0 {
+			FileMode::Executable
+		} else {
+			FileMode::Regular
+		}
+	}
+
+	#[cfg(not(unix))]
+	pub fn from_metadata(metadata: &Metadata) -> FileMode {
+		if metadata.file_type().is_symlink() {
+			FileMode::Symlink
+		} else {
+			FileMode::Regular
+		}
+	}
+}
+
+impl Tree {
+	// Like parse_structure, but names that would write into a VCS directory,
+	// e.g. ".git" or ".unrusty", are refused
+	pub fn parse(data: &[u8]) -> Result<Tree, TreeError> {
+		let tree = Tree::parse_structure(data)?;
+		if let Some(entry) = tree.entries.iter().find(|e| is_reserved_name(&e.name)) {
+			return Err(malformed(&format!("reserved name {:?}", entry.name)));
+		}
+		Ok(tree)
+	}
+
+	// Format: repeated "<octal mode> <name>\0<binary hash>", with hashes of the
+	// length of the object format. Reserved names are accepted, so that the
+	// objects of such trees can still be walked, e.g. by gc
+	pub fn parse_structure(data: &[u8]) -> Result<Tree, TreeError> {
+		let hash_len = ObjectFormat::current().hash_len();
+		let mut entries = Vec::new();
+		let mut rest = data;
+		while !rest.is_empty() {
+			let space = rest
+				.iter()
+				.position(|&b| b == b' ')
+				.ok_or_else(|| malformed("missing space after mode"))?;
+			let null = rest
+				.iter()
+				.position(|&b| b == b'\0')
+				.ok_or_else(|| malformed("missing null byte after name"))?;
+			if null < space || rest.len() < null + 1 + hash_len {
+				return Err(malformed("truncated entry"));
+			}
+			let mode = from_utf8(&rest[..space])
+				.ok()
+				.and_then(FileMode::from_octal)
+				.ok_or_else(|| malformed("invalid mode"))?;
+			let name = from_utf8(&rest[(space + 1)..null])
+				.map_err(|_| malformed("name is not valid UTF-8"))?;
+			if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+				return Err(malformed(&format!("invalid name {:?}", name)));
+			}
+			let hash = ObjectRepr::from_bytes(&rest[(null + 1)..(null + 1 + hash_len)])
+				.map_err(|_| malformed("invalid hash"))?;
+			entries.push(TreeEntry {
+				mode,
+				name: name.to_owned(),
+				hash,
+			});
+			rest = &rest[(null + 1 + hash_len)..];
+		}
+		Ok(Tree { entries })
+	}
+
+	// Entries are sorted by name, where directories sort as if their name ended
+	// with a slash
+	pub fn serialize(&self) -> Vec<u8> {
+		let mut entries = self.entries.iter().collect::<Vec<_>>();
+		entries.sort_by_key(|e| sort_key(e));
+		let mut data = Vec::new();
+		for entry in entries {
+			data.extend_from_slice(entry.mode.octal().as_bytes());
+			data.push(b' ');
+			data.extend_from_slice(entry.name.as_bytes());
+			data.push(b'\0');
+			data.extend_from_slice(&entry.hash.to_bytes());
+		}
+		data
+	}
+
+	// Whether the entries are in the order serialize writes them, without
+	// duplicate names
+	pub fn is_sorted(&self) -> bool {
+		self.entries
+			.windows(2)
+			.all(|pair| sort_key(&pair[0]) < sort_key(&pair[1]))
+	}
+
+	pub fn write(&self) -> Result<ObjectRepr, TreeError> {
+		insert(&self.serialize(), ObjectType::Tree).map_err(|e| ObjectError { source: e })
+	}
+
+	pub fn read(object_repr: &ObjectRepr) -> Result<Tree, TreeError> {
+		let object = read(object_repr).map_err(|e| ObjectError { source: e })?;
+		match object.object_type {
+			ObjectType::Tree => Tree::parse(&object.data),
+			_ => Err(NotATreeError {
+				hash: object_repr.to_string(),
+			}),
+		}
+	}
+}
+
+fn sort_key(entry: &TreeEntry) -> Vec<u8> {
+	let mut key = entry.name.as_bytes().to_vec();
+	if entry.mode == FileMode::Directory {
+		key.push(b'/');
+	}
+	key
+}
+
+fn malformed(msg: &str) -> TreeError {
+	MalformedError {
+		msg: msg.to_owned(),
+	}
+}
+
+pub fn flatten_tree(object_repr: &ObjectRepr) -> Result<FlatTree, TreeError> {
+	let mut flat = FlatTree::new();
+	flatten_tree_helper(object_repr, Path::new(""), &mut flat)?;
+	Ok(flat)
+}
+
+fn flatten_tree_helper(
+	object_repr: &ObjectRepr,
+	prefix: &Path,
+	flat: &mut FlatTree,
+) -> Result<(), TreeError> {
+	for entry in Tree::read(object_repr)?.entries {
+		let path = prefix.join(&entry.name);
+		if entry.mode == FileMode::Directory {
+			flatten_tree_helper(&entry.hash, &path, flat)?;
+		} else {
+			flat.insert(
+				RelativePathToBase::new(path),
+				FlatEntry {
+					mode: entry.mode,
+					hash: entry.hash,
+				},
+			);
+		}
+	}
+	Ok(())
+}
+
+// Writes the nested tree objects for all files and returns the root tree
+pub fn write_flat_tree(flat: &FlatTree) -> Result<ObjectRepr, TreeError> {
+	let entries = flat
+		.iter()
+		.map(|(path, entry)| {
+			let components = path
+				.as_path()
+				.components()
+				.filter_map(|c| match c {
+					Component::Normal(name) => name.to_str().map(|s| s.to_owned()),
+					_ => None,
+				})
+				.collect::<Vec<_>>();
+			(components, entry)
+		})
+		.collect::<Vec<_>>();
+	write_flat_tree_helper(&entries)
+}
+
+fn write_flat_tree_helper(entries: &[(Vec<String>, &FlatEntry)]) -> Result<ObjectRepr, TreeError> {
+	// Group by first component, subdirectories become subtrees
+	let mut files = Vec::new();
+	let mut dirs: BTreeMap<&str, Vec<(Vec<String>, &FlatEntry)>> = BTreeMap::new();
+	for (components, entry) in entries {
+		match components.split_first() {
+			Some((name, [])) => files.push(TreeEntry {
+				mode: entry.mode,
+				name: name.clone(),
+				hash: entry.hash.clone(),
+			}),
+			Some((name, rest)) => dirs
+				.entry(name.as_str())
+				.or_default()
+				.push((rest.to_vec(), *entry)),
+			None => {},
+		}
+	}
+	for (name, sub_entries) in dirs {
+		files.push(TreeEntry {
+			mode: FileMode::Directory,
+			name: name.to_owned(),
+			hash: write_flat_tree_helper(&sub_entries)?,
+		});
+	}
+	Tree { entries: files }.write()
+}
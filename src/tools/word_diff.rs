@@ -0,0 +1,129 @@
+// Differences between changed lines word by word, in the styles of git diff
+// --word-diff. Words are runs of non-whitespace, the whitespace between them
+// is taken from the new side
+use crate::tools::{
+	diff::hunks,
+	patch::{GREEN, RED, RESET},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WordDiff {
+	// [-removed-]{+added+}
+	Plain,
+	// Removed and added words in red and green
+	Color,
+	// One line per run of words, prefixed like patch lines
+	Porcelain,
+}
+
+impl WordDiff {
+	pub fn from_name(name: &str) -> Option<WordDiff> {
+		match name {
+			"plain" => Some(WordDiff::Plain),
+			"color" => Some(WordDiff::Color),
+			"porcelain" => Some(WordDiff::Porcelain),
+			_ => None,
+		}
+	}
+
+	// Color, prefix and suffix of unchanged, removed and added text
+	fn styles(self) -> [(&'static str, &'static str, &'static str); 3] {
+		match self {
+			WordDiff::Plain => [("", "", ""), ("", "[-", "-]"), ("", "{+", "+}")],
+			WordDiff::Color => [("", "", ""), (RED, "", ""), (GREEN, "", "")],
+			WordDiff::Porcelain => [("", " ", "\n"), ("", "-", "\n"), ("", "+", "\n")],
+		}
+	}
+
+	// What newlines in the text are shown as
+	fn newline(self) -> &'static [u8] {
+		match self {
+			WordDiff::Porcelain => b"~\n",
+			_ => b"\n",
+		}
+	}
+}
+
+const CONTEXT: usize = 0;
+const REMOVED: usize = 1;
+const ADDED: usize = 2;
+
+// Start and end of every word
+fn words(text: &[u8]) -> Vec<(usize, usize)> {
+	let mut words = Vec::new();
+	let mut i = 0;
+	while i < text.len() {
+		while i < text.len() && text[i].is_ascii_whitespace() {
+			i += 1;
+		}
+		let start = i;
+		while i < text.len() && !text[i].is_ascii_whitespace() {
+			i += 1;
+		}
+		if start < i {
+			words.push((start, i));
+		}
+	}
+	words
+}
+
+// Styles every line of `text` on its own
+fn write_text(out: &mut Vec<u8>, text: &[u8], mode: WordDiff, kind: usize) {
+	let (color, prefix, suffix) = mode.styles()[kind];
+	let mut rest = text;
+	loop {
+		let end = rest.iter().position(|&b| b == b'\n');
+		let line = &rest[..end.unwrap_or(rest.len())];
+		if !line.is_empty() {
+			out.extend_from_slice(color.as_bytes());
+			out.extend_from_slice(prefix.as_bytes());
+			out.extend_from_slice(line);
+			out.extend_from_slice(suffix.as_bytes());
+			if !color.is_empty() {
+				out.extend_from_slice(RESET.as_bytes());
+			}
+		}
+		match end {
+			Some(end) => {
+				out.extend_from_slice(mode.newline());
+				rest = &rest[end + 1..];
+			},
+			None => return,
+		}
+	}
+}
+
+// The removed lines `old` replaced by the added lines `new`, as one text with
+// the changed words marked
+pub fn write_word_diff(out: &mut Vec<u8>, old: &[u8], new: &[u8], mode: WordDiff) {
+	if new.is_empty() {
+		write_text(out, old, mode, REMOVED);
+		return;
+	}
+	let (old_words, new_words) = (words(old), words(new));
+	let old_tokens = old_words
+		.iter()
+		.map(|&(s, e)| &old[s..e])
+		.collect::<Vec<_>>();
+	let new_tokens = new_words
+		.iter()
+		.map(|&(s, e)| &new[s..e])
+		.collect::<Vec<_>>();
+	// Byte range of words, empty ranges are placed after the word before them
+	let range = |words: &[(usize, usize)], start: usize, len: usize| match (start, len) {
+		(0, 0) => (0, 0),
+		(start, 0) => (words[start - 1].1, words[start - 1].1),
+		(start, len) => (words[start - 1].0, words[start + len - 2].1),
+	};
+
+	let mut shown = 0;
+	for hunk in hunks(&old_tokens, &new_tokens, 0) {
+		let (old_start, old_end) = range(&old_words, hunk.old_start, hunk.old_len);
+		let (new_start, new_end) = range(&new_words, hunk.new_start, hunk.new_len);
+		write_text(out, &new[shown..new_start], mode, CONTEXT);
+		write_text(out, &old[old_start..old_end], mode, REMOVED);
+		write_text(out, &new[new_start..new_end], mode, ADDED);
+		shown = new_end;
+	}
+	write_text(out, &new[shown..], mode, CONTEXT);
+}
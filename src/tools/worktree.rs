@@ -0,0 +1,353 @@
+use crate::tools::{
+	attributes::Attributes,
+	db::{read, DbError},
+	filter::{FilterError, Filters},
+	index::Index,
+	merge::TreeMerge,
+	path::{root_path_required, vcs_dir_from_base, PathError, RelativePathToBase},
+	tree::{FileMode, FlatEntry, FlatTree},
+};
+use std::{
+	error::Error,
+	fs, io,
+	io::ErrorKind,
+	path::{Path, PathBuf},
+};
+use thiserror::Error;
+use WorktreeError::{
+	ConvertError, NoRepoError, ObjectError, ReadError, SymlinkInPathError, UnsafePathError,
+	WriteError,
+};
+
+#[derive(Error, Debug)]
+pub enum WorktreeError {
+	#[error("{source:?}")]
+	NoRepoError { source: PathError },
+	#[error("{source:?}")]
+	ObjectError { source: DbError },
+	#[error("Failed writing {path:?}: {source:?}")]
+	WriteError { path: PathBuf, source: io::Error },
+	#[error("Failed reading {path:?}: {source:?}")]
+	ReadError { path: PathBuf, source: io::Error },
+	#[error("{source:?}")]
+	ConvertError { source: FilterError },
+	#[error("Refusing to write {path}, it is outside the working tree or in a VCS directory")]
+	UnsafePathError { path: RelativePathToBase },
+	#[error("Refusing to write {path} through the symbolic link {link:?}")]
+	SymlinkInPathError {
+		path: RelativePathToBase,
+		link: PathBuf,
+	},
+}
+
+fn write_error(path: &Path) -> impl FnOnce(io::Error) -> WorktreeError + '_ {
+	move |e| WriteError {
+		path:   path.to_path_buf(),
+		source: e,
+	}
+}
+
+// Replaces the file at `path` with the content of the blob in `entry`
+pub fn checkout_entry(
+	path: &RelativePathToBase,
+	entry: &FlatEntry,
+	filters: &Filters,
+) -> Result<(), WorktreeError> {
+	let object = read(&entry.hash).map_err(|e| ObjectError { source: e })?;
+	checkout_content(path, entry.mode, &object.data, filters)
+}
+
+// The first of the directories leading to the file that is a symbolic link.
+// Following it would write outside of the working tree
+fn symlink_in_path(path: &RelativePathToBase) -> Result<Option<PathBuf>, WorktreeError> {
+	let mut dir = root_path_required().map_err(|e| NoRepoError { source: e })?;
+	let parent = path.as_path().parent().unwrap_or_else(|| Path::new(""));
+	for component in parent.components() {
+		dir.push(component);
+		match fs::symlink_metadata(&dir) {
+			Ok(metadata) if metadata.file_type().is_symlink() => return Ok(Some(dir)),
+			Ok(_) => {},
+			Err(e) if e.kind() == ErrorKind::NotFound => break,
+			Err(e) => {
+				return Err(ReadError {
+					path:   dir,
+					source: e,
+				})
+			},
+		}
+	}
+	Ok(None)
+}
+
+// Content is given as stored, files get it through the smudge filters. Paths
+// into VCS directories or through symbolic links are refused, as trees and
+// patches come from elsewhere
+pub fn checkout_content(
+	path: &RelativePathToBase,
+	mode: FileMode,
+	content: &[u8],
+	filters: &Filters,
+) -> Result<(), WorktreeError> {
+	if !path.is_safe() {
+		return Err(UnsafePathError { path: path.clone() });
+	}
+	if let Some(link) = symlink_in_path(path)? {
+		return Err(SymlinkInPathError {
+			path: path.clone(),
+			link,
+		});
+	}
+	let absolute = path.absolute().map_err(|e| NoRepoError { source: e })?;
+	if let Some(parent) = absolute.parent() {
+		fs::create_dir_all(parent).map_err(write_error(&absolute))?;
+	}
+	remove_if_exists(&absolute)?;
+	match mode {
+		FileMode::Symlink => write_symlink(&absolute, content),
+		_ => {
+			let content = filters
+				.smudge(path, content.to_vec())
+				.map_err(|e| ConvertError { source: e })?;
+			fs::write(&absolute, content).map_err(write_error(&absolute))?;
+			set_executable(&absolute, mode == FileMode::Executable)
+		},
+	}
+}
+
+// Removes the file and any directories that became empty through this. Files
+// behind symbolic links are not in the working tree and stay
+pub fn remove_entry(path: &RelativePathToBase) -> Result<(), WorktreeError> {
+	if !path.is_safe() || symlink_in_path(path)?.is_some() {
+		return Ok(());
+	}
+	let absolute = path.absolute().map_err(|e| NoRepoError { source: e })?;
+	remove_if_exists(&absolute)?;
+	let root = RelativePathToBase::new(PathBuf::new())
+		.absolute()
+		.map_err(|e| NoRepoError { source: e })?;
+	let mut dir = absolute.parent();
+	while let Some(d) = dir {
+		if d == root || fs::remove_dir(d).is_err() {
+			break;
+		}
+		dir = d.parent();
+	}
+	Ok(())
+}
+
+// Whether going from `from` to `to` changes the files attributes are read from
+fn attributes_changed(from: &FlatTree, to: &FlatTree) -> bool {
+	from.keys()
+		.chain(to.keys())
+		.any(|path| Attributes::is_attributes_file(path) && from.get(path) != to.get(path))
+}
+
+// Moves the working tree and the index from `from` to `to`. Only paths that
+// differ are written, unless `force` is set. Attribute files are written
+// first, the other files are converted as they say
+pub fn switch_tree(
+	index: &mut Index,
+	from: &FlatTree,
+	to: &FlatTree,
+	force: bool,
+	filters: &Filters,
+) -> Result<(), Box<dyn Error>> {
+	for path in from.keys().filter(|p| !to.contains_key(p)) {
+		remove_entry(path)?;
+		index.remove(path);
+	}
+	let tracked = index.flat_tree();
+	let (attributes, others): (Vec<_>, Vec<_>) = to
+		.iter()
+		.partition(|(path, _)| Attributes::is_attributes_file(path));
+	for (path, entry) in attributes {
+		switch_entry(index, &tracked, from, path, entry, force, filters)?;
+	}
+	let reloaded;
+	let filters = if force || attributes_changed(from, to) {
+		reloaded = Filters::read()?;
+		&reloaded
+	} else {
+		filters
+	};
+	for (path, entry) in others {
+		switch_entry(index, &tracked, from, path, entry, force, filters)?;
+	}
+	Ok(())
+}
+
+fn switch_entry(
+	index: &mut Index,
+	tracked: &FlatTree,
+	from: &FlatTree,
+	path: &RelativePathToBase,
+	entry: &FlatEntry,
+	force: bool,
+	filters: &Filters,
+) -> Result<(), Box<dyn Error>> {
+	if force || from.get(path) != Some(entry) {
+		checkout_entry(path, entry, filters)?;
+		index.set_entry(path.clone(), entry, true)?;
+	} else if tracked.get(path) != Some(entry) {
+		index.set_entry(path.clone(), entry, false)?;
+	}
+	Ok(())
+}
+
+// Writes the result of a merge over the working tree and the index, which
+// hold `from`. Conflicted paths get their content with conflict markers and
+// their stages in the index
+pub fn checkout_merge(
+	index: &mut Index,
+	from: &FlatTree,
+	merge: &TreeMerge,
+	filters: &Filters,
+) -> Result<(), Box<dyn Error>> {
+	let mut from = from.clone();
+	for conflict in &merge.conflicts {
+		from.remove(&conflict.path);
+	}
+	switch_tree(index, &from, &merge.tree, false, filters)?;
+	let reloaded;
+	let filters = if attributes_changed(&from, &merge.tree) {
+		reloaded = Filters::read()?;
+		&reloaded
+	} else {
+		filters
+	};
+	for conflict in &merge.conflicts {
+		let (mode, content) = &conflict.worktree;
+		checkout_content(&conflict.path, *mode, content, filters)?;
+		index.set_conflict(
+			conflict.path.clone(),
+			conflict.base.as_ref(),
+			conflict.ours.as_ref(),
+			conflict.theirs.as_ref(),
+		);
+	}
+	Ok(())
+}
+
+// Paths about to be written that hold files unknown to the index
+pub fn untracked_in_the_way(
+	index: &Index,
+	to: &FlatTree,
+) -> Result<Vec<RelativePathToBase>, Box<dyn Error>> {
+	let tracked = index.flat_tree();
+	let mut in_the_way = Vec::new();
+	for path in to.keys().filter(|p| !tracked.contains_key(p)) {
+		if fs::symlink_metadata(path.absolute()?).is_ok() {
+			in_the_way.push(path.clone());
+		}
+	}
+	Ok(in_the_way)
+}
+
+// All files and symlinks in the working tree, outside the VCS directory,
+// sorted by path
+pub fn list_worktree_files() -> Result<Vec<RelativePathToBase>, WorktreeError> {
+	let root = root_path_required().map_err(|e| NoRepoError { source: e })?;
+	let vcs_dir = vcs_dir_from_base(&root);
+	let mut files = Vec::new();
+	let mut dirs = vec![root.clone()];
+	while let Some(dir) = dirs.pop() {
+		let read_error = |e| ReadError {
+			path:   dir.clone(),
+			source: e,
+		};
+		for entry in fs::read_dir(&dir).map_err(read_error)? {
+			let entry = entry.map_err(read_error)?;
+			let path = entry.path();
+			if path == vcs_dir {
+				continue;
+			}
+			if entry.file_type().map_err(read_error)?.is_dir() {
+				dirs.push(path);
+			} else if let Ok(relative) = path.strip_prefix(&root) {
+				files.push(RelativePathToBase::new(relative.to_path_buf()));
+			}
+		}
+	}
+	files.sort();
+	Ok(files)
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), WorktreeError> {
+	match fs::symlink_metadata(path) {
+		Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path).map_err(write_error(path)),
+		Ok(_) => fs::remove_file(path).map_err(write_error(path)),
+		Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+		Err(e) => Err(write_error(path)(e)),
+	}
+}
+
+// The mode and content of the file at `path` as they would be stored, through
+// the clean filters. None if there is no file
+pub fn read_worktree_entry(
+	path: &RelativePathToBase,
+	filters: &Filters,
+) -> Result<Option<(FileMode, Vec<u8>)>, WorktreeError> {
+	let absolute = path.absolute().map_err(|e| NoRepoError { source: e })?;
+	let read_error = |e| ReadError {
+		path:   absolute.clone(),
+		source: e,
+	};
+	let metadata = match fs::symlink_metadata(&absolute) {
+		Ok(metadata) => metadata,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(read_error(e)),
+	};
+	let mode = FileMode::from_metadata(&metadata);
+	let content = match mode {
+		FileMode::Symlink => read_symlink(&absolute).map_err(read_error)?,
+		_ => {
+			let content = fs::read(&absolute).map_err(read_error)?;
+			filters
+				.clean(path, content)
+				.map_err(|e| ConvertError { source: e })?
+		},
+	};
+	Ok(Some((mode, content)))
+}
+
+#[cfg(unix)]
+fn write_symlink(path: &Path, target: &[u8]) -> Result<(), WorktreeError> {
+	use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+	std::os::unix::fs::symlink(OsStr::from_bytes(target), path).map_err(write_error(path))
+}
+
+// Without symlink support, the link is checked out as a file holding the
+// target, like git does with core.symlinks=false
+#[cfg(not(unix))]
+fn write_symlink(path: &Path, target: &[u8]) -> Result<(), WorktreeError> {
+	fs::write(path, target).map_err(write_error(path))
+}
+
+#[cfg(unix)]
+fn read_symlink(path: &Path) -> io::Result<Vec<u8>> {
+	use std::os::unix::ffi::OsStrExt;
+	Ok(fs::read_link(path)?.as_os_str().as_bytes().to_vec())
+}
+
+#[cfg(not(unix))]
+fn read_symlink(path: &Path) -> io::Result<Vec<u8>> {
+	fs::read(path)
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path, executable: bool) -> Result<(), WorktreeError> {
+	use std::os::unix::fs::PermissionsExt;
+	let mut permissions = fs::metadata(path).map_err(write_error(path))?.permissions();
+	let mode = if executable {
+		permissions.mode() | 0o111
+	} else {
+		permissions.mode() & !0o111
+	};
+	permissions.set_mode(mode);
+	fs::set_permissions(path, permissions).map_err(write_error(path))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path, _executable: bool) -> Result<(), WorktreeError> {
+	Ok(())
+}
@@ -0,0 +1,3 @@
+pub mod upload_pack_cmd;
+pub mod upload_pack_do;
+mod upload_pack_parse;
@@ -0,0 +1,20 @@
+use super::{upload_pack_do::upload_pack, upload_pack_parse::Args};
+use crate::tools::failed::failed;
+use std::{io, path::Path};
+
+pub fn cmd_upload_pack<'a, I, J>(argv_it: I)
+where
+	I: IntoIterator<Item = &'a J>,
+	J: AsRef<str> + 'a,
+{
+	let args = Args::from_cmd(argv_it);
+
+	let res = upload_pack(
+		Path::new(&args.arg_directory),
+		&mut io::stdin().lock(),
+		&mut io::stdout().lock(),
+	);
+	if let Err(e) = res {
+		failed(&format!("Failed upload-pack: {:?}", e));
+	}
+}
@@ -0,0 +1,465 @@
+use crate::tools::{
+	db::{read, ObjectRepr, ObjectType},
+	object_format::ObjectFormat,
+	pack::write_pack,
+	pktline::{
+		read_until_flush, write_band, write_line, write_packet, Packet, AGENT, BAND_DATA,
+		BAND_PROGRESS,
+	},
+	reachable::objects_between,
+	refs::{list_refs, read_ref, read_ref_raw, RefValue, HEAD},
+	remote::TAGS_PREFIX,
+	rev::peel_tags,
+	transport::{find_repository, in_repository},
+};
+use std::{
+	collections::BTreeSet,
+	convert::TryFrom,
+	error::Error,
+	io::{Read, Write},
+	path::Path,
+};
+
+// A command of the client: its name, capabilities and arguments
+struct Request {
+	command:   String,
+	arguments: Vec<String>,
+}
+
+fn parse_request(packets: &[Packet]) -> Result<Request, Box<dyn Error>> {
+	let mut lines = packets.iter();
+	let command = lines
+		.next()
+		.and_then(|p| p.text())
+		.and_then(|line| line.strip_prefix("command="))
+		.ok_or("Request without command")?
+		.to_owned();
+	// Capabilities of the client are not needed, they end at the delimiter
+	let arguments = lines
+		.skip_while(|p| **p != Packet::Delim)
+		.skip(1)
+		.map(|p| p.text().map(str::to_owned).ok_or("Invalid argument"))
+		.collect::<Result<_, _>>()?;
+	Ok(Request { command, arguments })
+}
+
+fn parse_object(hash: &str) -> Result<ObjectRepr, Box<dyn Error>> {
+	ObjectRepr::try_from(hash).map_err(|_| format!("Invalid object {}", hash).into())
+}
+
+fn advertise_capabilities(output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+	write_line(output, "version 2")?;
+	write_line(output, &format!("agent={}", AGENT))?;
+	write_line(output, "ls-refs=unborn")?;
+	write_line(output, "fetch")?;
+	write_line(
+		output,
+		&format!("object-format={}", ObjectFormat::current()),
+	)?;
+	write_packet(output, &Packet::Flush)?;
+	Ok(())
+}
+
+// Lists HEAD and the refs, optionally restricted to some prefixes, with the
+// targets of symbolic refs and the objects annotated tags peel to
+fn ls_refs(arguments: &[String], output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+	let symrefs = arguments.iter().any(|a| a == "symrefs");
+	let peel = arguments.iter().any(|a| a == "peel");
+	let unborn = arguments.iter().any(|a| a == "unborn");
+	let prefixes = arguments
+		.iter()
+		.filter_map(|a| a.strip_prefix("ref-prefix "))
+		.collect::<Vec<_>>();
+	let wanted = |name: &str| prefixes.is_empty() || prefixes.iter().any(|p| name.starts_with(p));
+
+	let mut refs = Vec::new();
+	if wanted(HEAD) {
+		let target = match read_ref_raw(HEAD)? {
+			Some(RefValue::Symbolic(target)) => Some(target),
+			_ => None,
+		};
+		match (read_ref(HEAD)?, target) {
+			(Some(object_repr), target) => {
+				refs.push((object_repr.to_string(), HEAD.to_owned(), target))
+			},
+			(None, Some(target)) if unborn => {
+				refs.push(("unborn".to_owned(), HEAD.to_owned(), Some(target)))
+			},
+			_ => {},
+		}
+	}
+	for (name, object_repr) in list_refs()? {
+		if wanted(&name) {
+			refs.push((object_repr.to_string(), name, None));
+		}
+	}
+
+	for (object, name, target) in refs {
+		let mut line = format!("{} {}", object, name);
+		if let (true, Some(target)) = (symrefs, target) {
+			line.push_str(&format!(" symref-target:{}", target));
+		}
+		if let (true, Ok(object_repr)) = (peel, ObjectRepr::try_from(object.as_str())) {
+			let (peeled, _) = peel_tags(&object_repr)?;
+			if peeled != object_repr {
+				line.push_str(&format!(" peeled:{}", peeled));
+			}
+		}
+		write_line(output, &line)?;
+	}
+	write_packet(output, &Packet::Flush)?;
+	Ok(())
+}
+
+// Annotated tags pointing to objects that are sent, or to other such tags
+fn tags_to_include(objects: &BTreeSet<ObjectRepr>) -> Result<Vec<ObjectRepr>, Box<dyn Error>> {
+	let mut tags = Vec::new();
+	for (name, object_repr) in list_refs()? {
+		if !name.starts_with(TAGS_PREFIX) || objects.contains(&object_repr) {
+			continue;
+		}
+		let (peeled, _) = peel_tags(&object_repr)?;
+		if objects.contains(&peeled) && read(&object_repr)?.object_type == ObjectType::Tag {
+			tags.push(object_repr);
+		}
+	}
+	Ok(tags)
+}
+
+// Negotiates the objects to send and sends them as a pack. Haves the
+// repository knows are acknowledged, and once there is one the pack is sent
+// right away: it may contain objects the client has but did not mention yet,
+// which is cheaper than more rounds of negotiation
+fn fetch(arguments: &[String], output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+	let mut wants = Vec::new();
+	let mut common = Vec::new();
+	let mut done = false;
+	let mut progress = true;
+	let mut include_tag = false;
+	for argument in arguments {
+		let (name, value) = argument.split_once(' ').unwrap_or((argument, ""));
+		match name {
+			"want" => {
+				let object_repr = parse_object(value)?;
+				if !object_repr.path()?.is_file() {
+					write_line(output, &format!("ERR upload-pack: not our ref {}", value))?;
+					return Err(format!("Unknown object {} wanted", value).into());
+				}
+				wants.push(object_repr);
+			},
+			"have" => {
+				let object_repr = parse_object(value)?;
+				if object_repr.path()?.is_file() {
+					common.push(object_repr);
+				}
+			},
+			"done" => done = true,
+			"no-progress" => progress = false,
+			"include-tag" => include_tag = true,
+			// Packs are never thin and never contain deltas
+			"thin-pack" | "ofs-delta" => {},
+			_ => return Err(format!("Unsupported fetch argument {:?}", argument).into()),
+		}
+	}
+
+	if !done {
+		write_line(output, "acknowledgments")?;
+		if common.is_empty() {
+			write_line(output, "NAK")?;
+		}
+		for object_repr in &common {
+			write_line(output, &format!("ACK {}", object_repr))?;
+		}
+		if common.is_empty() {
+			write_packet(output, &Packet::Flush)?;
+			return Ok(());
+		}
+		write_line(output, "ready")?;
+		write_packet(output, &Packet::Delim)?;
+	}
+
+	let mut objects = objects_between(&wants, &common)?;
+	if include_tag {
+		let sent = objects.iter().cloned().collect();
+		let tags = tags_to_include(&sent)?;
+		objects.extend(
+			objects_between(&tags, &common)?
+				.into_iter()
+				.filter(|o| !sent.contains(o)),
+		);
+	}
+	let mut pack = Vec::new();
+	write_pack(&mut pack, &objects)?;
+	write_line(output, "packfile")?;
+	if progress {
+		let message = format!("Total {} (delta 0), reused 0 (delta 0)\n", objects.len());
+		write_band(output, BAND_PROGRESS, message.as_bytes())?;
+	}
+	write_band(output, BAND_DATA, &pack)?;
+	write_packet(output, &Packet::Flush)?;
+	Ok(())
+}
+
+// Serves fetches from the repository at `directory` with protocol v2: the
+// capabilities are advertised, then the commands of the client are answered
+// until it sends a flush packet or closes the input
+pub fn upload_pack(
+	directory: &Path,
+	input: &mut dyn Read,
+	output: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+	let root = find_repository(directory)?;
+	in_repository(&root, || {
+		advertise_capabilities(output)?;
+		output.flush()?;
+		loop {
+			let packets = read_until_flush(input)?;
+			if packets.is_empty() {
+				return Ok(());
+			}
+			let request = parse_request(&packets)?;
+			match request.command.as_str() {
+				"ls-refs" => ls_refs(&request.arguments, output)?,
+				"fetch" => fetch(&request.arguments, output)?,
+				command => return Err(format!("Unknown command {}", command).into()),
+			}
+			output.flush()?;
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tools::{
+		db::{insert, simulate_insert},
+		pack::read_pack,
+		pktline::read_packet,
+		refs::{update_ref, write_ref},
+		testing::{commit, lock, TempRepo},
+	};
+	use std::slice;
+
+	// Two commits on master, the second one tagged v1 with an annotated tag
+	struct Fixture {
+		repo:   TempRepo,
+		first:  ObjectRepr,
+		second: ObjectRepr,
+		tag:    ObjectRepr,
+	}
+
+	fn fixture() -> Fixture {
+		let repo = TempRepo::new(ObjectFormat::Sha1);
+		let (first, second, tag) = repo.run(|| {
+			let first = commit(&[("a.txt", "a\n")], &[])?;
+			let second = commit(
+				&[("a.txt", "a\n"), ("b.txt", "b\n")],
+				slice::from_ref(&first),
+			)?;
+			update_ref("refs/heads/master", &second, "test")?;
+			let tag = insert(
+				format!(
+					"object {}\ntype commit\ntag v1\ntagger A U Thor <author@example.com> \
+					 1700000000 +0000\n\nv1\n",
+					second
+				)
+				.as_bytes(),
+				ObjectType::Tag,
+			)?;
+			write_ref("refs/tags/v1", &tag)?;
+			Ok((first, second, tag))
+		});
+		Fixture {
+			repo,
+			first,
+			second,
+			tag,
+		}
+	}
+
+	fn request(command: &str, arguments: &[String]) -> Vec<u8> {
+		let mut input = Vec::new();
+		write_line(&mut input, &format!("command={}", command)).unwrap();
+		write_line(&mut input, "agent=test").unwrap();
+		write_packet(&mut input, &Packet::Delim).unwrap();
+		for argument in arguments {
+			write_line(&mut input, argument).unwrap();
+		}
+		write_packet(&mut input, &Packet::Flush).unwrap();
+		input
+	}
+
+	// The advertisement, then the responses up to the end of the output
+	fn serve(repo: &TempRepo, input: &[u8]) -> (Vec<Packet>, Vec<Packet>) {
+		let mut output = Vec::new();
+		upload_pack(&repo.path, &mut &input[..], &mut output).unwrap();
+		let mut reader = &output[..];
+		let advertisement = read_until_flush(&mut reader).unwrap();
+		let mut responses = Vec::new();
+		while let Some(packet) = read_packet(&mut reader).unwrap() {
+			responses.push(packet);
+		}
+		(advertisement, responses)
+	}
+
+	fn lines(packets: &[Packet]) -> Vec<&str> {
+		packets.iter().map(|p| p.text().unwrap_or("")).collect()
+	}
+
+	// The objects of the pack sent after the packfile line, by hash
+	fn received_objects(packets: &[Packet]) -> BTreeSet<ObjectRepr> {
+		let start = packets
+			.iter()
+			.position(|p| p.text() == Some("packfile"))
+			.expect("a packfile is sent");
+		let mut pack = Vec::new();
+		for packet in &packets[start + 1..] {
+			if let Packet::Data(data) = packet {
+				if data[0] == BAND_DATA {
+					pack.extend_from_slice(&data[1..]);
+				}
+			}
+		}
+		read_pack(&pack)
+			.unwrap()
+			.into_iter()
+			.map(|(object_type, data)| simulate_insert(&data, object_type).unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn advertises_capabilities() {
+		let _lock = lock();
+		let fixture = fixture();
+		let (advertisement, responses) = serve(&fixture.repo, b"");
+		assert_eq!(
+			lines(&advertisement),
+			vec![
+				"version 2",
+				&format!("agent={}", AGENT),
+				"ls-refs=unborn",
+				"fetch",
+				"object-format=sha1"
+			]
+		);
+		assert!(responses.is_empty());
+	}
+
+	#[test]
+	fn lists_refs_with_symrefs_and_peeled_tags() {
+		let _lock = lock();
+		let fixture = fixture();
+		let arguments = ["symrefs", "peel"]
+			.iter()
+			.map(|a| a.to_string())
+			.collect::<Vec<_>>();
+		let (_, responses) = serve(&fixture.repo, &request("ls-refs", &arguments));
+		assert_eq!(
+			lines(&responses),
+			vec![
+				format!("{} HEAD symref-target:refs/heads/master", fixture.second),
+				format!("{} refs/heads/master", fixture.second),
+				format!("{} refs/tags/v1 peeled:{}", fixture.tag, fixture.second),
+				String::new(),
+			]
+		);
+		assert_eq!(responses.last(), Some(&Packet::Flush));
+
+		let prefix = vec!["ref-prefix refs/tags/".to_owned()];
+		let (_, responses) = serve(&fixture.repo, &request("ls-refs", &prefix));
+		assert_eq!(
+			lines(&responses),
+			vec![format!("{} refs/tags/v1", fixture.tag), String::new()]
+		);
+	}
+
+	#[test]
+	fn lists_unborn_head() {
+		let _lock = lock();
+		let repo = TempRepo::new(ObjectFormat::Sha1);
+		let arguments = vec!["symrefs".to_owned(), "unborn".to_owned()];
+		let (_, responses) = serve(&repo, &request("ls-refs", &arguments));
+		assert_eq!(
+			lines(&responses),
+			vec!["unborn HEAD symref-target:refs/heads/master", ""]
+		);
+	}
+
+	#[test]
+	fn negotiates_until_a_common_commit_is_found() {
+		let _lock = lock();
+		let fixture = fixture();
+		let unknown = simulate_insert(b"unknown", ObjectType::Blob).unwrap();
+		let want = format!("want {}", fixture.second);
+		let mut input = request("fetch", &[want.clone(), format!("have {}", unknown)]);
+		input.extend(request(
+			"fetch",
+			&[
+				want,
+				format!("have {}", fixture.first),
+				"include-tag".to_owned(),
+			],
+		));
+		let (_, responses) = serve(&fixture.repo, &input);
+
+		let end = responses.iter().position(|p| *p == Packet::Flush).unwrap();
+		assert_eq!(lines(&responses[..end]), vec!["acknowledgments", "NAK"]);
+		let second_round = &responses[end + 1..];
+		assert_eq!(
+			lines(&second_round[..4]),
+			vec![
+				"acknowledgments",
+				&format!("ACK {}", fixture.first),
+				"ready",
+				""
+			]
+		);
+		assert_eq!(second_round[3], Packet::Delim);
+		assert_eq!(second_round.last(), Some(&Packet::Flush));
+		// The commit, its tree, the new blob and the tag pointing to it
+		let received = received_objects(second_round);
+		assert_eq!(received.len(), 4);
+		assert!(received.contains(&fixture.second));
+		assert!(received.contains(&fixture.tag));
+		assert!(!received.contains(&fixture.first));
+	}
+
+	#[test]
+	fn sends_everything_wanted_when_done() {
+		let _lock = lock();
+		let fixture = fixture();
+		let arguments = vec![
+			format!("want {}", fixture.second),
+			"no-progress".to_owned(),
+			"done".to_owned(),
+		];
+		let (_, responses) = serve(&fixture.repo, &request("fetch", &arguments));
+		assert_eq!(responses[0].text(), Some("packfile"));
+		// Both commits, both trees and both blobs
+		let received = received_objects(&responses);
+		assert_eq!(received.len(), 6);
+		assert!(received.contains(&fixture.first));
+		let progress = responses.iter().any(|p| match p {
+			Packet::Data(data) => data[0] == BAND_PROGRESS,
+			_ => false,
+		});
+		assert!(!progress);
+	}
+
+	#[test]
+	fn refuses_unknown_wants() {
+		let _lock = lock();
+		let fixture = fixture();
+		let unknown = simulate_insert(b"unknown", ObjectType::Blob).unwrap();
+		let input = request("fetch", &[format!("want {}", unknown), "done".to_owned()]);
+		let mut output = Vec::new();
+		assert!(upload_pack(&fixture.repo.path, &mut &input[..], &mut output).is_err());
+		let mut reader = &output[..];
+		read_until_flush(&mut reader).unwrap();
+		let error = read_packet(&mut reader).unwrap().unwrap();
+		assert_eq!(
+			error.text(),
+			Some(format!("ERR upload-pack: not our ref {}", unknown).as_str())
+		);
+	}
+}